@@ -0,0 +1,76 @@
+// Compares B+ tree pages in the legacy (protobuf) and slotted formats, on full 4KiB leaves of
+// rows with 10 int columns. Run with `cargo +nightly bench --bench page`.
+#![feature(test)]
+
+extern crate test;
+
+use socks::bench::page::{self, Format, Leaf};
+use std::hint::black_box;
+use test::Bencher;
+
+const PAGE_SIZE: usize = 4096;
+const NUM_COLS: usize = 10;
+
+fn encode(b: &mut Bencher, format: Format) {
+    let leaf = Leaf::full(NUM_COLS, PAGE_SIZE);
+    b.iter(|| leaf.encode(format));
+}
+
+// Reads whole nodes, e.g. before they're modified.
+fn decode(b: &mut Bencher, format: Format) {
+    let bytes = Leaf::full(NUM_COLS, PAGE_SIZE).encode(format);
+    b.iter(|| page::decode(black_box(&bytes)));
+}
+
+// Reads a single row, e.g. of a point lookup.
+fn read_row(b: &mut Bencher, format: Format) {
+    let leaf = Leaf::full(NUM_COLS, PAGE_SIZE);
+    let bytes = leaf.encode(format);
+    let key = leaf.num_rows() as u32 / 2;
+    b.iter(|| page::read_row(black_box(&bytes), key).unwrap());
+}
+
+fn is_full(b: &mut Bencher, format: Format) {
+    let leaf = Leaf::full(NUM_COLS, PAGE_SIZE);
+    b.iter(|| leaf.is_full(format));
+}
+
+#[bench]
+fn encode_legacy(b: &mut Bencher) {
+    encode(b, Format::Legacy);
+}
+
+#[bench]
+fn encode_slotted(b: &mut Bencher) {
+    encode(b, Format::Slotted);
+}
+
+#[bench]
+fn decode_legacy(b: &mut Bencher) {
+    decode(b, Format::Legacy);
+}
+
+#[bench]
+fn decode_slotted(b: &mut Bencher) {
+    decode(b, Format::Slotted);
+}
+
+#[bench]
+fn read_row_legacy(b: &mut Bencher) {
+    read_row(b, Format::Legacy);
+}
+
+#[bench]
+fn read_row_slotted(b: &mut Bencher) {
+    read_row(b, Format::Slotted);
+}
+
+#[bench]
+fn is_full_legacy(b: &mut Bencher) {
+    is_full(b, Format::Legacy);
+}
+
+#[bench]
+fn is_full_slotted(b: &mut Bencher) {
+    is_full(b, Format::Slotted);
+}
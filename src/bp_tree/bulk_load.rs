@@ -0,0 +1,214 @@
+use crate::bp_tree::page::{self, Node};
+use crate::buffer::Buffer;
+use crate::error::{ErrorKind::*, *};
+use crate::filelike::Filelike;
+use crate::mvcc;
+use crate::options::WriteStrategy::*;
+use crate::protos::generated::chunk::*;
+use crate::statistics;
+use crate::table::Table;
+use std::sync::atomic::Ordering;
+
+// Builds the B+ tree of an empty table bottom-up, from rows sorted by key, rather than inserting
+// them one at a time. Leaves are filled in key order (up to DatabaseOptions::bulk_load_fill_factor)
+// and written out as soon as the next row doesn't fit, adding an entry for each to the node being
+// filled on the level above, which is written out in turn once full, and so on. Once all rows
+// are pushed, the partially filled nodes are written out, up to the first level holding a single
+// node, which becomes the root.
+//
+// NOTE: nodes are written straight to the table's file, bypassing the buffer pool, since nothing
+// can reference them until the root is set. Parent offsets aren't set, as they're never read.
+
+// The node being filled on a level of the tree.
+#[derive(Default)]
+struct Level {
+    node: NodeProto,
+    // The first key under the node, i.e. its separator in the parent.
+    first_key: u32,
+    // The size of the node's entries, see page.rs.
+    size: usize,
+    // The offset of the node, if already claimed (i.e. by its left sibling).
+    offset: Option<u32>,
+    // The offset of the last node written on the level, 0 if none.
+    left_offset: u32,
+}
+
+pub(crate) struct BulkLoader<'a, F: Filelike> {
+    table: &'a Table<F>,
+    // Whether keys must be distinct, e.g. unlike those of secondary indexes.
+    is_unique: bool,
+    // The size nodes are filled up to, and the most any entry may take.
+    fill_size: usize,
+    max_size: usize,
+    // The nodes being filled, from the leaves up.
+    levels: Vec<Level>,
+    last_key: Option<u32>,
+    statistics: TableStatisticsProto,
+}
+
+impl<'a, F: Filelike> BulkLoader<'a, F> {
+    // Fails with FailedPrecondition unless the table is empty.
+    pub(crate) async fn new(table: &'a Table<F>, is_unique: bool) -> Result<Self, Error> {
+        let root_lock = table
+            .buffer_pool
+            .read_from_table(table, table.root_chunk_offset)
+            .await?;
+        if !root_lock
+            .read()
+            .await
+            .get()
+            .internal()
+            .child_offsets
+            .is_empty()
+        {
+            return Err(Error::new(
+                FailedPrecondition,
+                format!("Unable to bulk load non-empty table {}!", table.name),
+            ));
+        }
+        let max_size = table.config.chunk_size as usize - page::HEADER_SIZE;
+        Ok(Self {
+            table,
+            is_unique,
+            fill_size: (max_size as f64 * table.options.bulk_load_fill_factor) as usize,
+            max_size,
+            levels: Vec::new(),
+            last_key: None,
+            statistics: table.statistics(),
+        })
+    }
+
+    // Adds the row with the given key, which must not be less than any pushed before.
+    pub(crate) async fn push_row(
+        &mut self,
+        key: u32,
+        mut row: InternalRowProto,
+    ) -> Result<(), Error> {
+        if let Some(last_key) = self.last_key {
+            if key < last_key || (self.is_unique && key == last_key) {
+                return Err(Error::new(
+                    InvalidArgument,
+                    format!(
+                        "Bulk loaded rows must be sorted by distinct keys, got {} after {}!",
+                        key, last_key
+                    ),
+                ));
+            }
+        }
+        // NOTE: loaded rows were written outside of any transaction, see mvcc.rs.
+        row.created_txn_id = 0;
+        row.deleted_txn_id = mvcc::NOT_DELETED;
+        let entry_size = page::leaf_entry_size(&row);
+        if entry_size > self.max_size {
+            return Err(Error::new(
+                InvalidArgument,
+                format!("Row with key {} is too large to fit in a page!", key),
+            ));
+        }
+        self.make_room(0, key, entry_size).await?;
+        let leaf = self.levels[0].node.mut_leaf();
+        leaf.keys.push(key);
+        leaf.rows.push(row);
+        self.last_key = Some(key);
+        statistics::record_insert(&mut self.statistics, key);
+        Ok(())
+    }
+
+    // Writes out the remaining nodes and sets the root, then commits the table's metadata.
+    pub(crate) async fn finish(mut self) -> Result<(), Error> {
+        if self.levels.is_empty() {
+            return Ok(());
+        }
+        let mut idx = 0;
+        // NOTE: the root is always an internal node, so the leaves are always written.
+        while idx == 0 || self.levels[idx].left_offset != 0 {
+            self.write_node(idx, None).await?;
+            idx += 1;
+        }
+        let mut root = std::mem::take(&mut self.levels[idx].node);
+
+        let table = self.table;
+        let root_lock = table
+            .buffer_pool
+            .read_from_table(table, table.root_chunk_offset)
+            .await?;
+        let mut root_buffer = table.buffer_pool.locks.write(&root_lock).await?;
+        if !root_buffer.get().internal().child_offsets.is_empty() {
+            return Err(Error::new(
+                FailedPrecondition,
+                format!("Table {} was written to during bulk load!", table.name),
+            ));
+        }
+        *root_buffer.get_mut().mut_internal() = root.take_internal();
+        drop(root_buffer);
+
+        self.statistics.height = idx as u32 + 1;
+        *table.statistics.lock().unwrap() = self.statistics;
+        table.commit_metadata().await
+    }
+
+    // Writes out the node being filled on the given level first, if an entry of the given size
+    // (and key) doesn't fit.
+    async fn make_room(&mut self, idx: usize, key: u32, entry_size: usize) -> Result<(), Error> {
+        if self.levels.len() == idx {
+            self.levels.push(Level::default());
+        }
+        let level = &self.levels[idx];
+        if level.size > 0 && level.size + entry_size > self.fill_size {
+            self.write_node(idx, Some(key)).await?;
+        }
+        let level = &mut self.levels[idx];
+        if level.size == 0 {
+            level.first_key = key;
+        }
+        level.size += entry_size;
+        Ok(())
+    }
+
+    async fn push_child(&mut self, idx: usize, first_key: u32, offset: u32) -> Result<(), Error> {
+        self.make_room(idx, first_key, page::INTERNAL_ENTRY_SIZE)
+            .await?;
+        let internal = self.levels[idx].node.mut_internal();
+        if !internal.child_offsets.is_empty() {
+            internal.keys.push(first_key);
+        }
+        internal.child_offsets.push(offset);
+        Ok(())
+    }
+
+    // Writes out the node being filled on the given level, and adds it to its parent. next_key
+    // is the first key of its right sibling, if there will be one.
+    async fn write_node(&mut self, idx: usize, next_key: Option<u32>) -> Result<(), Error> {
+        let table = self.table;
+        let level = &mut self.levels[idx];
+        let mut node = std::mem::take(&mut level.node);
+        let offset = level
+            .offset
+            .take()
+            .unwrap_or_else(|| table.next_chunk_offset());
+        node.offset = offset;
+        // B-link trees only: siblings are linked, see insert_b_link.rs.
+        if table.options.write_strategy == BLinkTree {
+            node.left_sibling_offset = level.left_offset;
+            if let Some(next_key) = next_key {
+                let next_offset = table.next_chunk_offset();
+                level.offset = Some(next_offset);
+                node.high_key = Some(next_key);
+                node.right_sibling_offset = next_offset;
+            }
+        }
+        level.left_offset = offset;
+        level.size = 0;
+        let first_key = level.first_key;
+
+        let buffer =
+            Buffer::<F, Node>::new_for_file(table.file.clone(), &table.config, offset, node.into());
+        debug_assert!(!buffer.would_overflow(0));
+        buffer.write_to_file().await?;
+        table
+            .counters
+            .bytes_written
+            .fetch_add(table.config.chunk_size as u64, Ordering::Relaxed);
+        Box::pin(self.push_child(idx + 1, first_key, offset)).await
+    }
+}
@@ -1,81 +1,115 @@
 use crate::bp_tree;
+use crate::bp_tree::page::{self, Node};
 use crate::buffer::Buffer;
+use crate::buffer_pool::PinnedBuffer;
 use crate::error::*;
 use crate::filelike::Filelike;
+use crate::lock_manager::LockGuard;
 use crate::protos::generated::chunk::*;
 use crate::table::*;
-use protobuf::Message;
-use std::sync::Arc;
-use tokio::sync::{RwLock, RwLockWriteGuard};
+use std::sync::atomic::Ordering;
+use tokio::sync::RwLockWriteGuard;
 
+// Inserts rows[*next], then any following rows which belong to the node (i.e. with keys less
+// than bound, if any) while they fit.
 // NOTE: Expects node to be non-full.
 async fn insert_leaf<F: Filelike>(
-    node_buffer: &mut Buffer<F, NodeProto>,
-    key: u32,
-    row: InternalRowProto,
+    table: &Table<F>,
+    node_buffer: &mut Buffer<F, Node>,
+    bound: Option<u32>,
+    rows: &[(u32, InternalRowProto)],
+    next: &mut usize,
 ) -> Result<(), Error> {
     debug_assert!(node_buffer.get().has_leaf());
-    let leaf: &mut LeafNodeProto = node_buffer.get_mut().mut_leaf();
-    let idx = bp_tree::find_row_idx_for_key(leaf, key);
-    leaf.keys.insert(idx, key);
-    leaf.rows.insert(idx, row);
-    Ok(())
+    loop {
+        let (key, row) = &rows[*next];
+        let leaf: &mut LeafNodeProto = node_buffer.get_mut().mut_leaf();
+        let idx = bp_tree::find_row_idx_for_key(table, (&leaf.keys).into(), *key);
+        leaf.keys.insert(idx, *key);
+        leaf.rows.insert(idx, row.clone());
+        *next += 1;
+        match rows.get(*next) {
+            Some((key, row))
+                if bound.is_none_or(|bound| *key < bound)
+                    && !node_buffer.would_overflow(page::leaf_entry_size(row)) => {}
+            _ => return Ok(()),
+        }
+    }
 }
 
+// Inserts rows[*next] (and possibly following rows, see insert_leaf) under the node, whose keys
+// are less than bound (if any).
 // NOTE: Expects node to be non-full.
 // TODO: Currently, all locks are write locks. This is easier to implement, but most
 // insertion reads do not need exclusive access. It's likely better (and more friendly
 // to concurrent read operations) to attempt to read before re-aquiring a write lock.
 async fn insert_internal<F: Filelike>(
     table: &Table<F>,
-    mut node_buffer: RwLockWriteGuard<'_, Buffer<F, NodeProto>>,
-    key: u32,
-    row: InternalRowProto,
+    mut node_buffer: LockGuard<'_, RwLockWriteGuard<'_, Buffer<F, Node>>>,
+    bound: Option<u32>,
+    rows: &[(u32, InternalRowProto)],
+    next: &mut usize,
 ) -> Result<(), Error> {
-    let idx = bp_tree::find_next_node_idx_for_key(node_buffer.get().internal(), key)?;
+    let (key, row) = &rows[*next];
+    let node = node_buffer.get().view();
+    let mut idx =
+        bp_tree::find_next_node_idx_for_key(table, node.keys(), node.child_offsets().len(), *key)?;
     let mut child_lock = table
         .buffer_pool
         .read_from_table(table, node_buffer.get().internal().child_offsets[idx])
         .await?;
-    let mut child_buffer = child_lock.write().await;
-    match &child_buffer.get().node_type {
+    let mut child_buffer = table.buffer_pool.locks.write(&child_lock).await?;
+    let is_leaf = match &child_buffer.get().node_type {
         Some(node_proto::Node_type::Internal(_)) => {
-            if child_buffer.would_overflow(std::mem::size_of::<i32>()) {
+            if child_buffer.would_overflow(page::INTERNAL_ENTRY_SIZE) {
                 let right_child_lock =
                     split_child_internal(table, &mut *node_buffer, &mut child_buffer, idx).await?;
-                if node_buffer.get().internal().keys[idx] < key {
+                if node_buffer.get().internal().keys[idx] < *key {
                     drop(child_buffer);
                     child_lock = right_child_lock;
-                    child_buffer = child_lock.write().await;
+                    child_buffer = table.buffer_pool.locks.write(&child_lock).await?;
+                    idx += 1;
                 }
             }
-            drop(node_buffer);
-            return Box::pin(insert_internal(table, child_buffer, key, row)).await;
+            false
         }
         Some(node_proto::Node_type::Leaf(_)) => {
-            if child_buffer.would_overflow(row.compute_size() as usize) {
+            if child_buffer.would_overflow(page::leaf_entry_size(row)) {
                 let right_child_lock =
                     split_child_leaf(table, &mut *node_buffer, &mut child_buffer, idx).await?;
-                if node_buffer.get().internal().keys[idx] < key {
+                if node_buffer.get().internal().keys[idx] < *key {
                     drop(child_buffer);
                     child_lock = right_child_lock;
-                    child_buffer = child_lock.write().await;
+                    child_buffer = table.buffer_pool.locks.write(&child_lock).await?;
+                    idx += 1;
                 }
             }
-            drop(node_buffer);
-            return insert_leaf(&mut *child_buffer, key, row).await;
+            true
         }
         None => unreachable!(),
+    };
+    let bound = node_buffer
+        .get()
+        .internal()
+        .keys
+        .get(idx)
+        .copied()
+        .or(bound);
+    drop(node_buffer);
+    match is_leaf {
+        true => insert_leaf(table, &mut *child_buffer, bound, rows, next).await,
+        false => Box::pin(insert_internal(table, child_buffer, bound, rows, next)).await,
     }
 }
 
-async fn split_child_leaf<F: Filelike>(
-    table: &Table<F>,
-    parent: &mut Buffer<F, NodeProto>,
-    child: &mut Buffer<F, NodeProto>,
+async fn split_child_leaf<'a, F: Filelike>(
+    table: &'a Table<F>,
+    parent: &mut Buffer<F, Node>,
+    child: &mut Buffer<F, Node>,
     child_chunk_idx: usize,
-) -> Result<Arc<RwLock<Buffer<F, NodeProto>>>, Error> {
+) -> Result<PinnedBuffer<'a, F>, Error> {
     log::trace!("Splitting leaf node.");
+    table.counters.splits.fetch_add(1, Ordering::Relaxed);
     debug_assert!(parent.get().has_internal());
     debug_assert!(child.get().has_leaf());
     let parent = parent.get_mut();
@@ -84,7 +118,7 @@ async fn split_child_leaf<F: Filelike>(
     let split_idx = left_child.leaf().keys.len() / 2;
 
     let right_child_lock = table.buffer_pool.new_next_for_table(table).await?;
-    let mut right_child_buffer = right_child_lock.write().await;
+    let mut right_child_buffer = table.buffer_pool.locks.write(&right_child_lock).await?;
     let offset = right_child_buffer.offset;
     let right_child = right_child_buffer.get_mut();
     right_child.offset = offset;
@@ -105,13 +139,14 @@ async fn split_child_leaf<F: Filelike>(
     Ok(right_child_lock)
 }
 
-async fn split_child_internal<F: Filelike>(
-    table: &Table<F>,
-    parent: &mut Buffer<F, NodeProto>,
-    child: &mut Buffer<F, NodeProto>,
+async fn split_child_internal<'a, F: Filelike>(
+    table: &'a Table<F>,
+    parent: &mut Buffer<F, Node>,
+    child: &mut Buffer<F, Node>,
     child_chunk_idx: usize,
-) -> Result<Arc<RwLock<Buffer<F, NodeProto>>>, Error> {
+) -> Result<PinnedBuffer<'a, F>, Error> {
     log::trace!("Splitting internal node.");
+    table.counters.splits.fetch_add(1, Ordering::Relaxed);
     debug_assert!(parent.get().has_internal());
     debug_assert!(child.get().has_internal());
     let parent = parent.get_mut();
@@ -120,7 +155,7 @@ async fn split_child_internal<F: Filelike>(
     let split_idx = left_child.internal().keys.len() / 2;
 
     let right_child_lock = table.buffer_pool.new_next_for_table(table).await?;
-    let mut right_child_buffer = right_child_lock.write().await;
+    let mut right_child_buffer = table.buffer_pool.locks.write(&right_child_lock).await?;
     let offset = right_child_buffer.offset;
     let right_child = right_child_buffer.get_mut();
     right_child.offset = offset;
@@ -142,60 +177,63 @@ async fn split_child_internal<F: Filelike>(
 
 // NOTE: https://www.geeksforgeeks.org/insertion-in-a-b-tree/
 // TODO: ensure key doesn't already exist
-pub(crate) async fn insert<F: Filelike>(
+pub(crate) async fn insert_batch<F: Filelike>(
     table: &Table<F>,
-    key: u32,
-    row: InternalRowProto,
+    rows: &[(u32, InternalRowProto)],
+    next: &mut usize,
 ) -> Result<(), Error> {
-    let root_node_lock = table
-        .buffer_pool
-        .read_from_table(table, table.root_chunk_offset)
-        .await?;
-    let mut root_buffer = root_node_lock.write().await;
-    debug_assert!(root_buffer.get().has_internal());
-
-    if root_buffer.get().internal().child_offsets.len() == 0 {
-        log::trace!("Inserting first value.");
-
-        let child_lock = table.buffer_pool.new_next_for_table(table).await?;
-        let mut child_buffer = child_lock.write().await;
-        let offset = child_buffer.offset;
-        let child_node = child_buffer.get_mut();
-        child_node.offset = offset;
-        child_node.parent_offset = root_buffer.get().offset;
-        child_node.mut_leaf().keys.push(key);
-        child_node.mut_leaf().rows.push(row);
-
-        root_buffer
-            .get_mut()
-            .mut_internal()
-            .child_offsets
-            .push(child_node.offset);
-
-        table.commit_metadata().await?;
-        return Ok(());
-    }
+    while *next < rows.len() {
+        let root_node_lock = table
+            .buffer_pool
+            .read_from_table(table, table.root_chunk_offset)
+            .await?;
+        let mut root_buffer = table.buffer_pool.locks.write(&root_node_lock).await?;
+        debug_assert!(root_buffer.get().has_internal());
+
+        if root_buffer.get().internal().child_offsets.is_empty() {
+            log::trace!("Inserting first value.");
+
+            let (key, row) = &rows[*next];
+            let child_lock = table.buffer_pool.new_next_for_table(table).await?;
+            let mut child_buffer = table.buffer_pool.locks.write(&child_lock).await?;
+            let offset = child_buffer.offset;
+            let child_node = child_buffer.get_mut();
+            child_node.offset = offset;
+            child_node.parent_offset = root_buffer.get().offset;
+            child_node.mut_leaf().keys.push(*key);
+            child_node.mut_leaf().rows.push(row.clone());
+
+            root_buffer
+                .get_mut()
+                .mut_internal()
+                .child_offsets
+                .push(child_node.offset);
+
+            table.statistics.lock().unwrap().height += 1;
+            *next += 1;
+            continue;
+        }
 
-    if root_buffer.would_overflow(std::mem::size_of::<i32>()) {
-        log::trace!("Root overflow detected.");
+        if root_buffer.would_overflow(page::INTERNAL_ENTRY_SIZE) {
+            log::trace!("Root overflow detected.");
 
-        let child_lock = table.buffer_pool.new_next_for_table(table).await?;
-        let mut child_buffer = child_lock.write().await;
-        let offset = child_buffer.offset;
-        let child_node = child_buffer.get_mut();
-        child_node.offset = offset;
-        child_node.set_internal(root_buffer.get().internal().clone());
+            let child_lock = table.buffer_pool.new_next_for_table(table).await?;
+            let mut child_buffer = table.buffer_pool.locks.write(&child_lock).await?;
+            let offset = child_buffer.offset;
+            let child_node = child_buffer.get_mut();
+            child_node.offset = offset;
+            child_node.set_internal(root_buffer.get().internal().clone());
 
-        let root_internal = root_buffer.get_mut().mut_internal();
-        root_internal.keys.clear();
-        root_internal.child_offsets.clear();
-        root_internal.child_offsets.push(child_node.offset);
+            let root_internal = root_buffer.get_mut().mut_internal();
+            root_internal.keys.clear();
+            root_internal.child_offsets.clear();
+            root_internal.child_offsets.push(child_node.offset);
 
-        split_child_internal(table, &mut *root_buffer, &mut *child_buffer, 0).await?;
-    }
-
-    insert_internal(table, root_buffer, key, row).await?;
-    table.commit_metadata().await?;
+            split_child_internal(table, &mut *root_buffer, &mut *child_buffer, 0).await?;
+            table.statistics.lock().unwrap().height += 1;
+        }
 
+        insert_internal(table, root_buffer, None, rows, next).await?;
+    }
     Ok(())
 }
@@ -0,0 +1,259 @@
+#[cfg(test)]
+#[path = "./insert_b_link_test.rs"]
+mod test;
+
+use crate::bp_tree;
+use crate::bp_tree::Node;
+use crate::buffer::Buffer;
+use crate::error::*;
+use crate::filelike::Filelike;
+use crate::lock_manager::LockGuard;
+use crate::protos::generated::chunk::*;
+use crate::table::*;
+use std::sync::atomic::Ordering;
+use tokio::sync::RwLockWriteGuard;
+
+// Lehman & Yao's B-link tree: https://www.csd.uoc.gr/~hy460/pdf/p650-lehman.pdf
+//
+// Every node links to its right sibling, and records a high key: keys greater or equal belong to
+// its right sibling (or further right). Inserts find the leaf for their key without holding any
+// locks, and then only lock the nodes they modify: the leaf, then (if it splits) each parent in
+// turn, bottom-up. Traversals which reach a node split since its parent was read simply move
+// right, see bp_tree::move_right.
+//
+// NOTE: inserts only take locks bottom-up and left-to-right, so they can't deadlock each other.
+
+type NodeWriteGuard<'a, F> = LockGuard<'a, RwLockWriteGuard<'a, Buffer<F, Node>>>;
+
+// Finds the leaf that should hold the given key, along with the internal nodes on the way there.
+// Returns None if the tree is empty.
+async fn find_leaf<F: Filelike>(
+    table: &Table<F>,
+    key: u32,
+) -> Result<Option<(Vec<u32>, u32)>, Error> {
+    let mut path = Vec::new();
+    let mut offset = table.root_chunk_offset;
+    loop {
+        let node_buffer_lock = table.buffer_pool.read_from_table(table, offset).await?;
+        let node_buffer = table.buffer_pool.locks.read(&node_buffer_lock).await?;
+        if let Some(right_offset) = bp_tree::move_right(node_buffer.get(), key) {
+            offset = right_offset;
+            continue;
+        }
+        let node = node_buffer.get().view();
+        if node.is_leaf() {
+            return Ok(Some((path, offset)));
+        }
+        let child_offsets = node.child_offsets();
+        if child_offsets.is_empty() {
+            return Ok(None);
+        }
+        path.push(offset);
+        let idx =
+            bp_tree::find_next_node_idx_for_key(table, node.keys(), child_offsets.len(), key)?;
+        offset = child_offsets.get(idx);
+    }
+}
+
+// Inserts the first row of the tree, returning false if another insert got there first.
+async fn insert_first<F: Filelike>(
+    table: &Table<F>,
+    key: u32,
+    row: &InternalRowProto,
+) -> Result<bool, Error> {
+    let root_node_lock = table
+        .buffer_pool
+        .read_from_table(table, table.root_chunk_offset)
+        .await?;
+    let mut root_buffer = table.buffer_pool.locks.write(&root_node_lock).await?;
+    if !root_buffer.get().internal().child_offsets.is_empty() {
+        return Ok(false);
+    }
+    log::trace!("Inserting first value.");
+
+    let child_lock = table.buffer_pool.new_next_for_table(table).await?;
+    let mut child_buffer = table.buffer_pool.locks.write(&child_lock).await?;
+    let offset = child_buffer.offset;
+    let child_node = child_buffer.get_mut();
+    child_node.offset = offset;
+    child_node.parent_offset = table.root_chunk_offset;
+    child_node.mut_leaf().keys.push(key);
+    child_node.mut_leaf().rows.push(row.clone());
+
+    root_buffer
+        .get_mut()
+        .mut_internal()
+        .child_offsets
+        .push(offset);
+    table.statistics.lock().unwrap().height += 1;
+    Ok(true)
+}
+
+// Moves the upper half of the node's keys into a new right sibling, and returns the separator
+// key and offset to insert into the parent.
+async fn split<F: Filelike>(
+    table: &Table<F>,
+    node: &mut Buffer<F, Node>,
+) -> Result<(u32, u32), Error> {
+    table.counters.splits.fetch_add(1, Ordering::Relaxed);
+    let right_lock = table.buffer_pool.new_next_for_table(table).await?;
+    let mut right_buffer = table.buffer_pool.locks.write(&right_lock).await?;
+    let right_offset = right_buffer.offset;
+    let left = node.get_mut();
+    let right = right_buffer.get_mut();
+    right.offset = right_offset;
+    right.parent_offset = left.parent_offset;
+
+    let separator = match &mut left.node_type {
+        Some(node_proto::Node_type::Leaf(leaf)) => {
+            log::trace!("Splitting leaf node.");
+            let split_idx = leaf.keys.len() / 2;
+            right.mut_leaf().keys = leaf.keys.split_off(split_idx);
+            right.mut_leaf().rows = leaf.rows.split_off(split_idx);
+            right.leaf().keys[0]
+        }
+        Some(node_proto::Node_type::Internal(internal)) => {
+            log::trace!("Splitting internal node.");
+            // NOTE: the separator moves up to the parent, rather than into either half.
+            let split_idx = internal.keys.len() / 2;
+            right.mut_internal().keys = internal.keys.split_off(split_idx + 1);
+            right.mut_internal().child_offsets = internal.child_offsets.split_off(split_idx + 1);
+            internal.keys.pop().unwrap()
+        }
+        None => unreachable!(),
+    };
+
+    right.high_key = left.high_key;
+    right.right_sibling_offset = left.right_sibling_offset;
+    right.left_sibling_offset = left.offset;
+    left.high_key = Some(separator);
+    left.right_sibling_offset = right_offset;
+    Ok((separator, right_offset))
+}
+
+// Splits the root, by moving its contents into a new child and splitting that instead.
+// NOTE: the root stays at the same offset, so that it can always be found.
+async fn split_root<F: Filelike>(
+    table: &Table<F>,
+    root: &mut Buffer<F, Node>,
+) -> Result<(), Error> {
+    log::trace!("Root overflow detected.");
+    let child_lock = table.buffer_pool.new_next_for_table(table).await?;
+    let mut child_buffer = table.buffer_pool.locks.write(&child_lock).await?;
+    let child_offset = child_buffer.offset;
+    let child_node = child_buffer.get_mut();
+    child_node.offset = child_offset;
+    child_node.parent_offset = root.offset;
+    child_node.set_internal(root.get().internal().clone());
+    let (separator, right_offset) = split(table, &mut child_buffer).await?;
+
+    let root_internal = root.get_mut().mut_internal();
+    root_internal.keys = vec![separator];
+    root_internal.child_offsets = vec![child_offset, right_offset];
+    table.statistics.lock().unwrap().height += 1;
+    Ok(())
+}
+
+// Inserts the separator of a split child (still locked) into its parent, the last node of the
+// path. Splits the parent in turn if it overflows.
+async fn insert_into_parent<F: Filelike>(
+    table: &Table<F>,
+    mut path: Vec<u32>,
+    child_buffer: NodeWriteGuard<'_, F>,
+    separator: u32,
+    right_offset: u32,
+) -> Result<(), Error> {
+    let child_offset = child_buffer.offset;
+    let mut child_buffer = Some(child_buffer);
+    let mut offset = path.pop().unwrap();
+    loop {
+        let node_buffer_lock = table.buffer_pool.read_from_table(table, offset).await?;
+        let mut node_buffer = table.buffer_pool.locks.write(&node_buffer_lock).await?;
+        let internal = node_buffer.get().internal();
+        // NOTE: the parent may have split since it was traversed, moving the child right.
+        let Some(idx) = internal
+            .child_offsets
+            .iter()
+            .position(|offset| *offset == child_offset)
+        else {
+            debug_assert!(node_buffer.get().high_key.is_some());
+            offset = node_buffer.get().right_sibling_offset;
+            continue;
+        };
+        drop(child_buffer.take());
+
+        let internal = node_buffer.get_mut().mut_internal();
+        internal.keys.insert(idx, separator);
+        internal.child_offsets.insert(idx + 1, right_offset);
+        if !node_buffer.would_overflow(0) {
+            return Ok(());
+        }
+        if offset == table.root_chunk_offset {
+            return split_root(table, &mut node_buffer).await;
+        }
+        let (separator, right_offset) = split(table, &mut node_buffer).await?;
+        return Box::pin(insert_into_parent(
+            table,
+            path,
+            node_buffer,
+            separator,
+            right_offset,
+        ))
+        .await;
+    }
+}
+
+// Inserts rows[*next] into the leaf at the given offset (or to its right), then any following
+// rows which belong to it, until it splits.
+async fn insert_into_leaf<F: Filelike>(
+    table: &Table<F>,
+    path: Vec<u32>,
+    mut offset: u32,
+    rows: &[(u32, InternalRowProto)],
+    next: &mut usize,
+) -> Result<(), Error> {
+    loop {
+        let node_buffer_lock = table.buffer_pool.read_from_table(table, offset).await?;
+        let mut node_buffer = table.buffer_pool.locks.write(&node_buffer_lock).await?;
+        if let Some(right_offset) = bp_tree::move_right(node_buffer.get(), rows[*next].0) {
+            offset = right_offset;
+            continue;
+        }
+        let high_key = node_buffer.get().high_key;
+        loop {
+            let (key, row) = &rows[*next];
+            let leaf = node_buffer.get_mut().mut_leaf();
+            let idx = bp_tree::find_row_idx_for_key(table, (&leaf.keys).into(), *key);
+            leaf.keys.insert(idx, *key);
+            leaf.rows.insert(idx, row.clone());
+            *next += 1;
+            if node_buffer.would_overflow(0) {
+                let (separator, right_offset) = split(table, &mut node_buffer).await?;
+                return insert_into_parent(table, path, node_buffer, separator, right_offset).await;
+            }
+            match rows.get(*next) {
+                Some((key, _)) if high_key.is_none_or(|high_key| *key < high_key) => {}
+                _ => return Ok(()),
+            }
+        }
+    }
+}
+
+pub(crate) async fn insert_batch<F: Filelike>(
+    table: &Table<F>,
+    rows: &[(u32, InternalRowProto)],
+    next: &mut usize,
+) -> Result<(), Error> {
+    while *next < rows.len() {
+        let (key, row) = &rows[*next];
+        match find_leaf(table, *key).await? {
+            Some((path, offset)) => insert_into_leaf(table, path, offset, rows, next).await?,
+            None => {
+                if insert_first(table, *key, row).await? {
+                    *next += 1;
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,210 @@
+use super::insert_batch;
+use crate::buffer_pool::BufferPool;
+use crate::error::Error;
+use crate::options::{DatabaseOptions, WriteStrategy};
+use crate::protos::generated::chunk::*;
+use crate::protos::generated::config::*;
+use crate::protos::generated::operations::*;
+use crate::table::Table;
+use protobuf::text_format::parse_from_str;
+use std::io::Cursor;
+use std::sync::Arc;
+
+struct TestContext {
+    table: Arc<Table<Cursor<Vec<u8>>>>,
+}
+
+async fn setup() -> TestContext {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let schema = parse_from_str::<TableSchema>(
+        "
+            key {
+                name: \"Key\"
+                column_type: INTEGER
+            }
+            ",
+    )
+    .unwrap();
+    let options = Arc::new(DatabaseOptions {
+        write_strategy: WriteStrategy::BLinkTree,
+        ..Default::default()
+    });
+    TestContext {
+        table: Arc::new(
+            Table::create(
+                Cursor::<Vec<u8>>::new(Vec::new()),
+                Arc::new(BufferPool::new(&options)),
+                options,
+                "TestTable".to_string(),
+                0,
+                schema,
+            )
+            .await
+            .unwrap(),
+        ),
+    }
+}
+
+fn row(value: i32) -> InternalRowProto {
+    padded_row(value, 1)
+}
+
+// Returns a row with the given number of (unnamed) columns, so that few rows fit in each leaf.
+fn padded_row(value: i32, num_cols: usize) -> InternalRowProto {
+    let mut col = ValueProto::new();
+    col.set_int_value(value);
+    let mut row = InternalRowProto::new();
+    row.col_values = vec![col; num_cols];
+    row
+}
+
+async fn insert(
+    table: &Table<Cursor<Vec<u8>>>,
+    key: u32,
+    row: InternalRowProto,
+) -> Result<(), Error> {
+    insert_batch(table, &[(key, row)], &mut 0).await
+}
+
+async fn read_all_keys(table: &Table<Cursor<Vec<u8>>>) -> Result<Vec<u32>, Error> {
+    let mut keys = Vec::new();
+    let mut cursor = table.read_range(0, u32::MAX);
+    while let Some((leaf_keys, _)) = cursor.next_leaf().await? {
+        keys.extend(leaf_keys);
+    }
+    Ok(keys)
+}
+
+#[tokio::test]
+async fn insert_with_splits_ok() -> Result<(), Error> {
+    let ctx = setup().await;
+    let table = ctx.table;
+    let num_iter = 4000;
+
+    // inserts in an order that splits leaves all over the tree, and internal nodes as well.
+    for i in 0..num_iter {
+        let key = (i * 7919) % num_iter;
+        insert(&table, key, padded_row(key as i32, 200)).await?;
+    }
+
+    assert!(table.statistics().height > 2);
+    assert_eq!(
+        read_all_keys(&table).await?,
+        (0..num_iter).collect::<Vec<u32>>()
+    );
+    for key in [0, 1, num_iter / 2, num_iter - 1] {
+        let read_result = table.read_row(key).await?;
+        assert_eq!(read_result.columns[0].value.int_value(), key as i32);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn insert_duplicate_keys_ok() -> Result<(), Error> {
+    let ctx = setup().await;
+    let table = ctx.table;
+
+    // runs of duplicate keys span several leaves.
+    for i in 0..1000 {
+        insert(&table, i / 400, row(i as i32)).await?;
+    }
+
+    let expected_keys: Vec<u32> = (0..1000).map(|i| i / 400).collect();
+    assert_eq!(read_all_keys(&table).await?, expected_keys);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn read_with_other_write_strategy_ok() -> Result<(), Error> {
+    let ctx = setup().await;
+    let table = ctx.table;
+    for i in 0..1000 {
+        insert(&table, i / 400, padded_row(i as i32, 10)).await?;
+    }
+    table.buffer_pool.flush().await?;
+
+    // the write strategy isn't persisted, so B-link trees may be read as any other: each leaf is
+    // still read once, through either its parent or its left sibling.
+    let options = Arc::new(DatabaseOptions::default());
+    let file = table.file.lock().await.get_ref().clone();
+    let table = Table::open(
+        Cursor::new(file),
+        Arc::new(BufferPool::new(&options)),
+        options,
+    )
+    .await?;
+    let expected_keys: Vec<u32> = (0..1000).map(|i| i / 400).collect();
+    assert_eq!(read_all_keys(&table).await?, expected_keys);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn insert_batch_with_splits_ok() -> Result<(), Error> {
+    let ctx = setup().await;
+    let table = ctx.table;
+    let num_batches = 8;
+    let batch_size = 500;
+
+    // each batch lands between the keys of earlier ones, splitting leaves (and internal nodes)
+    // partway through.
+    for batch in 0..num_batches {
+        let rows: Vec<(u32, InternalRowProto)> = (0..batch_size)
+            .map(|i| {
+                let key = i * num_batches + batch;
+                (key, padded_row(key as i32, 200))
+            })
+            .collect();
+        let mut next = 0;
+        insert_batch(&table, &rows, &mut next).await?;
+        assert_eq!(next, rows.len());
+    }
+
+    assert!(table.statistics().height > 2);
+    assert_eq!(
+        read_all_keys(&table).await?,
+        (0..num_batches * batch_size).collect::<Vec<u32>>()
+    );
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_insert_and_read_success() -> Result<(), Error> {
+    let ctx = setup().await;
+    let table = ctx.table;
+    let num_tasks = 8;
+    let num_iter = 500;
+
+    let mut task_set = tokio::task::JoinSet::new();
+    for task in 0..num_tasks {
+        let table = table.clone();
+        task_set.spawn(async move {
+            for i in 0..num_iter {
+                let key = i * num_tasks + task;
+                insert(&table, key, padded_row(key as i32, 20)).await?;
+                // reads racing with splits must still find rows already inserted.
+                for i in [0, i / 2, i] {
+                    table.read_row(i * num_tasks + task).await?;
+                }
+            }
+            Ok::<(), Error>(())
+        });
+    }
+    for result in task_set.join_all().await {
+        result?;
+    }
+
+    assert_eq!(
+        read_all_keys(&table).await?,
+        (0..num_tasks * num_iter).collect::<Vec<u32>>()
+    );
+    for key in 0..num_tasks * num_iter {
+        let read_result = table.read_row(key).await?;
+        assert_eq!(read_result.columns[0].value.int_value(), key as i32);
+    }
+
+    Ok(())
+}
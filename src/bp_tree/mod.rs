@@ -1,88 +1,569 @@
 use crate::error::{Error, ErrorKind::*};
 use crate::filelike::Filelike;
+use crate::mvcc;
+use crate::options::{DeleteStrategy::*, ReadStrategy::*, WriteStrategy::*};
 use crate::protos::generated::chunk::*;
 use crate::table::Table;
-use crate::{
-    DeleteStrategy::*, ReadStrategy::*, WriteStrategy::*, DELETE_STRATEGY, READ_STRATEGY,
-    WRITE_STRATEGY,
-};
+use protobuf::Message;
 
+mod bulk_load;
 mod insert_aggressive_split;
+mod insert_b_link;
+pub(crate) mod page;
 mod read_binary_search;
 mod read_sequential;
 mod unbalanced_delete;
 
+pub(crate) use bulk_load::BulkLoader;
+pub(crate) use page::Node;
+use page::{NodeRef, U32s};
+
 // find what table of the current internal node's child nodes should be traversed
-// next in order to find the row with the given key.
-pub(crate) fn find_next_node_idx_for_key(
-    internal: &InternalNodeProto,
+// next in order to find the row with the given key, given the node's keys and number of children.
+pub(crate) fn find_next_node_idx_for_key<F: Filelike>(
+    table: &Table<F>,
+    keys: U32s<'_>,
+    num_children: usize,
     key: u32,
 ) -> Result<usize, Error> {
-    match READ_STRATEGY {
-        SequentialSearch => return read_sequential::find_next_node_idx_for_key(internal, key),
-        BinarySearch => return read_binary_search::find_next_node_idx_for_key(internal, key),
+    match table.options.read_strategy {
+        SequentialSearch => read_sequential::find_next_node_idx_for_key(keys, num_children, key),
+        BinarySearch => read_binary_search::find_next_node_idx_for_key(
+            keys,
+            num_children,
+            key,
+            table.options.binary_read_iter_cutoff,
+        ),
     }
 }
 
 // find what table in the current leaf node the key should be placed.
 // for read calls, this returns the row with the key, else the keys will mismatch.
 // for write calls, this returns where the row should be inserted into the leaf.
-pub(crate) fn find_row_idx_for_key(leaf: &LeafNodeProto, key: u32) -> usize {
-    match READ_STRATEGY {
-        SequentialSearch => read_sequential::find_row_idx_for_key(leaf, key),
-        BinarySearch => read_binary_search::find_row_idx_for_key(leaf, key),
+pub(crate) fn find_row_idx_for_key<F: Filelike>(
+    table: &Table<F>,
+    keys: U32s<'_>,
+    key: u32,
+) -> usize {
+    match table.options.read_strategy {
+        SequentialSearch => read_sequential::find_row_idx_for_key(keys, key),
+        BinarySearch => read_binary_search::find_row_idx_for_key(
+            keys,
+            key,
+            table.options.binary_read_iter_cutoff,
+        ),
+    }
+}
+
+// B-link trees only (see insert_b_link.rs): returns the right sibling to continue at, if the key
+// belongs further right than the node, e.g. after it was split concurrently with the traversal.
+pub(crate) fn move_right<'a>(node: impl Into<NodeRef<'a>>, key: u32) -> Option<u32> {
+    let node = node.into();
+    match node.high_key() {
+        Some(high_key) if key >= high_key => Some(node.right_sibling_offset()),
+        _ => None,
     }
 }
 
-// inserts the row with the associated key into the table.
-pub(crate) async fn insert<F: Filelike>(
+// B-link trees only: returns the right sibling holding any further keys up to upper, for
+// traversals which visit all keys in a range.
+fn right_link_up_to<'a>(node: impl Into<NodeRef<'a>>, upper: u32) -> Option<u32> {
+    let node = node.into();
+    match node.high_key() {
+        Some(high_key) if high_key <= upper => Some(node.right_sibling_offset()),
+        _ => None,
+    }
+}
+
+// inserts the given rows (sorted by key) into the table, starting from rows[*next]. next is
+// advanced past each row as it's inserted, so that on error it counts the rows inserted.
+// Consecutive rows belonging to the same leaf are inserted while it's locked, rather than
+// traversing the tree for each.
+pub(crate) async fn insert_batch<F: Filelike>(
     table: &Table<F>,
-    key: u32,
-    row: InternalRowProto,
+    rows: &[(u32, InternalRowProto)],
+    next: &mut usize,
 ) -> Result<(), Error> {
-    match WRITE_STRATEGY {
-        AggressiveSplit => insert_aggressive_split::insert::<F>(table, key, row).await,
+    debug_assert!(rows.is_sorted_by_key(|(key, _)| *key));
+    match table.options.write_strategy {
+        AggressiveSplit => insert_aggressive_split::insert_batch::<F>(table, rows, next).await,
+        BLinkTree => insert_b_link::insert_batch::<F>(table, rows, next).await,
     }
 }
 
 // Deletes the row with the given key, and returns it.
+#[cfg(test)]
 pub(crate) async fn delete<F: Filelike>(
     table: &Table<F>,
     key: u32,
 ) -> Result<InternalRowProto, Error> {
-    match DELETE_STRATEGY {
+    match table.options.delete_strategy {
         UnbalancedDelete => unbalanced_delete::delete(table, table.root_chunk_offset, key).await,
     }
 }
 
-// finds the row with the associated key, else returns NotFound.
-pub(crate) async fn read_row<F: Filelike>(
+// Deletes the row with the given key and contents, else returns NotFound.
+pub(crate) async fn delete_matching<F: Filelike>(
+    table: &Table<F>,
+    key: u32,
+    row: &InternalRowProto,
+) -> Result<(), Error> {
+    let is_deleted = match table.options.delete_strategy {
+        UnbalancedDelete => {
+            unbalanced_delete::delete_matching(table, table.root_chunk_offset, key, row).await?
+        }
+    };
+    if !is_deleted {
+        return Err(Error::new(
+            NotFound,
+            format!("Row with key {} not found!", key),
+        ));
+    }
+    Ok(())
+}
+
+// Replaces the row with the given key and contents, else returns NotFound.
+// NOTE: the new row must be the same size as the old one, so that its leaf can't overflow.
+pub(crate) async fn replace_matching<F: Filelike>(
+    table: &Table<F>,
+    key: u32,
+    row: &InternalRowProto,
+    new_row: &InternalRowProto,
+) -> Result<(), Error> {
+    debug_assert_eq!(row.compute_size(), new_row.compute_size());
+    let is_replaced = update_matching(table, table.root_chunk_offset, key, row, &|leaf, idx| {
+        leaf.rows[idx] = new_row.clone();
+    })
+    .await?;
+    if !is_replaced {
+        return Err(Error::new(
+            NotFound,
+            format!("Row with key {} not found!", key),
+        ));
+    }
+    Ok(())
+}
+
+// Finds the row with the given key and contents, and applies the given update to its leaf
+// (along with its index), returning false if there is none. Unlike lookups by key alone, this
+// finds the exact row amongst rows with duplicate keys (e.g. in secondary indexes), which may
+// span several leaves.
+pub(crate) async fn update_matching<F: Filelike>(
     table: &Table<F>,
     curr_offset: u32,
     key: u32,
-) -> Result<InternalRowProto, Error> {
+    row: &InternalRowProto,
+    update: &(dyn Fn(&mut LeafNodeProto, usize) + Sync),
+) -> Result<bool, Error> {
     let node_buffer_lock = table
         .buffer_pool
         .read_from_table(table, curr_offset)
         .await?;
-    let node_buffer = node_buffer_lock.read().await;
+    let node_buffer = table.buffer_pool.locks.read(&node_buffer_lock).await?;
+    let mut right_link = right_link_up_to(node_buffer.get(), key);
+    if node_buffer
+        .get()
+        .high_key
+        .is_some_and(|high_key| key > high_key)
+    {
+        drop(node_buffer);
+        return Box::pin(update_matching(
+            table,
+            right_link.unwrap(),
+            key,
+            row,
+            update,
+        ))
+        .await;
+    }
     match &node_buffer.get().node_type {
         Some(node_proto::Node_type::Internal(internal)) => {
-            let idx = find_next_node_idx_for_key(&internal, key)?;
-            let child_offset = internal.child_offsets[idx];
+            if internal.child_offsets.is_empty() {
+                return Ok(false);
+            }
+            let keys = internal.keys.clone();
+            let child_offsets = internal.child_offsets.clone();
+            drop(node_buffer);
+            let start = std::cmp::min(keys.partition_point(|k| *k < key), child_offsets.len() - 1);
+            for idx in start..child_offsets.len() {
+                if idx > start && keys[idx - 1] > key {
+                    break;
+                }
+                if Box::pin(update_matching(table, child_offsets[idx], key, row, update)).await? {
+                    return Ok(true);
+                }
+            }
+        }
+        Some(node_proto::Node_type::Leaf(_)) => {
             drop(node_buffer);
-            return Box::pin(read_row(table, child_offset, key)).await;
-        }
-        Some(node_proto::Node_type::Leaf(leaf)) => {
-            let idx = find_row_idx_for_key(&leaf, key);
-            if leaf.rows.len() <= idx || leaf.keys[idx] != key {
-                return Err(Error::new(
-                    NotFound,
-                    format!("Row with key {} not found!", key),
-                ));
+            let mut node_buffer = table.buffer_pool.locks.write(&node_buffer_lock).await?;
+            // NOTE: the leaf may have split while unlocked.
+            right_link = right_link_up_to(node_buffer.get(), key);
+            let leaf = node_buffer.get_mut().mut_leaf();
+            let start = leaf.keys.partition_point(|k| *k < key);
+            let end = leaf.keys.partition_point(|k| *k <= key);
+            if let Some(idx) = (start..end).find(|idx| leaf.rows[*idx] == *row) {
+                update(leaf, idx);
+                return Ok(true);
             }
-            return Ok(leaf.rows[idx].clone());
         }
         None => panic!(),
     }
+    match right_link {
+        Some(right_offset) => {
+            Box::pin(update_matching(table, right_offset, key, row, update)).await
+        }
+        None => Ok(false),
+    }
+}
+
+// finds the row with the associated key, else returns NotFound.
+pub(crate) async fn read_row<F: Filelike>(
+    table: &Table<F>,
+    curr_offset: u32,
+    key: u32,
+) -> Result<InternalRowProto, Error> {
+    let node_buffer_lock = table
+        .buffer_pool
+        .read_from_table(table, curr_offset)
+        .await?;
+    let node_buffer = table.buffer_pool.locks.read(&node_buffer_lock).await?;
+    let node = node_buffer.get().view();
+    if let Some(right_offset) = move_right(node, key) {
+        drop(node_buffer);
+        return Box::pin(read_row(table, right_offset, key)).await;
+    }
+    let keys = node.keys();
+    if !node.is_leaf() {
+        let child_offsets = node.child_offsets();
+        let idx = find_next_node_idx_for_key(table, keys, child_offsets.len(), key)?;
+        let child_offset = child_offsets.get(idx);
+        drop(node_buffer);
+        return Box::pin(read_row(table, child_offset, key)).await;
+    }
+    let idx = find_row_idx_for_key(table, keys, key);
+    if keys.len() <= idx || keys.get(idx) != key {
+        return Err(Error::new(
+            NotFound,
+            format!("Row with key {} not found!", key),
+        ));
+    }
+    Ok(node.rows(idx..idx + 1)?.pop().unwrap())
+}
+
+// Finds the rows with the given (sorted, distinct) keys, returning every row found with each
+// key (e.g. its versions), in key order. Each node on the way is read once for all the keys
+// under it, and independent subtrees are read concurrently.
+pub(crate) async fn read_rows<F: Filelike>(
+    table: &Table<F>,
+    curr_offset: u32,
+    keys: &[u32],
+) -> Result<Vec<(u32, InternalRowProto)>, Error> {
+    let node_buffer_lock = table
+        .buffer_pool
+        .read_from_table(table, curr_offset)
+        .await?;
+    let node_buffer = table.buffer_pool.locks.read(&node_buffer_lock).await?;
+    let node = node_buffer.get().view();
+    // B-link trees only: keys from the high key on (may) have moved to the right sibling.
+    let (keys, right) = match node.high_key() {
+        Some(high_key) => (
+            &keys[..keys.partition_point(|key| *key <= high_key)],
+            Some((
+                node.right_sibling_offset(),
+                &keys[keys.partition_point(|key| *key < high_key)..],
+            )),
+        ),
+        None => (keys, None),
+    };
+    let mut rows = Vec::new();
+    // the nodes left to read, along with the keys to find in each.
+    let mut next = Vec::new();
+    let node_keys = node.keys();
+    if node.is_leaf() {
+        for key in keys {
+            let start = node_keys.partition_point(|k| k < *key);
+            let end = node_keys.partition_point(|k| k <= *key);
+            rows.extend(node.rows(start..end)?.into_iter().map(|row| (*key, row)));
+        }
+    } else {
+        let child_offsets = node.child_offsets();
+        let num_children = child_offsets.len();
+        for (idx, child_offset) in child_offsets.iter().enumerate() {
+            // NOTE: with duplicate keys, the child holds keys from keys[idx - 1] to keys[idx]
+            // (inclusive).
+            let start = match idx {
+                0 => 0,
+                _ => keys.partition_point(|key| *key < node_keys.get(idx - 1)),
+            };
+            let end = match idx + 1 < num_children {
+                true => keys.partition_point(|key| *key <= node_keys.get(idx)),
+                false => keys.len(),
+            };
+            if start < end {
+                next.push((child_offset, &keys[start..end]));
+            }
+        }
+    }
+    next.extend(right.filter(|(_, keys)| !keys.is_empty()));
+    drop(node_buffer);
+    rows.extend(read_rows_from_nodes(table, &next).await?);
+    Ok(rows)
+}
+
+// Reads the rows with the given keys from each of the given nodes, concurrently.
+async fn read_rows_from_nodes<F: Filelike>(
+    table: &Table<F>,
+    nodes: &[(u32, &[u32])],
+) -> Result<Vec<(u32, InternalRowProto)>, Error> {
+    match nodes {
+        [] => Ok(Vec::new()),
+        [(offset, keys)] => Box::pin(read_rows(table, *offset, keys)).await,
+        _ => {
+            // NOTE: the halves are joined rather than spawned, so that they are read within the
+            // enclosing task's snapshot and lock scope. No locks are held while awaiting.
+            let (left, right) = nodes.split_at(nodes.len() / 2);
+            let (left, right) = tokio::join!(
+                Box::pin(read_rows_from_nodes(table, left)),
+                Box::pin(read_rows_from_nodes(table, right))
+            );
+            let mut rows = left?;
+            rows.extend(right?);
+            Ok(rows)
+        }
+    }
+}
+
+// Rows of a leaf (or part of one) returned by a range read.
+type LeafRows = (Vec<u32>, Vec<InternalRowProto>);
+
+// Reads the leaves a range read is about to visit ahead of time, in the background, see
+// DatabaseOptions::read_ahead_window.
+#[derive(Default)]
+struct ReadAhead {
+    // Set when a leaf is read, so that its parent knows its children are leaves.
+    reached_leaf: bool,
+    // The last leaf prefetched, if any.
+    last_prefetched: Option<u32>,
+}
+
+impl ReadAhead {
+    // Called by the parent of the leaves being read, after reading its child idx. Prefetches
+    // the children after it, up to the window (or end), unless enough already are.
+    fn prefetch<F: Filelike>(
+        &mut self,
+        table: &Table<F>,
+        child_offsets: &[u32],
+        idx: usize,
+        end: usize,
+    ) {
+        let window = table.options.read_ahead_window;
+        if !std::mem::take(&mut self.reached_leaf) || window == 0 {
+            return;
+        }
+        let end = std::cmp::min(end, idx + 1 + window);
+        let mut next = idx + 1;
+        let num_prefetched = self.last_prefetched.and_then(|last| {
+            child_offsets[next..end]
+                .iter()
+                .position(|offset| *offset == last)
+                .map(|pos| pos + 1)
+        });
+        if let Some(num_prefetched) = num_prefetched {
+            // NOTE: only refilled once half the window has been read, so that leaves are
+            // prefetched in batches.
+            if num_prefetched > window / 2 {
+                return;
+            }
+            next += num_prefetched;
+        }
+        if next >= end {
+            return;
+        }
+        table
+            .buffer_pool
+            .prefetch(table, child_offsets[next..end].to_vec());
+        self.last_prefetched = Some(child_offsets[end - 1]);
+    }
+}
+
+// finds the first leaf (in key order) holding rows with keys in the (inclusive) range lower, upper,
+// after skipping `skip` rows with key lower, and returns those rows.
+// NOTE: keys may be duplicated in secondary indexes, in which case runs of a key may span several
+// leaves, and internal node keys may equal (rather than exceed) the keys of their left child. so
+// traversal starts at the first child which may hold the key, continuing on to later children.
+async fn read_next_leaf_in_range<F: Filelike>(
+    table: &Table<F>,
+    curr_offset: u32,
+    lower: u32,
+    upper: u32,
+    skip: &mut usize,
+    read_ahead: &mut ReadAhead,
+) -> Result<Option<LeafRows>, Error> {
+    let node_buffer_lock = table
+        .buffer_pool
+        .read_from_table(table, curr_offset)
+        .await?;
+    let node_buffer = table.buffer_pool.locks.read(&node_buffer_lock).await?;
+    let node = node_buffer.get().view();
+    let right_link = right_link_up_to(node, upper);
+    if node.high_key().is_some_and(|high_key| lower > high_key) {
+        drop(node_buffer);
+        return Box::pin(read_next_leaf_in_range(
+            table,
+            right_link.unwrap(),
+            lower,
+            upper,
+            skip,
+            read_ahead,
+        ))
+        .await;
+    }
+    let keys = node.keys();
+    if node.is_leaf() {
+        read_ahead.reached_leaf = true;
+        let mut idx = keys.partition_point(|key| key < lower);
+        while *skip > 0 && idx < keys.len() && keys.get(idx) == lower {
+            idx += 1;
+            *skip -= 1;
+        }
+        let end = keys.partition_point(|key| key <= upper);
+        if idx < end {
+            let leaf_keys = (idx..end).map(|idx| keys.get(idx)).collect();
+            return Ok(Some((leaf_keys, node.rows(idx..end)?)));
+        }
+    } else {
+        if node.child_offsets().is_empty() {
+            return Ok(None);
+        }
+        let keys = keys.to_vec();
+        let child_offsets = node.child_offsets().to_vec();
+        drop(node_buffer);
+        let start = std::cmp::min(
+            keys.partition_point(|key| *key < lower),
+            child_offsets.len() - 1,
+        );
+        // NOTE: keys[idx - 1] is a lower bound of the child's keys.
+        let end = std::cmp::min(
+            std::cmp::max(keys.partition_point(|key| *key <= upper), start) + 1,
+            child_offsets.len(),
+        );
+        for idx in start..end {
+            let rows = Box::pin(read_next_leaf_in_range(
+                table,
+                child_offsets[idx],
+                lower,
+                upper,
+                skip,
+                read_ahead,
+            ))
+            .await?;
+            read_ahead.prefetch(table, &child_offsets, idx, end);
+            if rows.is_some() {
+                return Ok(rows);
+            }
+            // NOTE: with B-link trees, the child already moved right through its siblings
+            // (i.e. the remaining children, and beyond) up to upper.
+            if table.options.write_strategy == BLinkTree {
+                return Ok(None);
+            }
+        }
+    }
+    // NOTE: with B-link trees, the parent may be missing children split off from this node, so
+    // they're reached through the right link instead. otherwise the parent moves on to the next
+    // child itself, e.g. when a B-link tree is opened with another write strategy.
+    match right_link {
+        Some(right_offset) if table.options.write_strategy == BLinkTree => {
+            Box::pin(read_next_leaf_in_range(
+                table,
+                right_offset,
+                lower,
+                upper,
+                skip,
+                read_ahead,
+            ))
+            .await
+        }
+        _ => Ok(None),
+    }
+}
+
+// Iterates over all rows with keys in the (inclusive) range lower, upper in key order,
+// one leaf at a time.
+// NOTE: no locks are held between calls, each leaf is found through a fresh traversal from
+// the root. Concurrent writes may or may not be observed.
+pub(crate) struct RangeCursor<'a, F: Filelike> {
+    table: &'a Table<F>,
+    // rows with keys less than next_key, and the first `skip` rows with key next_key, have
+    // already been returned.
+    next_key: Option<u32>,
+    skip: usize,
+    upper: u32,
+    all_versions: bool,
+    read_ahead: ReadAhead,
+}
+
+impl<'a, F: Filelike> RangeCursor<'a, F> {
+    pub(crate) fn new(table: &'a Table<F>, lower: u32, upper: u32) -> Self {
+        Self {
+            table,
+            next_key: if lower <= upper { Some(lower) } else { None },
+            skip: 0,
+            upper,
+            all_versions: false,
+            read_ahead: ReadAhead::default(),
+        }
+    }
+
+    // Returns all versions of rows, rather than only those visible to the current snapshot.
+    pub(crate) fn all_versions(mut self) -> Self {
+        self.all_versions = true;
+        self
+    }
+
+    // Returns the keys and rows of the next non-empty leaf in range, or None when done.
+    // NOTE: only rows visible to the snapshot of the enclosing task (if any) are returned.
+    pub(crate) async fn next_leaf(&mut self) -> Result<Option<LeafRows>, Error> {
+        loop {
+            let Some(key) = self.next_key else {
+                return Ok(None);
+            };
+            let mut skip = self.skip;
+            let root_offset = self.table.root_chunk_offset;
+            let Some((keys, rows)) = read_next_leaf_in_range(
+                self.table,
+                root_offset,
+                key,
+                self.upper,
+                &mut skip,
+                &mut self.read_ahead,
+            )
+            .await?
+            else {
+                self.next_key = None;
+                return Ok(None);
+            };
+
+            let last_key = *keys.last().unwrap();
+            let num_last_key = keys.len() - keys.partition_point(|key| *key < last_key);
+            if last_key == key {
+                self.skip += num_last_key;
+            } else {
+                self.skip = num_last_key;
+            }
+            self.next_key = Some(last_key);
+
+            if self.all_versions || !mvcc::has_snapshot() {
+                return Ok(Some((keys, rows)));
+            }
+            let (keys, rows): LeafRows = keys
+                .into_iter()
+                .zip(rows)
+                .filter(|(_, row)| mvcc::is_visible(row))
+                .unzip();
+            if !keys.is_empty() {
+                return Ok(Some((keys, rows)));
+            }
+        }
+    }
 }
@@ -0,0 +1,814 @@
+#[cfg(test)]
+#[path = "./page_test.rs"]
+mod test;
+
+use crate::buffer::{self, Encoding};
+use crate::error::{ErrorKind::*, *};
+use crate::mvcc::NOT_DELETED;
+use crate::protos::generated::chunk::*;
+use crate::protos::generated::config::*;
+use crate::protos::generated::operations::*;
+use crate::LANE_WIDTH;
+use std::ops::{Deref, DerefMut, Range};
+use std::simd::Simd;
+use std::sync::OnceLock;
+
+// B+ tree nodes are laid out in their pages as slotted pages, rather than as protobuf messages
+// (see buffer.rs), and read in place: keys and child offsets are read straight from the page's
+// bytes, and only the rows returned are decoded, see NodeRef. Nodes are decoded into (and encoded
+// from) NodeProto messages only once modified, see Node. The size of a node is exact, rather than
+// an estimate, and checking whether a node is full doesn't compute the (protobuf) size of every
+// row either.
+//
+// Byte format of each page (integers are little-endian):
+// 1. header, HEADER_SIZE bytes:
+//    - format tag: u16, always FORMAT_TAG. Pages in the legacy (protobuf) format start with the
+//      (big-endian) size of their message instead, which is always less.
+//    - node type: u8, see NODE_TYPE_*.
+//    - flags: u8, bit 0 set iff the node has a high key.
+//    - offset, parent offset, left and right sibling offsets, high key: u32 each.
+//    - the number of keys, and of child offsets (internal nodes) or rows (leaves): u16 each.
+// 2. keys: u32 each.
+// 3. internal nodes: child offsets, u32 each.
+//    leaves: slot directory, for each row the distance of its start from the end of the page,
+//    u16 each. Rows are laid out back to back, i.e. each ends where the one before it starts.
+// 4. free space.
+// 5. leaves only: row heap, rows from the end of the page backwards (i.e. the first row is last).
+//
+// Each row starts with a u8 of ROW_* flags, followed by its created and deleted transaction ids
+// (u64 each) unless 0 (or NOT_DELETED), then its column values, each a u8 VALUE_TYPE_* tag, then
+// the value (4 or 8 bytes, none if unset).
+//
+// NOTE: legacy pages remain readable, and are rewritten in this format once written back (see
+// Encoding::from_bytes), or by Database::migrate. Those too large to fit are rewritten in the
+// legacy format until split.
+
+const FORMAT_TAG: u16 = u16::MAX;
+pub(crate) const HEADER_SIZE: usize = 28;
+const SLOT_SIZE: usize = 2;
+// The size each entry of an internal node takes, i.e. its key and child offset.
+pub(crate) const INTERNAL_ENTRY_SIZE: usize = 8;
+
+// The position of each header field, see above.
+const NODE_TYPE_POS: usize = 2;
+const FLAGS_POS: usize = 3;
+const OFFSET_POS: usize = 4;
+const PARENT_OFFSET_POS: usize = 8;
+const LEFT_SIBLING_OFFSET_POS: usize = 12;
+const RIGHT_SIBLING_OFFSET_POS: usize = 16;
+const HIGH_KEY_POS: usize = 20;
+const NUM_KEYS_POS: usize = 24;
+const NUM_VALUES_POS: usize = 26;
+
+const NODE_TYPE_NONE: u8 = 0;
+const NODE_TYPE_INTERNAL: u8 = 1;
+const NODE_TYPE_LEAF: u8 = 2;
+
+// The created transaction id follows.
+const ROW_CREATED: u8 = 1;
+// The deleted transaction id follows.
+const ROW_DELETED: u8 = 2;
+// The deleted transaction id is NOT_DELETED.
+const ROW_NOT_DELETED: u8 = 4;
+
+const VALUE_TYPE_NONE: u8 = 0;
+const VALUE_TYPE_INT: u8 = 1;
+const VALUE_TYPE_UINT: u8 = 2;
+const VALUE_TYPE_LONG: u8 = 3;
+const VALUE_TYPE_DOUBLE: u8 = 4;
+
+fn value_size(value: &ValueProto) -> usize {
+    1 + match value.value_type {
+        None => 0,
+        Some(value_proto::Value_type::IntValue(_))
+        | Some(value_proto::Value_type::UintValue(_)) => 4,
+        Some(value_proto::Value_type::LongValue(_))
+        | Some(value_proto::Value_type::DoubleValue(_)) => 8,
+    }
+}
+
+fn row_flags(row: &InternalRowProto) -> u8 {
+    let mut flags = 0;
+    if row.created_txn_id != 0 {
+        flags |= ROW_CREATED;
+    }
+    match row.deleted_txn_id {
+        0 => {}
+        NOT_DELETED => flags |= ROW_NOT_DELETED,
+        _ => flags |= ROW_DELETED,
+    }
+    flags
+}
+
+// The size of the given row in the row heap.
+fn row_size(row: &InternalRowProto) -> usize {
+    let flags = row_flags(row);
+    let num_txn_ids = (flags & ROW_CREATED != 0) as usize + (flags & ROW_DELETED != 0) as usize;
+    1 + std::mem::size_of::<u64>() * num_txn_ids
+        + row.col_values.iter().map(value_size).sum::<usize>()
+}
+
+// The size the given row takes in a leaf, along with its key.
+pub(crate) fn leaf_entry_size(row: &InternalRowProto) -> usize {
+    std::mem::size_of::<u32>() + SLOT_SIZE + row_size(row)
+}
+
+// The exact size of the given node, once encoded.
+pub(crate) fn encoded_size(node: &NodeProto) -> usize {
+    HEADER_SIZE
+        + match &node.node_type {
+            Some(node_proto::Node_type::Internal(internal)) => {
+                std::mem::size_of::<u32>() * (internal.keys.len() + internal.child_offsets.len())
+            }
+            Some(node_proto::Node_type::Leaf(leaf)) => {
+                std::mem::size_of::<u32>() * leaf.keys.len()
+                    + leaf
+                        .rows
+                        .iter()
+                        .map(|row| SLOT_SIZE + row_size(row))
+                        .sum::<usize>()
+            }
+            None => 0,
+        }
+}
+
+fn corrupt() -> Error {
+    Error::new(DataLoss, "Unable to interpret corrupt page!".to_string())
+}
+
+// Writes the given bytes at cursor, and increments it past them.
+// NOTE: the page must have room for them, see encoded_size.
+fn put(bytes: &mut [u8], cursor: &mut usize, src: &[u8]) {
+    bytes[*cursor..*cursor + src.len()].copy_from_slice(src);
+    *cursor += src.len();
+}
+
+fn encode_row(row: &InternalRowProto, bytes: &mut [u8]) {
+    let mut cursor = 0;
+    let flags = row_flags(row);
+    put(bytes, &mut cursor, &[flags]);
+    if flags & ROW_CREATED != 0 {
+        put(bytes, &mut cursor, &row.created_txn_id.to_le_bytes());
+    }
+    if flags & ROW_DELETED != 0 {
+        put(bytes, &mut cursor, &row.deleted_txn_id.to_le_bytes());
+    }
+    for value in &row.col_values {
+        match value.value_type {
+            None => put(bytes, &mut cursor, &[VALUE_TYPE_NONE]),
+            Some(value_proto::Value_type::IntValue(value)) => {
+                put(bytes, &mut cursor, &[VALUE_TYPE_INT]);
+                put(bytes, &mut cursor, &value.to_le_bytes());
+            }
+            Some(value_proto::Value_type::UintValue(value)) => {
+                put(bytes, &mut cursor, &[VALUE_TYPE_UINT]);
+                put(bytes, &mut cursor, &value.to_le_bytes());
+            }
+            Some(value_proto::Value_type::LongValue(value)) => {
+                put(bytes, &mut cursor, &[VALUE_TYPE_LONG]);
+                put(bytes, &mut cursor, &value.to_le_bytes());
+            }
+            Some(value_proto::Value_type::DoubleValue(value)) => {
+                put(bytes, &mut cursor, &[VALUE_TYPE_DOUBLE]);
+                put(bytes, &mut cursor, &value.to_le_bytes());
+            }
+        }
+    }
+}
+
+// Encodes the given node as a slotted page of the given size.
+// NOTE: the node must fit, see encoded_size.
+fn encode(node: &NodeProto, size: usize) -> Vec<u8> {
+    debug_assert!(encoded_size(node) <= size);
+    let mut bytes = vec![0; size];
+    let (node_type, keys, num_values): (u8, &[u32], usize) = match &node.node_type {
+        Some(node_proto::Node_type::Internal(internal)) => (
+            NODE_TYPE_INTERNAL,
+            &internal.keys,
+            internal.child_offsets.len(),
+        ),
+        Some(node_proto::Node_type::Leaf(leaf)) => (NODE_TYPE_LEAF, &leaf.keys, leaf.rows.len()),
+        None => (NODE_TYPE_NONE, &[], 0),
+    };
+
+    let mut cursor = 0;
+    put(&mut bytes, &mut cursor, &FORMAT_TAG.to_le_bytes());
+    put(
+        &mut bytes,
+        &mut cursor,
+        &[node_type, node.high_key.is_some() as u8],
+    );
+    for value in [
+        node.offset,
+        node.parent_offset,
+        node.left_sibling_offset,
+        node.right_sibling_offset,
+        node.high_key.unwrap_or_default(),
+    ] {
+        put(&mut bytes, &mut cursor, &value.to_le_bytes());
+    }
+    put(&mut bytes, &mut cursor, &(keys.len() as u16).to_le_bytes());
+    put(&mut bytes, &mut cursor, &(num_values as u16).to_le_bytes());
+    for key in keys {
+        put(&mut bytes, &mut cursor, &key.to_le_bytes());
+    }
+
+    match &node.node_type {
+        Some(node_proto::Node_type::Internal(internal)) => {
+            for child_offset in &internal.child_offsets {
+                put(&mut bytes, &mut cursor, &child_offset.to_le_bytes());
+            }
+        }
+        Some(node_proto::Node_type::Leaf(leaf)) => {
+            let mut heap_start = size;
+            for row in &leaf.rows {
+                let row_end = heap_start;
+                heap_start -= row_size(row);
+                put(
+                    &mut bytes,
+                    &mut cursor,
+                    &((size - heap_start) as u16).to_le_bytes(),
+                );
+                encode_row(row, &mut bytes[heap_start..row_end]);
+            }
+        }
+        None => {}
+    }
+    bytes
+}
+
+// Reads fixed size fields of a row, failing with DataLoss past its end.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    cursor: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, cursor: 0 }
+    }
+
+    fn read<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        Ok(self.skip(N)?.try_into().unwrap())
+    }
+
+    // Returns the next size bytes.
+    fn skip(&mut self, size: usize) -> Result<&'a [u8], Error> {
+        let slice = self
+            .bytes
+            .get(self.cursor..self.cursor + size)
+            .ok_or_else(corrupt)?;
+        self.cursor += size;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.read::<1>()?[0])
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(self.read()?))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.cursor == self.bytes.len()
+    }
+}
+
+fn decode_row(bytes: &[u8]) -> Result<InternalRowProto, Error> {
+    let mut reader = Reader::new(bytes);
+    let mut row = InternalRowProto::new();
+    let flags = reader.u8()?;
+    if flags & ROW_CREATED != 0 {
+        row.created_txn_id = reader.u64()?;
+    }
+    if flags & ROW_DELETED != 0 {
+        row.deleted_txn_id = reader.u64()?;
+    } else if flags & ROW_NOT_DELETED != 0 {
+        row.deleted_txn_id = NOT_DELETED;
+    }
+    while !reader.is_empty() {
+        let mut value = ValueProto::new();
+        match reader.u8()? {
+            VALUE_TYPE_NONE => {}
+            VALUE_TYPE_INT => value.set_int_value(i32::from_le_bytes(reader.read()?)),
+            VALUE_TYPE_UINT => value.set_uint_value(u32::from_le_bytes(reader.read()?)),
+            VALUE_TYPE_LONG => value.set_long_value(i64::from_le_bytes(reader.read()?)),
+            VALUE_TYPE_DOUBLE => value.set_double_value(f64::from_le_bytes(reader.read()?)),
+            _ => return Err(corrupt()),
+        }
+        row.col_values.push(value);
+    }
+    Ok(row)
+}
+
+// Checks that the given row can be decoded, without decoding it.
+fn check_row(bytes: &[u8]) -> Result<(), Error> {
+    let mut reader = Reader::new(bytes);
+    let flags = reader.u8()?;
+    let num_txn_ids = (flags & ROW_CREATED != 0) as usize + (flags & ROW_DELETED != 0) as usize;
+    reader.skip(std::mem::size_of::<u64>() * num_txn_ids)?;
+    while !reader.is_empty() {
+        let value_size = match reader.u8()? {
+            VALUE_TYPE_NONE => 0,
+            VALUE_TYPE_INT | VALUE_TYPE_UINT => 4,
+            VALUE_TYPE_LONG | VALUE_TYPE_DOUBLE => 8,
+            _ => return Err(corrupt()),
+        };
+        reader.skip(value_size)?;
+    }
+    Ok(())
+}
+
+// An array of u32s of a node (i.e. its keys or child offsets), either decoded or read in place
+// from its page.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum U32s<'a> {
+    Decoded(&'a [u32]),
+    Page(&'a [u8]),
+}
+
+impl<'a> U32s<'a> {
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Self::Decoded(values) => values.len(),
+            Self::Page(bytes) => bytes.len() / std::mem::size_of::<u32>(),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) fn get(&self, idx: usize) -> u32 {
+        match self {
+            Self::Decoded(values) => values[idx],
+            Self::Page(bytes) => {
+                let start = idx * std::mem::size_of::<u32>();
+                u32::from_le_bytes(bytes[start..start + 4].try_into().unwrap())
+            }
+        }
+    }
+
+    // See slice::partition_point.
+    pub(crate) fn partition_point(&self, pred: impl Fn(u32) -> bool) -> usize {
+        if let Self::Decoded(values) = self {
+            return values.partition_point(|value| pred(*value));
+        }
+        let (mut lower, mut upper) = (0, self.len());
+        while lower < upper {
+            let mid = lower + (upper - lower) / 2;
+            if pred(self.get(mid)) {
+                lower = mid + 1;
+            } else {
+                upper = mid;
+            }
+        }
+        lower
+    }
+
+    // Loads the values from start up to end (or LANE_WIDTH of them), the remaining lanes are 0.
+    pub(crate) fn load_lanes(&self, start: usize, end: usize) -> Simd<u32, LANE_WIDTH> {
+        let end = std::cmp::min(end, start + LANE_WIDTH);
+        match self {
+            Self::Decoded(values) => Simd::load_or_default(&values[start..end]),
+            Self::Page(_) => Simd::from_array(std::array::from_fn(|lane| match start + lane {
+                idx if idx < end => self.get(idx),
+                _ => 0,
+            })),
+        }
+    }
+
+    // Gathers the values at the given (in bounds) indices.
+    pub(crate) fn gather_lanes(&self, idxs: Simd<usize, LANE_WIDTH>) -> Simd<u32, LANE_WIDTH> {
+        match self {
+            Self::Decoded(values) => Simd::gather_or_default(values, idxs),
+            Self::Page(_) => Simd::from_array(idxs.to_array().map(|idx| self.get(idx))),
+        }
+    }
+
+    pub(crate) fn iter(self) -> impl Iterator<Item = u32> + 'a {
+        (0..self.len()).map(move |idx| self.get(idx))
+    }
+
+    pub(crate) fn to_vec(self) -> Vec<u32> {
+        match self {
+            Self::Decoded(values) => values.to_vec(),
+            Self::Page(_) => self.iter().collect(),
+        }
+    }
+}
+
+impl<'a> From<&'a Vec<u32>> for U32s<'a> {
+    fn from(values: &'a Vec<u32>) -> Self {
+        Self::Decoded(values)
+    }
+}
+
+// A node read in place from its slotted page.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct PageRef<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> PageRef<'a> {
+    // Checks that the given bytes hold a well formed page, so that it can be read in place
+    // without going out of bounds.
+    fn new(bytes: &'a [u8]) -> Result<Self, Error> {
+        if bytes.len() < HEADER_SIZE || is_legacy(bytes) {
+            return Err(corrupt());
+        }
+        let page = Self { bytes };
+        let value_size = match page.node_type() {
+            NODE_TYPE_NONE => 0,
+            NODE_TYPE_INTERNAL => std::mem::size_of::<u32>(),
+            NODE_TYPE_LEAF => SLOT_SIZE,
+            _ => return Err(corrupt()),
+        };
+        let values_end = page.values_start() + page.num_values() * value_size;
+        if values_end > bytes.len() {
+            return Err(corrupt());
+        }
+        if page.node_type() == NODE_TYPE_LEAF {
+            let mut row_end = bytes.len();
+            for idx in 0..page.num_values() {
+                let row_start = bytes
+                    .len()
+                    .checked_sub(page.u16_at(page.values_start() + idx * SLOT_SIZE) as usize)
+                    .filter(|row_start| (values_end..=row_end).contains(row_start))
+                    .ok_or_else(corrupt)?;
+                check_row(&bytes[row_start..row_end])?;
+                row_end = row_start;
+            }
+        }
+        Ok(page)
+    }
+
+    fn u16_at(&self, pos: usize) -> u16 {
+        u16::from_le_bytes(self.bytes[pos..pos + 2].try_into().unwrap())
+    }
+
+    fn u32_at(&self, pos: usize) -> u32 {
+        u32::from_le_bytes(self.bytes[pos..pos + 4].try_into().unwrap())
+    }
+
+    fn node_type(&self) -> u8 {
+        self.bytes[NODE_TYPE_POS]
+    }
+
+    pub(crate) fn high_key(&self) -> Option<u32> {
+        (self.bytes[FLAGS_POS] & 1 != 0).then(|| self.u32_at(HIGH_KEY_POS))
+    }
+
+    pub(crate) fn right_sibling_offset(&self) -> u32 {
+        self.u32_at(RIGHT_SIBLING_OFFSET_POS)
+    }
+
+    fn num_keys(&self) -> usize {
+        self.u16_at(NUM_KEYS_POS) as usize
+    }
+
+    fn num_values(&self) -> usize {
+        self.u16_at(NUM_VALUES_POS) as usize
+    }
+
+    // Where the child offsets (internal nodes) or slot directory (leaves) start.
+    fn values_start(&self) -> usize {
+        HEADER_SIZE + std::mem::size_of::<u32>() * self.num_keys()
+    }
+
+    pub(crate) fn keys(&self) -> U32s<'a> {
+        U32s::Page(&self.bytes[HEADER_SIZE..self.values_start()])
+    }
+
+    // Internal nodes only.
+    pub(crate) fn child_offsets(&self) -> U32s<'a> {
+        debug_assert_eq!(self.node_type(), NODE_TYPE_INTERNAL);
+        let start = self.values_start();
+        U32s::Page(&self.bytes[start..start + std::mem::size_of::<u32>() * self.num_values()])
+    }
+
+    // Leaves only: where the row at the given index lies in the row heap.
+    fn row_range(&self, idx: usize) -> Range<usize> {
+        let distance = |idx: usize| self.u16_at(self.values_start() + idx * SLOT_SIZE) as usize;
+        let end = match idx {
+            0 => 0,
+            _ => distance(idx - 1),
+        };
+        self.bytes.len() - distance(idx)..self.bytes.len() - end
+    }
+
+    // Leaves only: decodes the row at the given index.
+    pub(crate) fn row(&self, idx: usize) -> Result<InternalRowProto, Error> {
+        decode_row(&self.bytes[self.row_range(idx)])
+    }
+
+    // The number of bytes in use, i.e. other than free space, see encoded_size.
+    fn used_size(&self) -> usize {
+        match self.node_type() {
+            NODE_TYPE_INTERNAL => {
+                self.values_start() + std::mem::size_of::<u32>() * self.num_values()
+            }
+            NODE_TYPE_LEAF => match self.num_values() {
+                0 => self.values_start(),
+                num_rows => {
+                    self.values_start() + SLOT_SIZE * num_rows + self.bytes.len()
+                        - self.row_range(num_rows - 1).start
+                }
+            },
+            _ => HEADER_SIZE,
+        }
+    }
+
+    // Decodes the whole node.
+    fn decode(&self) -> Result<NodeProto, Error> {
+        let mut node = NodeProto::new();
+        node.offset = self.u32_at(OFFSET_POS);
+        node.parent_offset = self.u32_at(PARENT_OFFSET_POS);
+        node.left_sibling_offset = self.u32_at(LEFT_SIBLING_OFFSET_POS);
+        node.right_sibling_offset = self.right_sibling_offset();
+        node.high_key = self.high_key();
+        match self.node_type() {
+            NODE_TYPE_INTERNAL => {
+                let internal = node.mut_internal();
+                internal.keys = self.keys().to_vec();
+                internal.child_offsets = self.child_offsets().to_vec();
+            }
+            NODE_TYPE_LEAF => {
+                let leaf = node.mut_leaf();
+                leaf.keys = self.keys().to_vec();
+                leaf.rows = (0..self.num_values())
+                    .map(|idx| self.row(idx))
+                    .collect::<Result<_, _>>()?;
+            }
+            _ => {}
+        }
+        Ok(node)
+    }
+}
+
+// A node as read by traversals: in place from its page, unless modified since it was read (see
+// Node), in which case its decoded message.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum NodeRef<'a> {
+    Page(PageRef<'a>),
+    Decoded(&'a NodeProto),
+}
+
+impl<'a> NodeRef<'a> {
+    pub(crate) fn high_key(&self) -> Option<u32> {
+        match self {
+            Self::Page(page) => page.high_key(),
+            Self::Decoded(node) => node.high_key,
+        }
+    }
+
+    pub(crate) fn right_sibling_offset(&self) -> u32 {
+        match self {
+            Self::Page(page) => page.right_sibling_offset(),
+            Self::Decoded(node) => node.right_sibling_offset,
+        }
+    }
+
+    pub(crate) fn is_leaf(&self) -> bool {
+        match self {
+            Self::Page(page) => page.node_type() == NODE_TYPE_LEAF,
+            Self::Decoded(node) => node.has_leaf(),
+        }
+    }
+
+    pub(crate) fn keys(&self) -> U32s<'a> {
+        match self {
+            Self::Page(page) => page.keys(),
+            Self::Decoded(node) => match &node.node_type {
+                Some(node_proto::Node_type::Internal(internal)) => (&internal.keys).into(),
+                Some(node_proto::Node_type::Leaf(leaf)) => (&leaf.keys).into(),
+                None => U32s::Decoded(&[]),
+            },
+        }
+    }
+
+    // Internal nodes only.
+    pub(crate) fn child_offsets(&self) -> U32s<'a> {
+        match self {
+            Self::Page(page) => page.child_offsets(),
+            Self::Decoded(node) => (&node.internal().child_offsets).into(),
+        }
+    }
+
+    // Leaves only: the rows at the given indices, decoded (or cloned).
+    pub(crate) fn rows(&self, idxs: Range<usize>) -> Result<Vec<InternalRowProto>, Error> {
+        match self {
+            Self::Page(page) => idxs.map(|idx| page.row(idx)).collect(),
+            Self::Decoded(node) => Ok(node.leaf().rows[idxs].to_vec()),
+        }
+    }
+}
+
+impl NodeRef<'_> {
+    // The size of the node once encoded, see encoded_size.
+    fn used_size(&self) -> usize {
+        match self {
+            Self::Page(page) => page.used_size(),
+            Self::Decoded(node) => encoded_size(node),
+        }
+    }
+}
+
+impl<'a> From<&'a NodeProto> for NodeRef<'a> {
+    fn from(node: &'a NodeProto) -> Self {
+        Self::Decoded(node)
+    }
+}
+
+// A B+ tree node, as held by the buffer pool. Nodes are read in place from their pages (see
+// view), and only decoded into NodeProto messages once dereferenced, e.g. by writes, after which
+// they're modified as messages.
+#[derive(Debug, Default)]
+pub(crate) struct Node {
+    // The page the node was read from, unless it was modified since.
+    page: Option<Vec<u8>>,
+    decoded: OnceLock<NodeProto>,
+}
+
+impl<'a> From<&'a Node> for NodeRef<'a> {
+    fn from(node: &'a Node) -> Self {
+        node.view()
+    }
+}
+
+impl From<NodeProto> for Node {
+    fn from(node: NodeProto) -> Self {
+        Self {
+            page: None,
+            decoded: OnceLock::from(node),
+        }
+    }
+}
+
+impl Node {
+    pub(crate) fn view(&self) -> NodeRef<'_> {
+        match &self.page {
+            Some(page) => NodeRef::Page(PageRef { bytes: page }),
+            None => NodeRef::Decoded(self.decoded()),
+        }
+    }
+
+    fn decoded(&self) -> &NodeProto {
+        self.decoded.get_or_init(|| match &self.page {
+            // NOTE: pages are checked once read, see PageRef::new.
+            Some(page) => PageRef { bytes: page }
+                .decode()
+                .expect("page checked once read"),
+            None => NodeProto::new(),
+        })
+    }
+}
+
+impl Deref for Node {
+    type Target = NodeProto;
+
+    fn deref(&self) -> &NodeProto {
+        self.decoded()
+    }
+}
+
+impl DerefMut for Node {
+    fn deref_mut(&mut self) -> &mut NodeProto {
+        self.decoded();
+        self.page = None;
+        self.decoded.get_mut().unwrap()
+    }
+}
+
+// Whether the given page is in the legacy (protobuf) format.
+fn is_legacy(bytes: &[u8]) -> bool {
+    !bytes.starts_with(&FORMAT_TAG.to_le_bytes())
+}
+
+impl Encoding for Node {
+    fn to_bytes(&self, size: usize) -> Result<Vec<u8>, Error> {
+        if let Some(page) = self.page.as_ref().filter(|page| page.len() == size) {
+            return Ok(page.clone());
+        }
+        if encoded_size(self) <= size {
+            return Ok(encode(self, size));
+        }
+        // NOTE: only nodes read from legacy pages may not fit, until they're split.
+        buffer::message_to_bytes(&**self, size)
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Result<(Self, bool), Error> {
+        if !is_legacy(&bytes) {
+            PageRef::new(&bytes)?;
+            let node = Self {
+                page: Some(bytes),
+                decoded: OnceLock::new(),
+            };
+            return Ok((node, false));
+        }
+        let node: NodeProto = buffer::message_from_bytes(&bytes)?;
+        let is_outdated = encoded_size(&node) <= bytes.len();
+        Ok((node.into(), is_outdated))
+    }
+
+    fn would_overflow(&self, addl_size: usize, config: &TableConfig) -> bool {
+        self.view().used_size() + addl_size > config.chunk_size as usize
+    }
+}
+
+// Entry points for the page format benchmarks, see benches/page.rs. Not part of the API.
+#[doc(hidden)]
+pub mod bench {
+    use super::*;
+    use crate::options::DatabaseOptions;
+    use protobuf::Message;
+
+    #[derive(Clone, Copy)]
+    pub enum Format {
+        // The protobuf based format of earlier versions, see buffer.rs.
+        Legacy,
+        Slotted,
+    }
+
+    // A leaf filled with rows of the given number of columns, until the next wouldn't fit in
+    // either format.
+    pub struct Leaf {
+        node: NodeProto,
+        config: TableConfig,
+    }
+
+    impl Leaf {
+        pub fn full(num_cols: usize, page_size: usize) -> Self {
+            let mut node = NodeProto::new();
+            for key in 0.. {
+                let mut value = ValueProto::new();
+                value.set_int_value(key as i32);
+                let mut row = InternalRowProto::new();
+                row.col_values = vec![value; num_cols];
+                row.created_txn_id = key as u64 + 1;
+                row.deleted_txn_id = NOT_DELETED;
+                node.mut_leaf().keys.push(key);
+                node.mut_leaf().rows.push(row);
+                if encoded_size(&node) > page_size
+                    || buffer::message_to_bytes(&node, page_size).is_err()
+                {
+                    node.mut_leaf().keys.pop();
+                    node.mut_leaf().rows.pop();
+                    break;
+                }
+            }
+            let config = DatabaseOptions {
+                page_size,
+                ..Default::default()
+            }
+            .table_config();
+            Self { node, config }
+        }
+
+        pub fn num_rows(&self) -> usize {
+            self.node.leaf().rows.len()
+        }
+
+        pub fn encode(&self, format: Format) -> Vec<u8> {
+            let size = self.config.chunk_size as usize;
+            match format {
+                Format::Legacy => buffer::message_to_bytes(&self.node, size).unwrap(),
+                Format::Slotted => encode(&self.node, size),
+            }
+        }
+
+        // Whether another row (of the first's size) would overflow the leaf, as checked on
+        // every insert.
+        pub fn is_full(&self, format: Format) -> bool {
+            let row = &self.node.leaf().rows[0];
+            match format {
+                Format::Legacy => buffer::message_would_overflow(
+                    &self.node,
+                    row.compute_size() as usize,
+                    &self.config,
+                ),
+                Format::Slotted => {
+                    encoded_size(&self.node) + leaf_entry_size(row)
+                        > self.config.chunk_size as usize
+                }
+            }
+        }
+    }
+
+    // Reads the given page, in either format, decoding the whole node.
+    pub fn decode(bytes: &[u8]) -> NodeProto {
+        let (node, _) = Node::from_bytes(bytes.to_vec()).unwrap();
+        node.decoded().clone()
+    }
+
+    // Reads the row with the given key from the given page, in either format, as reads do.
+    pub fn read_row(bytes: &[u8], key: u32) -> Option<InternalRowProto> {
+        let (node, _) = Node::from_bytes(bytes.to_vec()).unwrap();
+        let view = node.view();
+        let keys = view.keys();
+        let idx = keys.partition_point(|k| k < key);
+        (idx < keys.len() && keys.get(idx) == key)
+            .then(|| view.rows(idx..idx + 1).unwrap().pop().unwrap())
+    }
+}
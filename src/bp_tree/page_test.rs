@@ -0,0 +1,286 @@
+use super::*;
+use crate::bp_tree::{read_binary_search, read_sequential};
+use crate::buffer::Buffer;
+use crate::buffer_pool::BufferPool;
+use crate::mvcc;
+use crate::options::DatabaseOptions;
+use crate::table::Table;
+use protobuf::text_format::parse_from_str;
+use std::io::Cursor;
+use std::sync::Arc;
+
+type NodeBuffer = Buffer<Cursor<Vec<u8>>, Node>;
+
+fn config() -> TableConfig {
+    DatabaseOptions::default().table_config()
+}
+
+fn row(value: i32, num_cols: usize) -> InternalRowProto {
+    let mut col = ValueProto::new();
+    col.set_int_value(value);
+    let mut row = InternalRowProto::new();
+    row.col_values = vec![col; num_cols];
+    row.created_txn_id = value as u64;
+    row.deleted_txn_id = mvcc::NOT_DELETED;
+    row
+}
+
+fn to_bytes(node: &NodeProto, size: usize) -> Result<Vec<u8>, Error> {
+    Node::from(node.clone()).to_bytes(size)
+}
+
+// Reads the given page, decoding its node.
+fn from_bytes(bytes: &[u8]) -> Result<(NodeProto, bool), Error> {
+    let (node, is_outdated) = Node::from_bytes(bytes.to_vec())?;
+    Ok(((*node).clone(), is_outdated))
+}
+
+// Returns a leaf filled with rows of the given number of columns, until the next wouldn't fit.
+fn full_leaf(num_cols: usize) -> NodeProto {
+    let config = config();
+    let mut node = NodeProto::new();
+    node.offset = 2;
+    node.parent_offset = 1;
+    node.mut_leaf();
+    for key in 0.. {
+        let row = row(key as i32, num_cols);
+        if encoded_size(&node) + leaf_entry_size(&row) > config.chunk_size as usize {
+            break;
+        }
+        node.mut_leaf().keys.push(key);
+        node.mut_leaf().rows.push(row);
+    }
+    node
+}
+
+#[test]
+fn encode_decode_ok() -> Result<(), Error> {
+    let size = config().chunk_size as usize;
+
+    let mut leaf = NodeProto::new();
+    leaf.offset = 3;
+    leaf.parent_offset = 1;
+    leaf.left_sibling_offset = 2;
+    leaf.right_sibling_offset = 4;
+    leaf.high_key = Some(20);
+    let mut values = vec![ValueProto::new(); 5];
+    values[1].set_int_value(-1);
+    values[2].set_uint_value(2);
+    values[3].set_long_value(-3);
+    values[4].set_double_value(4.5);
+    let mut row = InternalRowProto::new();
+    row.col_values = values;
+    row.created_txn_id = 7;
+    row.deleted_txn_id = mvcc::NOT_DELETED;
+    leaf.mut_leaf().keys = vec![10, 10, 15];
+    leaf.mut_leaf().rows = vec![row.clone(), InternalRowProto::new(), row];
+
+    let mut internal = NodeProto::new();
+    internal.offset = 1;
+    internal.mut_internal().keys = vec![10, 20];
+    internal.mut_internal().child_offsets = vec![2, 3, 4];
+
+    for node in [leaf, internal, NodeProto::new()] {
+        let bytes = to_bytes(&node, size)?;
+        assert!(!is_legacy(&bytes));
+        assert_eq!(from_bytes(&bytes)?, (node, false));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn read_in_place_ok() -> Result<(), Error> {
+    let size = config().chunk_size as usize;
+    let mut leaf = full_leaf(3);
+    leaf.high_key = Some(1000);
+    leaf.right_sibling_offset = 3;
+    let (mut node, _) = Node::from_bytes(to_bytes(&leaf, size)?)?;
+
+    // keys, and only the rows read, are read from the page without decoding the node.
+    let view = node.view();
+    assert!(matches!(view, NodeRef::Page(_)));
+    assert!(view.is_leaf());
+    assert_eq!(view.high_key(), Some(1000));
+    assert_eq!(view.right_sibling_offset(), 3);
+    assert_eq!(view.keys().to_vec(), leaf.leaf().keys);
+    assert_eq!(view.rows(5..7)?, leaf.leaf().rows[5..7]);
+    assert!(node.decoded.get().is_none());
+
+    // until the node is modified.
+    node.mut_leaf().keys.pop();
+    node.mut_leaf().rows.pop();
+    assert!(matches!(node.view(), NodeRef::Decoded(_)));
+    let (modified, _) = from_bytes(&node.to_bytes(size)?)?;
+    assert_eq!(modified.leaf().rows.len(), leaf.leaf().rows.len() - 1);
+
+    let mut internal = NodeProto::new();
+    internal.mut_internal().keys = (0..100).collect();
+    internal.mut_internal().child_offsets = (100..201).collect();
+    let (node, _) = Node::from_bytes(to_bytes(&internal, size)?)?;
+    let view = node.view();
+    assert!(!view.is_leaf());
+    assert_eq!(view.high_key(), None);
+    assert_eq!(
+        view.child_offsets().to_vec(),
+        internal.internal().child_offsets
+    );
+
+    Ok(())
+}
+
+#[test]
+fn search_in_place_ok() -> Result<(), Error> {
+    let size = config().chunk_size as usize;
+    let mut internal = NodeProto::new();
+    // with duplicates, as in secondary indexes.
+    internal.mut_internal().keys = (0..300).map(|idx| idx / 3 * 2 + 1).collect();
+    internal.mut_internal().child_offsets = (0..301).collect();
+    let (node, _) = Node::from_bytes(to_bytes(&internal, size)?)?;
+    let page_keys = node.view().keys();
+    let keys = U32s::from(&internal.internal().keys);
+
+    for key in 0..205 {
+        assert_eq!(
+            read_sequential::find_next_node_idx_for_key(page_keys, 301, key)?,
+            read_sequential::find_next_node_idx_for_key(keys, 301, key)?
+        );
+        assert_eq!(
+            read_binary_search::find_next_node_idx_for_key(page_keys, 301, key, 8)?,
+            read_binary_search::find_next_node_idx_for_key(keys, 301, key, 8)?
+        );
+        let idx = keys.partition_point(|k| k < key);
+        assert_eq!(page_keys.partition_point(|k| k < key), idx);
+        assert_eq!(read_sequential::find_row_idx_for_key(page_keys, key), idx);
+        assert_eq!(
+            read_binary_search::find_row_idx_for_key(page_keys, key, 8),
+            idx
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn size_is_exact() -> Result<(), Error> {
+    let size = config().chunk_size as usize;
+    for num_cols in [0, 1, 10] {
+        let node = full_leaf(num_cols);
+        let free_space = size - encoded_size(&node);
+        assert!(free_space < leaf_entry_size(&row(1, num_cols)));
+        // the rows fill the page up to its header and slot directory.
+        let bytes = to_bytes(&node, size)?;
+        let heap_start = size - node.leaf().rows.iter().map(row_size).sum::<usize>();
+        assert_eq!(
+            bytes[heap_start - free_space..heap_start],
+            vec![0; free_space]
+        );
+        assert_eq!(from_bytes(&bytes)?.0, node);
+        let (node, _) = Node::from_bytes(bytes)?;
+        assert_eq!(size - free_space, node.view().used_size());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn read_legacy_page_ok() -> Result<(), Error> {
+    let size = config().chunk_size as usize;
+
+    // legacy pages are read, and rewritten in the slotted format.
+    let mut node = full_leaf(10);
+    let num_rows = node.leaf().keys.len() / 2;
+    node.mut_leaf().keys.truncate(num_rows);
+    node.mut_leaf().rows.truncate(num_rows);
+    let bytes = buffer::message_to_bytes(&node, size)?;
+    assert!(is_legacy(&bytes));
+    assert_eq!(from_bytes(&bytes)?, (node, true));
+
+    // unless they don't fit, e.g. small int values take more space than in protobuf.
+    let mut node = NodeProto::new();
+    for key in 0..18 {
+        node.mut_leaf().keys.push(key);
+        node.mut_leaf().rows.push(row(0, 50));
+    }
+    assert!(encoded_size(&node) > size);
+    let bytes = to_bytes(&node, size)?;
+    assert!(is_legacy(&bytes));
+    assert_eq!(from_bytes(&bytes)?, (node, false));
+
+    Ok(())
+}
+
+#[test]
+fn read_corrupt_page_fails() {
+    let size = config().chunk_size as usize;
+    let node = full_leaf(1);
+    let mut bytes = to_bytes(&node, size).unwrap();
+    // the first slot points past the end of the page.
+    let slot = HEADER_SIZE + std::mem::size_of::<u32>() * node.leaf().keys.len();
+    bytes[slot..slot + 2].copy_from_slice(&u16::MAX.to_le_bytes());
+    assert_eq!(Node::from_bytes(bytes).unwrap_err().kind, DataLoss);
+}
+
+#[tokio::test]
+async fn migrate_legacy_table_ok() -> Result<(), Error> {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let options = Arc::new(DatabaseOptions {
+        page_size: 512,
+        ..Default::default()
+    });
+    let schema = parse_from_str::<TableSchema>(
+        "
+            key {
+                name: \"Key\"
+                column_type: INTEGER
+            }
+            ",
+    )
+    .unwrap();
+    let table = Table::create(
+        Cursor::<Vec<u8>>::new(Vec::new()),
+        Arc::new(BufferPool::new(&options)),
+        options.clone(),
+        "TestTable".to_string(),
+        0,
+        schema,
+    )
+    .await?;
+    for key in 0..500 {
+        table.insert(key, row(key as i32, 1)).await?;
+    }
+    table.buffer_pool.flush().await?;
+
+    // rewrites every page in the legacy format, as written by earlier versions.
+    let num_pages = table
+        .next_chunk_offset
+        .load(std::sync::atomic::Ordering::Relaxed);
+    let size = table.config.chunk_size as usize;
+    let mut file = table.file.lock().await.get_ref().clone();
+    for offset in 1..num_pages {
+        let buffer = NodeBuffer::read_from_file(table.file.clone(), &table.config, offset).await?;
+        let bytes = buffer::message_to_bytes(&*buffer.data, size)?;
+        file[offset as usize * size..(offset as usize + 1) * size].copy_from_slice(&bytes);
+    }
+    let is_migrated =
+        |file: &[u8]| (1..num_pages as usize).all(|offset| !is_legacy(&file[offset * size..]));
+    assert!(!is_migrated(&file));
+
+    let table = Table::open(
+        Cursor::new(file),
+        Arc::new(BufferPool::new(&options)),
+        options.clone(),
+    )
+    .await?;
+    for key in 0..500 {
+        table.read_row(key).await?;
+    }
+    table.migrate().await?;
+    table.buffer_pool.flush().await?;
+    assert!(is_migrated(table.file.lock().await.get_ref()));
+    for key in 0..500 {
+        table.read_row(key).await?;
+    }
+
+    Ok(())
+}
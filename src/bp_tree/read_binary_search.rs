@@ -1,6 +1,6 @@
+use crate::bp_tree::page::U32s;
 use crate::error::{ErrorKind::*, *};
-use crate::protos::generated::chunk::*;
-use crate::{BINARY_READ_ITER_CUTOFF, LANE_WIDTH};
+use crate::LANE_WIDTH;
 use std::simd::cmp::SimdPartialOrd;
 use std::simd::{Mask, Simd};
 
@@ -21,20 +21,26 @@ fn fan_over_range(low: usize, high: usize) -> Simd<usize, LANE_WIDTH> {
     Simd::from_slice(&idxs)
 }
 
-pub fn find_next_node_idx_for_key(internal: &InternalNodeProto, key: u32) -> Result<usize, Error> {
-    if internal.keys.len() == 0 {
-        debug_assert!(internal.child_offsets.len() > 0);
+// iter_cutoff: see DatabaseOptions::binary_read_iter_cutoff.
+pub fn find_next_node_idx_for_key(
+    keys: U32s<'_>,
+    num_children: usize,
+    key: u32,
+    iter_cutoff: usize,
+) -> Result<usize, Error> {
+    if keys.is_empty() {
+        debug_assert!(num_children > 0);
         return Ok(0);
     }
 
-    let keys = Simd::<u32, LANE_WIDTH>::splat(key);
+    let search_keys = Simd::<u32, LANE_WIDTH>::splat(key);
 
     let mut lower: usize = 0;
-    let mut upper: usize = std::cmp::max(internal.keys.len(), 1) - 1;
-    while upper - lower > BINARY_READ_ITER_CUTOFF {
+    let mut upper: usize = std::cmp::max(keys.len(), 1) - 1;
+    while upper - lower > iter_cutoff {
         let idxs: Simd<usize, LANE_WIDTH> = fan_over_range(lower, upper);
-        let test_keys = Simd::gather_or_default(&internal.keys, idxs);
-        let comp: Mask<isize, LANE_WIDTH> = keys.simd_lt(test_keys).into();
+        let test_keys = keys.gather_lanes(idxs);
+        let comp: Mask<isize, LANE_WIDTH> = search_keys.simd_lt(test_keys).into();
         match comp.first_set() {
             None => {
                 lower = idxs.to_array()[LANE_WIDTH - 1];
@@ -50,28 +56,21 @@ pub fn find_next_node_idx_for_key(internal: &InternalNodeProto, key: u32) -> Res
         debug_assert!(lower <= upper);
     }
 
-    let mut idx: usize = lower;
-    for chunk in internal
-        .keys
-        .get(lower..upper + 1)
-        .unwrap()
-        .chunks(LANE_WIDTH)
-    {
-        let test_keys = Simd::<u32, LANE_WIDTH>::load_or_default(chunk);
-        let mask = keys.simd_lt(test_keys);
+    for idx in (lower..upper + 1).step_by(LANE_WIDTH) {
+        let test_keys = keys.load_lanes(idx, upper + 1);
+        let mask = search_keys.simd_lt(test_keys);
         match mask.first_set() {
             Some(j) => {
-                idx += j;
-                return Ok(idx + (key == internal.keys[idx]) as usize);
+                let idx = idx + j;
+                return Ok(idx + (key == keys.get(idx)) as usize);
             }
             None => {}
         }
-        idx += chunk.len();
     }
 
-    if internal.keys.len() != internal.child_offsets.len() {
-        debug_assert!(internal.child_offsets.len() == internal.keys.len() + 1);
-        return Ok(internal.child_offsets.len() - 1);
+    if keys.len() != num_children {
+        debug_assert!(num_children == keys.len() + 1);
+        return Ok(num_children - 1);
     }
 
     Err(Error::new(
@@ -80,18 +79,18 @@ pub fn find_next_node_idx_for_key(internal: &InternalNodeProto, key: u32) -> Res
     ))
 }
 
-pub fn find_row_idx_for_key(leaf: &LeafNodeProto, key: u32) -> usize {
-    if leaf.keys.is_empty() {
+pub fn find_row_idx_for_key(keys: U32s<'_>, key: u32, iter_cutoff: usize) -> usize {
+    if keys.is_empty() {
         return 0;
     }
-    let keys = Simd::<u32, LANE_WIDTH>::splat(key);
+    let search_keys = Simd::<u32, LANE_WIDTH>::splat(key);
 
     let mut lower: usize = 0;
-    let mut upper: usize = std::cmp::max(leaf.keys.len(), 1) - 1;
-    while upper - lower > BINARY_READ_ITER_CUTOFF {
+    let mut upper: usize = std::cmp::max(keys.len(), 1) - 1;
+    while upper - lower > iter_cutoff {
         let idxs: Simd<usize, LANE_WIDTH> = fan_over_range(lower, upper);
-        let test_keys = Simd::gather_or_default(&leaf.keys, idxs);
-        let comp: Mask<isize, LANE_WIDTH> = keys.simd_le(test_keys).into();
+        let test_keys = keys.gather_lanes(idxs);
+        let comp: Mask<isize, LANE_WIDTH> = search_keys.simd_le(test_keys).into();
         match comp.first_set() {
             None => {
                 lower = idxs.to_array()[LANE_WIDTH - 1];
@@ -107,18 +106,15 @@ pub fn find_row_idx_for_key(leaf: &LeafNodeProto, key: u32) -> usize {
         debug_assert!(lower <= upper);
     }
 
-    let mut idx: usize = lower;
-    for chunk in leaf.keys.get(lower..upper + 1).unwrap().chunks(LANE_WIDTH) {
-        let test_keys = Simd::<u32, LANE_WIDTH>::load_or_default(chunk);
-        let mask = keys.simd_le(test_keys);
+    for idx in (lower..upper + 1).step_by(LANE_WIDTH) {
+        let test_keys = keys.load_lanes(idx, upper + 1);
+        let mask = search_keys.simd_le(test_keys);
         match mask.first_set() {
             Some(j) => {
-                idx += j;
-                return idx;
+                return idx + j;
             }
             None => {}
         }
-        idx += chunk.len();
     }
-    idx
+    upper + 1
 }
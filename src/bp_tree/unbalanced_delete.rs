@@ -1,9 +1,10 @@
 use crate::bp_tree;
-use crate::error::{Error, ErrorKind::*};
+use crate::error::Error;
 use crate::filelike::Filelike;
 use crate::protos::generated::chunk::*;
 use crate::table::Table;
 
+#[cfg(test)]
 pub(crate) async fn delete<F: Filelike>(
     table: &Table<F>,
     curr_offset: u32,
@@ -13,22 +14,36 @@ pub(crate) async fn delete<F: Filelike>(
         .buffer_pool
         .read_from_table(table, curr_offset)
         .await?;
-    let node_buffer = node_buffer_lock.read().await;
+    let node_buffer = table.buffer_pool.locks.read(&node_buffer_lock).await?;
+    if let Some(right_offset) = bp_tree::move_right(node_buffer.get(), key) {
+        drop(node_buffer);
+        return Box::pin(delete(table, right_offset, key)).await;
+    }
     match &node_buffer.get().node_type {
         Some(node_proto::Node_type::Internal(internal)) => {
-            let idx = bp_tree::find_next_node_idx_for_key(&internal, key)?;
+            let idx = bp_tree::find_next_node_idx_for_key(
+                table,
+                (&internal.keys).into(),
+                internal.child_offsets.len(),
+                key,
+            )?;
             let child_offset = internal.child_offsets[idx];
             drop(node_buffer);
             return Box::pin(delete(table, child_offset, key)).await;
         }
         Some(node_proto::Node_type::Leaf(_)) => {
             drop(node_buffer);
-            let mut node_buffer = node_buffer_lock.write().await;
+            let mut node_buffer = table.buffer_pool.locks.write(&node_buffer_lock).await?;
+            // NOTE: the leaf may have split while unlocked.
+            if let Some(right_offset) = bp_tree::move_right(node_buffer.get(), key) {
+                drop(node_buffer);
+                return Box::pin(delete(table, right_offset, key)).await;
+            }
             let leaf = node_buffer.get_mut().mut_leaf();
-            let idx = bp_tree::find_row_idx_for_key(&leaf, key);
+            let idx = bp_tree::find_row_idx_for_key(table, (&leaf.keys).into(), key);
             if leaf.rows.len() <= idx || leaf.keys[idx] != key {
                 return Err(Error::new(
-                    NotFound,
+                    crate::error::ErrorKind::NotFound,
                     format!("Row with key {} not found!", key),
                 ));
             }
@@ -39,3 +54,17 @@ pub(crate) async fn delete<F: Filelike>(
         None => panic!(),
     }
 }
+
+// Deletes the row with the given key and contents, returning false if there is none.
+pub(crate) async fn delete_matching<F: Filelike>(
+    table: &Table<F>,
+    curr_offset: u32,
+    key: u32,
+    row: &InternalRowProto,
+) -> Result<bool, Error> {
+    bp_tree::update_matching(table, curr_offset, key, row, &|leaf, idx| {
+        leaf.keys.remove(idx);
+        leaf.rows.remove(idx);
+    })
+    .await
+}
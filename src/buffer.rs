@@ -4,116 +4,182 @@ mod test;
 
 use crate::error::{ErrorKind::*, *};
 use crate::filelike::Filelike;
+use crate::protos::generated::chunk::*;
+use crate::protos::generated::config::*;
 use crate::table::Table;
-use crate::{BUFFER_OVERFLOW_BUFFER, BUFFER_SIZE};
 use protobuf::Message;
 use std::io::SeekFrom;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 use tokio::sync::Mutex;
 
-// Buffers represent a single page, chunk, etc. of data stored on disk. Each chunk
-// is guaranteed to be of a static size BUFFER_SIZE. Each buffer stores a single
+// Buffers represent a single page, chunk, etc. of data stored on disk. All chunks
+// of a file are of the same size, see TableConfig. Each buffer stores a single
 // protobuf message, e.g. a B+ tree node, or table metadata, etc. Not thread safe /
 // intended to be accessed behind some locking mechanism.
 //
-// Byte format of each buffer is the following:
+// Byte format of each buffer is the following, unless its message is encoded
+// otherwise (see Encoding):
 // 1. data size: u16 / 2 bytes.
 // 2. data: [u8] proto message to end of section.
 #[derive(Debug)]
-pub(crate) struct Buffer<F: Filelike, M: Message> {
+pub(crate) struct Buffer<F: Filelike, M: Encoding> {
     pub(crate) file: Arc<Mutex<F>>,
+    pub(crate) config: TableConfig,
     pub(crate) offset: u32,
     pub(crate) data: M,
-    pub(crate) is_dirty: bool,
+    // NOTE: atomic, so that buffers can be marked clean once written while only read locked.
+    is_dirty: AtomicBool,
 }
 
-impl<F: Filelike, M: Message> Buffer<F, M> {
-    // Writes all bytes from src into dest at cursor. Increments cursor by the size of src.
-    fn write_bytes(
-        src: &[u8],
-        dest: &mut [u8; BUFFER_SIZE],
-        cursor: &mut usize,
-    ) -> Result<(), Error> {
-        let Some(slice) = dest.get_mut(*cursor..*cursor + src.len()) else {
-            return Err(Error::new(
-                OutOfBounds,
-                format!(
-                    "Source data of size: {} cannot fit in dest buffer of size {} at pos {}!",
-                    src.len(),
-                    dest.len(),
-                    cursor
-                ),
-            ));
-        };
-        slice.copy_from_slice(src);
-        *cursor += src.len();
-        Ok(())
-    }
+// How a buffer's message is laid out within its chunk. By default, in the protobuf based format
+// above, see MessageEncoding. B+ tree nodes are laid out as slotted pages instead, see
+// bp_tree/page.rs.
+pub(crate) trait Encoding: Default {
+    // Transforms the message into a buffer byte array of the given (chunk) size.
+    fn to_bytes(&self, size: usize) -> Result<Vec<u8>, Error>;
 
-    // Transforms the given protobuf message into a buffer byte array.
-    fn message_to_bytes(msg: &M) -> Result<[u8; BUFFER_SIZE], Error> {
-        let data: Vec<u8> = msg
-            .write_to_bytes()
-            .map_err(|e| Error::new(DataLoss, format!("Unable to convert buffer to bytes: {e}")))?;
-        let data_len: u16 = data.len().try_into().unwrap();
-        let mut bytes = [0; BUFFER_SIZE];
-        let mut cursor: usize = 0;
-        Self::write_bytes(&data_len.to_be_bytes(), &mut bytes, &mut cursor)?;
-        Self::write_bytes(&data, &mut bytes, &mut cursor)?;
-        Ok(bytes)
+    // Interprets the given buffer bytes as a message. Also returns whether the bytes are
+    // outdated, i.e. should be rewritten (e.g. in a newer format).
+    fn from_bytes(bytes: Vec<u8>) -> Result<(Self, bool), Error>;
+
+    // Returns true iff adding the provided size to the message will exceed the chunk size.
+    fn would_overflow(&self, addl_size: usize, config: &TableConfig) -> bool;
+}
+
+// Messages laid out in the protobuf based format above.
+pub(crate) trait MessageEncoding: Message {}
+
+impl<M: MessageEncoding> Encoding for M {
+    fn to_bytes(&self, size: usize) -> Result<Vec<u8>, Error> {
+        message_to_bytes(self, size)
     }
 
-    // Reads and returns size bytes from the provided src buffer. Increments Cursor by size.
-    // Intended to consume a byte array / transform into a known structure.
-    fn read_slice<'a>(src: &'a [u8], size: usize, cursor: &mut usize) -> Result<&'a [u8], Error> {
-        let Some(slice) = src.get(*cursor..*cursor + size) else {
-            return Err(Error::new(
-                OutOfBounds,
-                format!("Requested size is too large for buffer!: {}", size).into(),
-            ));
-        };
-        *cursor += size;
-        Ok(slice)
+    fn from_bytes(bytes: Vec<u8>) -> Result<(Self, bool), Error> {
+        Ok((message_from_bytes(&bytes)?, false))
     }
 
-    // Interprets the given buffer bytes as a buffer protobuf message.
-    fn message_from_bytes(bytes: &[u8]) -> Result<M, Error> {
-        let mut cursor: usize = 0;
-        let slice = Self::read_slice(bytes, std::mem::size_of::<u16>(), &mut cursor)?;
-        let buffer_size = u16::from_be_bytes(slice.try_into().unwrap());
-        let slice = Self::read_slice(bytes, buffer_size as usize, &mut cursor)?;
-        let msg = M::parse_from_bytes(&slice).map_err(|e| {
-            Error::new(
-                DataLoss,
-                format!("Unable to interpret buffer from bytes: {e}"),
-            )
-        })?;
-        Ok(msg)
+    fn would_overflow(&self, addl_size: usize, config: &TableConfig) -> bool {
+        message_would_overflow(self, addl_size, config)
     }
+}
 
+impl MessageEncoding for DatabaseSchema {}
+impl MessageEncoding for InternalQueryResultsProto {}
+impl MessageEncoding for LogRecordProto {}
+impl MessageEncoding for TableMetadataProto {}
+
+// Writes all bytes from src into dest at cursor. Increments cursor by the size of src.
+fn write_bytes(src: &[u8], dest: &mut [u8], cursor: &mut usize) -> Result<(), Error> {
+    let Some(slice) = dest.get_mut(*cursor..*cursor + src.len()) else {
+        return Err(Error::new(
+            OutOfBounds,
+            format!(
+                "Source data of size: {} cannot fit in dest buffer of size {} at pos {}!",
+                src.len(),
+                dest.len(),
+                cursor
+            ),
+        ));
+    };
+    slice.copy_from_slice(src);
+    *cursor += src.len();
+    Ok(())
+}
+
+// Transforms the given protobuf message into a buffer byte array.
+pub(crate) fn message_to_bytes<M: Message>(msg: &M, size: usize) -> Result<Vec<u8>, Error> {
+    let data: Vec<u8> = msg
+        .write_to_bytes()
+        .map_err(|e| Error::new(DataLoss, format!("Unable to convert buffer to bytes: {e}")))?;
+    let data_len: u16 = data.len().try_into().unwrap();
+    let mut bytes = vec![0; size];
+    let mut cursor: usize = 0;
+    write_bytes(&data_len.to_be_bytes(), &mut bytes, &mut cursor)?;
+    write_bytes(&data, &mut bytes, &mut cursor)?;
+    Ok(bytes)
+}
+
+// Reads and returns size bytes from the provided src buffer. Increments Cursor by size.
+// Intended to consume a byte array / transform into a known structure.
+fn read_slice<'a>(src: &'a [u8], size: usize, cursor: &mut usize) -> Result<&'a [u8], Error> {
+    let Some(slice) = src.get(*cursor..*cursor + size) else {
+        return Err(Error::new(
+            OutOfBounds,
+            format!("Requested size is too large for buffer!: {}", size),
+        ));
+    };
+    *cursor += size;
+    Ok(slice)
+}
+
+// Interprets the given buffer bytes as a buffer protobuf message.
+pub(crate) fn message_from_bytes<M: Message>(bytes: &[u8]) -> Result<M, Error> {
+    let mut cursor: usize = 0;
+    let slice = read_slice(bytes, std::mem::size_of::<u16>(), &mut cursor)?;
+    let buffer_size = u16::from_be_bytes(slice.try_into().unwrap());
+    let slice = read_slice(bytes, buffer_size as usize, &mut cursor)?;
+    let msg = M::parse_from_bytes(slice).map_err(|e| {
+        Error::new(
+            DataLoss,
+            format!("Unable to interpret buffer from bytes: {e}"),
+        )
+    })?;
+    Ok(msg)
+}
+
+// NOTE: an estimate, hence the chunk's overflow size.
+pub(crate) fn message_would_overflow<M: Message>(
+    msg: &M,
+    addl_size: usize,
+    config: &TableConfig,
+) -> bool {
+    let size_estimate = std::mem::size_of::<u16>()
+        + msg.compute_size() as usize
+        + addl_size
+        + config.chunk_overflow_size as usize;
+    config.chunk_size as usize <= size_estimate
+}
+
+impl<F: Filelike, M: Encoding> Buffer<F, M> {
     // Creates an empty buffer associated with the given file / offset.
-    pub(crate) fn new_for_file(file: Arc<Mutex<F>>, offset: u32, data: M) -> Self {
+    pub(crate) fn new_for_file(
+        file: Arc<Mutex<F>>,
+        config: &TableConfig,
+        offset: u32,
+        data: M,
+    ) -> Self {
         Self {
             file: file,
+            config: config.clone(),
             offset: offset,
             data: data,
-            is_dirty: true,
+            is_dirty: AtomicBool::new(true),
         }
     }
 
     // Claims the next offset for the given table and creates an empty buffer
     // at that location.
     pub(crate) async fn new_next_for_table(table: &Table<F>) -> Self {
-        Self::new_for_file(table.file.clone(), table.next_chunk_offset(), M::new())
+        Self::new_for_file(
+            table.file.clone(),
+            &table.config,
+            table.next_chunk_offset(),
+            M::default(),
+        )
     }
 
     // Reads the buffer at the given file / offset and returns it.
-    pub(crate) async fn read_from_file(file: Arc<Mutex<F>>, offset: u32) -> Result<Self, Error> {
-        let mut bytes = [0; BUFFER_SIZE];
+    pub(crate) async fn read_from_file(
+        file: Arc<Mutex<F>>,
+        config: &TableConfig,
+        offset: u32,
+    ) -> Result<Self, Error> {
+        let mut bytes = vec![0; config.chunk_size as usize];
         {
             let mut file = file.lock().await;
-            file.seek(SeekFrom::Start(offset as u64 * BUFFER_SIZE as u64))
+            file.seek(SeekFrom::Start(offset as u64 * config.chunk_size as u64))
                 .await
                 .map_err(|e| {
                     Error::new(Internal, format!("Unable to seek file for read call: {e}"))
@@ -122,34 +188,33 @@ impl<F: Filelike, M: Message> Buffer<F, M> {
                 .await
                 .map_err(|e| Error::new(Internal, format!("Unable to read file: {e}")))?;
         }
+        let (data, is_outdated) = M::from_bytes(bytes)?;
+        // NOTE: outdated buffers are dirty, so that they're rewritten once evicted or flushed.
         Ok(Self {
             file: file,
+            config: config.clone(),
             offset: offset,
-            data: Self::message_from_bytes(&bytes)?,
-            is_dirty: false,
+            data,
+            is_dirty: AtomicBool::new(is_outdated),
         })
     }
 
-    // Reads the buffer at the given table's file / offset and returns it.
-    pub(crate) async fn read_from_table(table: &Table<F>, offset: u32) -> Result<Self, Error> {
-        Self::read_from_file(table.file.clone(), offset).await
-    }
-
     // Writes the buffer's current contents to its configured location.
-    // NOTE: Expects that the buffer does not exceed the static size limit.
+    // NOTE: Expects that the buffer does not exceed the chunk size, else fails.
     pub(crate) async fn write_to_file(&self) -> Result<(), Error> {
-        assert!(!self.would_overflow(0));
-        if !self.is_dirty {
+        if !self.is_dirty() {
             return Ok(());
         }
-        let bytes: [u8; BUFFER_SIZE] = Self::message_to_bytes(&self.data)?;
+        let bytes = self.data.to_bytes(self.config.chunk_size as usize)?;
         {
             let mut file = self.file.lock().await;
-            file.seek(SeekFrom::Start(self.offset as u64 * BUFFER_SIZE as u64))
-                .await
-                .map_err(|e| {
-                    Error::new(Internal, format!("Unable to seek file for write call: {e}"))
-                })?;
+            file.seek(SeekFrom::Start(
+                self.offset as u64 * self.config.chunk_size as u64,
+            ))
+            .await
+            .map_err(|e| {
+                Error::new(Internal, format!("Unable to seek file for write call: {e}"))
+            })?;
             file.write(&bytes)
                 .await
                 .map_err(|e| Error::new(Internal, format!("Unable to write to file: {e}")))?;
@@ -157,17 +222,19 @@ impl<F: Filelike, M: Message> Buffer<F, M> {
                 .await
                 .map_err(|e| Error::new(Internal, format!("Unable to flush file: {e}")))?;
         }
+        self.is_dirty.store(false, Ordering::Release);
         Ok(())
     }
 
+    // Whether the buffer has changes not yet written to its file.
+    pub(crate) fn is_dirty(&self) -> bool {
+        self.is_dirty.load(Ordering::Acquire)
+    }
+
     // Returns true iff adding the provided size to the buffer will exceed
-    // the static size limit.
+    // the chunk size.
     pub(crate) fn would_overflow(&self, addl_size: usize) -> bool {
-        let size_estimate = std::mem::size_of::<u16>()
-            + self.data.compute_size() as usize
-            + addl_size
-            + BUFFER_OVERFLOW_BUFFER as usize;
-        BUFFER_SIZE <= size_estimate.try_into().unwrap()
+        self.data.would_overflow(addl_size, &self.config)
     }
 
     // Retrieve an immutable reference to the underlying proto.
@@ -178,7 +245,7 @@ impl<F: Filelike, M: Message> Buffer<F, M> {
     // Retrieve a mutable reference to the underlying proto.
     // Silently marks the buffer as having uncommitted changes.
     pub(crate) fn get_mut<'a>(&'a mut self) -> &'a mut M {
-        self.is_dirty = true;
+        self.is_dirty.store(true, Ordering::Release);
         &mut self.data
     }
 }
@@ -1,244 +1,663 @@
+#[cfg(test)]
+#[path = "./buffer_pool_test.rs"]
+mod test;
+
+use crate::bp_tree::Node;
 use crate::buffer::Buffer;
-use crate::error::*;
+use crate::error::{ErrorKind::*, *};
 use crate::filelike::Filelike;
+use crate::lock_manager::LockManager;
+use crate::metrics::TableCounters;
+use crate::options::{DatabaseOptions, ReplacementPolicy};
 use crate::protos::generated::chunk::*;
+use crate::protos::generated::config::*;
+use crate::replacer::{self, PageId, Replacer};
 use crate::table::*;
-use crate::{BUFFER_POOL_SHARD_COUNT, BUFFER_POOL_SHARD_SIZE};
-use std::cell::OnceCell;
 use std::collections::HashMap;
-use std::ops::{Deref, DerefMut};
+use std::future::Future;
+use std::ops::Deref;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
-use tokio::sync::{Mutex, RwLock};
+use std::time::Duration;
+use tokio::sync::futures::Notified;
+use tokio::sync::{Mutex, MutexGuard, Notify, RwLock};
+use tokio::time::Instant;
 
-// Individual LRU cache entries. Buffers are stored behind a lock to ensure
-// buffers cannot be written to while read.
-#[derive(Debug)]
-struct CacheEntry<F: Filelike> {
-    data: OnceCell<Arc<RwLock<Buffer<F, NodeProto>>>>,
-    table_id: u32,
-    offset: u32,
-    left: CacheEntryPtr<F>,
-    right: CacheEntryPtr<F>,
+// A cached buffer, along with the number of pins held on it.
+// Buffers are stored behind a lock to ensure buffers cannot be written to while read.
+struct Frame<F: Filelike> {
+    pins: AtomicUsize,
+    buffer: RwLock<Buffer<F, Node>>,
+    // The counters of the buffer's table.
+    counters: Arc<TableCounters>,
 }
 
-#[derive(Debug)]
-struct CacheEntryBox<F: Filelike>(*mut CacheEntry<F>);
-
-impl<F: Filelike> CacheEntryBox<F> {
-    fn new(entry: CacheEntry<F>) -> Self {
-        Self(Box::into_raw(Box::new(entry)))
+impl<F: Filelike> Frame<F> {
+    // Writes the (locked) buffer to disk if dirty, returning whether it was.
+    async fn write(&self, buffer: &Buffer<F, Node>) -> Result<bool, Error> {
+        if !buffer.is_dirty() {
+            return Ok(false);
+        }
+        buffer.write_to_file().await?;
+        self.counters
+            .bytes_written
+            .fetch_add(buffer.config.chunk_size as u64, Ordering::Relaxed);
+        Ok(true)
     }
+}
 
-    fn as_ptr(&self) -> CacheEntryPtr<F> {
-        CacheEntryPtr(self.0)
-    }
+// A buffer evicted from the cache.
+struct Evicted {
+    size: usize,
+    was_dirty: bool,
 }
 
-impl<F: Filelike> Drop for CacheEntryBox<F> {
-    fn drop(&mut self) {
-        unsafe {
-            drop(Box::from_raw(self.0 as *mut CacheEntry<F>));
-        }
-    }
+// A buffer chosen to be evicted, see Cache::choose_victim.
+enum Victim<F: Filelike> {
+    // A clean buffer, already evicted.
+    Evicted(Evicted),
+    // A dirty buffer, which is pinned (and untracked by the replacer) until written, see
+    // BufferPool::write_back.
+    Dirty(PageId, Arc<Frame<F>>),
 }
 
-#[derive(Debug)]
-struct CacheEntryPtr<F: Filelike>(*mut CacheEntry<F>);
+// The outcome of BufferPool::reserve.
+enum Reservation<'a, F: Filelike> {
+    // The bytes were claimed, with the shard still locked.
+    Claimed(MutexGuard<'a, Cache<F>>),
+    // A dirty buffer was written (and perhaps evicted) with the shard unlocked, so the shard may
+    // have changed meanwhile and the caller should retry.
+    Retry,
+    // Every buffer is pinned.
+    Exhausted,
+}
 
-unsafe impl<F: Filelike> Send for CacheEntryPtr<F> {}
+// A pinned buffer, which can't be evicted from the buffer pool until unpinned (dropped).
+// NOTE: buffers can only be locked through pins, so unpinned buffers are never locked.
+pub(crate) struct PinnedBuffer<'a, F: Filelike> {
+    pool: &'a BufferPool<F>,
+    frame: Arc<Frame<F>>,
+}
 
-impl<F: Filelike> Deref for CacheEntryPtr<F> {
-    type Target = CacheEntry<F>;
+impl<F: Filelike> Deref for PinnedBuffer<'_, F> {
+    type Target = RwLock<Buffer<F, Node>>;
     fn deref(&self) -> &Self::Target {
-        unsafe { &*self.0 }
+        &self.frame.buffer
     }
 }
 
-impl<F: Filelike> DerefMut for CacheEntryPtr<F> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        unsafe { &mut *self.0 }
+impl<F: Filelike> Drop for PinnedBuffer<'_, F> {
+    fn drop(&mut self) {
+        if self.frame.pins.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.pool.unpinned.notify_waiters();
+        }
     }
 }
 
-impl<F: Filelike> Clone for CacheEntryPtr<F> {
-    fn clone(&self) -> Self {
-        CacheEntryPtr(self.0)
+// What the buffer pool needs to read the pages of a table, borrowed from the table (or from
+// clones of its parts, e.g. in background tasks).
+struct TableFile<'a, F: Filelike> {
+    id: u32,
+    file: &'a Arc<Mutex<F>>,
+    config: &'a TableConfig,
+    counters: &'a Arc<TableCounters>,
+}
+
+impl<'a, F: Filelike> TableFile<'a, F> {
+    fn of(table: &'a Table<F>) -> Self {
+        Self {
+            id: table.id,
+            file: &table.file,
+            config: &table.config,
+            counters: &table.counters,
+        }
     }
 }
 
-// LRU cache implementation. Intended to be accessed behind a Mutex.
-// Recency is tracked through a doubly-linked list (tracked by sentinel).
-// Performant data retrieval is supported using the HashMap.
-struct Cache<F: Filelike> {
-    sentinel: CacheEntryBox<F>,
-    map: HashMap<(u32, u32), CacheEntryBox<F>>,
+// Set for background tasks reading pages ahead of their use, see BufferPool::prefetch.
+// NOTE: a task local is used so that prefetching can reuse B+ tree traversals, as with the
+// profiler.
+tokio::task_local! {
+    static PREFETCHING: ();
 }
 
-unsafe impl<F: Filelike> Send for Cache<F> {}
+fn is_prefetching() -> bool {
+    PREFETCHING.try_with(|_| ()).is_ok()
+}
+
+// Runs the given future in the background, with the pages it reads counted as prefetched rather
+// than hits or misses. Errors are ignored, since prefetching is only an optimization.
+pub(crate) fn spawn_prefetch(future: impl Future<Output = Result<(), Error>> + Send + 'static) {
+    tokio::spawn(PREFETCHING.scope((), async move {
+        if let Err(e) = future.await {
+            log::debug!("Stopped prefetching: {}", e.msg);
+        }
+    }));
+}
+
+// Cache of buffers, keyed on table id + offset. Once the buffer pool is full, the replacer
+// chooses which buffer to evict. Intended to be accessed behind a Mutex.
+struct Cache<F: Filelike> {
+    map: HashMap<PageId, Arc<Frame<F>>>,
+    replacer: Box<dyn Replacer>,
+}
 
 impl<F: Filelike> Cache<F> {
-    fn new() -> Self {
-        let sentinel = CacheEntryBox::new(CacheEntry {
-            data: OnceCell::new(),
-            table_id: 0,
-            offset: 0,
-            left: CacheEntryPtr(std::ptr::null_mut()),
-            right: CacheEntryPtr(std::ptr::null_mut()),
-        });
-        let mut sentinel_ptr = sentinel.as_ptr();
-        sentinel_ptr.left = sentinel_ptr.clone();
-        sentinel_ptr.right = sentinel_ptr.clone();
+    fn new(policy: ReplacementPolicy, capacity: usize) -> Self {
         Self {
-            sentinel: sentinel,
             map: HashMap::new(),
+            replacer: replacer::new(policy, capacity),
         }
     }
 
-    // Evict the least recently used item from the cache.
-    // NOTE: Expects the cache to have at least one element!
-    async fn evict(&mut self) -> Result<(), Error> {
-        debug_assert!(self.map.len() > 0);
-        let mut lru = self.sentinel.as_ptr().left.clone();
-        lru.left.right = lru.right.clone();
-        lru.right.left = lru.left.clone();
-
-        // NOTE: since this shard must be locked to retrieve the buffer lock, once
-        // this exclusive lock request succeeds we know there are no races on the
-        // evicted buffer. if there is a pending request to read the buffer we're
-        // about to evict, we will simply re-read it in, after any dirty data has
-        // been committed and the locks are released.
-        let buffer = lru.data.get().unwrap().write().await;
-        buffer.write_to_file().await?;
-
-        self.map.remove(&(lru.table_id, lru.offset));
-
-        Ok(())
-    }
+    // Evicts the unpinned buffer chosen by the replacer from the cache if it's clean, or pins it
+    // to be written first if it's dirty, see Victim. Returns None if all buffers are pinned.
+    // NOTE: no I/O is done here, so that the shard isn't locked while it's waited on.
+    fn choose_victim(&mut self) -> Result<Option<Victim<F>>, Error> {
+        // NOTE: pins are only taken with this shard locked, so unpinned buffers stay unpinned.
+        let map = &self.map;
+        let Some(page) = self
+            .replacer
+            .evict(&|page| map[page].pins.load(Ordering::Acquire) == 0)
+        else {
+            return Ok(None);
+        };
 
-    // Marks the provided node as "most" recently used by moving it to the
-    // front of the list.
-    fn promote(&mut self, entry_ptr: &mut CacheEntryPtr<F>) {
-        entry_ptr.left = self.sentinel.as_ptr().clone();
-        entry_ptr.right = self.sentinel.as_ptr().right.clone();
-        entry_ptr.left.right = entry_ptr.clone();
-        entry_ptr.right.left = entry_ptr.clone();
+        let frame = &self.map[&page];
+        let buffer = frame
+            .buffer
+            .try_read()
+            .map_err(|_| Error::new(Internal, format!("Unpinned buffer {:?} is locked!", page)))?;
+        if buffer.is_dirty() {
+            drop(buffer);
+            frame.pins.fetch_add(1, Ordering::AcqRel);
+            return Ok(Some(Victim::Dirty(page, frame.clone())));
+        }
+        let size = buffer.config.chunk_size as usize;
+        drop(buffer);
+        self.map.remove(&page);
+        Ok(Some(Victim::Evicted(Evicted {
+            size,
+            was_dirty: false,
+        })))
     }
 
-    // Inserts the buffer into the cache, marks it as most recently used.
+    // Inserts the buffer into the cache, pinned.
     // NOTE: Expects the buffer to not already be present!
-    async fn insert(
-        &mut self,
-        table_id: u32,
-        offset: u32,
-        buffer: Buffer<F, NodeProto>,
-    ) -> Result<Arc<RwLock<Buffer<F, NodeProto>>>, Error> {
-        debug_assert!(self.get(table_id, offset).await.is_none());
-        if self.map.len() >= BUFFER_POOL_SHARD_SIZE {
-            self.evict().await?;
-        }
-        let entry_box = CacheEntryBox::new(CacheEntry {
-            data: OnceCell::from(Arc::new(RwLock::new(buffer))),
-            table_id: table_id,
-            offset: offset,
-            left: CacheEntryPtr(std::ptr::null_mut()),
-            right: CacheEntryPtr(std::ptr::null_mut()),
+    fn insert(&mut self, table: &TableFile<'_, F>, buffer: Buffer<F, Node>) -> Arc<Frame<F>> {
+        let page = (table.id, buffer.offset);
+        debug_assert!(!self.map.contains_key(&page));
+        let frame = Arc::new(Frame {
+            pins: AtomicUsize::new(1),
+            buffer: RwLock::new(buffer),
+            counters: table.counters.clone(),
         });
-        let mut entry_ptr = entry_box.as_ptr();
-        self.map.insert((table_id, offset), entry_box);
-        self.promote(&mut entry_ptr);
-        Ok(entry_ptr.deref().data.get().unwrap().clone())
+        self.map.insert(page, frame.clone());
+        self.replacer.insert(page);
+        frame
     }
 
-    // Gets the buffer associated with the given table and offset.
-    // Marks it as most recently used before returning.
-    async fn get(
-        &mut self,
-        table_id: u32,
-        offset: u32,
-    ) -> Option<Arc<RwLock<Buffer<F, NodeProto>>>> {
-        match self.map.get(&(table_id, offset)) {
-            Some(entry_box) => {
-                let mut entry_ptr = entry_box.as_ptr();
-                // remove entry_ptr from the recency list.
-                entry_ptr.left.right = entry_ptr.right.clone();
-                entry_ptr.right.left = entry_ptr.left.clone();
-                self.promote(&mut entry_ptr);
-                return Some(entry_ptr.data.get().unwrap().clone());
-            }
-            None => {
-                return None;
-            }
-        }
+    // Pins (up to limit) dirty buffers, without recording accesses. Buffers locked for writing
+    // are assumed dirty.
+    fn pin_dirty(&self, limit: usize) -> Vec<Arc<Frame<F>>> {
+        self.map
+            .values()
+            .filter(|frame| {
+                frame
+                    .buffer
+                    .try_read()
+                    .map_or(true, |buffer| buffer.is_dirty())
+            })
+            .take(limit)
+            .map(|frame| {
+                frame.pins.fetch_add(1, Ordering::AcqRel);
+                frame.clone()
+            })
+            .collect()
     }
 
-    // Empties all buffers in the cache.
-    // This forces all dirty / in-flight buffers to commit any changes to disk.
-    #[cfg(test)]
-    async fn flush(&mut self) -> Result<(), Error> {
-        while self.map.len() > 0 {
-            self.evict().await?;
-        }
-        Ok(())
+    // Pins the buffer associated with the given table and offset, recording the access.
+    fn pin(&mut self, table_id: u32, offset: u32) -> Option<Arc<Frame<F>>> {
+        let frame = self.map.get(&(table_id, offset))?.clone();
+        frame.pins.fetch_add(1, Ordering::AcqRel);
+        self.replacer.access((table_id, offset));
+        Some(frame)
     }
 }
 
 // Manages all in-memory buffers (B+ node buffers specifically). Intended to be
-// shared across threads. Internally represented as an LRU cache, keyed on
+// shared across threads. Internally represented as a cache, keyed on
 // table id + offset. Sharded for more efficient concurrent access.
+//
+// Buffers are pinned while in use, and only unpinned buffers are evicted. The shards share a
+// budget of bytes: once it's spent, buffers are evicted from the shard being read into first,
+// then from any other shard. Dirty buffers are written before they're evicted, without holding
+// any shard locks. If every buffer is pinned, reads wait (without holding any shard locks) for
+// buffers to be unpinned, and eventually fail with ResourceExhausted, rather than exceeding the
+// budget.
 pub(crate) struct BufferPool<F: Filelike> {
     shards: Vec<Mutex<Cache<F>>>,
+    // Buffers should be locked through the lock manager, so that deadlocks are detected.
+    pub(crate) locks: LockManager,
+    // The budget, and the bytes of it used by cached buffers.
+    size: usize,
+    used: AtomicUsize,
+    wait_timeout: Duration,
+    // Notified whenever a buffer is fully unpinned.
+    unpinned: Notify,
+    // The shard the next dirty buffers are written from, see write_dirty.
+    next_dirty_shard: AtomicUsize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    prefetches: AtomicU64,
+    evictions: AtomicU64,
+    dirty_writes: AtomicU64,
+    resident_pages: AtomicU64,
+    // The total time spent waiting to lock each shard, in microseconds.
+    shard_lock_wait_micros: Vec<AtomicU64>,
+}
+
+// Counters describing the buffer pool's usage since it was created.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct BufferPoolStats {
+    pub(crate) hits: u64,
+    pub(crate) misses: u64,
 }
 
 impl<F: Filelike> BufferPool<F> {
     // Finds what shard the given key is associated with.
-    fn shard_idx(table_id: u32, offset: u32) -> usize {
+    fn shard_idx(&self, table_id: u32, offset: u32) -> usize {
         let cantor = (table_id + offset) * (table_id + offset + 1) / 2 + table_id;
-        cantor as usize % BUFFER_POOL_SHARD_COUNT
+        cantor as usize % self.shards.len()
+    }
+
+    pub(crate) fn new(options: &DatabaseOptions) -> Self {
+        // NOTE: replacers size their bookkeeping by the number of pages each shard holds, which
+        // is only an estimate since tables may have different page sizes.
+        let shard_capacity =
+            (options.buffer_pool_size / options.page_size / options.buffer_pool_shard_count).max(1);
+        let mut shards: Vec<Mutex<Cache<F>>> = Vec::with_capacity(options.buffer_pool_shard_count);
+        for _ in 0..options.buffer_pool_shard_count {
+            shards.push(Mutex::new(Cache::new(
+                options.replacement_policy,
+                shard_capacity,
+            )));
+        }
+        Self {
+            shards,
+            locks: LockManager::new(),
+            size: options.buffer_pool_size,
+            used: AtomicUsize::new(0),
+            wait_timeout: options.buffer_pool_wait_timeout,
+            unpinned: Notify::new(),
+            next_dirty_shard: AtomicUsize::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            prefetches: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            dirty_writes: AtomicU64::new(0),
+            resident_pages: AtomicU64::new(0),
+            shard_lock_wait_micros: (0..options.buffer_pool_shard_count)
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+        }
     }
 
-    pub(crate) fn new() -> Self {
-        let mut shards: Vec<Mutex<Cache<F>>> = Vec::with_capacity(BUFFER_POOL_SHARD_COUNT);
-        for _ in 0..BUFFER_POOL_SHARD_COUNT {
-            shards.push(Mutex::new(Cache::new()));
+    pub(crate) fn stats(&self) -> BufferPoolStats {
+        BufferPoolStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
         }
-        Self { shards: shards }
     }
 
-    // Claims the next offset for the given table and creates an empty buffer
+    pub(crate) fn metrics(&self) -> BufferPoolMetricsProto {
+        let mut metrics = BufferPoolMetricsProto::new();
+        metrics.hits = self.hits.load(Ordering::Relaxed);
+        metrics.misses = self.misses.load(Ordering::Relaxed);
+        metrics.prefetches = self.prefetches.load(Ordering::Relaxed);
+        metrics.evictions = self.evictions.load(Ordering::Relaxed);
+        metrics.dirty_writes = self.dirty_writes.load(Ordering::Relaxed);
+        metrics.resident_pages = self.resident_pages.load(Ordering::Relaxed);
+        metrics.resident_bytes = self.used.load(Ordering::Relaxed) as u64;
+        metrics.capacity_bytes = self.size as u64;
+        metrics.shard_lock_wait_micros = self
+            .shard_lock_wait_micros
+            .iter()
+            .map(|micros| micros.load(Ordering::Relaxed))
+            .collect();
+        metrics
+    }
+
+    // Locks the given shard, recording how long that took.
+    async fn lock_shard(&self, shard_idx: usize) -> MutexGuard<'_, Cache<F>> {
+        if let Ok(shard) = self.shards[shard_idx].try_lock() {
+            return shard;
+        }
+        let start = Instant::now();
+        let shard = self.shards[shard_idx].lock().await;
+        self.shard_lock_wait_micros[shard_idx]
+            .fetch_add(start.elapsed().as_micros() as u64, Ordering::Relaxed);
+        shard
+    }
+
+    fn record_eviction(&self, evicted: Evicted) {
+        self.used.fetch_sub(evicted.size, Ordering::AcqRel);
+        self.resident_pages.fetch_sub(1, Ordering::Relaxed);
+        self.evictions.fetch_add(1, Ordering::Relaxed);
+        if evicted.was_dirty {
+            self.dirty_writes.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // Inserts the buffer into the (locked) shard, pinned.
+    fn insert(
+        &self,
+        shard: &mut Cache<F>,
+        table: &TableFile<'_, F>,
+        buffer: Buffer<F, Node>,
+    ) -> PinnedBuffer<'_, F> {
+        self.resident_pages.fetch_add(1, Ordering::Relaxed);
+        let frame = shard.insert(table, buffer);
+        PinnedBuffer { pool: self, frame }
+    }
+
+    // Claims size bytes of the budget, evicting unpinned buffers (from the given, locked, shard
+    // first) to make room, see Reservation. Dirty buffers are written with no shard locked.
+    async fn reserve<'a>(
+        &'a self,
+        mut shard: MutexGuard<'a, Cache<F>>,
+        shard_idx: usize,
+        size: usize,
+    ) -> Result<Reservation<'a, F>, Error> {
+        loop {
+            let claimed = self
+                .used
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                    (used + size <= self.size).then_some(used + size)
+                });
+            if claimed.is_ok() {
+                return Ok(Reservation::Claimed(shard));
+            }
+            let mut victim = shard.choose_victim()?.map(|victim| (shard_idx, victim));
+            // NOTE: other shards are only locked if free, since their holders may in turn be
+            // waiting on this one.
+            for (idx, other) in self.shards.iter().enumerate() {
+                if victim.is_some() {
+                    break;
+                }
+                if idx == shard_idx {
+                    continue;
+                }
+                if let Ok(mut other) = other.try_lock() {
+                    victim = other.choose_victim()?.map(|victim| (idx, victim));
+                }
+            }
+            match victim {
+                Some((_, Victim::Evicted(evicted))) => self.record_eviction(evicted),
+                Some((idx, Victim::Dirty(page, frame))) => {
+                    drop(shard);
+                    self.write_back(idx, page, frame).await?;
+                    return Ok(Reservation::Retry);
+                }
+                None => return Ok(Reservation::Exhausted),
+            }
+        }
+    }
+
+    // Writes the given dirty victim (see Cache::choose_victim) with no shard locked, then evicts
+    // it unless it was used meanwhile, returning whether it was. If the write fails, the buffer
+    // stays cached (and dirty).
+    async fn write_back(
+        &self,
+        shard_idx: usize,
+        page: PageId,
+        frame: Arc<Frame<F>>,
+    ) -> Result<bool, Error> {
+        let pinned = PinnedBuffer { pool: self, frame };
+        let written = match self.locks.read(&pinned).await {
+            Ok(buffer) => pinned.frame.write(&buffer).await,
+            Err(e) => Err(e),
+        };
+        let mut shard = self.lock_shard(shard_idx).await;
+        let frame = pinned.frame.clone();
+        drop(pinned);
+        // NOTE: evictable only if still unpinned and clean, i.e. not used since it was written.
+        let clean_size = match frame.pins.load(Ordering::Acquire) {
+            0 => frame
+                .buffer
+                .try_read()
+                .ok()
+                .filter(|buffer| !buffer.is_dirty())
+                .map(|buffer| buffer.config.chunk_size as usize),
+            _ => None,
+        };
+        let (Ok(was_dirty), Some(size)) = (&written, clean_size) else {
+            shard.replacer.reinstate(page);
+            return written.map(|_| false);
+        };
+        shard.map.remove(&page);
+        self.record_eviction(Evicted {
+            size,
+            was_dirty: *was_dirty,
+        });
+        Ok(true)
+    }
+
+    // Waits for a buffer to be unpinned, failing with ResourceExhausted after the deadline.
+    async fn wait_unpinned(
+        &self,
+        unpinned: Pin<&mut Notified<'_>>,
+        deadline: Instant,
+    ) -> Result<(), Error> {
+        log::debug!("All cached buffers are pinned, waiting for one to be unpinned.");
+        tokio::time::timeout_at(deadline, unpinned)
+            .await
+            .map_err(|_| {
+                Error::new(
+                    ResourceExhausted,
+                    format!(
+                        "Timed out after {:?} waiting for buffers to be unpinned!",
+                        self.wait_timeout
+                    ),
+                )
+            })
+    }
+
+    // Claims the next offset for the given table and creates an empty, pinned, buffer
     // at that location.
+    // NOTE: the offset is claimed even if the buffer pool is exhausted, leaving it unused.
     pub(crate) async fn new_next_for_table(
         &self,
         table: &Table<F>,
-    ) -> Result<Arc<RwLock<Buffer<F, NodeProto>>>, Error> {
+    ) -> Result<PinnedBuffer<'_, F>, Error> {
         let buffer = Buffer::new_next_for_table(table).await;
-        let mut shard = self.shards[Self::shard_idx(table.id, buffer.offset)]
-            .lock()
-            .await;
-        shard.insert(table.id, buffer.offset, buffer).await
+        let shard_idx = self.shard_idx(table.id, buffer.offset);
+        let size = buffer.config.chunk_size as usize;
+        let deadline = Instant::now() + self.wait_timeout;
+        loop {
+            // NOTE: registered before reserving, so that unpins meanwhile aren't missed.
+            let unpinned = self.unpinned.notified();
+            tokio::pin!(unpinned);
+            unpinned.as_mut().enable();
+
+            let shard = self.lock_shard(shard_idx).await;
+            match self.reserve(shard, shard_idx, size).await? {
+                Reservation::Claimed(mut shard) => {
+                    return Ok(self.insert(&mut shard, &TableFile::of(table), buffer));
+                }
+                Reservation::Retry => continue,
+                Reservation::Exhausted => self.wait_unpinned(unpinned, deadline).await?,
+            }
+        }
     }
 
-    // Retrieves / reads the buffer on the given table at the given index.
+    // Retrieves / reads the buffer on the given table at the given index, pinned.
     pub(crate) async fn read_from_table(
         &self,
         table: &Table<F>,
         offset: u32,
-    ) -> Result<Arc<RwLock<Buffer<F, NodeProto>>>, Error> {
-        let mut shard = self.shards[Self::shard_idx(table.id, offset)].lock().await;
-        match shard.get(table.id, offset).await {
-            Some(buffer) => return Ok(buffer),
-            None => {
-                let buffer = Buffer::read_from_table(table, offset).await?;
-                return Ok(shard.insert(table.id, buffer.offset, buffer).await?);
+    ) -> Result<PinnedBuffer<'_, F>, Error> {
+        self.read_page(&TableFile::of(table), offset).await
+    }
+
+    async fn read_page(
+        &self,
+        table: &TableFile<'_, F>,
+        offset: u32,
+    ) -> Result<PinnedBuffer<'_, F>, Error> {
+        let shard_idx = self.shard_idx(table.id, offset);
+        let size = table.config.chunk_size as usize;
+        let deadline = Instant::now() + self.wait_timeout;
+        // NOTE: reads ahead of use are neither hits nor misses, and fail rather than wait.
+        let is_prefetching = is_prefetching();
+        loop {
+            let unpinned = self.unpinned.notified();
+            tokio::pin!(unpinned);
+            unpinned.as_mut().enable();
+
+            let mut shard = self.lock_shard(shard_idx).await;
+            if let Some(frame) = shard.pin(table.id, offset) {
+                if !is_prefetching {
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                }
+                return Ok(PinnedBuffer { pool: self, frame });
             }
+            let mut shard = match self.reserve(shard, shard_idx, size).await? {
+                Reservation::Claimed(shard) => shard,
+                Reservation::Retry => continue,
+                Reservation::Exhausted if is_prefetching => {
+                    return Err(Error::new(
+                        ResourceExhausted,
+                        "All cached buffers are pinned!".to_string(),
+                    ));
+                }
+                Reservation::Exhausted => {
+                    self.wait_unpinned(unpinned, deadline).await?;
+                    continue;
+                }
+            };
+            let counter = match is_prefetching {
+                true => &self.prefetches,
+                false => &self.misses,
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+            let buffer =
+                match Buffer::read_from_file(table.file.clone(), table.config, offset).await {
+                    Ok(buffer) => buffer,
+                    Err(e) => {
+                        self.used.fetch_sub(size, Ordering::AcqRel);
+                        return Err(e);
+                    }
+                };
+            table
+                .counters
+                .bytes_read
+                .fetch_add(size as u64, Ordering::Relaxed);
+            return Ok(self.insert(&mut shard, table, buffer));
         }
     }
 
-    // Forces all dirty / in-flight buffers to commit any changes to disk.
-    #[cfg(test)]
+    // Reads the given pages of the table into the pool in the background, e.g. ahead of a scan.
+    pub(crate) fn prefetch(self: &Arc<Self>, table: &Table<F>, offsets: Vec<u32>) {
+        let pool = self.clone();
+        let (id, file, config, counters) = (
+            table.id,
+            table.file.clone(),
+            table.config.clone(),
+            table.counters.clone(),
+        );
+        spawn_prefetch(async move {
+            let table = TableFile {
+                id,
+                file: &file,
+                config: &config,
+                counters: &counters,
+            };
+            for offset in offsets {
+                pool.read_page(&table, offset).await?;
+            }
+            Ok(())
+        });
+    }
+
+    // Writes (up to limit) dirty buffers to disk, without evicting them, and returns how many
+    // were written. Buffers locked for writing are skipped, unless wait is set.
+    async fn write_dirty(&self, limit: usize, wait: bool) -> Result<usize, Error> {
+        let mut num_written = 0;
+        // NOTE: starts from a different shard each call, so that all shards are trickled.
+        let start = self.next_dirty_shard.fetch_add(1, Ordering::Relaxed);
+        for i in 0..self.shards.len() {
+            if num_written >= limit {
+                break;
+            }
+            let frames = self
+                .lock_shard((start + i) % self.shards.len())
+                .await
+                .pin_dirty(limit - num_written);
+            for frame in frames {
+                let pinned = PinnedBuffer { pool: self, frame };
+                // NOTE: waits through the lock manager, as the buffer may be locked by a
+                // transaction waiting on locks this caller holds.
+                let buffer = match wait {
+                    true => self.locks.read(&pinned).await?,
+                    false => match self.locks.try_read(&pinned) {
+                        Some(buffer) => buffer,
+                        None => continue,
+                    },
+                };
+                if pinned.frame.write(&buffer).await? {
+                    self.dirty_writes.fetch_add(1, Ordering::Relaxed);
+                    num_written += 1;
+                }
+            }
+        }
+        Ok(num_written)
+    }
+
+    // Writes some dirty buffers to disk in the background, so that evicting them later (e.g.
+    // while reading) needn't, and returns how many were written.
+    pub(crate) async fn trickle_dirty(&self, limit: usize) -> Result<usize, Error> {
+        self.write_dirty(limit, false).await
+    }
+
+    // Writes all buffers dirty when called to disk, without evicting them, e.g. to checkpoint.
+    pub(crate) async fn write_all_dirty(&self) -> Result<usize, Error> {
+        self.write_dirty(usize::MAX, true).await
+    }
+
+    // Forces all dirty / in-flight buffers to commit any changes to disk, emptying the cache.
+    // Waits for pinned buffers to be unpinned, failing with FailedPrecondition if some are still
+    // pinned after the wait timeout.
     pub(crate) async fn flush(&self) -> Result<(), Error> {
-        for shard in &self.shards {
-            let mut shard = shard.lock().await;
-            shard.flush().await?;
+        let deadline = Instant::now() + self.wait_timeout;
+        for shard_idx in 0..self.shards.len() {
+            loop {
+                let unpinned = self.unpinned.notified();
+                tokio::pin!(unpinned);
+                unpinned.as_mut().enable();
+
+                let mut shard = self.lock_shard(shard_idx).await;
+                if shard.map.is_empty() {
+                    break;
+                }
+                match shard.choose_victim()? {
+                    Some(Victim::Evicted(evicted)) => self.record_eviction(evicted),
+                    Some(Victim::Dirty(page, frame)) => {
+                        drop(shard);
+                        self.write_back(shard_idx, page, frame).await?;
+                    }
+                    None => {
+                        drop(shard);
+                        self.wait_unpinned(unpinned, deadline).await.map_err(|_| {
+                            Error::new(
+                                FailedPrecondition,
+                                "Unable to flush buffers which are pinned!".to_string(),
+                            )
+                        })?;
+                    }
+                }
+            }
         }
         Ok(())
     }
@@ -0,0 +1,398 @@
+use crate::buffer_pool::{BufferPool, PinnedBuffer};
+use crate::error::{Error, ErrorKind::*};
+use crate::faulty_file::{Faults, FaultyFile};
+use crate::filelike::Filelike;
+use crate::options::{DatabaseOptions, ReplacementPolicy};
+use crate::protos::generated::chunk::*;
+use crate::protos::generated::config::*;
+use crate::protos::generated::operations::*;
+use crate::table::Table;
+use protobuf::text_format::parse_from_str;
+use std::io::Cursor;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+const NUM_ROWS: u32 = 5000;
+// Point lookups mostly read keys from this range, which spans fewer leaves than the pool holds.
+const HOT_KEYS: u32 = 300;
+const NUM_LOOKUPS: usize = 10000;
+
+async fn create_table(options: DatabaseOptions) -> Table<Cursor<Vec<u8>>> {
+    create_table_on(Cursor::<Vec<u8>>::new(Vec::new()), options).await
+}
+
+async fn create_table_on<F: Filelike>(file: F, options: DatabaseOptions) -> Table<F> {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let schema = parse_from_str::<TableSchema>(
+        "
+            key {
+                name: \"Key\"
+                column_type: INTEGER
+            }
+            ",
+    )
+    .unwrap();
+    let options = Arc::new(options);
+    Table::create(
+        file,
+        Arc::new(BufferPool::new(&options)),
+        options,
+        "TestTable".to_string(),
+        0,
+        schema,
+    )
+    .await
+    .unwrap()
+}
+
+async fn setup(policy: ReplacementPolicy) -> Table<Cursor<Vec<u8>>> {
+    setup_with(DatabaseOptions {
+        replacement_policy: policy,
+        ..Default::default()
+    })
+    .await
+}
+
+async fn setup_with(options: DatabaseOptions) -> Table<Cursor<Vec<u8>>> {
+    let table = create_table(DatabaseOptions {
+        buffer_pool_shard_count: 1,
+        buffer_pool_size: 32 * 4096,
+        ..options
+    })
+    .await;
+    for key in 0..NUM_ROWS {
+        let mut col = ValueProto::new();
+        col.set_int_value(key as i32);
+        let mut row = InternalRowProto::new();
+        // NOTE: padded, so that the table spans many more leaves than the pool holds.
+        row.col_values = vec![col; 20];
+        table.insert(key, row).await.unwrap();
+    }
+    table.buffer_pool.flush().await.unwrap();
+    table
+}
+
+// Returns a deterministic sequence of keys, mostly from the hot range.
+fn lookup_keys() -> Vec<u32> {
+    let mut state: u64 = 0x2545F4914F6CDD1D;
+    let mut next = || {
+        // xorshift64
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    (0..NUM_LOOKUPS)
+        .map(|_| match next() % 10 {
+            0 => (next() % NUM_ROWS as u64) as u32,
+            _ => (next() % HOT_KEYS as u64) as u32,
+        })
+        .collect()
+}
+
+// Runs point lookups, with a full table scan every scan_interval lookups (if any), and returns
+// the pool's hit rate for the lookups.
+async fn run_workload(
+    table: &Table<Cursor<Vec<u8>>>,
+    scan_interval: Option<usize>,
+) -> Result<f64, Error> {
+    let (mut hits, mut misses) = (0, 0);
+    for (i, key) in lookup_keys().into_iter().enumerate() {
+        if scan_interval.is_some_and(|interval| i % interval == 0) {
+            let mut cursor = table.read_range(0, u32::MAX);
+            while cursor.next_leaf().await?.is_some() {}
+        }
+        let start = table.buffer_pool.stats();
+        table.read_row(key).await?;
+        let end = table.buffer_pool.stats();
+        hits += end.hits - start.hits;
+        misses += end.misses - start.misses;
+    }
+    Ok(hits as f64 / (hits + misses) as f64)
+}
+
+// Compares the hit rates of each replacement policy on point lookups, with and without
+// concurrent scans.
+#[tokio::test]
+async fn replacement_policy_hit_rates() -> Result<(), Error> {
+    // NOTE: range cursors read each leaf twice (once more when moving on to the next leaf), so
+    // LRU-K needs K > 2 to tell scanned pages apart from hot ones.
+    let policies = [
+        ReplacementPolicy::Lru,
+        ReplacementPolicy::Clock,
+        ReplacementPolicy::LruK(3),
+        ReplacementPolicy::TwoQueue,
+    ];
+    let mut hit_rates = Vec::new();
+    for policy in policies {
+        let table = setup(policy).await;
+        let point_hit_rate = run_workload(&table, None).await?;
+        table.buffer_pool.flush().await?;
+        let scan_hit_rate = run_workload(&table, Some(500)).await?;
+        hit_rates.push((point_hit_rate, scan_hit_rate));
+    }
+
+    // scans evict the hot pages from LRU caches, but not scan resistant ones.
+    let [lru, clock, lru_k, two_queue] = hit_rates.try_into().unwrap();
+    assert!(lru.1 < lru.0);
+    assert!(clock.1 < clock.0);
+    assert!(lru_k.1 > lru.1);
+    assert!(lru_k.1 > clock.1);
+    assert!(two_queue.1 > lru.1);
+    assert!(two_queue.1 > clock.1);
+
+    Ok(())
+}
+
+// Scans the whole table, pausing at each leaf, and returns the pool's misses and prefetches.
+async fn scan_misses(read_ahead_window: usize) -> Result<(u64, u64), Error> {
+    let table = setup_with(DatabaseOptions {
+        read_ahead_window,
+        ..Default::default()
+    })
+    .await;
+    let start = table.buffer_pool.metrics();
+    let mut cursor = table.read_range(0, u32::MAX);
+    while cursor.next_leaf().await?.is_some() {
+        // NOTE: stands in for processing the leaf's rows, during which it is read ahead of.
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    let end = table.buffer_pool.metrics();
+    Ok((end.misses - start.misses, end.prefetches - start.prefetches))
+}
+
+// Compares the misses of a scan with and without read-ahead.
+#[tokio::test]
+async fn read_ahead_reduces_scan_misses() -> Result<(), Error> {
+    let (baseline_misses, baseline_prefetches) = scan_misses(0).await?;
+    assert_eq!(baseline_prefetches, 0);
+
+    let (small_misses, small_prefetches) = scan_misses(4).await?;
+    assert!(small_prefetches > 0);
+    assert!(small_misses < baseline_misses);
+    // NOTE: every leaf is either prefetched or missed.
+    assert!(small_misses + small_prefetches >= baseline_misses);
+
+    let (large_misses, large_prefetches) = scan_misses(16).await?;
+    assert!(large_prefetches > 0);
+    assert!(large_misses <= small_misses);
+
+    Ok(())
+}
+
+// A pool with room for POOL_PAGES pages, which waits briefly for pages to be unpinned.
+const POOL_PAGES: usize = 4;
+
+async fn setup_small_pool() -> Table<Cursor<Vec<u8>>> {
+    setup_small_pool_on(Cursor::<Vec<u8>>::new(Vec::new()), 2).await
+}
+
+async fn setup_small_pool_on<F: Filelike>(file: F, shard_count: usize) -> Table<F> {
+    let table = create_table_on(
+        file,
+        DatabaseOptions {
+            buffer_pool_shard_count: shard_count,
+            buffer_pool_size: POOL_PAGES * 4096,
+            buffer_pool_wait_timeout: Duration::from_millis(50),
+            ..Default::default()
+        },
+    )
+    .await;
+    table.buffer_pool.flush().await.unwrap();
+    table
+}
+
+// Creates a page recording its own offset.
+async fn new_page<F: Filelike>(table: &Table<F>) -> Result<PinnedBuffer<'_, F>, Error> {
+    let page = table.buffer_pool.new_next_for_table(table).await?;
+    let mut buffer = page.write().await;
+    let offset = buffer.offset;
+    buffer.get_mut().offset = offset;
+    drop(buffer);
+    Ok(page)
+}
+
+#[tokio::test]
+async fn pinned_buffers_are_not_evicted() -> Result<(), Error> {
+    let table = setup_small_pool().await;
+    let pool = &table.buffer_pool;
+
+    let pinned = new_page(&table).await?;
+    let pinned_offset = pinned.read().await.offset;
+    // cycle many more pages than the pool holds through the unpinned slots.
+    let mut offsets = Vec::new();
+    for _ in 0..(POOL_PAGES * 4) {
+        offsets.push(new_page(&table).await?.read().await.offset);
+    }
+    for offset in offsets {
+        pool.read_from_table(&table, offset).await?;
+    }
+
+    let misses = pool.stats().misses;
+    let reread = pool.read_from_table(&table, pinned_offset).await?;
+    assert_eq!(pool.stats().misses, misses);
+    assert_eq!(reread.read().await.get().offset, pinned_offset);
+    drop(pinned);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn exhausted_pool_fails() -> Result<(), Error> {
+    let table = setup_small_pool().await;
+    let pool = &table.buffer_pool;
+
+    let mut pinned = Vec::new();
+    for _ in 0..POOL_PAGES {
+        pinned.push(new_page(&table).await?);
+    }
+    let result = pool.new_next_for_table(&table).await;
+    assert_eq!(result.err().unwrap().kind, ResourceExhausted);
+    let result = pool.read_from_table(&table, table.root_chunk_offset).await;
+    assert_eq!(result.err().unwrap().kind, ResourceExhausted);
+    assert_eq!(pool.flush().await.unwrap_err().kind, FailedPrecondition);
+
+    // pages are available again once unpinned.
+    pinned.pop();
+    pool.read_from_table(&table, table.root_chunk_offset)
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn flush_waits_for_unpinned() -> Result<(), Error> {
+    let table = setup_small_pool().await;
+    let pool = &table.buffer_pool;
+
+    let pinned = new_page(&table).await?;
+    let (flushed, _) = tokio::join!(pool.flush(), async move {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        drop(pinned);
+    });
+    flushed?;
+    assert_eq!(pool.metrics().resident_pages, 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn exhausted_pool_waits_for_unpin() -> Result<(), Error> {
+    let table = setup_small_pool().await;
+    let pool = &table.buffer_pool;
+
+    let mut pinned = Vec::new();
+    for _ in 0..POOL_PAGES {
+        pinned.push(new_page(&table).await?);
+    }
+    let unpin = async {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        pinned.pop();
+    };
+    let (root, _) = tokio::join!(pool.read_from_table(&table, table.root_chunk_offset), unpin);
+    assert!(root?.read().await.get().has_internal());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn write_dirty_keeps_buffers_cached() -> Result<(), Error> {
+    let table = setup_small_pool().await;
+    let pool = &table.buffer_pool;
+
+    let mut offsets = Vec::new();
+    for _ in 0..POOL_PAGES {
+        offsets.push(new_page(&table).await?.read().await.offset);
+    }
+    // buffers locked for writing are skipped.
+    let locked = pool.read_from_table(&table, offsets[0]).await?;
+    let locked_buffer = locked.write().await;
+    assert_eq!(pool.trickle_dirty(2).await?, 2);
+    assert_eq!(pool.trickle_dirty(POOL_PAGES).await?, POOL_PAGES - 3);
+    drop(locked_buffer);
+    drop(locked);
+    assert_eq!(pool.write_all_dirty().await?, 1);
+    assert_eq!(pool.write_all_dirty().await?, 0);
+
+    let misses = pool.stats().misses;
+    for offset in offsets {
+        assert!(!pool
+            .read_from_table(&table, offset)
+            .await?
+            .read()
+            .await
+            .is_dirty());
+    }
+    assert_eq!(pool.stats().misses, misses);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn failed_eviction_keeps_dirty_buffer() -> Result<(), Error> {
+    let faults = Arc::new(Faults::default());
+    let file = FaultyFile {
+        faults: faults.clone(),
+        ..Default::default()
+    };
+    let table = setup_small_pool_on(file, 1).await;
+    let pool = &table.buffer_pool;
+
+    let mut offsets = Vec::new();
+    for _ in 0..POOL_PAGES {
+        offsets.push(new_page(&table).await?.read().await.offset);
+    }
+    let resident_bytes = pool.metrics().resident_bytes;
+    faults.fail_writes.store(true, Ordering::Release);
+    assert_eq!(new_page(&table).await.err().unwrap().kind, Internal);
+
+    // the buffer which couldn't be written stays cached, and dirty.
+    assert_eq!(pool.metrics().resident_bytes, resident_bytes);
+    let misses = pool.stats().misses;
+    for offset in &offsets {
+        let page = pool.read_from_table(&table, *offset).await?;
+        assert!(page.read().await.is_dirty());
+    }
+    assert_eq!(pool.stats().misses, misses);
+
+    faults.fail_writes.store(false, Ordering::Release);
+    pool.flush().await?;
+    assert_eq!(pool.metrics().resident_bytes, 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn eviction_writes_without_shard_locked() -> Result<(), Error> {
+    let faults = Arc::new(Faults::default());
+    let file = FaultyFile {
+        faults: faults.clone(),
+        ..Default::default()
+    };
+    let table = setup_small_pool_on(file, 1).await;
+    let pool = &table.buffer_pool;
+
+    let mut offsets = Vec::new();
+    for _ in 0..POOL_PAGES {
+        offsets.push(new_page(&table).await?.read().await.offset);
+    }
+    faults.block_writes.store(true, Ordering::Release);
+    // while a dirty buffer is written to make room, cached buffers of its shard can be read.
+    let read = async {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let read = tokio::time::timeout(
+            Duration::from_secs(1),
+            pool.read_from_table(&table, offsets[1]),
+        )
+        .await;
+        faults.unblock_writes();
+        read
+    };
+    let (page, read) = tokio::join!(new_page(&table), read);
+    page?;
+    assert!(read.is_ok(), "read waited on the write");
+    read.unwrap()?;
+
+    Ok(())
+}
@@ -1,7 +1,8 @@
 use crate::buffer::Buffer;
 use crate::error::*;
+use crate::options::DatabaseOptions;
 use crate::protos::generated::chunk::*;
-use crate::BUFFER_SIZE;
+use crate::protos::generated::config::*;
 use std::io::Cursor;
 use std::sync::Arc;
 use tokio::sync::Mutex;
@@ -10,12 +11,14 @@ type MetadataBuffer = Buffer<Cursor<Vec<u8>>, TableMetadataProto>;
 
 struct TestContext {
     file: Arc<Mutex<Cursor<Vec<u8>>>>,
+    config: TableConfig,
 }
 
 fn setup() -> TestContext {
     let _ = env_logger::builder().is_test(true).try_init();
     TestContext {
         file: Arc::new(Mutex::new(Cursor::<Vec<u8>>::new(Vec::new()))),
+        config: DatabaseOptions::default().table_config(),
     }
 }
 
@@ -25,11 +28,16 @@ async fn read_write_single_buffer() -> Result<(), Error> {
 
     let mut metadata = TableMetadataProto::new();
     metadata.next_chunk_offset = 1;
-    let buffer_out = MetadataBuffer::new_for_file(context.file.clone(), 0, metadata);
+    let buffer_out =
+        MetadataBuffer::new_for_file(context.file.clone(), &context.config, 0, metadata);
     buffer_out.write_to_file().await?;
-    assert_eq!(context.file.lock().await.get_ref().len(), BUFFER_SIZE);
+    assert_eq!(
+        context.file.lock().await.get_ref().len(),
+        context.config.chunk_size as usize
+    );
 
-    let buffer_in = MetadataBuffer::read_from_file(context.file.clone(), 0).await?;
+    let buffer_in =
+        MetadataBuffer::read_from_file(context.file.clone(), &context.config, 0).await?;
     assert_eq!(buffer_out.data, buffer_in.data);
 
     Ok(())
@@ -44,17 +52,19 @@ async fn read_write_many_buffers() -> Result<(), Error> {
     for i in 0..n {
         let mut metadata = TableMetadataProto::new();
         metadata.next_chunk_offset = i;
-        let buffer = MetadataBuffer::new_for_file(context.file.clone(), i, metadata);
+        let buffer =
+            MetadataBuffer::new_for_file(context.file.clone(), &context.config, i, metadata);
         buffer.write_to_file().await?;
         buffers.push(buffer);
     }
     assert_eq!(
         context.file.lock().await.get_ref().len(),
-        BUFFER_SIZE * (n as usize)
+        context.config.chunk_size as usize * (n as usize)
     );
 
     for i in 0..n {
-        let buffer = MetadataBuffer::read_from_file(context.file.clone(), i).await?;
+        let buffer =
+            MetadataBuffer::read_from_file(context.file.clone(), &context.config, i).await?;
         assert_eq!(buffer.data, buffers[i as usize].data);
     }
 
@@ -67,20 +77,26 @@ async fn overwrite_buffer() -> Result<(), Error> {
 
     let mut metadata_1 = TableMetadataProto::new();
     metadata_1.next_chunk_offset = 1;
-    let buffer_1 = MetadataBuffer::new_for_file(context.file.clone(), 0, metadata_1);
+    let buffer_1 =
+        MetadataBuffer::new_for_file(context.file.clone(), &context.config, 0, metadata_1);
     buffer_1.write_to_file().await?;
-    assert_eq!(context.file.lock().await.get_ref().len(), BUFFER_SIZE);
+    assert_eq!(
+        context.file.lock().await.get_ref().len(),
+        context.config.chunk_size as usize
+    );
 
     let mut metadata_2 = TableMetadataProto::new();
     metadata_2.next_chunk_offset = 2;
-    let buffer_2 = MetadataBuffer::new_for_file(context.file.clone(), 0, metadata_2);
+    let buffer_2 =
+        MetadataBuffer::new_for_file(context.file.clone(), &context.config, 0, metadata_2);
     buffer_2.write_to_file().await?;
     assert_eq!(
         context.file.lock().await.get_ref().len(),
-        BUFFER_SIZE as usize
+        context.config.chunk_size as usize
     );
 
-    let buffer_final = MetadataBuffer::read_from_file(context.file.clone(), 0).await?;
+    let buffer_final =
+        MetadataBuffer::read_from_file(context.file.clone(), &context.config, 0).await?;
     assert_eq!(buffer_final.data, buffer_2.data);
 
     Ok(())
@@ -90,7 +106,12 @@ async fn overwrite_buffer() -> Result<(), Error> {
 async fn would_buffer_overflow_false() -> Result<(), Error> {
     let context = setup();
 
-    let buffer = MetadataBuffer::new_for_file(context.file.clone(), 0, TableMetadataProto::new());
+    let buffer = MetadataBuffer::new_for_file(
+        context.file.clone(),
+        &context.config,
+        0,
+        TableMetadataProto::new(),
+    );
     assert!(!buffer.would_overflow(0));
     Ok(())
 }
@@ -99,7 +120,12 @@ async fn would_buffer_overflow_false() -> Result<(), Error> {
 async fn would_buffer_overflow_true() -> Result<(), Error> {
     let context = setup();
 
-    let buffer = MetadataBuffer::new_for_file(context.file.clone(), 0, TableMetadataProto::new());
-    assert!(buffer.would_overflow(BUFFER_SIZE));
+    let buffer = MetadataBuffer::new_for_file(
+        context.file.clone(),
+        &context.config,
+        0,
+        TableMetadataProto::new(),
+    );
+    assert!(buffer.would_overflow(context.config.chunk_size as usize));
     Ok(())
 }
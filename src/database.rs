@@ -2,22 +2,60 @@
 #[path = "./database_test.rs"]
 mod test;
 
+use crate::bp_tree::BulkLoader;
+use crate::buffer::Buffer;
 use crate::buffer_pool::BufferPool;
 use crate::error::{ErrorKind::*, *};
 use crate::filelike::Filelike;
+use crate::lock_manager::LockManager;
+use crate::metrics;
+use crate::mvcc::{self, TransactionManager};
+use crate::options::{self, DatabaseOptions};
+use crate::protos::generated::chunk::*;
 use crate::protos::generated::config::*;
 use crate::protos::generated::operations::*;
 use crate::query;
+use crate::query::reader::ResultsReader;
+use crate::query::sort::ExternalSorter;
+use crate::query::writer::ResultsWriter;
 use crate::schema;
+use crate::sql;
 use crate::table::Table;
+use crate::transaction::Transaction;
+use crate::wal::Wal;
+use protobuf::MessageField;
+use std::collections::HashSet;
+use std::future::Future;
+use std::iter;
 use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
 
 pub struct Database<F: Filelike> {
     pub(crate) table: Arc<Table<F>>,
     pub(crate) secondary_indexes: Vec<Arc<Table<F>>>,
+    pub(crate) wal: Arc<Wal<F>>,
+    pub(crate) transactions: Arc<TransactionManager>,
+    garbage_collector: JoinHandle<()>,
+    page_writer: JoinHandle<()>,
+    checkpointer: JoinHandle<()>,
 }
 
 impl<F: Filelike> Database<F> {
+    pub(crate) fn locks(&self) -> &LockManager {
+        &self.table.buffer_pool.locks
+    }
+
+    // Runs the given future with reads made from a snapshot of all committed transactions.
+    async fn read_scope<T>(&self, future: impl Future<Output = T>) -> T {
+        mvcc::with_snapshot(
+            self.transactions.snapshot(),
+            self.locks()
+                .scope(self.table.options.lock_wait_timeout, future),
+        )
+        .await
+    }
+
     pub(crate) fn find_table_keyed_on_column(
         &self,
         col_name: &str,
@@ -38,14 +76,21 @@ impl<F: Filelike> Database<F> {
 
     // TODO: validate dir doesn't exist, schema.
     // probably want to move validations to the db level instead of the index level.
-    pub async fn create(dir: &str, schema: DatabaseSchema) -> Result<Self, Error> {
-        let buffer_pool = Arc::new(BufferPool::new());
+    pub async fn create(
+        dir: &str,
+        schema: DatabaseSchema,
+        options: DatabaseOptions,
+    ) -> Result<Self, Error> {
+        options.validate()?;
+        let options = Arc::new(options);
+        let buffer_pool = Arc::new(BufferPool::new(&options));
 
         let mut next_table_id = 0;
         let table = Arc::new(
             Table::create(
                 F::create(format!("{}/{}", dir, "table").as_str()).await?,
                 buffer_pool.clone(),
+                options.clone(),
                 format!("Table{}", schema.table.key.name),
                 next_table_id,
                 schema.table.clone().unwrap(),
@@ -55,12 +100,13 @@ impl<F: Filelike> Database<F> {
         next_table_id += 1;
 
         let mut secondary_indexes = Vec::<Arc<Table<F>>>::new();
-        for secondary_index_schema in schema.secondary_indexes {
+        for secondary_index_schema in &schema.secondary_indexes {
             secondary_indexes.push(Arc::new(
                 Table::create(
                     F::create(format!("{}/{}", dir, &secondary_index_schema.key.name).as_str())
                         .await?,
                     buffer_pool.clone(),
+                    options.clone(),
                     format!(
                         "Table{}Index{}",
                         schema.table.key.name, secondary_index_schema.key.name
@@ -76,57 +122,560 @@ impl<F: Filelike> Database<F> {
             next_table_id += 1;
         }
 
-        Ok(Self {
-            table: table,
-            secondary_indexes: secondary_indexes,
-        })
+        // NOTE: the schema is recorded so that the database can be opened later, see open.
+        Buffer::new_for_file(
+            Arc::new(Mutex::new(
+                F::create(format!("{}/{}", dir, "schema").as_str()).await?,
+            )),
+            &table.config,
+            0,
+            schema,
+        )
+        .write_to_file()
+        .await?;
+
+        let wal = Wal::new(
+            F::create(format!("{}/{}", dir, "wal").as_str()).await?,
+            &table.config,
+        );
+        Ok(Self::new(table, secondary_indexes, wal, 1))
     }
 
-    // TODO: parallel insertion, failure recovery.
-    // can't spawn tasks as-is due to lifetime constraints, consider the
-    // actor paradigm https://ryhl.io/blog/actors-with-tokio/
-    pub async fn insert(&self, op: InsertProto) -> Result<(), Error> {
-        let table_key = schema::get_hashed_key_from_row(&op.row, &self.table.schema);
-        let table_row_internal = schema::row_to_internal_row(&op.row);
-        self.table.insert(table_key, table_row_internal).await?;
+    // Opens a database previously created in dir. Its tables keep the page size they were
+    // created with, other options may differ from those the database was created with.
+    // NOTE: transactions committed since the last checkpoint are replayed from the write-ahead
+    // log, so pages cached when the database was last used needn't have been flushed (e.g. after
+    // a crash), see recover.
+    pub async fn open(dir: &str, options: DatabaseOptions) -> Result<Self, Error> {
+        options.validate()?;
+        let options = Arc::new(options);
+        let buffer_pool = Arc::new(BufferPool::new(&options));
 
-        for secondary_index in &self.secondary_indexes {
-            let index_row = schema::table_row_to_index_row(
-                &op.row,
-                &secondary_index.schema,
-                &self.table.schema,
-            );
-            let index_key = schema::get_hashed_key_from_row(&index_row, &secondary_index.schema);
-            let index_row_internal = schema::row_to_internal_row(&index_row);
-            secondary_index
-                .insert(index_key, index_row_internal)
-                .await?;
+        let schema: Buffer<F, DatabaseSchema> = Buffer::read_from_file(
+            Arc::new(Mutex::new(
+                F::open(format!("{}/{}", dir, "schema").as_str()).await?,
+            )),
+            &options::max_table_config(),
+            0,
+        )
+        .await?;
+        let schema = schema.data;
+        if schema.table.is_none() {
+            return Err(Error::new(
+                DataLoss,
+                format!("Database schema in {} is missing or corrupt!", dir),
+            ));
+        }
+
+        let table = Arc::new(
+            Table::open(
+                F::open(format!("{}/{}", dir, "table").as_str()).await?,
+                buffer_pool.clone(),
+                options.clone(),
+            )
+            .await?,
+        );
+        let mut secondary_indexes = Vec::<Arc<Table<F>>>::new();
+        for secondary_index_schema in &schema.secondary_indexes {
+            secondary_indexes.push(Arc::new(
+                Table::open(
+                    F::open(format!("{}/{}", dir, secondary_index_schema.key.name).as_str())
+                        .await?,
+                    buffer_pool.clone(),
+                    options.clone(),
+                )
+                .await?,
+            ));
+        }
+
+        let (wal, logged) = Wal::open(
+            F::open(format!("{}/{}", dir, "wal").as_str()).await?,
+            &table.config,
+        )
+        .await?;
+        let tables: Vec<_> = iter::once(table.clone())
+            .chain(secondary_indexes.iter().cloned())
+            .collect();
+        // NOTE: before garbage is collected in the background, which would otherwise remove the
+        // versions of rows deleted by transactions which never committed.
+        let is_recovered = Self::recover(&tables, &wal, &logged.committed).await?;
+        let db = Self::new(table, secondary_indexes, wal, logged.next_txn_id);
+        if is_recovered {
+            db.checkpoint().await?;
+        }
+        Ok(db)
+    }
+
+    // Restores the tables to the changes of committed transactions, as of when the database was
+    // last used: the changes of transactions which never committed are undone (see
+    // mvcc::undo_uncommitted), then those of transactions committed since the last checkpoint
+    // are replayed. Returns whether the tables changed, and so should be checkpointed.
+    async fn recover(
+        tables: &[Arc<Table<F>>],
+        wal: &Wal<F>,
+        committed: &HashSet<u64>,
+    ) -> Result<bool, Error> {
+        let mut num_changed = 0;
+        for table in tables {
+            num_changed += mvcc::undo_uncommitted(table, committed).await?;
+        }
+        num_changed += Self::replay(tables, wal).await?;
+        if num_changed == 0 {
+            return Ok(false);
+        }
+        // NOTE: statistics may have been written with or without the recovered changes, so are
+        // rebuilt rather than relied on.
+        for table in tables {
+            table.analyze().await?;
         }
+        Ok(true)
+    }
+
+    // Reapplies the changes of transactions committed since the last checkpoint, in commit
+    // order, and returns how many there were. Changes already written to tables are skipped, so
+    // that replaying again (e.g. after a crash meanwhile) is harmless.
+    async fn replay(tables: &[Arc<Table<F>>], wal: &Wal<F>) -> Result<usize, Error> {
+        let committed = wal.read_committed().await?;
+        log::trace!("Replaying {} committed transactions.", committed.len());
+        let mut num_changes = 0;
+        for (txn_id, changes) in committed {
+            for change in changes {
+                let table = tables
+                    .iter()
+                    .find(|table| table.id == change.table_id)
+                    .ok_or_else(|| {
+                        Error::new(
+                            DataLoss,
+                            format!("Logged change to unknown table {}!", change.table_id),
+                        )
+                    })?;
+                Self::replay_change(table, txn_id, change).await?;
+                num_changes += 1;
+            }
+        }
+        Ok(num_changes)
+    }
+
+    async fn replay_change(
+        table: &Table<F>,
+        txn_id: u64,
+        change: RowChangeProto,
+    ) -> Result<(), Error> {
+        let row = change.row.unwrap_or_default();
+        if change.is_delete {
+            // NOTE: NotFound if already marked (or since removed by garbage collection).
+            return match table.mark_deleted(change.key, &row, txn_id).await {
+                Err(e) if e.kind == NotFound => Ok(()),
+                result => result,
+            };
+        }
+        let is_written = table
+            .read_versions(change.key)
+            .await?
+            .iter()
+            .any(|version| {
+                version.created_txn_id == row.created_txn_id && version.col_values == row.col_values
+            });
+        if is_written {
+            return Ok(());
+        }
+        table.insert_batch(&[(change.key, row)], &mut 0).await
+    }
+
+    fn new(
+        table: Arc<Table<F>>,
+        secondary_indexes: Vec<Arc<Table<F>>>,
+        wal: Wal<F>,
+        next_txn_id: u64,
+    ) -> Self {
+        let transactions = Arc::new(TransactionManager::new(next_txn_id));
+        let wal = Arc::new(wal);
+        let tables: Vec<_> = iter::once(table.clone())
+            .chain(secondary_indexes.iter().cloned())
+            .collect();
+        let garbage_collector = tokio::spawn(Self::collect_garbage_periodically(
+            transactions.clone(),
+            tables.clone(),
+            table.options.clone(),
+        ));
+        let page_writer = tokio::spawn(Self::write_dirty_pages_periodically(
+            table.buffer_pool.clone(),
+            table.options.clone(),
+        ));
+        let checkpointer = tokio::spawn(Self::checkpoint_periodically(
+            table.buffer_pool.clone(),
+            tables,
+            wal.clone(),
+            transactions.clone(),
+            table.options.clone(),
+        ));
+        Self {
+            table,
+            secondary_indexes,
+            wal,
+            transactions,
+            garbage_collector,
+            page_writer,
+            checkpointer,
+        }
+    }
+
+    // Writes all pages cached in memory to disk.
+    pub async fn flush(&self) -> Result<(), Error> {
+        self.table.buffer_pool.flush().await
+    }
+
+    // Writes all dirty pages (and table metadata, e.g. statistics) to disk, and records a
+    // checkpoint in the write-ahead log, so that recovery need only replay transactions
+    // committed since. NOTE: this also happens periodically in the background, see
+    // DatabaseOptions::checkpoint_interval.
+    pub async fn checkpoint(&self) -> Result<(), Error> {
+        let tables: Vec<_> = iter::once(self.table.clone())
+            .chain(self.secondary_indexes.iter().cloned())
+            .collect();
+        Self::checkpoint_with(
+            &self.table.buffer_pool,
+            &tables,
+            &self.wal,
+            &self.transactions,
+        )
+        .await
+    }
 
+    async fn checkpoint_with(
+        buffer_pool: &BufferPool<F>,
+        tables: &[Arc<Table<F>>],
+        wal: &Wal<F>,
+        transactions: &TransactionManager,
+    ) -> Result<(), Error> {
+        // NOTE: changes are applied to tables before transactions commit, so the changes of all
+        // transactions committed before redo_offset are written along with the dirty pages.
+        let redo_offset = wal.end_offset().await;
+        let num_written = buffer_pool.write_all_dirty().await?;
+        for table in tables {
+            table.commit_metadata().await?;
+        }
+        wal.checkpoint(redo_offset, transactions.next_txn_id())
+            .await?;
+        log::trace!(
+            "Checkpointed at {} after writing {} pages",
+            redo_offset,
+            num_written
+        );
         Ok(())
     }
 
-    pub async fn delete(&self, op: DeleteProto) -> Result<(), Error> {
-        let hashed_key = schema::get_hashed_col_value(&op.key.value);
-        let internal_row = self.table.delete(hashed_key).await?;
-        let row = schema::internal_row_to_row(&internal_row, &self.table.schema);
+    // Writes some dirty pages to disk every DatabaseOptions::dirty_page_write_interval.
+    async fn write_dirty_pages_periodically(
+        buffer_pool: Arc<BufferPool<F>>,
+        options: Arc<DatabaseOptions>,
+    ) {
+        let mut interval = tokio::time::interval(options.dirty_page_write_interval);
+        loop {
+            interval.tick().await;
+            if let Err(e) = buffer_pool
+                .trickle_dirty(options.dirty_page_write_batch)
+                .await
+            {
+                log::error!("Unable to write dirty pages: {}", e.msg);
+            }
+        }
+    }
+
+    // Checkpoints every DatabaseOptions::checkpoint_interval.
+    async fn checkpoint_periodically(
+        buffer_pool: Arc<BufferPool<F>>,
+        tables: Vec<Arc<Table<F>>>,
+        wal: Arc<Wal<F>>,
+        transactions: Arc<TransactionManager>,
+        options: Arc<DatabaseOptions>,
+    ) {
+        let mut interval = tokio::time::interval_at(
+            tokio::time::Instant::now() + options.checkpoint_interval,
+            options.checkpoint_interval,
+        );
+        loop {
+            interval.tick().await;
+            if let Err(e) = Self::checkpoint_with(&buffer_pool, &tables, &wal, &transactions).await
+            {
+                log::error!("Unable to checkpoint: {}", e.msg);
+            }
+        }
+    }
 
+    // Removes versions of rows which are no longer visible, every
+    // DatabaseOptions::garbage_collection_interval.
+    async fn collect_garbage_periodically(
+        transactions: Arc<TransactionManager>,
+        tables: Vec<Arc<Table<F>>>,
+        options: Arc<DatabaseOptions>,
+    ) {
+        let mut interval = tokio::time::interval(options.garbage_collection_interval);
+        loop {
+            interval.tick().await;
+            for table in &tables {
+                let result = table
+                    .buffer_pool
+                    .locks
+                    .scope(
+                        options.lock_wait_timeout,
+                        mvcc::collect_garbage(table, transactions.horizon()),
+                    )
+                    .await;
+                if let Err(e) = result {
+                    log::error!("Unable to collect garbage from {}: {}", table.name, e.msg);
+                }
+            }
+        }
+    }
+
+    // Removes versions of rows which are no longer visible to any snapshot, and returns how many
+    // were removed. NOTE: this also happens periodically in the background.
+    pub async fn collect_garbage(&self) -> Result<usize, Error> {
+        let mut num_removed =
+            mvcc::collect_garbage(&self.table, self.transactions.horizon()).await?;
+        for secondary_index in &self.secondary_indexes {
+            num_removed +=
+                mvcc::collect_garbage(secondary_index, self.transactions.horizon()).await?;
+        }
+        Ok(num_removed)
+    }
+
+    // Starts a transaction, see Transaction.
+    pub fn begin(&self) -> Transaction<'_, F> {
+        Transaction::new(self, self.transactions.begin())
+    }
+
+    // NOTE: single operations run in their own transaction, so that a failure partway through
+    // doesn't leave the table and its secondary indexes inconsistent. The table and each
+    // secondary index are updated concurrently, see Transaction::apply.
+    pub async fn insert(&self, op: InsertProto) -> Result<(), Error> {
+        let mut txn = self.begin();
+        let result = txn.insert(op).await;
+        txn.commit_if_ok(result).await
+    }
+
+    // Inserts the given rows in a single transaction, see Transaction::insert_batch.
+    pub async fn insert_batch(&self, ops: Vec<InsertProto>) -> Result<(), Error> {
+        let mut txn = self.begin();
+        let result = txn.insert_batch(ops).await;
+        txn.commit_if_ok(result).await
+    }
+
+    // Loads the given rows into the (empty) database, building its table and secondary indexes
+    // bottom-up (see bp_tree::BulkLoader), which is far faster than inserting them one at a
+    // time. Rows must have distinct keys and, unless sorted is set, are sorted by key first
+    // (spilling to disk as needed).
+    // NOTE: loaded rows aren't logged in the write-ahead log, instead the database is
+    // checkpointed once they're loaded. Nothing else should write to it in the meantime.
+    pub async fn bulk_load(
+        &self,
+        rows: impl IntoIterator<Item = RowProto>,
+        sorted: bool,
+    ) -> Result<(), Error> {
+        let table = &self.table;
+        let mut table_loader = BulkLoader::new(table, true).await?;
+        let mut table_sorter =
+            ExternalSorter::<F>::new(Vec::new(), &table.config, table.options.sort_run_size);
+        let mut index_loaders = Vec::new();
+        let mut index_sorters = Vec::new();
         for secondary_index in &self.secondary_indexes {
-            let index_row =
-                schema::table_row_to_index_row(&row, &secondary_index.schema, &self.table.schema);
-            let index_key = schema::get_hashed_key_from_row(&index_row, &secondary_index.schema);
-            secondary_index.delete(index_key).await?;
+            index_loaders.push(BulkLoader::new(secondary_index, false).await?);
+            index_sorters.push(ExternalSorter::<F>::new(
+                Vec::new(),
+                &table.config,
+                table.options.sort_run_size,
+            ));
         }
 
+        for row in rows {
+            for (secondary_index, sorter) in self.secondary_indexes.iter().zip(&mut index_sorters) {
+                let index_row =
+                    schema::table_row_to_index_row(&row, &secondary_index.schema, &table.schema);
+                let index_key =
+                    schema::get_hashed_key_from_row(&index_row, &secondary_index.schema);
+                sorter
+                    .push(index_key, index_row, InternalRowProto::new())
+                    .await?;
+            }
+            let key = schema::get_hashed_key_from_row(&row, &table.schema);
+            match sorted {
+                true => {
+                    table_loader
+                        .push_row(key, schema::row_to_internal_row(&row))
+                        .await?
+                }
+                false => table_sorter.push(key, row, InternalRowProto::new()).await?,
+            }
+        }
+
+        if !sorted {
+            Self::load_sorted(table_sorter, &mut table_loader, &table.config).await?;
+        }
+        table_loader.finish().await?;
+        for (sorter, mut loader) in index_sorters.into_iter().zip(index_loaders) {
+            Self::load_sorted(sorter, &mut loader, &table.config).await?;
+            loader.finish().await?;
+        }
+        self.checkpoint().await
+    }
+
+    // Pushes the rows of the given sorter (sorted by key) to the given loader.
+    async fn load_sorted(
+        sorter: ExternalSorter<F>,
+        loader: &mut BulkLoader<'_, F>,
+        config: &TableConfig,
+    ) -> Result<(), Error> {
+        let mut sorted = ResultsWriter::new(F::temp().await?, config);
+        sorter.finish(&mut sorted, None).await?;
+        let mut reader = ResultsReader::new(sorted.finish().await?, config);
+        while let Some((key, row)) = reader.next_key_row().await? {
+            loader
+                .push_row(key, schema::row_to_internal_row(&row.unwrap()))
+                .await?;
+        }
         Ok(())
     }
 
+    pub async fn delete(&self, op: DeleteProto) -> Result<(), Error> {
+        let mut txn = self.begin();
+        let result = txn.delete(op).await;
+        txn.commit_if_ok(result).await
+    }
+
+    // Replaces the given columns of the row with the given key.
+    pub async fn update(&self, op: UpdateProto) -> Result<(), Error> {
+        let mut txn = self.begin();
+        let result = txn.update(op).await;
+        txn.commit_if_ok(result).await
+    }
+
     pub async fn read_row(&self, op: ReadRowProto) -> Result<RowProto, Error> {
         let hashed_key = schema::get_hashed_col_value(&op.key.value);
-        self.table.read_row(hashed_key).await
+        self.read_scope(self.table.read_row(hashed_key)).await
     }
 
+    // Returns the rows with the given keys, in the same order, or None for keys without one.
+    pub async fn read_rows(&self, op: ReadRowsProto) -> Result<Vec<Option<RowProto>>, Error> {
+        let hashed_keys: Vec<u32> = op
+            .keys
+            .iter()
+            .map(|key| schema::get_hashed_col_value(&key.value))
+            .collect();
+        self.read_scope(self.table.read_rows(&hashed_keys)).await
+    }
+
+    // NOTE: reads outside of transactions see the changes of all transactions committed before
+    // they started, and none after.
     pub async fn query(&self, op: QueryProto) -> Result<F, Error> {
-        query::execute_query::<F>(self, op).await
+        self.read_scope(query::execute_query::<F>(self, op)).await
+    }
+
+    // Returns a single page of results, and a token to request the next page with (if there
+    // may be one).
+    pub async fn query_page(
+        &self,
+        op: LimitProto,
+    ) -> Result<(F, Option<ContinuationTokenProto>), Error> {
+        self.read_scope(query::execute_page::<F>(self, op)).await
+    }
+
+    // Describes how the query would be executed, along with the number of rows each stage is
+    // estimated to produce.
+    pub async fn explain(&self, op: QueryProto) -> Result<QueryPlanProto, Error> {
+        query::explain::<F>(self, op).await
+    }
+
+    // Executes the query, describing how each stage was executed: the rows it produced, pages it
+    // read from the buffer pool and how long it took.
+    pub async fn explain_analyze(&self, op: QueryProto) -> Result<QueryPlanProto, Error> {
+        self.read_scope(query::explain_analyze::<F>(self, op)).await
+    }
+
+    // Rewrites the pages of the table and all secondary indexes still in the legacy (protobuf)
+    // format, e.g. written by earlier versions, in the current one (see bp_tree/page.rs), then
+    // checkpoints. Legacy pages are otherwise only rewritten once read.
+    pub async fn migrate(&self) -> Result<(), Error> {
+        self.table.migrate().await?;
+        for secondary_index in &self.secondary_indexes {
+            secondary_index.migrate().await?;
+        }
+        self.checkpoint().await
+    }
+
+    // Rebuilds the statistics of the table and all secondary indexes from their contents.
+    pub async fn analyze(&self) -> Result<(), Error> {
+        self.read_scope(async {
+            self.table.analyze().await?;
+            for secondary_index in &self.secondary_indexes {
+                secondary_index.analyze().await?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    pub fn statistics(&self) -> DatabaseStatisticsProto {
+        let mut statistics = DatabaseStatisticsProto::new();
+        statistics.table = MessageField::some(self.table.statistics());
+        statistics.secondary_indexes = self
+            .secondary_indexes
+            .iter()
+            .map(|secondary_index| secondary_index.statistics())
+            .collect();
+        statistics
+    }
+
+    // Returns a snapshot of the database's buffer pool and storage metrics.
+    pub fn metrics(&self) -> DatabaseMetricsProto {
+        let mut metrics = DatabaseMetricsProto::new();
+        metrics.buffer_pool = MessageField::some(self.table.buffer_pool.metrics());
+        metrics.table = MessageField::some(self.table.counters.metrics(&self.table.name));
+        metrics.secondary_indexes = self
+            .secondary_indexes
+            .iter()
+            .map(|secondary_index| secondary_index.counters.metrics(&secondary_index.name))
+            .collect();
+        metrics
+    }
+
+    // Returns the database's metrics in the Prometheus text format, e.g. to be served for
+    // scraping.
+    pub fn metrics_prometheus(&self) -> String {
+        metrics::to_prometheus(&self.metrics())
+    }
+
+    // Executes a parsed SQL statement, see sql::parse.
+    // NOTE: a database only holds the table (and secondary indexes) it was created with, so
+    // CREATE TABLE and CREATE INDEX are unimplemented.
+    pub async fn execute(&self, statement: sql::Statement) -> Result<sql::Output<F>, Error> {
+        match statement {
+            sql::Statement::Query(query) => Ok(sql::Output::Rows(self.query(query).await?)),
+            sql::Statement::Explain(query) => Ok(sql::Output::Plan(self.explain(query).await?)),
+            sql::Statement::ExplainAnalyze(query) => {
+                Ok(sql::Output::Plan(self.explain_analyze(query).await?))
+            }
+            sql::Statement::Insert(insert) => self.insert(insert).await.map(|_| sql::Output::Done),
+            sql::Statement::Delete(delete) => self.delete(delete).await.map(|_| sql::Output::Done),
+            sql::Statement::Update(update) => self.update(update).await.map(|_| sql::Output::Done),
+            sql::Statement::Analyze => self.analyze().await.map(|_| sql::Output::Done),
+            sql::Statement::CreateTable(_) | sql::Statement::CreateIndex(_) => Err(Error::new(
+                Unimplemented,
+                "Tables and indexes can only be created with the database!".to_string(),
+            )),
+        }
+    }
+
+    // Returns the physical query the planner would run for the given predicate.
+    pub fn plan(&self, op: PredicateProto) -> Result<QueryProto, Error> {
+        query::plan_predicate(self, op)
+    }
+}
+
+impl<F: Filelike> Drop for Database<F> {
+    fn drop(&mut self) {
+        self.garbage_collector.abort();
+        self.page_writer.abort();
+        self.checkpointer.abort();
     }
 }
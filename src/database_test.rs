@@ -1,14 +1,18 @@
 use crate::buffer::Buffer;
 use crate::database::Database;
 use crate::error::{Error, ErrorKind::*};
+use crate::options::{DatabaseOptions, ReadStrategy, WriteStrategy};
 use crate::protos::generated::chunk::*;
 use crate::protos::generated::config::*;
 use crate::protos::generated::operations::*;
 use crate::schema;
+use crate::sql;
 use protobuf::text_format::parse_from_str;
 use protobuf::MessageField;
 use std::io::Cursor;
 use std::sync::Arc;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio::sync::Mutex;
 
 type QueryResultsBuffer = Buffer<Cursor<Vec<u8>>, InternalQueryResultsProto>;
@@ -17,9 +21,8 @@ struct TestContext {
     db: Arc<Database<Cursor<Vec<u8>>>>,
 }
 
-async fn setup() -> TestContext {
-    let _ = env_logger::builder().is_test(true).try_init();
-    let schema = parse_from_str::<DatabaseSchema>(
+fn test_schema() -> DatabaseSchema {
+    parse_from_str::<DatabaseSchema>(
         "
             table {
                 key {
@@ -39,9 +42,56 @@ async fn setup() -> TestContext {
             }
             ",
     )
-    .unwrap();
+    .unwrap()
+}
+
+async fn setup() -> TestContext {
+    let _ = env_logger::builder().is_test(true).try_init();
     TestContext {
-        db: Arc::new(Database::create("", schema).await.unwrap()),
+        db: Arc::new(
+            Database::create("", test_schema(), DatabaseOptions::default())
+                .await
+                .unwrap(),
+        ),
+    }
+}
+
+// Reads all chunks of the given query results into a single proto.
+async fn read_query_results(file: Cursor<Vec<u8>>) -> Result<InternalQueryResultsProto, Error> {
+    let file = Arc::new(Mutex::new(file));
+    let mut query_results = InternalQueryResultsProto::new();
+    for offset in 0.. {
+        let buffer = QueryResultsBuffer::read_from_file(
+            file.clone(),
+            &DatabaseOptions::default().table_config(),
+            offset,
+        )
+        .await?;
+        if buffer.data.keys.is_empty() {
+            break;
+        }
+        query_results.keys.extend(buffer.data.keys);
+        query_results.rows.extend(buffer.data.rows);
+    }
+    Ok(query_results)
+}
+
+async fn insert_rows(db: &Database<Cursor<Vec<u8>>>, num_rows: i32, value_fn: fn(i32) -> i32) {
+    for i in 0..num_rows {
+        let mut key = ColumnProto::new();
+        key.name = "Key".to_string();
+        key.value.mut_or_insert_default().set_int_value(i);
+        let mut val = ColumnProto::new();
+        val.name = "Value".to_string();
+        val.value.mut_or_insert_default().set_int_value(value_fn(i));
+
+        let mut row = RowProto::new();
+        row.columns.push(key);
+        row.columns.push(val);
+
+        let mut insert_operation = InsertProto::new();
+        insert_operation.row = MessageField::some(row);
+        db.insert(insert_operation).await.unwrap();
     }
 }
 
@@ -177,8 +227,12 @@ async fn query_success() -> Result<(), Error> {
     )
     .unwrap();
     let query_results_file = db.query(query_operation).await?;
-    let query_results =
-        QueryResultsBuffer::read_from_file(Arc::new(Mutex::new(query_results_file)), 0).await?;
+    let query_results = QueryResultsBuffer::read_from_file(
+        Arc::new(Mutex::new(query_results_file)),
+        &DatabaseOptions::default().table_config(),
+        0,
+    )
+    .await?;
 
     let expected_query_results = parse_from_str::<InternalQueryResultsProto>(
         "
@@ -206,88 +260,59 @@ async fn query_success() -> Result<(), Error> {
 }
 
 #[tokio::test]
-async fn async_read_write_success() -> Result<(), Error> {
+async fn query_select_columns_success() -> Result<(), Error> {
     let ctx = setup().await;
     let db = ctx.db;
-    let num_iter = 100;
 
-    let mut task_set = tokio::task::JoinSet::new();
-    for i in 0..num_iter {
-        let db = db.clone();
-        task_set.spawn(async move {
-            let insert_operation = parse_from_str::<InsertProto>(
-                format!(
-                    "
-                row {{
-                    columns {{
-                        name: \"Key\"
-                        value {{
-                            int_value: {i}
-                        }}
-                    }}
-                    columns {{
-                        name: \"Value\"
-                        value {{
-                            int_value: {i}
-                        }}
-                    }}
-                }}
-                ",
-                )
-                .as_str(),
-            )
-            .unwrap();
-            db.insert(insert_operation.clone()).await.unwrap();
-        });
-    }
+    insert_rows(&db, 50, |i| i * 10).await;
 
-    for i in 0..num_iter {
-        let db = db.clone();
-        tokio::spawn(async move {
-            let read_operation = parse_from_str::<ReadRowProto>(
-                format!(
-                    "
-                key {{
-                    name: \"Key\"
-                    value {{
-                        int_value: {i}
-                    }}
-                }}
-                ",
-                )
-                .as_str(),
-            )
-            .unwrap();
-            let expected_row = parse_from_str::<RowProto>(
-                format!(
-                    "
-                    columns {{
+    let query_operation = parse_from_str::<QueryProto>(
+        "
+        select {
+            dep {
+                filter {
+                    equals {
                         name: \"Key\"
-                        value {{
-                            int_value: {i}
-                        }}
-                    }}
-                    columns {{
-                        name: \"Value\"
-                        value {{
-                            int_value: {i}
-                        }}
-                    }}
-                ",
-                )
-                .as_str(),
-            )
-            .unwrap();
-            let row = db.read_row(read_operation).await.unwrap();
-            assert_eq!(expected_row, row);
-        });
-    }
+                        value {
+                            int_value: 25
+                        }
+                    }
+                }
+            }
+            columns: \"Value\"
+        }
+        ",
+    )
+    .unwrap();
+    let query_results_file = db.query(query_operation).await?;
+    let query_results = QueryResultsBuffer::read_from_file(
+        Arc::new(Mutex::new(query_results_file)),
+        &DatabaseOptions::default().table_config(),
+        0,
+    )
+    .await?;
+
+    let expected_query_results = parse_from_str::<InternalQueryResultsProto>(
+        "
+        keys: 25
+        rows {
+            columns {
+                name: \"Value\"
+                value {
+                    int_value: 250
+                }
+            }
+        }
+        ",
+    )
+    .unwrap();
+    assert_eq!(query_results.data, expected_query_results);
 
     Ok(())
 }
 
 #[tokio::test]
-async fn delete_single_success() -> Result<(), Error> {
+async fn query_select_columns_index_only_success() -> Result<(), Error> {
     let ctx = setup().await;
     let db = ctx.db;
 
@@ -310,26 +335,2633 @@ async fn delete_single_success() -> Result<(), Error> {
         ",
     )
     .unwrap();
-    db.insert(insert_operation.clone()).await?;
+    db.insert(insert_operation).await?;
 
-    let delete_operation = parse_from_str::<DeleteProto>(
+    // remove the row from the main table only; an index-only scan never reads it.
+    db.table.delete(1).await?;
+
+    let query_operation = parse_from_str::<QueryProto>(
         "
-        key {
-        name: \"Key\"
-        value {
-        int_value: 1
+        select {
+            dep {
+                filter {
+                    equals {
+                        name: \"Value\"
+                        value {
+                            int_value: 2
+                        }
+                    }
+                }
+            }
+            columns: \"Key\"
+            columns: \"Value\"
         }
+        ",
+    )
+    .unwrap();
+    let query_results_file = db.query(query_operation).await?;
+    let query_results = QueryResultsBuffer::read_from_file(
+        Arc::new(Mutex::new(query_results_file)),
+        &DatabaseOptions::default().table_config(),
+        0,
+    )
+    .await?;
+
+    let expected_query_results = parse_from_str::<InternalQueryResultsProto>(
+        "
+        keys: 1
+        rows {
+            columns {
+                name: \"Key\"
+                value {
+                    int_value: 1
+                }
+            }
+            columns {
+                name: \"Value\"
+                value {
+                    int_value: 2
+                }
+            }
         }
         ",
     )
     .unwrap();
-    db.delete(delete_operation.clone()).await?;
+    assert_eq!(query_results.data, expected_query_results);
 
-    let table_row_internal = db.table.read_row(1).await;
-    assert_eq!(table_row_internal.unwrap_err().kind, NotFound);
+    Ok(())
+}
 
-    let index_row_internal = db.secondary_indexes[0].read_row(2).await;
-    assert_eq!(index_row_internal.unwrap_err().kind, NotFound);
+#[tokio::test]
+async fn query_select_unknown_column_fails() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+
+    let query_operation = parse_from_str::<QueryProto>(
+        "
+        select {
+            dep {
+                filter {
+                    equals {
+                        name: \"Key\"
+                        value {
+                            int_value: 1
+                        }
+                    }
+                }
+            }
+            columns: \"Missing\"
+        }
+        ",
+    )
+    .unwrap();
+    let query_results = db.query(query_operation).await;
+    assert_eq!(query_results.unwrap_err().kind, InvalidArgument);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn query_filter_in_range_success() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    insert_rows(&db, 100, |i| i * 10).await;
+
+    let query_operation = parse_from_str::<QueryProto>(
+        "
+        filter {
+            in_range {
+                name: \"Key\"
+                lower_value {
+                    int_value: 10
+                }
+                upper_value {
+                    int_value: 19
+                }
+            }
+        }
+        ",
+    )
+    .unwrap();
+    let query_results = read_query_results(db.query(query_operation).await?).await?;
+    assert_eq!(query_results.keys, (10..20).collect::<Vec<u32>>());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn query_filter_in_range_secondary_index_success() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    // NOTE: enough rows to spill sorted runs when re-sorting by primary key.
+    let num_rows = 1500;
+    insert_rows(&db, num_rows, |i| (i * 7) % 1500).await;
+
+    let query_operation = parse_from_str::<QueryProto>(
+        "
+        filter {
+            in_range {
+                name: \"Value\"
+                lower_value {
+                    int_value: 0
+                }
+                upper_value {
+                    int_value: 1499
+                }
+            }
+        }
+        ",
+    )
+    .unwrap();
+    let query_results = read_query_results(db.query(query_operation).await?).await?;
+    assert_eq!(
+        query_results.keys,
+        (0..num_rows as u32).collect::<Vec<u32>>()
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn query_order_by_success() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    let num_rows = 1500;
+    insert_rows(&db, num_rows, |i| (i * 7) % 1500).await;
+
+    let query_operation = parse_from_str::<QueryProto>(
+        "
+        order_by {
+            dep {
+                select {
+                    dep {
+                        filter {
+                            in_range {
+                                name: \"Key\"
+                                lower_value {
+                                    int_value: 0
+                                }
+                                upper_value {
+                                    int_value: 1499
+                                }
+                            }
+                        }
+                    }
+                    columns: \"Key\"
+                }
+            }
+            columns {
+                name: \"Value\"
+                descending: true
+            }
+        }
+        ",
+    )
+    .unwrap();
+    let query_results = read_query_results(db.query(query_operation).await?).await?;
+    assert_eq!(query_results.keys.len(), num_rows as usize);
+    assert_eq!(query_results.rows.len(), num_rows as usize);
+    for (i, (key, row)) in query_results
+        .keys
+        .iter()
+        .zip(query_results.rows.iter())
+        .enumerate()
+    {
+        let expected_value = num_rows - 1 - i as i32;
+        assert_eq!((*key as i32 * 7) % 1500, expected_value);
+        // the sort column was projected out by the select stage.
+        assert_eq!(row.columns.len(), 1);
+        assert_eq!(row.columns[0].name, "Key");
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn query_limit_offset_success() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    insert_rows(&db, 100, |i| i * 10).await;
+
+    let query_operation = parse_from_str::<QueryProto>(
+        "
+        limit {
+            dep {
+                filter {
+                    in_range {
+                        name: \"Key\"
+                        lower_value {
+                            int_value: 0
+                        }
+                        upper_value {
+                            int_value: 99
+                        }
+                    }
+                }
+            }
+            limit: 10
+            offset: 5
+        }
+        ",
+    )
+    .unwrap();
+    let query_results = read_query_results(db.query(query_operation).await?).await?;
+    assert_eq!(query_results.keys, (5..15).collect::<Vec<u32>>());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn query_limit_short_circuits_select() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    insert_rows(&db, 100, |i| i * 10).await;
+
+    // remove a row from the main table only; select would fail if it were to read it.
+    db.table.delete(50).await?;
+
+    let query_operation = parse_from_str::<QueryProto>(
+        "
+        limit {
+            dep {
+                select {
+                    dep {
+                        filter {
+                            in_range {
+                                name: \"Value\"
+                                lower_value {
+                                    int_value: 0
+                                }
+                                upper_value {
+                                    int_value: 990
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            limit: 10
+        }
+        ",
+    )
+    .unwrap();
+    let query_results = read_query_results(db.query(query_operation).await?).await?;
+    assert_eq!(query_results.keys, (0..10).collect::<Vec<u32>>());
+    assert_eq!(query_results.rows.len(), 10);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn query_page_continuation_success() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    insert_rows(&db, 25, |i| i * 10).await;
+
+    let mut page_operation = parse_from_str::<LimitProto>(
+        "
+        dep {
+            filter {
+                in_range {
+                    name: \"Value\"
+                    lower_value {
+                        int_value: 0
+                    }
+                    upper_value {
+                        int_value: 1000
+                    }
+                }
+            }
+        }
+        limit: 10
+        ",
+    )
+    .unwrap();
+
+    let mut keys = Vec::new();
+    let mut page_sizes = Vec::new();
+    loop {
+        let (page, continuation_token) = db.query_page(page_operation.clone()).await?;
+        let page = read_query_results(page).await?;
+        page_sizes.push(page.keys.len());
+        keys.extend(page.keys);
+        match continuation_token {
+            Some(token) => page_operation.continuation_token = MessageField::some(token),
+            None => break,
+        }
+    }
+    assert_eq!(page_sizes, vec![10, 10, 5]);
+    assert_eq!(keys, (0..25).collect::<Vec<u32>>());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn query_page_unsorted_continuation_fails() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+
+    let page_operation = parse_from_str::<LimitProto>(
+        "
+        dep {
+            order_by {
+                dep {
+                    filter {
+                        equals {
+                            name: \"Key\"
+                            value {
+                                int_value: 1
+                            }
+                        }
+                    }
+                }
+                columns {
+                    name: \"Value\"
+                }
+            }
+        }
+        limit: 10
+        continuation_token {
+            last_key: 1
+        }
+        ",
+    )
+    .unwrap();
+    let page = db.query_page(page_operation).await;
+    assert_eq!(page.unwrap_err().kind, InvalidArgument);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn query_aggregate_success() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    insert_rows(&db, 100, |i| i * 10).await;
+
+    let query_operation = parse_from_str::<QueryProto>(
+        "
+        aggregate {
+            dep {
+                filter {
+                    in_range {
+                        name: \"Key\"
+                        lower_value {
+                            int_value: 10
+                        }
+                        upper_value {
+                            int_value: 19
+                        }
+                    }
+                }
+            }
+            aggregates {
+                function: COUNT
+            }
+            aggregates {
+                function: SUM
+                name: \"Value\"
+            }
+            aggregates {
+                function: MIN
+                name: \"Value\"
+            }
+            aggregates {
+                function: MAX
+                name: \"Value\"
+            }
+            aggregates {
+                function: AVG
+                name: \"Value\"
+            }
+        }
+        ",
+    )
+    .unwrap();
+    let query_results = read_query_results(db.query(query_operation).await?).await?;
+
+    let expected_query_results = parse_from_str::<InternalQueryResultsProto>(
+        "
+        keys: 0
+        rows {
+            columns {
+                name: \"COUNT(*)\"
+                value {
+                    long_value: 10
+                }
+            }
+            columns {
+                name: \"SUM(Value)\"
+                value {
+                    long_value: 1450
+                }
+            }
+            columns {
+                name: \"MIN(Value)\"
+                value {
+                    int_value: 100
+                }
+            }
+            columns {
+                name: \"MAX(Value)\"
+                value {
+                    int_value: 190
+                }
+            }
+            columns {
+                name: \"AVG(Value)\"
+                value {
+                    double_value: 145
+                }
+            }
+        }
+        ",
+    )
+    .unwrap();
+    assert_eq!(query_results, expected_query_results);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn query_aggregate_count_index_range_uses_keys_only() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    insert_rows(&db, 100, |i| i * 10).await;
+
+    // remove a row from the main table only; counting must not read it.
+    db.table.delete(5).await?;
+
+    let query_operation = parse_from_str::<QueryProto>(
+        "
+        aggregate {
+            dep {
+                filter {
+                    in_range {
+                        name: \"Value\"
+                        lower_value {
+                            int_value: 0
+                        }
+                        upper_value {
+                            int_value: 495
+                        }
+                    }
+                }
+            }
+            aggregates {
+                function: COUNT
+            }
+        }
+        ",
+    )
+    .unwrap();
+    let query_results = read_query_results(db.query(query_operation).await?).await?;
+    assert_eq!(query_results.rows[0].columns[0].value.long_value(), 50);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn query_aggregate_missing_column_fails() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+
+    let query_operation = parse_from_str::<QueryProto>(
+        "
+        aggregate {
+            dep {
+                filter {
+                    equals {
+                        name: \"Key\"
+                        value {
+                            int_value: 1
+                        }
+                    }
+                }
+            }
+            aggregates {
+                function: SUM
+            }
+        }
+        ",
+    )
+    .unwrap();
+    let query_results = db.query(query_operation).await;
+    assert_eq!(query_results.unwrap_err().kind, InvalidArgument);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn query_group_by_success() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    insert_rows(&db, 100, |i| i % 10).await;
+
+    let query_operation = parse_from_str::<QueryProto>(
+        "
+        group_by {
+            dep {
+                filter {
+                    in_range {
+                        name: \"Key\"
+                        lower_value {
+                            int_value: 0
+                        }
+                        upper_value {
+                            int_value: 99
+                        }
+                    }
+                }
+            }
+            columns: \"Value\"
+            aggregates {
+                function: COUNT
+            }
+            aggregates {
+                function: SUM
+                name: \"Key\"
+            }
+        }
+        ",
+    )
+    .unwrap();
+    let query_results = read_query_results(db.query(query_operation).await?).await?;
+
+    let mut groups: Vec<(i32, i64, i64)> = query_results
+        .rows
+        .iter()
+        .map(|row| {
+            (
+                row.columns[0].value.int_value(),
+                row.columns[1].value.long_value(),
+                row.columns[2].value.long_value(),
+            )
+        })
+        .collect();
+    groups.sort();
+    let expected_groups: Vec<(i32, i64, i64)> =
+        (0..10).map(|i| (i, 10, 10 * i as i64 + 450)).collect();
+    assert_eq!(groups, expected_groups);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn query_group_by_spill_success() -> Result<(), Error> {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let options = DatabaseOptions {
+        group_by_memory_budget: 16 * 1024,
+        ..Default::default()
+    };
+    let db = Database::create("", test_schema(), options).await?;
+    // NOTE: more groups than can be held in memory at once.
+    let num_rows = 1500;
+    insert_rows(&db, num_rows, |i| (i * 7) % 1500).await;
+
+    let query_operation = parse_from_str::<QueryProto>(
+        "
+        group_by {
+            dep {
+                filter {
+                    in_range {
+                        name: \"Key\"
+                        lower_value {
+                            int_value: 0
+                        }
+                        upper_value {
+                            int_value: 1499
+                        }
+                    }
+                }
+            }
+            columns: \"Value\"
+            aggregates {
+                function: COUNT
+            }
+        }
+        ",
+    )
+    .unwrap();
+    let query_results = read_query_results(db.query(query_operation).await?).await?;
+    assert_eq!(
+        query_results.keys,
+        (0..num_rows as u32).collect::<Vec<u32>>()
+    );
+
+    let mut values: Vec<i32> = query_results
+        .rows
+        .iter()
+        .map(|row| {
+            assert_eq!(row.columns[1].value.long_value(), 1);
+            row.columns[0].value.int_value()
+        })
+        .collect();
+    values.sort();
+    assert_eq!(values, (0..num_rows).collect::<Vec<i32>>());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn query_group_by_sorted_input_success() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    insert_rows(&db, 100, |i| i % 10).await;
+
+    let query_operation = parse_from_str::<QueryProto>(
+        "
+        group_by {
+            dep {
+                order_by {
+                    dep {
+                        filter {
+                            in_range {
+                                name: \"Key\"
+                                lower_value {
+                                    int_value: 0
+                                }
+                                upper_value {
+                                    int_value: 99
+                                }
+                            }
+                        }
+                    }
+                    columns {
+                        name: \"Value\"
+                        descending: true
+                    }
+                }
+            }
+            columns: \"Value\"
+            aggregates {
+                function: COUNT
+            }
+        }
+        ",
+    )
+    .unwrap();
+    let query_results = read_query_results(db.query(query_operation).await?).await?;
+
+    // NOTE: groups are produced in the order of their input, rather than hashed.
+    let groups: Vec<(i32, i64)> = query_results
+        .rows
+        .iter()
+        .map(|row| {
+            (
+                row.columns[0].value.int_value(),
+                row.columns[1].value.long_value(),
+            )
+        })
+        .collect();
+    let expected_groups: Vec<(i32, i64)> = (0..10).rev().map(|i| (i, 10)).collect();
+    assert_eq!(groups, expected_groups);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn query_group_by_sorted_index_success() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    insert_rows(&db, 100, |i| i % 10).await;
+
+    let query_operation = parse_from_str::<QueryProto>(
+        "
+        group_by {
+            dep {
+                filter {
+                    in_range {
+                        name: \"Value\"
+                        lower_value {
+                            int_value: 2
+                        }
+                        upper_value {
+                            int_value: 5
+                        }
+                    }
+                }
+            }
+            columns: \"Value\"
+            aggregates {
+                function: COUNT
+            }
+            aggregates {
+                function: SUM
+                name: \"Key\"
+            }
+        }
+        ",
+    )
+    .unwrap();
+    let query_results = read_query_results(db.query(query_operation).await?).await?;
+
+    // NOTE: results are produced in index order.
+    let groups: Vec<(i32, i64, i64)> = query_results
+        .rows
+        .iter()
+        .map(|row| {
+            (
+                row.columns[0].value.int_value(),
+                row.columns[1].value.long_value(),
+                row.columns[2].value.long_value(),
+            )
+        })
+        .collect();
+    let expected_groups: Vec<(i32, i64, i64)> =
+        (2..6).map(|i| (i, 10, 10 * i as i64 + 450)).collect();
+    assert_eq!(groups, expected_groups);
+
+    Ok(())
+}
+
+// Returns (lhs.Key, rhs.Key) pairs of the given join results, sorted.
+fn joined_keys(query_results: &InternalQueryResultsProto) -> Vec<(i32, Option<i32>)> {
+    let mut keys: Vec<(i32, Option<i32>)> = query_results
+        .rows
+        .iter()
+        .map(|row| {
+            let lhs_key = schema::get_col(row, "lhs.Key").value.int_value();
+            let rhs_key = schema::find_col(row, "rhs.Key").map(|col| col.value.int_value());
+            (lhs_key, rhs_key)
+        })
+        .collect();
+    keys.sort();
+    keys
+}
+
+#[tokio::test]
+async fn query_join_index_nested_loop_success() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    insert_rows(&db, 20, |i| i % 10).await;
+
+    let query_operation = parse_from_str::<QueryProto>(
+        "
+        join {
+            lhs {
+                filter {
+                    in_range {
+                        name: \"Key\"
+                        lower_value {
+                            int_value: 0
+                        }
+                        upper_value {
+                            int_value: 4
+                        }
+                    }
+                }
+            }
+            lhs_column: \"Key\"
+            rhs_column: \"Value\"
+        }
+        ",
+    )
+    .unwrap();
+    let query_results = read_query_results(db.query(query_operation.clone()).await?).await?;
+
+    let mut expected_keys: Vec<(i32, Option<i32>)> = (0..5)
+        .flat_map(|i| [(i, Some(i)), (i, Some(i + 10))])
+        .collect();
+    expected_keys.sort();
+    assert_eq!(joined_keys(&query_results), expected_keys);
+    assert_eq!(query_results.rows[0].columns.len(), 4);
+
+    // index matches outside of an explicit rhs are skipped.
+    let mut query_operation = query_operation;
+    query_operation.mut_join().rhs = MessageField::some(
+        parse_from_str::<QueryProto>(
+            "
+            predicate {
+                filter {
+                    in_range {
+                        name: \"Key\"
+                        lower_value {
+                            int_value: 0
+                        }
+                        upper_value {
+                            int_value: 9
+                        }
+                    }
+                }
+            }
+            ",
+        )
+        .unwrap(),
+    );
+    let plan = db.explain(query_operation.clone()).await?;
+    assert_eq!(
+        plan.detail,
+        "inner index nested loop on lhs.Key = rhs.Value where rhs Key in [0, 9]"
+    );
+    assert_eq!(plan.table, db.secondary_indexes[0].name);
+    assert_eq!(plan.deps.len(), 1);
+    let query_results = read_query_results(db.query(query_operation).await?).await?;
+    assert_eq!(
+        joined_keys(&query_results),
+        (0..5).map(|i| (i, Some(i))).collect::<Vec<_>>()
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn query_join_sort_merge_success() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    insert_rows(&db, 20, |i| i % 10).await;
+
+    let query_operation = parse_from_str::<QueryProto>(
+        "
+        join {
+            lhs {
+                filter {
+                    in_range {
+                        name: \"Key\"
+                        lower_value {
+                            int_value: 0
+                        }
+                        upper_value {
+                            int_value: 4
+                        }
+                    }
+                }
+            }
+            rhs {
+                select {
+                    dep {
+                        filter {
+                            in_range {
+                                name: \"Key\"
+                                lower_value {
+                                    int_value: 0
+                                }
+                                upper_value {
+                                    int_value: 19
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            lhs_column: \"Key\"
+            rhs_column: \"Value\"
+        }
+        ",
+    )
+    .unwrap();
+    // NOTE: the rhs isn't a filter or predicate, so can't be checked against index matches.
+    let plan = db.explain(query_operation.clone()).await?;
+    assert!(plan.detail.starts_with("inner sort merge"));
+    let query_results = read_query_results(db.query(query_operation).await?).await?;
+
+    let mut expected_keys: Vec<(i32, Option<i32>)> = (0..5)
+        .flat_map(|i| [(i, Some(i)), (i, Some(i + 10))])
+        .collect();
+    expected_keys.sort();
+    assert_eq!(joined_keys(&query_results), expected_keys);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn query_join_left_outer_success() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    insert_rows(&db, 20, |i| i % 10).await;
+
+    let mut query_operation = parse_from_str::<QueryProto>(
+        "
+        join {
+            lhs {
+                filter {
+                    in_range {
+                        name: \"Key\"
+                        lower_value {
+                            int_value: 8
+                        }
+                        upper_value {
+                            int_value: 12
+                        }
+                    }
+                }
+            }
+            lhs_column: \"Key\"
+            rhs_column: \"Value\"
+            join_type: LEFT_OUTER
+        }
+        ",
+    )
+    .unwrap();
+    let expected_keys = vec![
+        (8, Some(8)),
+        (8, Some(18)),
+        (9, Some(9)),
+        (9, Some(19)),
+        (10, None),
+        (11, None),
+        (12, None),
+    ];
+
+    // index nested loop join.
+    let query_results = read_query_results(db.query(query_operation.clone()).await?).await?;
+    assert_eq!(joined_keys(&query_results), expected_keys);
+
+    // sort merge join.
+    query_operation.mut_join().rhs = MessageField::some(
+        parse_from_str::<QueryProto>(
+            "
+            select {
+                dep {
+                    filter {
+                        in_range {
+                            name: \"Key\"
+                            lower_value {
+                                int_value: 0
+                            }
+                            upper_value {
+                                int_value: 19
+                            }
+                        }
+                    }
+                }
+            }
+            ",
+        )
+        .unwrap(),
+    );
+    let query_results = read_query_results(db.query(query_operation).await?).await?;
+    assert_eq!(joined_keys(&query_results), expected_keys);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn update_success() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    insert_rows(&db, 3, |i| i).await;
+
+    let update_operation = parse_from_str::<UpdateProto>(
+        "
+        key {
+            name: \"Key\"
+            value {
+                int_value: 1
+            }
+        }
+        columns {
+            name: \"Value\"
+            value {
+                int_value: 7
+            }
+        }
+        ",
+    )
+    .unwrap();
+    db.update(update_operation).await?;
+    db.collect_garbage().await?;
+
+    let row = db.table.read_row(1).await?;
+    assert_eq!(schema::get_col_value_as_i64(&row.columns[1].value), 7);
+    // the old index entry is removed
+    assert_eq!(
+        db.secondary_indexes[0].read_row(1).await.unwrap_err().kind,
+        NotFound
+    );
+    db.secondary_indexes[0].read_row(7).await?;
+
+    let mut update_key = parse_from_str::<UpdateProto>(
+        "
+        key {
+            name: \"Key\"
+            value {
+                int_value: 1
+            }
+        }
+        ",
+    )
+    .unwrap();
+    update_key.columns.push(update_key.key.clone().unwrap());
+    assert_eq!(
+        db.update(update_key).await.unwrap_err().kind,
+        InvalidArgument
+    );
+
+    Ok(())
+}
+
+fn key_column(key: i32) -> ColumnProto {
+    let mut column = ColumnProto::new();
+    column.name = "Key".to_string();
+    column.value.mut_or_insert_default().set_int_value(key);
+    column
+}
+
+fn insert_row_operation(key: i32, value: i32) -> InsertProto {
+    let mut val = ColumnProto::new();
+    val.name = "Value".to_string();
+    val.value.mut_or_insert_default().set_int_value(value);
+    let mut insert_operation = InsertProto::new();
+    insert_operation.row.mut_or_insert_default().columns = vec![key_column(key), val];
+    insert_operation
+}
+
+fn delete_row_operation(key: i32) -> DeleteProto {
+    let mut delete_operation = DeleteProto::new();
+    delete_operation.key = MessageField::some(key_column(key));
+    delete_operation
+}
+
+fn read_row_operation(key: i32) -> ReadRowProto {
+    let mut read_operation = ReadRowProto::new();
+    read_operation.key = MessageField::some(key_column(key));
+    read_operation
+}
+
+#[tokio::test]
+async fn transaction_commit_success() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    insert_rows(&db, 3, |i| i).await;
+
+    let mut txn = db.begin();
+    txn.insert(insert_row_operation(10, 20)).await?;
+    txn.delete(delete_row_operation(0)).await?;
+    // the transaction observes its own changes
+    txn.read_row(read_row_operation(10)).await?;
+    txn.commit().await?;
+    db.collect_garbage().await?;
+
+    db.table.read_row(10).await?;
+    db.secondary_indexes[0].read_row(20).await?;
+    assert_eq!(db.table.read_row(0).await.unwrap_err().kind, NotFound);
+    assert_eq!(
+        db.secondary_indexes[0].read_row(0).await.unwrap_err().kind,
+        NotFound
+    );
+
+    // 3 auto-committed inserts, then the transaction
+    let committed = db.wal.read_committed().await?;
+    assert_eq!(committed.len(), 4);
+    let (_, changes) = committed.last().unwrap();
+    let change_kinds: Vec<(u32, bool)> = changes
+        .iter()
+        .map(|change| (change.table_id, change.is_delete))
+        .collect();
+    let table_id = db.table.id;
+    let index_id = db.secondary_indexes[0].id;
+    assert_eq!(
+        change_kinds,
+        vec![
+            (table_id, false),
+            (index_id, false),
+            (table_id, true),
+            (index_id, true)
+        ]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn transaction_rollback_success() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    insert_rows(&db, 3, |i| i % 2).await;
+
+    let mut txn = db.begin();
+    txn.insert(insert_row_operation(10, 1)).await?;
+    txn.delete(delete_row_operation(1)).await?;
+    txn.rollback().await?;
+
+    assert_eq!(db.table.read_row(10).await.unwrap_err().kind, NotFound);
+    let row = db.table.read_row(1).await?;
+    assert_eq!(schema::get_col_value_as_i64(&row.columns[1].value), 1);
+    // only the index entry of the restored row remains
+    let mut cursor = db.secondary_indexes[0].read_range(1, 1);
+    let mut num_index_rows = 0;
+    while let Some((keys, _)) = cursor.next_leaf().await? {
+        num_index_rows += keys.len();
+    }
+    assert_eq!(num_index_rows, 1);
+    assert_eq!(db.wal.read_committed().await?.len(), 3);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn transaction_failed_operation_rolls_back() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    insert_rows(&db, 3, |i| i).await;
+
+    // the main table insert succeeds, but the update of a missing row fails
+    let mut txn = db.begin();
+    txn.insert(insert_row_operation(10, 20)).await?;
+    let mut update_operation = UpdateProto::new();
+    update_operation.key = MessageField::some(key_column(5));
+    let result = txn.update(update_operation).await;
+    assert_eq!(txn.commit_if_ok(result).await.unwrap_err().kind, NotFound);
+    assert_eq!(db.table.read_row(10).await.unwrap_err().kind, NotFound);
+    assert_eq!(
+        db.secondary_indexes[0].read_row(20).await.unwrap_err().kind,
+        NotFound
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn transaction_failed_operation_undone() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    insert_rows(&db, 3, |i| i).await;
+    // the table's row can be marked deleted, but not its index entry.
+    db.secondary_indexes[0].delete(1).await?;
+
+    let mut txn = db.begin();
+    txn.insert(insert_row_operation(10, 20)).await?;
+    assert_eq!(
+        txn.delete(delete_row_operation(1)).await.unwrap_err().kind,
+        Aborted
+    );
+    // only the failed operation is undone, the rest of the transaction may still commit.
+    txn.read_row(read_row_operation(1)).await?;
+    txn.commit().await?;
+
+    db.read_row(read_row_operation(1)).await?;
+    db.read_row(read_row_operation(10)).await?;
+    let committed = db.wal.read_committed().await?;
+    assert_eq!(committed.last().unwrap().1.len(), 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn transaction_snapshot_isolation_success() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    insert_rows(&db, 3, |i| i).await;
+
+    let reader = db.begin();
+    db.insert(insert_row_operation(10, 20)).await?;
+    let mut writer = db.begin();
+    writer.delete(delete_row_operation(0)).await?;
+
+    // uncommitted changes aren't visible, even to later readers
+    db.read_row(read_row_operation(0)).await?;
+    assert_eq!(
+        db.read_row(read_row_operation(10)).await?,
+        insert_row_operation(10, 20).row.unwrap()
+    );
+    writer.commit().await?;
+    assert_eq!(
+        db.read_row(read_row_operation(0)).await.unwrap_err().kind,
+        NotFound
+    );
+
+    // changes committed after the reader began aren't visible to it
+    reader.read_row(read_row_operation(0)).await?;
+    assert_eq!(
+        reader
+            .read_row(read_row_operation(10))
+            .await
+            .unwrap_err()
+            .kind,
+        NotFound
+    );
+    let query = parse_statement_query("SELECT * FROM t WHERE Value BETWEEN 0 AND 100");
+    let query_results = read_query_results(reader.query(query.clone()).await?).await?;
+    assert_eq!(query_results.keys, vec![0, 1, 2]);
+    let query_results = read_query_results(db.query(query).await?).await?;
+    assert_eq!(query_results.keys, vec![1, 2, 10]);
+    reader.commit().await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn transaction_write_conflict_fails() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    insert_rows(&db, 3, |i| i).await;
+
+    let mut txn = db.begin();
+    txn.delete(delete_row_operation(1)).await?;
+    txn.insert(insert_row_operation(10, 20)).await?;
+    assert_eq!(
+        db.delete(delete_row_operation(1)).await.unwrap_err().kind,
+        Aborted
+    );
+    assert_eq!(
+        db.insert(insert_row_operation(10, 20))
+            .await
+            .unwrap_err()
+            .kind,
+        Aborted
+    );
+    txn.commit().await?;
+
+    assert_eq!(
+        db.insert(insert_row_operation(10, 20))
+            .await
+            .unwrap_err()
+            .kind,
+        AlreadyExists
+    );
+    // deleted keys may be reused
+    db.insert(insert_row_operation(1, 5)).await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn collect_garbage_keeps_visible_versions() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    insert_rows(&db, 3, |i| i).await;
+
+    let reader = db.begin();
+    db.delete(delete_row_operation(0)).await?;
+    assert_eq!(db.collect_garbage().await?, 0);
+    reader.read_row(read_row_operation(0)).await?;
+
+    reader.commit().await?;
+    // the row and its index entry
+    assert_eq!(db.collect_garbage().await?, 2);
+    assert_eq!(db.table.read_versions(0).await?.len(), 0);
+    assert_eq!(db.statistics().table.row_count, 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn dropped_transaction_rolled_back_success() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    insert_rows(&db, 3, |i| i).await;
+
+    {
+        let mut txn = db.begin();
+        txn.insert(insert_row_operation(3, 3)).await?;
+        txn.delete(delete_row_operation(1)).await?;
+        // e.g. returned early, without commit or rollback.
+    }
+    db.delete(delete_row_operation(0)).await?;
+    // garbage is collected once the dropped transaction is rolled back, rather than never.
+    // NOTE: possibly by the background garbage collector.
+    for _ in 0..100 {
+        db.collect_garbage().await?;
+        if db.table.read_versions(0).await?.is_empty() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    assert_eq!(db.table.read_versions(0).await?.len(), 0);
+    assert_eq!(db.table.read_versions(3).await?.len(), 0);
+    db.read_row(read_row_operation(1)).await?;
+    db.delete(delete_row_operation(1)).await?;
+    assert_eq!(db.statistics().table.row_count, 1);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_insert_same_key_fails() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    let num_keys = 200;
+
+    let mut task_set = tokio::task::JoinSet::new();
+    for i in 0..num_keys {
+        for value in 0..2 {
+            let db = db.clone();
+            task_set.spawn(async move {
+                let mut txn = db.begin();
+                let result = txn.insert(insert_row_operation(i, value)).await;
+                tokio::task::yield_now().await;
+                txn.commit_if_ok(result).await
+            });
+        }
+    }
+    let mut num_committed = 0;
+    while let Some(result) = task_set.join_next().await {
+        match result.unwrap() {
+            Ok(()) => num_committed += 1,
+            Err(err) => assert!(
+                matches!(err.kind, Aborted | AlreadyExists),
+                "unexpected error {err:?}"
+            ),
+        }
+    }
+
+    // exactly one insert of each key commits.
+    assert_eq!(num_committed, num_keys);
+    for i in 0..num_keys {
+        assert_eq!(db.table.read_versions(i as u32).await?.len(), 1);
+    }
+    assert_eq!(db.statistics().table.row_count, num_keys as u64);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn query_sql_success() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    insert_rows(&db, 20, |i| i % 5).await;
+
+    let query_operation = match sql::parse(
+        "SELECT Key FROM t WHERE Value = 1 OR Key BETWEEN 10 AND 12 ORDER BY Key DESC LIMIT 4",
+    )? {
+        sql::Statement::Query(query) => query,
+        _ => panic!("expected query"),
+    };
+    let query_results = read_query_results(db.query(query_operation).await?).await?;
+
+    assert_eq!(query_results.keys, vec![16, 12, 11, 10]);
+    assert!(query_results.rows.iter().all(|row| row.columns.len() == 1));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn query_sql_negative_ranges_success() -> Result<(), Error> {
+    let mut unindexed_schema = test_schema();
+    unindexed_schema.secondary_indexes.clear();
+    for schema in [test_schema(), unindexed_schema] {
+        let db = Database::create("", schema, DatabaseOptions::default()).await?;
+        insert_rows(&db, 20, |i| i - 10).await;
+
+        let query = |sql: &str| {
+            let statement = sql::parse(sql);
+            let db = &db;
+            async move {
+                let sql::Output::Rows(results) = db.execute(statement?).await? else {
+                    panic!("expected rows");
+                };
+                Ok::<_, Error>(read_query_results(results).await?.keys)
+            }
+        };
+        // NOTE: negative values hash above all non-negative ones.
+        assert_eq!(
+            query("SELECT * FROM t WHERE Value < 0").await?,
+            (0..10).collect::<Vec<u32>>()
+        );
+        assert_eq!(
+            query("SELECT * FROM t WHERE Value BETWEEN -5 AND 5").await?,
+            (5..16).collect::<Vec<u32>>()
+        );
+        assert_eq!(
+            query("SELECT * FROM t WHERE Value > 5").await?,
+            (16..20).collect::<Vec<u32>>()
+        );
+        assert_eq!(
+            query("SELECT * FROM t WHERE Value >= -2 AND Value < 1 OR Value <= -10").await?,
+            vec![0, 8, 9, 10]
+        );
+        assert!(query("SELECT * FROM t WHERE Key < 0").await?.is_empty());
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn execute_sql_success() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+
+    for sql in [
+        "INSERT INTO t (Key, Value) VALUES (1, 2)",
+        "INSERT INTO t (Key, Value) VALUES (2, 3)",
+        "UPDATE t SET Value = 4 WHERE Key = 2",
+        "DELETE FROM t WHERE Key = 1",
+        "ANALYZE t",
+    ] {
+        assert!(matches!(
+            db.execute(sql::parse(sql)?).await?,
+            sql::Output::Done
+        ));
+    }
+    assert_eq!(db.statistics().table.row_count, 1);
+
+    let sql::Output::Rows(results) = db.execute(sql::parse("SELECT * FROM t")?).await? else {
+        panic!("expected rows");
+    };
+    let results = read_query_results(results).await?;
+    assert_eq!(results.keys, vec![2]);
+    assert_eq!(results.rows[0].columns[1].value.int_value(), 4);
+
+    let sql::Output::Plan(plan) = db
+        .execute(sql::parse("EXPLAIN SELECT * FROM t WHERE Value > 3")?)
+        .await?
+    else {
+        panic!("expected plan");
+    };
+    assert_eq!(plan.deps[0].detail, "Value in [4, inf]");
+
+    for sql in [
+        "CREATE TABLE u (Key INTEGER PRIMARY KEY)",
+        "CREATE INDEX ON t (Value)",
+    ] {
+        let err = db.execute(sql::parse(sql)?).await.unwrap_err();
+        assert_eq!(err.kind, Unimplemented);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn query_row_missing_column_fails() -> Result<(), Error> {
+    // NOTE: without a secondary index, which would be keyed on the missing column.
+    let mut schema = test_schema();
+    schema.secondary_indexes.clear();
+    let db = Database::<Cursor<Vec<u8>>>::create("", schema, DatabaseOptions::default()).await?;
+    let insert_operation =
+        parse_from_str::<InsertProto>("row { columns { name: \"Key\" value { int_value: 1 } } }")
+            .unwrap();
+    db.insert(insert_operation).await?;
+
+    for sql in [
+        "SELECT Value FROM t WHERE Key = 1",
+        "SELECT * FROM t WHERE Key = 1 ORDER BY Value",
+    ] {
+        let sql::Statement::Query(query) = sql::parse(sql)? else {
+            panic!("expected query");
+        };
+        let err = db.query(query).await.unwrap_err();
+        assert_eq!(err.kind, DataLoss);
+    }
+
+    Ok(())
+}
+
+// Extracts the predicate from a parsed `SELECT * FROM t WHERE ...` statement.
+fn parse_predicate(where_clause: &str) -> PredicateProto {
+    match sql::parse(&format!("SELECT * FROM t WHERE {}", where_clause)).unwrap() {
+        sql::Statement::Query(query) => query.select().dep.predicate().clone(),
+        _ => panic!("expected query"),
+    }
+}
+
+#[tokio::test]
+async fn plan_predicate_success() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    insert_rows(&db, 20, |i| i % 5).await;
+
+    // primary key equality is a point lookup, secondary index equality a range.
+    let plan = db.plan(parse_predicate("Key = 1 OR Value = 2"))?;
+    let expected = parse_from_str::<QueryProto>(
+        "
+        union {
+            lhs {
+                filter {
+                    equals {
+                        name: \"Key\"
+                        value { int_value: 1 }
+                    }
+                }
+            }
+            rhs {
+                filter {
+                    in_range {
+                        name: \"Value\"
+                        lower_value { int_value: 2 }
+                        upper_value { int_value: 2 }
+                    }
+                }
+            }
+        }
+        ",
+    )
+    .unwrap();
+    assert_eq!(plan, expected);
+
+    // ranges on the same column are merged, and the most selective input comes first.
+    let plan = db.plan(parse_predicate("Key >= 5 AND Key <= 100 AND Value = 2"))?;
+    let expected = parse_from_str::<QueryProto>(
+        "
+        intersect {
+            lhs {
+                filter {
+                    in_range {
+                        name: \"Value\"
+                        lower_value { int_value: 2 }
+                        upper_value { int_value: 2 }
+                    }
+                }
+            }
+            rhs {
+                filter {
+                    in_range {
+                        name: \"Key\"
+                        lower_value { uint_value: 5 }
+                        upper_value { uint_value: 100 }
+                    }
+                }
+            }
+        }
+        ",
+    )
+    .unwrap();
+    assert_eq!(plan, expected);
+
+    let err = db.plan(parse_predicate("Missing = 1")).unwrap_err();
+    assert_eq!(err.kind, InvalidArgument);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn query_predicate_unindexed_success() -> Result<(), Error> {
+    let schema = parse_from_str::<DatabaseSchema>(
+        "
+        table {
+            key {
+                name: \"Key\"
+                column_type: INTEGER
+            }
+            columns {
+                name: \"Value\"
+                column_type: INTEGER
+            }
+            columns {
+                name: \"Other\"
+                column_type: INTEGER
+            }
+        }
+        secondary_indexes {
+            key {
+                name: \"Value\"
+                column_type: INTEGER
+            }
+        }
+        ",
+    )
+    .unwrap();
+    let db = Database::create("", schema, DatabaseOptions::default()).await?;
+    for i in 0..20 {
+        let insert = match sql::parse(&format!(
+            "INSERT INTO t (Key, Value, Other) VALUES ({}, {}, {})",
+            i,
+            i,
+            i % 3
+        ))? {
+            sql::Statement::Insert(insert) => insert,
+            _ => panic!("expected insert"),
+        };
+        db.insert(insert).await?;
+    }
+
+    // the unindexed filter is evaluated against the rows of the indexed range.
+    let predicate = parse_predicate("Value BETWEEN 5 AND 10 AND Other = 1");
+    let plan = db.plan(predicate.clone())?;
+    assert!(plan.scan().dep.has_filter());
+    assert!(plan.scan().predicate.is_some());
+    let mut query = QueryProto::new();
+    query.set_predicate(predicate);
+    let query_results = read_query_results(db.query(query).await?).await?;
+    assert_eq!(query_results.keys, vec![7, 10]);
+
+    // a disjunction with an unindexed filter needs a full scan.
+    let predicate = parse_predicate("Other = 2 OR Key = 0");
+    let plan = db.plan(predicate.clone())?;
+    assert!(plan.scan().dep.is_none());
+    let mut query = QueryProto::new();
+    query.set_predicate(predicate);
+    let query_results = read_query_results(db.query(query).await?).await?;
+    assert_eq!(query_results.keys, vec![0, 2, 5, 8, 11, 14, 17]);
+
+    Ok(())
+}
+
+fn parse_statement_query(sql: &str) -> QueryProto {
+    match sql::parse(sql).unwrap() {
+        sql::Statement::Query(query) => query,
+        _ => panic!("expected query"),
+    }
+}
+
+#[tokio::test]
+async fn explain_success() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    insert_rows(&db, 20, |i| i % 5).await;
+
+    let plan = db
+        .explain(parse_statement_query(
+            "SELECT Value FROM t WHERE Key < 10 AND Value = 1 LIMIT 1",
+        ))
+        .await?;
+    assert_eq!(plan.stage, "Limit");
+    assert_eq!(plan.estimated_rows, 1);
+    let select = &plan.deps[0];
+    assert_eq!(
+        (select.stage.as_str(), select.detail.as_str()),
+        ("Select", "Value")
+    );
+    let predicate = &select.deps[0];
+    assert_eq!(predicate.detail, "Key in [-inf, 9] AND Value = 1");
+
+    // the more selective index filter comes first.
+    let intersect = &predicate.deps[0];
+    assert_eq!(intersect.stage, "Intersect");
+    let filters: Vec<(&str, &str, u64)> = intersect
+        .deps
+        .iter()
+        .map(|dep| (dep.detail.as_str(), dep.table.as_str(), dep.estimated_rows))
+        .collect();
+    assert_eq!(
+        filters,
+        vec![
+            ("Value in [1, 1]", db.secondary_indexes[0].name.as_str(), 4),
+            ("", "", 10),
+        ]
+    );
+    assert_eq!(intersect.estimated_rows, 2);
+    // negative keys hash above all non-negative ones, so are read from a second range.
+    let union = &intersect.deps[1];
+    assert_eq!(union.stage, "Union");
+    let filters: Vec<(&str, u64)> = union
+        .deps
+        .iter()
+        .map(|dep| (dep.detail.as_str(), dep.estimated_rows))
+        .collect();
+    assert_eq!(
+        filters,
+        vec![
+            ("Key in [0, 9]", 10),
+            ("Key in [2147483648, 4294967295]", 0)
+        ]
+    );
+    assert!(plan.stats.is_none());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn explain_analyze_success() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    insert_rows(&db, 20, |i| i % 5).await;
+
+    let plan = db
+        .explain_analyze(parse_statement_query(
+            "SELECT * FROM t WHERE Key >= 5 ORDER BY Value DESC",
+        ))
+        .await?;
+    let stages: Vec<(&str, u64)> = std::iter::successors(Some(&plan), |plan| plan.deps.first())
+        .map(|plan| (plan.stage.as_str(), plan.stats.rows))
+        .collect();
+    assert_eq!(
+        stages,
+        vec![
+            ("OrderBy", 15),
+            ("Select", 15),
+            ("Predicate", 15),
+            ("Filter", 15)
+        ]
+    );
+
+    let filter = &plan.deps[0].deps[0].deps[0];
+    assert_eq!(filter.estimated_rows, 15);
+    assert!(filter.stats.pages_read > 0);
+    assert_eq!(
+        filter.stats.pages_read,
+        filter.stats.cache_hits + filter.stats.cache_misses
+    );
+    // stats include those of dependencies.
+    assert!(plan.stats.pages_read >= filter.stats.pages_read);
+    assert!(plan.stats.wall_time_micros >= filter.stats.wall_time_micros);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn statistics_success() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    // skewed values: half of the rows have the value 0.
+    insert_rows(&db, 1000, |i| if i % 2 == 0 { 0 } else { i }).await;
+
+    // maintained on every write.
+    let statistics = db.statistics();
+    assert_eq!(statistics.table.row_count, 1000);
+    assert_eq!(statistics.table.min_key, 0);
+    assert_eq!(statistics.table.max_key, 999);
+    assert!(statistics.table.height >= 2);
+    assert!(statistics.table.histogram_bounds.is_empty());
+    assert_eq!(statistics.secondary_indexes[0].row_count, 1000);
+
+    db.analyze().await?;
+    let statistics = db.statistics();
+    let table = &statistics.table;
+    assert_eq!(table.distinct_count, 1000);
+    assert_eq!(table.modified_count, 0);
+    assert_eq!(
+        table.histogram_bounds.len(),
+        db.table.options.statistics_histogram_buckets
+    );
+    assert_eq!(*table.histogram_bounds.last().unwrap(), 999);
+    assert!(table.histogram_bounds.is_sorted());
+    let index = &statistics.secondary_indexes[0];
+    assert_eq!(index.distinct_count, 501);
+    // the histogram reflects the skew.
+    let num_zero_bounds = index.histogram_bounds.iter().filter(|b| **b == 0).count();
+    assert_eq!(index.row_count, 1000);
+    assert_eq!(
+        num_zero_bounds,
+        db.table.options.statistics_histogram_buckets / 2
+    );
+
+    let plan = db
+        .explain(parse_statement_query(
+            "SELECT * FROM t WHERE Value BETWEEN 0 AND 10",
+        ))
+        .await?;
+    let filter = &plan.deps[0].deps[0];
+    assert_eq!(filter.stage, "Filter");
+    assert!((500..=520).contains(&filter.estimated_rows));
+
+    let mut delete = DeleteProto::new();
+    delete.key = MessageField::some(ColumnProto::new());
+    delete.key.mut_or_insert_default().name = "Key".to_string();
+    delete
+        .key
+        .mut_or_insert_default()
+        .value
+        .mut_or_insert_default()
+        .set_int_value(1);
+    db.delete(delete).await?;
+    let statistics = db.statistics();
+    assert_eq!(statistics.table.row_count, 999);
+    assert_eq!(statistics.table.modified_count, 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn async_read_write_success() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    let num_iter = 100;
+
+    let mut task_set = tokio::task::JoinSet::new();
+    for i in 0..num_iter {
+        let db = db.clone();
+        task_set.spawn(async move {
+            let insert_operation = parse_from_str::<InsertProto>(
+                format!(
+                    "
+                row {{
+                    columns {{
+                        name: \"Key\"
+                        value {{
+                            int_value: {i}
+                        }}
+                    }}
+                    columns {{
+                        name: \"Value\"
+                        value {{
+                            int_value: {i}
+                        }}
+                    }}
+                }}
+                ",
+                )
+                .as_str(),
+            )
+            .unwrap();
+            db.insert(insert_operation.clone()).await.unwrap();
+        });
+    }
+
+    for i in 0..num_iter {
+        let db = db.clone();
+        tokio::spawn(async move {
+            let read_operation = parse_from_str::<ReadRowProto>(
+                format!(
+                    "
+                key {{
+                    name: \"Key\"
+                    value {{
+                        int_value: {i}
+                    }}
+                }}
+                ",
+                )
+                .as_str(),
+            )
+            .unwrap();
+            let expected_row = parse_from_str::<RowProto>(
+                format!(
+                    "
+                    columns {{
+                        name: \"Key\"
+                        value {{
+                            int_value: {i}
+                        }}
+                    }}
+                    columns {{
+                        name: \"Value\"
+                        value {{
+                            int_value: {i}
+                        }}
+                    }}
+                ",
+                )
+                .as_str(),
+            )
+            .unwrap();
+            let row = db.read_row(read_operation).await.unwrap();
+            assert_eq!(expected_row, row);
+        });
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn delete_single_success() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+
+    let insert_operation = parse_from_str::<InsertProto>(
+        "
+        row {
+            columns {
+                name: \"Key\"
+                value {
+                    int_value: 1
+                }
+            }
+            columns {
+                name: \"Value\"
+                value {
+                    int_value: 2
+                }
+            }
+        }
+        ",
+    )
+    .unwrap();
+    db.insert(insert_operation.clone()).await?;
+
+    let delete_operation = parse_from_str::<DeleteProto>(
+        "
+        key {
+        name: \"Key\"
+        value {
+        int_value: 1
+        }
+        }
+        ",
+    )
+    .unwrap();
+    db.delete(delete_operation.clone()).await?;
+    // deleted versions remain until no snapshot can see them
+    assert_eq!(db.collect_garbage().await?, 2);
+
+    let table_row_internal = db.table.read_row(1).await;
+    assert_eq!(table_row_internal.unwrap_err().kind, NotFound);
+
+    let index_row_internal = db.secondary_indexes[0].read_row(2).await;
+    assert_eq!(index_row_internal.unwrap_err().kind, NotFound);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn create_invalid_options_fails() -> Result<(), Error> {
+    for options in [
+        DatabaseOptions {
+            page_size: 100,
+            ..Default::default()
+        },
+        DatabaseOptions {
+            sort_run_size: 0,
+            ..Default::default()
+        },
+        DatabaseOptions {
+            garbage_collection_interval: std::time::Duration::ZERO,
+            ..Default::default()
+        },
+    ] {
+        let result = Database::<Cursor<Vec<u8>>>::create("", test_schema(), options).await;
+        assert_eq!(result.err().unwrap().kind, InvalidArgument);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn open_success() -> Result<(), Error> {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let dir = std::env::temp_dir().join(format!("socks_open_success_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let dir = dir.to_str().unwrap();
+
+    let options = DatabaseOptions {
+        page_size: 1024,
+        write_strategy: WriteStrategy::BLinkTree,
+        ..Default::default()
+    };
+    {
+        let db = Database::<File>::create(dir, test_schema(), options).await?;
+        for i in 0..200 {
+            db.insert(insert_row_operation(i, i % 10)).await?;
+        }
+        db.flush().await?;
+    }
+
+    // tables are read with the page size they were created with, regardless of options.
+    let options = DatabaseOptions {
+        read_strategy: ReadStrategy::SequentialSearch,
+        ..Default::default()
+    };
+    let db = Database::<File>::open(dir, options).await?;
+    assert_eq!(db.table.config.chunk_size, 1024);
+    assert_eq!(db.secondary_indexes[0].config.chunk_size, 1024);
+    assert_eq!(db.statistics().table.row_count, 200);
+    assert!(db.statistics().table.height >= 2);
+    for i in 0..200 {
+        db.read_row(read_row_operation(i)).await?;
+    }
+    let mut num_rows = 0;
+    let mut cursor = db.secondary_indexes[0].read_range(3, 3);
+    while let Some((keys, _)) = cursor.next_leaf().await? {
+        num_rows += keys.len();
+    }
+    assert_eq!(num_rows, 20);
+
+    // transactions committed after reopening are visible.
+    db.insert(insert_row_operation(200, 0)).await?;
+    db.read_row(read_row_operation(200)).await?;
+
+    drop(db);
+    std::fs::remove_dir_all(dir).unwrap();
+    Ok(())
+}
+
+#[tokio::test]
+async fn open_missing_fails() -> Result<(), Error> {
+    let dir = std::env::temp_dir().join(format!("socks_open_missing_{}", std::process::id()));
+    let result = Database::<File>::open(dir.to_str().unwrap(), DatabaseOptions::default()).await;
+    assert_eq!(result.err().unwrap().kind, NotFound);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn checkpoint_success() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    insert_rows(&db, 3, |i| i).await;
+    assert_eq!(db.wal.read_committed().await?.len(), 3);
+
+    db.checkpoint().await?;
+    assert!(db.wal.read_committed().await?.is_empty());
+    db.insert(insert_row_operation(3, 3)).await?;
+    let committed = db.wal.read_committed().await?;
+    assert_eq!(committed.len(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn open_after_checkpoint_success() -> Result<(), Error> {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let dir = std::env::temp_dir().join(format!("socks_open_checkpoint_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let dir = dir.to_str().unwrap();
+
+    {
+        let db = Database::<File>::create(dir, test_schema(), DatabaseOptions::default()).await?;
+        for i in 0..100 {
+            db.insert(insert_row_operation(i, i % 10)).await?;
+        }
+        // NOTE: not flushed, the checkpoint alone writes the rows.
+        db.checkpoint().await?;
+        db.insert(insert_row_operation(100, 0)).await?;
+    }
+
+    let db = Database::<File>::open(dir, DatabaseOptions::default()).await?;
+    for i in 0..101 {
+        db.read_row(read_row_operation(i)).await?;
+    }
+    // the transaction committed since the checkpoint was replayed, then checkpointed.
+    assert!(db.wal.read_committed().await?.is_empty());
+
+    drop(db);
+    std::fs::remove_dir_all(dir).unwrap();
+    Ok(())
+}
+
+#[tokio::test]
+async fn open_after_crash_success() -> Result<(), Error> {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let dir = std::env::temp_dir().join(format!("socks_open_crash_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let dir = dir.to_str().unwrap();
+
+    // NOTE: dirty pages are never written in the background, nor checkpointed.
+    let options = || DatabaseOptions {
+        dirty_page_write_interval: std::time::Duration::from_secs(3600),
+        checkpoint_interval: std::time::Duration::from_secs(3600),
+        ..Default::default()
+    };
+    {
+        let db = Database::<File>::create(dir, test_schema(), options()).await?;
+        for i in 0..100 {
+            db.insert(insert_row_operation(i, i % 10)).await?;
+        }
+        db.checkpoint().await?;
+        for i in 100..200 {
+            db.insert(insert_row_operation(i, i % 10)).await?;
+        }
+        db.delete(delete_row_operation(0)).await?;
+        let mut update_operation = UpdateProto::new();
+        update_operation.key = MessageField::some(key_column(1));
+        let mut val = ColumnProto::new();
+        val.name = "Value".to_string();
+        val.value.mut_or_insert_default().set_int_value(5);
+        update_operation.columns.push(val);
+        db.update(update_operation).await?;
+        // dropped without flushing, as on a crash.
+    }
+
+    let db = Database::<File>::open(dir, options()).await?;
+    assert_eq!(
+        db.read_row(read_row_operation(0)).await.unwrap_err().kind,
+        NotFound
+    );
+    for i in 1..200 {
+        db.read_row(read_row_operation(i)).await?;
+    }
+    let mut num_rows = 0;
+    let mut cursor = db.secondary_indexes[0].read_range(5, 5);
+    while let Some((keys, _)) = cursor.next_leaf().await? {
+        num_rows += keys.len();
+    }
+    assert_eq!(num_rows, 21);
+    drop(db);
+
+    // replaying is only needed once.
+    let db = Database::<File>::open(dir, options()).await?;
+    assert!(db.wal.read_committed().await?.is_empty());
+    // statistics were rebuilt, counting the deleted versions not yet collected.
+    assert_eq!(db.statistics().table.row_count, 201);
+
+    drop(db);
+    std::fs::remove_dir_all(dir).unwrap();
+    Ok(())
+}
+
+#[tokio::test]
+async fn open_after_uncommitted_success() -> Result<(), Error> {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let dir = std::env::temp_dir().join(format!("socks_open_uncommitted_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let dir = dir.to_str().unwrap();
+
+    let txn_id = {
+        let db = Database::<File>::create(dir, test_schema(), DatabaseOptions::default()).await?;
+        for i in 0..10 {
+            db.insert(insert_row_operation(i, i)).await?;
+        }
+        let mut txn = db.begin();
+        txn.insert(insert_row_operation(10, 10)).await?;
+        txn.delete(delete_row_operation(0)).await?;
+        // the uncommitted versions are written to disk, then the transaction never finishes,
+        // as on a crash.
+        db.checkpoint().await?;
+        let txn_id = txn.id();
+        std::mem::forget(txn);
+        txn_id
+    };
+
+    let db = Database::<File>::open(dir, DatabaseOptions::default()).await?;
+    assert!(db.table.read_versions(10).await?.is_empty());
+    assert_eq!(db.table.read_versions(0).await?.len(), 1);
+    assert_eq!(db.collect_garbage().await?, 0);
+    db.read_row(read_row_operation(0)).await?;
+    assert_eq!(
+        db.read_row(read_row_operation(10)).await.unwrap_err().kind,
+        NotFound
+    );
+    // ids of transactions which never committed aren't reused.
+    let txn = db.begin();
+    assert!(txn.id() > txn_id);
+    txn.rollback().await?;
+    db.delete(delete_row_operation(0)).await?;
+    db.insert(insert_row_operation(10, 10)).await?;
+
+    drop(db);
+    std::fs::remove_dir_all(dir).unwrap();
+    Ok(())
+}
+
+#[tokio::test]
+async fn statistics_persisted_at_checkpoint_success() -> Result<(), Error> {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let dir = std::env::temp_dir().join(format!("socks_statistics_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let dir = dir.to_str().unwrap();
+
+    {
+        let db = Database::<File>::create(dir, test_schema(), DatabaseOptions::default()).await?;
+        for i in 0..100 {
+            db.insert(insert_row_operation(i, i % 10)).await?;
+        }
+        db.checkpoint().await?;
+
+        // deletes only update statistics in memory, rather than writing metadata per row.
+        let bytes_written = db.metrics().table.bytes_written;
+        for i in 0..10 {
+            db.delete(delete_row_operation(i)).await?;
+        }
+        assert_eq!(db.metrics().table.bytes_written, bytes_written);
+        assert_eq!(db.statistics().table.row_count, 90);
+        db.checkpoint().await?;
+    }
+
+    let db = Database::<File>::open(dir, DatabaseOptions::default()).await?;
+    assert_eq!(db.statistics().table.row_count, 90);
+    assert_eq!(db.statistics().secondary_indexes[0].row_count, 90);
+
+    drop(db);
+    std::fs::remove_dir_all(dir).unwrap();
+    Ok(())
+}
+
+#[tokio::test]
+async fn query_order_by_on_disk_success() -> Result<(), Error> {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let dir = std::env::temp_dir().join(format!("socks_order_by_disk_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let dir = dir.to_str().unwrap();
+
+    // NOTE: enough rows to spill several sorted runs, each to its own temporary file.
+    let num_rows = 2500;
+    let options = DatabaseOptions {
+        sort_run_size: 500,
+        ..Default::default()
+    };
+    let db = Database::<File>::create(dir, test_schema(), options).await?;
+    db.insert_batch(insert_batch_operations(0..num_rows))
+        .await?;
+    let query_operation = parse_from_str::<QueryProto>(
+        "
+        order_by {
+            dep {
+                filter {
+                    in_range {
+                        name: \"Key\"
+                        lower_value {
+                            int_value: 0
+                        }
+                        upper_value {
+                            int_value: 2499
+                        }
+                    }
+                }
+            }
+            columns {
+                name: \"Value\"
+                descending: true
+            }
+        }
+        ",
+    )
+    .unwrap();
+    let mut file = db.query(query_operation).await?;
+    let mut bytes = Vec::new();
+    file.seek(std::io::SeekFrom::Start(0)).await.unwrap();
+    file.read_to_end(&mut bytes).await.unwrap();
+    let query_results = read_query_results(Cursor::new(bytes)).await?;
+    let values: Vec<u32> = query_results.keys.iter().map(|key| key % 10).collect();
+    assert_eq!(values.len(), num_rows as usize);
+    assert!(values.is_sorted_by(|lhs, rhs| lhs >= rhs));
+
+    drop(db);
+    std::fs::remove_dir_all(dir).unwrap();
+    Ok(())
+}
+
+#[tokio::test]
+async fn metrics_success() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    insert_rows(&db, 1000, |i| i % 10).await;
+    db.read_row(read_row_operation(0)).await?;
+
+    let metrics = db.metrics();
+    let buffer_pool = &metrics.buffer_pool;
+    assert!(buffer_pool.hits > 0);
+    assert!(buffer_pool.resident_pages > 0);
+    assert!(buffer_pool.resident_bytes <= buffer_pool.capacity_bytes);
+    assert_eq!(
+        buffer_pool.shard_lock_wait_micros.len(),
+        DatabaseOptions::default().buffer_pool_shard_count
+    );
+    assert_eq!(metrics.table.name, db.table.name);
+    assert!(metrics.table.splits > 0);
+    assert!(metrics.secondary_indexes[0].splits > 0);
+
+    db.flush().await?;
+    let flushed = db.metrics();
+    assert_eq!(flushed.buffer_pool.resident_pages, 0);
+    assert_eq!(flushed.buffer_pool.resident_bytes, 0);
+    assert_eq!(
+        flushed.buffer_pool.evictions,
+        metrics.buffer_pool.evictions + metrics.buffer_pool.resident_pages
+    );
+    assert!(flushed.buffer_pool.dirty_writes > metrics.buffer_pool.dirty_writes);
+    assert!(flushed.table.bytes_written > metrics.table.bytes_written);
+
+    // pages are read back from disk.
+    db.read_row(read_row_operation(0)).await?;
+    let reread = db.metrics();
+    assert!(reread.buffer_pool.misses > flushed.buffer_pool.misses);
+    assert!(reread.table.bytes_read > flushed.table.bytes_read);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn metrics_prometheus_success() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    insert_rows(&db, 10, |i| i).await;
+
+    let text = db.metrics_prometheus();
+    assert!(text.contains("# TYPE socks_buffer_pool_hits_total counter\n"));
+    assert!(text.contains(&format!(
+        "socks_buffer_pool_hits_total {}\n",
+        db.metrics().buffer_pool.hits
+    )));
+    assert!(text.contains("socks_buffer_pool_shard_lock_wait_seconds_total{shard=\"0\"} "));
+    assert!(text.contains(&format!(
+        "socks_table_splits_total{{table=\"{}\"}} 0\n",
+        db.table.name
+    )));
+
+    Ok(())
+}
+
+fn read_rows_operation(keys: &[i32]) -> ReadRowsProto {
+    let mut read_operation = ReadRowsProto::new();
+    read_operation.keys = keys.iter().map(|key| key_column(*key)).collect();
+    read_operation
+}
+
+// The value column of each row read, or None if missing.
+fn row_values(rows: &[Option<RowProto>]) -> Vec<Option<i32>> {
+    rows.iter()
+        .map(|row| row.as_ref().map(|row| row.columns[1].value.int_value()))
+        .collect()
+}
+
+#[tokio::test]
+async fn read_rows_success() -> Result<(), Error> {
+    let _ = env_logger::builder().is_test(true).try_init();
+    for write_strategy in [WriteStrategy::AggressiveSplit, WriteStrategy::BLinkTree] {
+        let options = DatabaseOptions {
+            page_size: 1024,
+            write_strategy,
+            ..Default::default()
+        };
+        let db = Database::create("", test_schema(), options).await?;
+        insert_rows(&db, 300, |i| i * 10).await;
+        db.delete(delete_row_operation(7)).await?;
+
+        // keys are returned in the order requested, including duplicates and missing keys.
+        let keys = [250, 3, 7, 3, 299, 1000, 0, 120];
+        let rows = db.read_rows(read_rows_operation(&keys)).await?;
+        assert_eq!(
+            row_values(&rows),
+            vec![
+                Some(2500),
+                Some(30),
+                None,
+                Some(30),
+                Some(2990),
+                None,
+                Some(0),
+                Some(1200)
+            ]
+        );
+
+        // rows are read from the transaction's snapshot.
+        let mut txn = db.begin();
+        txn.delete(delete_row_operation(3)).await?;
+        txn.insert(insert_row_operation(7, 70)).await?;
+        let rows = txn.read_rows(read_rows_operation(&[3, 7])).await?;
+        assert_eq!(row_values(&rows), vec![None, Some(70)]);
+        let rows = db.read_rows(read_rows_operation(&[3, 7])).await?;
+        assert_eq!(row_values(&rows), vec![Some(30), None]);
+        txn.commit().await?;
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn read_rows_reads_each_page_once() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    insert_rows(&db, 500, |i| i).await;
+    let keys: Vec<i32> = (0..500).step_by(5).collect();
+
+    let pages_read = |db: &Database<Cursor<Vec<u8>>>| {
+        let metrics = db.metrics().buffer_pool;
+        metrics.hits + metrics.misses
+    };
+    let start = pages_read(&db);
+    for key in &keys {
+        db.read_row(read_row_operation(*key)).await?;
+    }
+    let serial_pages_read = pages_read(&db) - start;
+
+    let start = pages_read(&db);
+    let rows = db.read_rows(read_rows_operation(&keys)).await?;
+    let batched_pages_read = pages_read(&db) - start;
+    assert!(rows.iter().all(|row| row.is_some()));
+    // each node on the way to the keys is read once, rather than once per key.
+    assert!(batched_pages_read * 2 < serial_pages_read);
+
+    Ok(())
+}
+
+fn table_row(key: i32, value: i32) -> RowProto {
+    insert_row_operation(key, value).row.unwrap()
+}
+
+#[tokio::test]
+async fn bulk_load_success() -> Result<(), Error> {
+    let _ = env_logger::builder().is_test(true).try_init();
+    for write_strategy in [WriteStrategy::AggressiveSplit, WriteStrategy::BLinkTree] {
+        let options = DatabaseOptions {
+            page_size: 512,
+            write_strategy,
+            ..Default::default()
+        };
+        let db = Database::<Cursor<Vec<u8>>>::create("", test_schema(), options).await?;
+        // NOTE: out of order, so that the rows (and index rows) are sorted first.
+        let rows = (0..10000).map(|i| table_row((i * 7) % 10000, (i * 7) % 10));
+        db.bulk_load(rows, false).await?;
+
+        let statistics = db.statistics();
+        assert_eq!(statistics.table.row_count, 10000);
+        assert!(statistics.table.height >= 3);
+        assert_eq!(statistics.secondary_indexes[0].row_count, 10000);
+        let keys: Vec<i32> = (0..10000).collect();
+        let rows = db.read_rows(read_rows_operation(&keys)).await?;
+        for (key, row) in keys.iter().zip(rows) {
+            assert_eq!(row.unwrap(), table_row(*key, key % 10));
+        }
+        let mut num_rows = 0;
+        let mut cursor = db.secondary_indexes[0].read_range(3, 3);
+        while let Some((keys, _)) = cursor.next_leaf().await? {
+            num_rows += keys.len();
+        }
+        assert_eq!(num_rows, 1000);
+
+        // loaded rows can be written to as usual.
+        db.delete(delete_row_operation(10)).await?;
+        db.insert(insert_row_operation(20000, 1)).await?;
+        assert_eq!(
+            db.read_row(read_row_operation(10)).await.unwrap_err().kind,
+            NotFound
+        );
+        db.read_row(read_row_operation(20000)).await?;
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn bulk_load_sorted_success() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    db.bulk_load((0..500).map(|i| table_row(i, i)), true)
+        .await?;
+
+    assert_eq!(db.statistics().table.row_count, 500);
+    for i in (0..500).step_by(50) {
+        assert_eq!(db.read_row(read_row_operation(i)).await?, table_row(i, i));
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn bulk_load_unsorted_fails() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    let rows = vec![table_row(1, 1), table_row(0, 0)];
+    let result = db.bulk_load(rows, true).await;
+    assert_eq!(result.unwrap_err().kind, InvalidArgument);
+
+    let ctx = setup().await;
+    let db = ctx.db;
+    let rows = vec![table_row(1, 1), table_row(1, 2)];
+    let result = db.bulk_load(rows, false).await;
+    assert_eq!(result.unwrap_err().kind, InvalidArgument);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn bulk_load_non_empty_fails() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    insert_rows(&db, 1, |i| i).await;
+
+    let result = db.bulk_load(vec![table_row(1, 1)], true).await;
+    assert_eq!(result.unwrap_err().kind, FailedPrecondition);
+
+    Ok(())
+}
+
+// Compares the bytes written by inserting rows one at a time, and bulk loading them.
+// Selects every row through the secondary index, with none cached, and returns the pool's misses
+// and prefetches.
+async fn select_misses(read_ahead_window: usize) -> Result<(u64, u64), Error> {
+    // NOTE: small pages, so that the rows read ahead of are on pages the select hasn't reached.
+    let options = DatabaseOptions {
+        page_size: 512,
+        read_ahead_window,
+        ..Default::default()
+    };
+    let db = Database::<Cursor<Vec<u8>>>::create("", test_schema(), options).await?;
+    db.insert_batch(insert_batch_operations(0..1000)).await?;
+    db.flush().await?;
+
+    let start = db.metrics().buffer_pool;
+    let sql::Statement::Query(query) = sql::parse("SELECT * FROM t WHERE Value BETWEEN 0 AND 9")?
+    else {
+        panic!("expected query");
+    };
+    db.query(query).await?;
+    let end = db.metrics().buffer_pool;
+    Ok((end.misses - start.misses, end.prefetches - start.prefetches))
+}
+
+// NOTE: rows are read ahead of in other tasks, so concurrently with the select.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn select_read_ahead_reduces_misses() -> Result<(), Error> {
+    let (baseline_misses, baseline_prefetches) = select_misses(0).await?;
+    assert_eq!(baseline_prefetches, 0);
+
+    let (misses, prefetches) = select_misses(16).await?;
+    assert!(prefetches > 0);
+    assert!(misses < baseline_misses);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn bulk_load_writes_less() -> Result<(), Error> {
+    let bytes_written = |db: &Database<Cursor<Vec<u8>>>| {
+        let metrics = db.metrics();
+        metrics.table.bytes_written
+            + metrics
+                .secondary_indexes
+                .iter()
+                .map(|index| index.bytes_written)
+                .sum::<u64>()
+    };
+
+    let ctx = setup().await;
+    insert_rows(&ctx.db, 1000, |i| i % 10).await;
+    ctx.db.checkpoint().await?;
+    let inserted_bytes = bytes_written(&ctx.db);
+
+    let ctx = setup().await;
+    let rows = (0..1000).map(|i| table_row(i, i % 10));
+    ctx.db.bulk_load(rows, true).await?;
+    let loaded_bytes = bytes_written(&ctx.db);
+
+    assert!(loaded_bytes * 10 < inserted_bytes);
+
+    Ok(())
+}
+
+fn insert_batch_operations(keys: impl IntoIterator<Item = i32>) -> Vec<InsertProto> {
+    keys.into_iter()
+        .map(|key| insert_row_operation(key, key % 10))
+        .collect()
+}
+
+#[tokio::test]
+async fn insert_batch_success() -> Result<(), Error> {
+    let _ = env_logger::builder().is_test(true).try_init();
+    for write_strategy in [WriteStrategy::AggressiveSplit, WriteStrategy::BLinkTree] {
+        let options = DatabaseOptions {
+            page_size: 1024,
+            write_strategy,
+            ..Default::default()
+        };
+        let db = Database::<Cursor<Vec<u8>>>::create("", test_schema(), options).await?;
+        insert_rows(&db, 100, |i| i % 10).await;
+
+        // rows are inserted in any order, between and after existing ones.
+        let keys = (0..1000).map(|i| 100 + (i * 7919) % 1000);
+        db.insert_batch(insert_batch_operations(keys)).await?;
+
+        let keys = [0, 99, 100, 101, 550, 1099];
+        let rows = db.read_rows(read_rows_operation(&keys)).await?;
+        assert_eq!(
+            row_values(&rows),
+            vec![Some(0), Some(9), Some(0), Some(1), Some(0), Some(9)]
+        );
+        let mut cursor = db.table.read_range(0, u32::MAX);
+        let mut num_rows = 0;
+        while let Some((keys, _)) = cursor.next_leaf().await? {
+            num_rows += keys.len();
+        }
+        assert_eq!(num_rows, 1100);
+        let mut cursor = db.secondary_indexes[0].read_range(3, 3);
+        let mut num_index_rows = 0;
+        while let Some((keys, _)) = cursor.next_leaf().await? {
+            num_index_rows += keys.len();
+        }
+        assert_eq!(num_index_rows, 110);
+
+        // the batch is committed as a single transaction.
+        let committed = db.wal.read_committed().await?;
+        assert_eq!(committed.len(), 101);
+        assert_eq!(committed.last().unwrap().1.len(), 2000);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn insert_batch_fails() -> Result<(), Error> {
+    let ctx = setup().await;
+    let db = ctx.db;
+    insert_rows(&db, 3, |i| i).await;
+
+    let result = db.insert_batch(insert_batch_operations([10, 11, 10])).await;
+    assert_eq!(result.unwrap_err().kind, AlreadyExists);
+    let result = db.insert_batch(insert_batch_operations([10, 11, 2])).await;
+    assert_eq!(result.unwrap_err().kind, AlreadyExists);
+    let mut txn = db.begin();
+    txn.insert(insert_row_operation(12, 2)).await?;
+    let result = db.insert_batch(insert_batch_operations([10, 11, 12])).await;
+    assert_eq!(result.unwrap_err().kind, Aborted);
+    txn.rollback().await?;
+
+    // nothing from the failed batches remains.
+    let rows = db.read_rows(read_rows_operation(&[10, 11, 12])).await?;
+    assert_eq!(row_values(&rows), vec![None, None, None]);
+    assert_eq!(
+        db.secondary_indexes[0].read_row(1).await?.columns[0]
+            .value
+            .int_value(),
+        1
+    );
+    assert_eq!(db.wal.read_committed().await?.len(), 3);
+
+    Ok(())
+}
+
+// Compares the bytes written by inserting rows one at a time, and in a single batch.
+#[tokio::test]
+async fn insert_batch_writes_less() -> Result<(), Error> {
+    let bytes_written = |db: &Database<Cursor<Vec<u8>>>| {
+        let metrics = db.metrics();
+        metrics.table.bytes_written
+            + metrics
+                .secondary_indexes
+                .iter()
+                .map(|index| index.bytes_written)
+                .sum::<u64>()
+    };
+
+    let ctx = setup().await;
+    insert_rows(&ctx.db, 1000, |i| i % 10).await;
+    ctx.db.checkpoint().await?;
+    let inserted_bytes = bytes_written(&ctx.db);
+
+    let ctx = setup().await;
+    ctx.db
+        .insert_batch(insert_batch_operations(0..1000))
+        .await?;
+    ctx.db.checkpoint().await?;
+    let batched_bytes = bytes_written(&ctx.db);
+
+    assert!(batched_bytes * 10 < inserted_bytes);
 
     Ok(())
 }
@@ -25,6 +25,14 @@ pub enum ErrorKind {
     AlreadyExists,
     Internal,
     DataLoss,
+    // The operation was aborted due to contention with concurrent operations, e.g. a deadlock,
+    // and may be retried.
+    Aborted,
+    // Some resource has run out, e.g. the buffer pool is full of pages in use, and the operation
+    // may be retried once others finish.
+    ResourceExhausted,
+    // The operation isn't supported, e.g. a SQL statement which can only be parsed.
+    Unimplemented,
 }
 
 impl ErrorKind {
@@ -38,6 +46,9 @@ impl ErrorKind {
             AlreadyExists => "ALREADY_EXISTS",
             Internal => "INTERNAL",
             DataLoss => "DATA_LOSS",
+            Aborted => "ABORTED",
+            ResourceExhausted => "RESOURCE_EXHAUSTED",
+            Unimplemented => "UNIMPLEMENTED",
         }
     }
 }
@@ -0,0 +1,106 @@
+// An in-memory file with faults injected into its writes, for tests of error handling.
+
+use crate::error::Error;
+use crate::filelike::Filelike;
+use std::io::Cursor;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+// Faults injected into the writes of a FaultyFile.
+#[derive(Debug, Default)]
+pub(crate) struct Faults {
+    pub(crate) fail_writes: AtomicBool,
+    // Writes fail once this many more have succeeded, if set.
+    pub(crate) fail_writes_after: Mutex<Option<usize>>,
+    pub(crate) block_writes: AtomicBool,
+    blocked: Mutex<Vec<Waker>>,
+}
+
+impl Faults {
+    pub(crate) fn unblock_writes(&self) {
+        self.block_writes.store(false, Ordering::Release);
+        for waker in self.blocked.lock().unwrap().drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+// An in-memory file whose writes fail, or wait, as its faults are set.
+#[derive(Debug, Default)]
+pub(crate) struct FaultyFile {
+    pub(crate) inner: Cursor<Vec<u8>>,
+    pub(crate) faults: Arc<Faults>,
+}
+
+impl Filelike for FaultyFile {
+    async fn create(_path: &str) -> Result<Self, Error> {
+        Ok(Self::default())
+    }
+
+    async fn open(_path: &str) -> Result<Self, Error> {
+        Ok(Self::default())
+    }
+
+    async fn temp() -> Result<Self, Error> {
+        Ok(Self::default())
+    }
+}
+
+impl AsyncRead for FaultyFile {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for FaultyFile {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let fail_after = match self.faults.fail_writes_after.lock().unwrap().as_mut() {
+            Some(0) => true,
+            Some(n) => {
+                *n -= 1;
+                false
+            }
+            None => false,
+        };
+        if fail_after || self.faults.fail_writes.load(Ordering::Acquire) {
+            return Poll::Ready(Err(std::io::Error::other("injected write failure")));
+        }
+        if self.faults.block_writes.load(Ordering::Acquire) {
+            self.faults.blocked.lock().unwrap().push(cx.waker().clone());
+            // NOTE: checked again, in case writes were unblocked before the waker was added.
+            if self.faults.block_writes.load(Ordering::Acquire) {
+                return Poll::Pending;
+            }
+        }
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+impl AsyncSeek for FaultyFile {
+    fn start_seek(mut self: Pin<&mut Self>, position: std::io::SeekFrom) -> std::io::Result<()> {
+        Pin::new(&mut self.inner).start_seek(position)
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<u64>> {
+        Pin::new(&mut self.inner).poll_complete(cx)
+    }
+}
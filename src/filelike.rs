@@ -2,6 +2,7 @@ use crate::error::{Error, ErrorKind::*};
 use std::fmt::Debug;
 use std::io::Cursor;
 use std::marker::Unpin;
+use std::sync::atomic::{AtomicU64, Ordering};
 use tokio::fs::{File, OpenOptions};
 use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite};
 
@@ -11,12 +12,26 @@ use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite};
 //
 // Cursor<T> can be used as an in-memory File, so create a trait to
 // facilitate that behavior.
+//
+// NOTE: 'static, so that tables can be shared with background tasks.
 
 #[allow(async_fn_in_trait)]
-pub trait Filelike: Debug + Unpin + Send + AsyncRead + AsyncWrite + AsyncSeek + Sized {
+pub trait Filelike:
+    Debug + Unpin + Send + 'static + AsyncRead + AsyncWrite + AsyncSeek + Sized
+{
     async fn create(path: &str) -> Result<Self, Error>;
+
+    // Opens an existing file for reading and writing.
+    async fn open(path: &str) -> Result<Self, Error>;
+
+    // Creates a new, anonymous file for intermediate results (e.g. sorted runs), which is
+    // removed once dropped.
+    async fn temp() -> Result<Self, Error>;
 }
 
+// Distinguishes the temporary files created by this process.
+static NEXT_TEMP_ID: AtomicU64 = AtomicU64::new(0);
+
 impl Filelike for File {
     async fn create(path: &str) -> Result<Self, Error> {
         let file = OpenOptions::new()
@@ -28,13 +43,58 @@ impl Filelike for File {
             .map_err(|e| Error::new(FailedPrecondition, format!("Unable to open file: {e}")))?;
         Ok(file)
     }
+
+    async fn open(path: &str) -> Result<Self, Error> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .await
+            .map_err(|e| {
+                let kind = match e.kind() {
+                    std::io::ErrorKind::NotFound => NotFound,
+                    _ => FailedPrecondition,
+                };
+                Error::new(kind, format!("Unable to open file: {e}"))
+            })?;
+        Ok(file)
+    }
+
+    async fn temp() -> Result<Self, Error> {
+        let path = std::env::temp_dir().join(format!(
+            "socks_{}_{}.tmp",
+            std::process::id(),
+            NEXT_TEMP_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = <Self as Filelike>::create(path.to_str().unwrap()).await?;
+        // NOTE: the file remains readable and writable through its handle until dropped, at
+        // which point its space is reclaimed, even if the process doesn't exit cleanly.
+        tokio::fs::remove_file(&path).await.map_err(|e| {
+            Error::new(
+                FailedPrecondition,
+                format!("Unable to remove temporary file: {e}"),
+            )
+        })?;
+        Ok(file)
+    }
 }
 
 impl<T: Default> Filelike for Cursor<T>
 where
-    Cursor<T>: Debug + Unpin + Send + AsyncRead + AsyncWrite + AsyncSeek,
+    Cursor<T>: Debug + Unpin + Send + 'static + AsyncRead + AsyncWrite + AsyncSeek,
 {
     async fn create(_path: &str) -> Result<Self, Error> {
         Ok(Cursor::<T>::new(T::default()))
     }
+
+    async fn open(path: &str) -> Result<Self, Error> {
+        Err(Error::new(
+            FailedPrecondition,
+            format!("Unable to open {path}: in-memory files don't outlive their database!"),
+        ))
+    }
+
+    async fn temp() -> Result<Self, Error> {
+        Ok(Cursor::<T>::new(T::default()))
+    }
 }
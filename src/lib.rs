@@ -4,55 +4,14 @@
 // Used to accelerate key comparison during B+ tree traversal.
 static LANE_WIDTH: usize = 8;
 
-// The size of each file chunk, in bytes. Influences various parts of the
-// database, e.g. the size of each B+ tree node, or how many query results are
-// grouped together. Each chunk stores 1 protobuf, so the value must be less
-// than the maximum protobuf size (2GiB).
-static BUFFER_SIZE: usize = 4096;
-
 // The byte size buffer before considering a chunk as full.
+// NOTE: only for chunks holding protobuf messages, B+ tree nodes are sized exactly, see
+// bp_tree/page.rs.
 // TODO: this shouldn't be required if calculating proto sizes correctly.
 static BUFFER_OVERFLOW_BUFFER: usize = 5;
 
-// A basic LRU cache is used to speed up read / write operations to frequently
-// accessed chunks. It is sharded to lower thread contention.
-static BUFFER_POOL_SHARD_COUNT: usize = 16;
-
-// The size (in chunks) of each cache shard before evicting the least
-// frequently used chunk. This effectively means socks can store a maximum of
-// BUFFER_POOL_SHARD_COUNT * BUFFER_POOL_SHARD_SIZE chunks in memory at any given time.
-static BUFFER_POOL_SHARD_SIZE: usize = 16;
-
-// When searching through table B+ tree nodes using a binary search, this is the
-// number of remaining elements left until the algorithm switches to a sequential
-// search. This is better for cache coherence when sufficiently low.
-static BINARY_READ_ITER_CUTOFF: usize = 100;
-
-// Configurable read strategies for table B+ tree traversal.
-#[allow(dead_code)]
-enum ReadStrategy {
-    SequentialSearch,
-    BinarySearch,
-}
-static READ_STRATEGY: ReadStrategy = ReadStrategy::BinarySearch;
-
-// Configurable write strategies for B+ tree insertion.
-#[allow(dead_code)]
-enum WriteStrategy {
-    AggressiveSplit,
-}
-static WRITE_STRATEGY: WriteStrategy = WriteStrategy::AggressiveSplit;
-
-// Configurable deletion strategies for B+ tree removal.
-//
-// TODO: research deletion algorithms that are better for concurrency.
-// Many examples require bottom-up recursion which may cause deadlocks without
-// blocking the whole table.
-#[allow(dead_code)]
-enum DeleteStrategy {
-    UnbalancedDelete,
-}
-static DELETE_STRATEGY: DeleteStrategy = DeleteStrategy::UnbalancedDelete;
+// NOTE: the size of chunks, buffer pool, B+ tree strategies, sorting, statistics, garbage
+// collection and lock timeouts are configured at runtime, see options::DatabaseOptions.
 
 extern crate self as socks;
 mod bp_tree;
@@ -60,8 +19,25 @@ mod buffer;
 mod buffer_pool;
 pub mod database;
 mod error;
+#[cfg(test)]
+mod faulty_file;
 mod filelike;
+mod lock_manager;
+mod metrics;
+mod mvcc;
+pub mod options;
 mod protos;
 mod query;
+mod replacer;
 mod schema;
+pub mod sql;
+mod statistics;
 mod table;
+pub mod transaction;
+mod wal;
+
+// Internals exercised by the benchmarks in benches/, not part of the API.
+#[doc(hidden)]
+pub mod bench {
+    pub use crate::bp_tree::page::bench as page;
+}
@@ -0,0 +1,333 @@
+#[cfg(test)]
+#[path = "./lock_manager_test.rs"]
+mod test;
+
+use crate::error::{ErrorKind::*, *};
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::task::Poll;
+use std::time::Duration;
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+// Tracks which operations hold, and wait on, which locks (e.g. of B+ tree nodes), in order to
+// detect deadlocks.
+//
+// Operations waiting on locks form a wait-for graph: an operation waits for the holders of the
+// lock it requested, and (for shared requests) for any operations already waiting to take it
+// exclusively, since those are served first. A request which would close a cycle in the graph
+// can never be granted, so it fails with Aborted instead, i.e. the requester is the victim.
+// Operations may also limit how long they wait for any single lock.
+//
+// NOTE: only locks taken within a scope (see LockManager::scope) are tracked, others are simply
+// awaited.
+
+// Identifies a lock by its address.
+type LockId = usize;
+
+struct LockScope {
+    owner: u64,
+    timeout: Option<Duration>,
+}
+
+// The operation that locks taken by the enclosing task belong to.
+// NOTE: a task local is used so that B+ tree operations need not be aware of lock owners.
+tokio::task_local! {
+    static LOCK_SCOPE: LockScope;
+}
+
+#[derive(Default)]
+struct LockManagerState {
+    // The number of times each owner holds each lock.
+    holders: HashMap<LockId, HashMap<u64, usize>>,
+    // The lock each owner is waiting on, and whether exclusively.
+    waiting: HashMap<u64, (LockId, bool)>,
+}
+
+impl LockManagerState {
+    // The owners the given request must wait for.
+    fn blockers(&self, lock_id: LockId, is_exclusive: bool) -> Vec<u64> {
+        let mut blockers: Vec<u64> = self
+            .holders
+            .get(&lock_id)
+            .map(|holders| holders.keys().copied().collect())
+            .unwrap_or_default();
+        if !is_exclusive {
+            blockers.extend(
+                self.waiting
+                    .iter()
+                    .filter(|(_, waiting)| **waiting == (lock_id, true))
+                    .map(|(owner, _)| *owner),
+            );
+        }
+        blockers
+    }
+
+    // Whether the owner waiting on the given lock would (transitively) wait on itself.
+    fn would_deadlock(&self, owner: u64, lock_id: LockId, is_exclusive: bool) -> bool {
+        let mut visited = HashSet::new();
+        let mut stack = self.blockers(lock_id, is_exclusive);
+        while let Some(blocker) = stack.pop() {
+            if blocker == owner {
+                return true;
+            }
+            if !visited.insert(blocker) {
+                continue;
+            }
+            if let Some((lock_id, is_exclusive)) = self.waiting.get(&blocker) {
+                stack.extend(self.blockers(*lock_id, *is_exclusive));
+            }
+        }
+        false
+    }
+
+    fn hold(&mut self, owner: u64, lock_id: LockId) {
+        *self
+            .holders
+            .entry(lock_id)
+            .or_default()
+            .entry(owner)
+            .or_default() += 1;
+    }
+
+    fn release(&mut self, owner: u64, lock_id: LockId) {
+        let Some(holders) = self.holders.get_mut(&lock_id) else {
+            return;
+        };
+        if let Some(count) = holders.get_mut(&owner) {
+            *count -= 1;
+            if *count == 0 {
+                holders.remove(&owner);
+            }
+        }
+        if holders.is_empty() {
+            self.holders.remove(&lock_id);
+        }
+    }
+}
+
+// Marks an owner as waiting on a lock, until dropped (e.g. if the wait is cancelled).
+struct Waiting<'a> {
+    manager: &'a LockManager,
+    owner: u64,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.manager
+            .state
+            .lock()
+            .unwrap()
+            .waiting
+            .remove(&self.owner);
+    }
+}
+
+pub(crate) struct LockManager {
+    state: Mutex<LockManagerState>,
+    next_owner: AtomicU64,
+}
+
+// A lock guard, which releases its lock with the lock manager when dropped.
+pub(crate) struct LockGuard<'a, G> {
+    guard: G,
+    manager: &'a LockManager,
+    // The owner and lock, if tracked.
+    held: Option<(u64, LockId)>,
+}
+
+impl<G: Deref> Deref for LockGuard<'_, G> {
+    type Target = G::Target;
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<G: DerefMut> DerefMut for LockGuard<'_, G> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+impl<G> Drop for LockGuard<'_, G> {
+    fn drop(&mut self) {
+        if let Some((owner, lock_id)) = self.held {
+            self.manager.state.lock().unwrap().release(owner, lock_id);
+        }
+    }
+}
+
+impl LockManager {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: Mutex::new(LockManagerState::default()),
+            next_owner: AtomicU64::new(0),
+        }
+    }
+
+    // Runs the given future as a single operation, i.e. with all locks it takes tracked under
+    // one owner, waiting at most timeout (if any) for each. Nested scopes join the enclosing one.
+    pub(crate) async fn scope<T>(
+        &self,
+        timeout: Option<Duration>,
+        future: impl Future<Output = T>,
+    ) -> T {
+        if LOCK_SCOPE.try_with(|_| ()).is_ok() {
+            return future.await;
+        }
+        let owner = self.next_owner.fetch_add(1, Ordering::Relaxed);
+        LOCK_SCOPE.scope(LockScope { owner, timeout }, future).await
+    }
+
+    // Runs the given futures concurrently (within the enclosing task), and returns their outputs
+    // in order. Within a scope, each runs as a separate operation with the same timeout, since
+    // an owner only waits on one lock at a time.
+    pub(crate) async fn join_all<T, Fut: Future<Output = T>>(
+        &self,
+        futures: impl IntoIterator<Item = Fut>,
+    ) -> Vec<T> {
+        let scope_timeout = LOCK_SCOPE.try_with(|scope| scope.timeout).ok();
+        let mut futures: Vec<_> = futures
+            .into_iter()
+            .map(|future| {
+                let scope = scope_timeout.map(|timeout| LockScope {
+                    owner: self.next_owner.fetch_add(1, Ordering::Relaxed),
+                    timeout,
+                });
+                Box::pin(async move {
+                    match scope {
+                        Some(scope) => LOCK_SCOPE.scope(scope, future).await,
+                        None => future.await,
+                    }
+                })
+            })
+            .collect();
+        let mut outputs: Vec<Option<T>> = futures.iter().map(|_| None).collect();
+        std::future::poll_fn(|cx| {
+            let mut is_ready = true;
+            for (future, output) in futures.iter_mut().zip(&mut outputs) {
+                if output.is_some() {
+                    continue;
+                }
+                match future.as_mut().poll(cx) {
+                    Poll::Ready(value) => *output = Some(value),
+                    Poll::Pending => is_ready = false,
+                }
+            }
+            match is_ready {
+                true => Poll::Ready(()),
+                false => Poll::Pending,
+            }
+        })
+        .await;
+        outputs.into_iter().map(Option::unwrap).collect()
+    }
+
+    pub(crate) async fn read<'a, T>(
+        &'a self,
+        lock: &'a RwLock<T>,
+    ) -> Result<LockGuard<'a, RwLockReadGuard<'a, T>>, Error> {
+        self.acquire(
+            lock as *const _ as LockId,
+            false,
+            lock.try_read().ok(),
+            lock.read(),
+        )
+        .await
+    }
+
+    // Locks for reading only if that needn't wait, e.g. to skip buffers in use.
+    pub(crate) fn try_read<'a, T>(
+        &'a self,
+        lock: &'a RwLock<T>,
+    ) -> Option<LockGuard<'a, RwLockReadGuard<'a, T>>> {
+        let guard = lock.try_read().ok()?;
+        let held = LOCK_SCOPE.try_with(|scope| scope.owner).ok().map(|owner| {
+            let lock_id = lock as *const _ as LockId;
+            self.state.lock().unwrap().hold(owner, lock_id);
+            (owner, lock_id)
+        });
+        Some(LockGuard {
+            guard,
+            manager: self,
+            held,
+        })
+    }
+
+    pub(crate) async fn write<'a, T>(
+        &'a self,
+        lock: &'a RwLock<T>,
+    ) -> Result<LockGuard<'a, RwLockWriteGuard<'a, T>>, Error> {
+        self.acquire(
+            lock as *const _ as LockId,
+            true,
+            lock.try_write().ok(),
+            lock.write(),
+        )
+        .await
+    }
+
+    // Returns the guard if the lock was acquired without waiting, else waits for it.
+    async fn acquire<'a, G>(
+        &'a self,
+        lock_id: LockId,
+        is_exclusive: bool,
+        guard: Option<G>,
+        wait: impl Future<Output = G>,
+    ) -> Result<LockGuard<'a, G>, Error> {
+        let Ok((owner, timeout)) = LOCK_SCOPE.try_with(|scope| (scope.owner, scope.timeout)) else {
+            let guard = match guard {
+                Some(guard) => guard,
+                None => wait.await,
+            };
+            return Ok(LockGuard {
+                guard,
+                manager: self,
+                held: None,
+            });
+        };
+
+        let guard = match guard {
+            Some(guard) => {
+                self.state.lock().unwrap().hold(owner, lock_id);
+                guard
+            }
+            None => {
+                {
+                    let mut state = self.state.lock().unwrap();
+                    if state.would_deadlock(owner, lock_id, is_exclusive) {
+                        return Err(Error::new(
+                            Aborted,
+                            "Deadlock detected while waiting on lock!".to_string(),
+                        ));
+                    }
+                    state.waiting.insert(owner, (lock_id, is_exclusive));
+                }
+                let waiting = Waiting {
+                    manager: self,
+                    owner,
+                };
+                let guard = match timeout {
+                    Some(timeout) => tokio::time::timeout(timeout, wait).await.ok(),
+                    None => Some(wait.await),
+                };
+                drop(waiting);
+                let Some(guard) = guard else {
+                    return Err(Error::new(
+                        Aborted,
+                        format!("Timed out after {:?} waiting on lock!", timeout.unwrap()),
+                    ));
+                };
+                self.state.lock().unwrap().hold(owner, lock_id);
+                guard
+            }
+        };
+        Ok(LockGuard {
+            guard,
+            manager: self,
+            held: Some((owner, lock_id)),
+        })
+    }
+}
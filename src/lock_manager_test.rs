@@ -0,0 +1,153 @@
+use crate::error::{Error, ErrorKind::*};
+use crate::lock_manager::LockManager;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Barrier, RwLock};
+
+struct TestContext {
+    locks: Arc<LockManager>,
+    lhs: Arc<RwLock<u32>>,
+    rhs: Arc<RwLock<u32>>,
+}
+
+fn setup() -> TestContext {
+    let _ = env_logger::builder().is_test(true).try_init();
+    TestContext {
+        locks: Arc::new(LockManager::new()),
+        lhs: Arc::new(RwLock::new(0)),
+        rhs: Arc::new(RwLock::new(0)),
+    }
+}
+
+#[tokio::test]
+async fn deadlock_aborts_requester() -> Result<(), Error> {
+    let ctx = setup();
+    let barrier = Arc::new(Barrier::new(2));
+
+    let first = {
+        let (locks, lhs, rhs, barrier) = (
+            ctx.locks.clone(),
+            ctx.lhs.clone(),
+            ctx.rhs.clone(),
+            barrier.clone(),
+        );
+        tokio::spawn(async move {
+            locks
+                .scope(None, async {
+                    let mut lhs = locks.write(&lhs).await?;
+                    barrier.wait().await;
+                    // waits until the second operation is aborted.
+                    let mut rhs = locks.write(&rhs).await?;
+                    *lhs += 1;
+                    *rhs += 1;
+                    Ok::<(), Error>(())
+                })
+                .await
+        })
+    };
+
+    let second = ctx
+        .locks
+        .scope(None, async {
+            let _rhs = ctx.locks.write(&ctx.rhs).await?;
+            barrier.wait().await;
+            // let the first operation start waiting on rhs.
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            ctx.locks.write(&ctx.lhs).await.map(|_| ())
+        })
+        .await;
+    assert_eq!(second.unwrap_err().kind, Aborted);
+
+    first.await.unwrap()?;
+    assert_eq!(*ctx.lhs.read().await, 1);
+    assert_eq!(*ctx.rhs.read().await, 1);
+    assert!(ctx.locks.state.lock().unwrap().holders.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn upgrade_held_lock_aborts() -> Result<(), Error> {
+    let ctx = setup();
+
+    let result = ctx
+        .locks
+        .scope(None, async {
+            let _lhs = ctx.locks.read(&ctx.lhs).await?;
+            ctx.locks.write(&ctx.lhs).await.map(|_| ())
+        })
+        .await;
+    assert_eq!(result.unwrap_err().kind, Aborted);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn try_read_is_held() -> Result<(), Error> {
+    let ctx = setup();
+
+    let result = ctx
+        .locks
+        .scope(None, async {
+            let _lhs = ctx.locks.try_read(&ctx.lhs).unwrap();
+            ctx.locks.write(&ctx.lhs).await.map(|_| ())
+        })
+        .await;
+    assert_eq!(result.unwrap_err().kind, Aborted);
+
+    let _lhs = ctx.lhs.write().await;
+    assert!(ctx.locks.try_read(&ctx.lhs).is_none());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn lock_timeout_aborts() -> Result<(), Error> {
+    let ctx = setup();
+
+    let _lhs = ctx.lhs.write().await;
+    let result = ctx
+        .locks
+        .scope(Some(Duration::from_millis(10)), async {
+            ctx.locks.read(&ctx.lhs).await.map(|_| ())
+        })
+        .await;
+    assert_eq!(result.unwrap_err().kind, Aborted);
+    assert!(ctx.locks.state.lock().unwrap().waiting.is_empty());
+
+    // other locks are unaffected.
+    ctx.locks
+        .scope(Some(Duration::from_millis(10)), async {
+            ctx.locks.read(&ctx.rhs).await.map(|_| ())
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn joined_futures_wait_separately() -> Result<(), Error> {
+    let ctx = setup();
+    let (locks, lhs) = (&ctx.locks, &ctx.lhs);
+
+    // within a single owner, the second future waiting on lhs would wait on itself.
+    let results = locks
+        .scope(None, async {
+            locks
+                .join_all((0..2).map(|i| async move {
+                    let mut lhs = locks.write(lhs).await?;
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    *lhs += i + 1;
+                    Ok::<(), Error>(())
+                }))
+                .await
+        })
+        .await;
+    for result in results {
+        result?;
+    }
+    assert_eq!(*ctx.lhs.read().await, 3);
+    assert!(ctx.locks.state.lock().unwrap().holders.is_empty());
+
+    Ok(())
+}
@@ -0,0 +1,158 @@
+use crate::protos::generated::chunk::*;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Counters describing the storage of a single table, shared with the buffer pool, which
+// updates them as the table's pages are read and written.
+#[derive(Default)]
+pub(crate) struct TableCounters {
+    pub(crate) bytes_read: AtomicU64,
+    pub(crate) bytes_written: AtomicU64,
+    pub(crate) splits: AtomicU64,
+}
+
+impl TableCounters {
+    pub(crate) fn metrics(&self, name: &str) -> TableMetricsProto {
+        let mut metrics = TableMetricsProto::new();
+        metrics.name = name.to_string();
+        metrics.bytes_read = self.bytes_read.load(Ordering::Relaxed);
+        metrics.bytes_written = self.bytes_written.load(Ordering::Relaxed);
+        metrics.splits = self.splits.load(Ordering::Relaxed);
+        metrics
+    }
+}
+
+// Escapes a label value, e.g. a table name.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// Appends a metric in the Prometheus text format, with a sample per (labels, value) pair:
+// https://prometheus.io/docs/instrumenting/exposition_formats/
+fn write_metric(
+    text: &mut String,
+    name: &str,
+    metric_type: &str,
+    help: &str,
+    samples: &[(String, f64)],
+) {
+    writeln!(text, "# HELP socks_{} {}", name, help).unwrap();
+    writeln!(text, "# TYPE socks_{} {}", name, metric_type).unwrap();
+    for (labels, value) in samples {
+        match labels.is_empty() {
+            true => writeln!(text, "socks_{} {}", name, value).unwrap(),
+            false => writeln!(text, "socks_{}{{{}}} {}", name, labels, value).unwrap(),
+        }
+    }
+}
+
+// Renders the metrics in the Prometheus text format, e.g. to be served for scraping.
+pub(crate) fn to_prometheus(metrics: &DatabaseMetricsProto) -> String {
+    let mut text = String::new();
+    let buffer_pool = &metrics.buffer_pool;
+    let unlabelled = |value: u64| vec![(String::new(), value as f64)];
+    write_metric(
+        &mut text,
+        "buffer_pool_hits_total",
+        "counter",
+        "Pages read from the buffer pool.",
+        &unlabelled(buffer_pool.hits),
+    );
+    write_metric(
+        &mut text,
+        "buffer_pool_misses_total",
+        "counter",
+        "Pages read from disk into the buffer pool.",
+        &unlabelled(buffer_pool.misses),
+    );
+    write_metric(
+        &mut text,
+        "buffer_pool_evictions_total",
+        "counter",
+        "Pages evicted from the buffer pool.",
+        &unlabelled(buffer_pool.evictions),
+    );
+    write_metric(
+        &mut text,
+        "buffer_pool_dirty_writes_total",
+        "counter",
+        "Dirty pages written to disk.",
+        &unlabelled(buffer_pool.dirty_writes),
+    );
+    write_metric(
+        &mut text,
+        "buffer_pool_resident_pages",
+        "gauge",
+        "Pages held in the buffer pool.",
+        &unlabelled(buffer_pool.resident_pages),
+    );
+    write_metric(
+        &mut text,
+        "buffer_pool_resident_bytes",
+        "gauge",
+        "Bytes of pages held in the buffer pool.",
+        &unlabelled(buffer_pool.resident_bytes),
+    );
+    write_metric(
+        &mut text,
+        "buffer_pool_capacity_bytes",
+        "gauge",
+        "Bytes of pages the buffer pool may hold.",
+        &unlabelled(buffer_pool.capacity_bytes),
+    );
+    write_metric(
+        &mut text,
+        "buffer_pool_shard_lock_wait_seconds_total",
+        "counter",
+        "Time spent waiting to lock each buffer pool shard.",
+        &buffer_pool
+            .shard_lock_wait_micros
+            .iter()
+            .enumerate()
+            .map(|(shard, micros)| (format!("shard=\"{}\"", shard), *micros as f64 / 1e6))
+            .collect::<Vec<_>>(),
+    );
+
+    let tables: Vec<&TableMetricsProto> = metrics
+        .table
+        .as_ref()
+        .into_iter()
+        .chain(&metrics.secondary_indexes)
+        .collect();
+    let per_table = |value: fn(&TableMetricsProto) -> u64| {
+        tables
+            .iter()
+            .map(|table| {
+                (
+                    format!("table=\"{}\"", escape(&table.name)),
+                    value(table) as f64,
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    write_metric(
+        &mut text,
+        "table_read_bytes_total",
+        "counter",
+        "Bytes read from each table file.",
+        &per_table(|table| table.bytes_read),
+    );
+    write_metric(
+        &mut text,
+        "table_written_bytes_total",
+        "counter",
+        "Bytes written to each table file.",
+        &per_table(|table| table.bytes_written),
+    );
+    write_metric(
+        &mut text,
+        "table_splits_total",
+        "counter",
+        "B+ tree nodes split in each table.",
+        &per_table(|table| table.splits),
+    );
+    text
+}
@@ -0,0 +1,238 @@
+use crate::error::*;
+use crate::filelike::Filelike;
+use crate::protos::generated::chunk::*;
+use crate::table::Table;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+// Multi-version concurrency control.
+//
+// Transactions never change the rows of tables in place. Instead, each version of a row records
+// the transaction which created it, and the transaction which deleted it (if any). Readers see a
+// snapshot of the database as of when they started: versions created (and not deleted) by
+// transactions which had committed by then, along with the changes of their own transaction.
+// Writers don't need to wait for readers, and versions no snapshot can see are removed later
+// by garbage collection.
+//
+// NOTE: rolled back transactions remove their changes before finishing, so any transaction
+// which has finished is considered committed. The changes of transactions which never finished
+// (e.g. on a crash) are undone when the database is next opened, see undo_uncommitted.
+
+// The deleted_txn_id of versions which haven't been deleted.
+// NOTE: not 0 (the proto3 default), so that the id is always written, and marking a version as
+// deleted doesn't change its size.
+pub(crate) const NOT_DELETED: u64 = u64::MAX;
+
+// The snapshot that reads of the enclosing task are made from.
+// NOTE: a task local is used so that query stages need not be aware of snapshots, as with the
+// profiler. Reads made outside of any snapshot see all versions.
+tokio::task_local! {
+    static SNAPSHOT: Arc<Snapshot>;
+}
+
+pub(crate) fn is_deleted(row: &InternalRowProto) -> bool {
+    row.deleted_txn_id != 0 && row.deleted_txn_id != NOT_DELETED
+}
+
+// Whether the version is visible to the snapshot of the enclosing task (if any).
+pub(crate) fn is_visible(row: &InternalRowProto) -> bool {
+    SNAPSHOT
+        .try_with(|snapshot| snapshot.is_visible(row))
+        .unwrap_or(true)
+}
+
+pub(crate) fn has_snapshot() -> bool {
+    SNAPSHOT.try_with(|_| ()).is_ok()
+}
+
+// Runs the given future with its reads made from the given snapshot.
+pub(crate) async fn with_snapshot<T>(
+    snapshot: Arc<Snapshot>,
+    future: impl Future<Output = T>,
+) -> T {
+    SNAPSHOT.scope(snapshot, future).await
+}
+
+struct TransactionManagerState {
+    // NOTE: 0 is reserved for the absence of a transaction.
+    next_txn_id: u64,
+    in_progress: BTreeSet<u64>,
+    // The number of live snapshots with each xmin.
+    snapshot_xmins: BTreeMap<u64, usize>,
+}
+
+// Assigns transaction ids and tracks which transactions are in progress, and which snapshots
+// are live.
+pub(crate) struct TransactionManager {
+    state: Mutex<TransactionManagerState>,
+}
+
+impl TransactionManager {
+    // NOTE: ids below next_txn_id may already be recorded in tables, e.g. of reopened databases.
+    pub(crate) fn new(next_txn_id: u64) -> Self {
+        Self {
+            state: Mutex::new(TransactionManagerState {
+                next_txn_id,
+                in_progress: BTreeSet::new(),
+                snapshot_xmins: BTreeMap::new(),
+            }),
+        }
+    }
+
+    fn new_snapshot(
+        self: &Arc<Self>,
+        state: &mut TransactionManagerState,
+        txn_id: u64,
+    ) -> Arc<Snapshot> {
+        let mut in_progress = state.in_progress.clone();
+        in_progress.remove(&txn_id);
+        let xmax = state.next_txn_id;
+        let xmin = in_progress.first().copied().unwrap_or(xmax);
+        *state.snapshot_xmins.entry(xmin).or_default() += 1;
+        Arc::new(Snapshot {
+            manager: self.clone(),
+            txn_id,
+            xmin,
+            xmax,
+            in_progress,
+        })
+    }
+
+    // Starts a transaction, returning its snapshot.
+    pub(crate) fn begin(self: &Arc<Self>) -> Arc<Snapshot> {
+        let mut state = self.state.lock().unwrap();
+        let txn_id = state.next_txn_id;
+        state.next_txn_id += 1;
+        state.in_progress.insert(txn_id);
+        self.new_snapshot(&mut state, txn_id)
+    }
+
+    // Returns a snapshot of all transactions committed so far, for reads outside of any
+    // transaction.
+    pub(crate) fn snapshot(self: &Arc<Self>) -> Arc<Snapshot> {
+        let mut state = self.state.lock().unwrap();
+        self.new_snapshot(&mut state, 0)
+    }
+
+    // The lowest transaction id not yet used.
+    pub(crate) fn next_txn_id(&self) -> u64 {
+        self.state.lock().unwrap().next_txn_id
+    }
+
+    // Marks the transaction as finished, i.e. committed or fully rolled back.
+    pub(crate) fn finish(&self, txn_id: u64) {
+        self.state.lock().unwrap().in_progress.remove(&txn_id);
+    }
+
+    // Versions deleted by transactions with lower ids than the horizon aren't visible to any
+    // live (or future) snapshot.
+    pub(crate) fn horizon(&self) -> u64 {
+        let state = self.state.lock().unwrap();
+        [
+            state.snapshot_xmins.keys().next().copied(),
+            state.in_progress.first().copied(),
+        ]
+        .into_iter()
+        .flatten()
+        .fold(state.next_txn_id, std::cmp::min)
+    }
+}
+
+pub(crate) struct Snapshot {
+    manager: Arc<TransactionManager>,
+    // The transaction the snapshot belongs to, or 0 if none.
+    pub(crate) txn_id: u64,
+    // Transactions with lower ids than xmin had finished when the snapshot was taken.
+    xmin: u64,
+    // Transactions with ids xmax or above hadn't started when the snapshot was taken.
+    xmax: u64,
+    // Transactions in progress when the snapshot was taken, other than its own.
+    in_progress: BTreeSet<u64>,
+}
+
+impl Snapshot {
+    // Whether the changes of the given transaction are visible to the snapshot.
+    pub(crate) fn is_committed(&self, txn_id: u64) -> bool {
+        txn_id == 0
+            || txn_id == self.txn_id
+            || txn_id < self.xmin
+            || (txn_id < self.xmax && !self.in_progress.contains(&txn_id))
+    }
+
+    pub(crate) fn is_visible(&self, row: &InternalRowProto) -> bool {
+        self.is_committed(row.created_txn_id)
+            && !(is_deleted(row) && self.is_committed(row.deleted_txn_id))
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        let mut state = self.manager.state.lock().unwrap();
+        if let Some(count) = state.snapshot_xmins.get_mut(&self.xmin) {
+            *count -= 1;
+            if *count == 0 {
+                state.snapshot_xmins.remove(&self.xmin);
+            }
+        }
+    }
+}
+
+// Removes all versions of rows in the table deleted by transactions below the horizon, and
+// returns how many were removed.
+// NOTE: versions are found and removed separately, so that the table isn't locked meanwhile.
+pub(crate) async fn collect_garbage<F: Filelike>(
+    table: &Table<F>,
+    horizon: u64,
+) -> Result<usize, Error> {
+    let mut garbage = Vec::new();
+    let mut cursor = table.read_range(0, u32::MAX);
+    while let Some((keys, rows)) = cursor.next_leaf().await? {
+        garbage.extend(
+            keys.into_iter()
+                .zip(rows)
+                .filter(|(_, row)| is_deleted(row) && row.deleted_txn_id < horizon),
+        );
+    }
+    for (key, row) in &garbage {
+        table.purge(*key, row).await?;
+    }
+    if !garbage.is_empty() {
+        log::trace!("Removed {} versions from {}", garbage.len(), table.name);
+    }
+    Ok(garbage.len())
+}
+
+// Undoes the changes of transactions which never committed (given the ids of all those which
+// did), e.g. whose versions were written to disk before a crash, and returns how many versions
+// were changed: versions they created are removed, and those they deleted are restored.
+// NOTE: every version is read, as such versions may have been written anywhere in the table.
+pub(crate) async fn undo_uncommitted<F: Filelike>(
+    table: &Table<F>,
+    committed: &HashSet<u64>,
+) -> Result<usize, Error> {
+    let is_committed = |txn_id| txn_id == 0 || committed.contains(&txn_id);
+    let mut uncommitted = Vec::new();
+    let mut cursor = table.read_range(0, u32::MAX).all_versions();
+    while let Some((keys, rows)) = cursor.next_leaf().await? {
+        uncommitted.extend(keys.into_iter().zip(rows).filter(|(_, row)| {
+            !is_committed(row.created_txn_id)
+                || (is_deleted(row) && !is_committed(row.deleted_txn_id))
+        }));
+    }
+    for (key, row) in &uncommitted {
+        if is_committed(row.created_txn_id) {
+            table.unmark_deleted(*key, row).await?;
+        } else {
+            table.delete_matching(*key, row).await?;
+        }
+    }
+    if !uncommitted.is_empty() {
+        log::trace!(
+            "Undid {} uncommitted versions in {}",
+            uncommitted.len(),
+            table.name
+        );
+    }
+    Ok(uncommitted.len())
+}
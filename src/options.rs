@@ -0,0 +1,246 @@
+use crate::error::{ErrorKind::*, *};
+use crate::protos::generated::config::*;
+use crate::BUFFER_OVERFLOW_BUFFER;
+use std::time::Duration;
+
+// Chunks start with their size as a u16, so they can't usefully be larger than this.
+const MAX_PAGE_SIZE: usize = u16::MAX as usize + std::mem::size_of::<u16>();
+// Small enough to test splits with, large enough for table metadata.
+const MIN_PAGE_SIZE: usize = 512;
+
+// Configurable read strategies for table B+ tree traversal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReadStrategy {
+    SequentialSearch,
+    BinarySearch,
+}
+
+// Configurable write strategies for B+ tree insertion.
+//
+// AggressiveSplit write locks every node from the root down, splitting full
+// nodes on the way. BLinkTree only write locks the nodes it modifies, so
+// concurrent inserts (and reads) rarely wait on each other.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WriteStrategy {
+    AggressiveSplit,
+    BLinkTree,
+}
+
+// Configurable deletion strategies for B+ tree removal.
+//
+// TODO: research deletion algorithms that are better for concurrency.
+// Many examples require bottom-up recursion which may cause deadlocks without
+// blocking the whole table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeleteStrategy {
+    UnbalancedDelete,
+}
+
+// Configurable replacement policies, choosing which page to evict from a full buffer pool
+// shard, see replacer/mod.rs.
+//
+// Lru evicts the least recently used page, so a single scan of a table larger than the buffer
+// pool evicts every other page. Clock approximates Lru more cheaply. LruK(k) and TwoQueue are
+// scan resistant: pages read only once are evicted before pages read repeatedly.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReplacementPolicy {
+    Lru,
+    Clock,
+    LruK(usize),
+    TwoQueue,
+}
+
+// Options a database is created or opened with, see Database::create and Database::open.
+// Defaults are used for any options not set, e.g.
+// DatabaseOptions { page_size: 8192, ..Default::default() }.
+#[derive(Clone, Debug)]
+pub struct DatabaseOptions {
+    // The size of each file chunk, in bytes. Influences various parts of the
+    // database, e.g. the size of each B+ tree node, or how many query results are
+    // grouped together. Each chunk stores 1 protobuf, prefixed by its size as a u16,
+    // so the value must be between 512 bytes and 64KiB (+2).
+    // NOTE: tables keep the page size they were created with, see TableConfig.
+    pub page_size: usize,
+
+    // A cache is used to speed up read / write operations to frequently accessed
+    // chunks. It is sharded to lower thread contention.
+    pub buffer_pool_shard_count: usize,
+
+    // The total size (in bytes) of the chunks the cache holds, across all shards, before
+    // evicting a chunk chosen by the replacement policy. Chunks in use (i.e. pinned) are never
+    // evicted, so must fit within this budget too.
+    pub buffer_pool_size: usize,
+
+    pub replacement_policy: ReplacementPolicy,
+
+    // How long reads wait for chunks to be unpinned when the cache is full of pinned chunks,
+    // before failing with ResourceExhausted.
+    pub buffer_pool_wait_timeout: Duration,
+
+    // How many chunks ahead of range scans (leaves) and selects (rows) are read into the cache
+    // in the background, so that they are already cached when reached. 0 disables read-ahead.
+    // NOTE: read-ahead never waits for pinned chunks, nor evicts chunks in use.
+    pub read_ahead_window: usize,
+
+    // Every dirty_page_write_interval, up to dirty_page_write_batch dirty chunks are written to
+    // disk in the background, so that reads rarely need to write chunks they evict.
+    pub dirty_page_write_interval: Duration,
+    pub dirty_page_write_batch: usize,
+
+    // How often all dirty chunks are written to disk and a checkpoint is recorded in the
+    // write-ahead log, see Database::checkpoint.
+    pub checkpoint_interval: Duration,
+
+    // How full (as a fraction of the page size) bulk loads fill each node, see
+    // Database::bulk_load. Room left in nodes is taken by later inserts without splitting.
+    pub bulk_load_fill_factor: f64,
+
+    // The approximate memory (in bytes) hash-based group bys hold groups in. Rows of any
+    // further groups are spilled to disk, split into group_by_partition_count partitions that
+    // are each grouped separately.
+    pub group_by_memory_budget: usize,
+    pub group_by_partition_count: usize,
+
+    // The number of rows sorted in memory at once, e.g. when ordering query results. Larger
+    // results are spilled to disk as sorted runs of this size, which are then merged.
+    pub sort_run_size: usize,
+
+    // The number of buckets in the equi-depth histograms built when analyzing a table. More
+    // buckets give better row estimates for skewed keys, at the cost of larger table metadata.
+    pub statistics_histogram_buckets: usize,
+
+    // How often versions of rows which are no longer visible to any snapshot are removed from
+    // tables in the background.
+    pub garbage_collection_interval: Duration,
+
+    // How long operations wait on any single lock (e.g. of a B+ tree node) before aborting, or
+    // None to wait indefinitely. Deadlocks are detected regardless. Transactions may override
+    // this, see Transaction::set_lock_timeout.
+    pub lock_wait_timeout: Option<Duration>,
+
+    // When searching through table B+ tree nodes using a binary search, this is the
+    // number of remaining elements left until the algorithm switches to a sequential
+    // search. This is better for cache coherence when sufficiently low.
+    pub binary_read_iter_cutoff: usize,
+
+    pub read_strategy: ReadStrategy,
+    pub write_strategy: WriteStrategy,
+    pub delete_strategy: DeleteStrategy,
+}
+
+impl Default for DatabaseOptions {
+    fn default() -> Self {
+        Self {
+            page_size: 4096,
+            buffer_pool_shard_count: 16,
+            buffer_pool_size: 16 * 16 * 4096,
+            replacement_policy: ReplacementPolicy::Lru,
+            buffer_pool_wait_timeout: Duration::from_secs(1),
+            read_ahead_window: 8,
+            dirty_page_write_interval: Duration::from_millis(100),
+            dirty_page_write_batch: 16,
+            checkpoint_interval: Duration::from_secs(60),
+            bulk_load_fill_factor: 0.9,
+            group_by_memory_budget: 1 << 20,
+            group_by_partition_count: 8,
+            sort_run_size: 1024,
+            statistics_histogram_buckets: 32,
+            garbage_collection_interval: Duration::from_secs(1),
+            lock_wait_timeout: None,
+            binary_read_iter_cutoff: 100,
+            read_strategy: ReadStrategy::BinarySearch,
+            write_strategy: WriteStrategy::AggressiveSplit,
+            delete_strategy: DeleteStrategy::UnbalancedDelete,
+        }
+    }
+}
+
+impl DatabaseOptions {
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        if !(MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&self.page_size) {
+            return Err(Error::new(
+                InvalidArgument,
+                format!(
+                    "Page size must be between {} and {} bytes, got {}!",
+                    MIN_PAGE_SIZE, MAX_PAGE_SIZE, self.page_size
+                ),
+            ));
+        }
+        if self.buffer_pool_shard_count == 0 {
+            return Err(Error::new(
+                InvalidArgument,
+                "Buffer pool shard count must be positive!".to_string(),
+            ));
+        }
+        if self.buffer_pool_size < self.page_size {
+            return Err(Error::new(
+                InvalidArgument,
+                format!(
+                    "Buffer pool size must be at least the page size ({} bytes), got {}!",
+                    self.page_size, self.buffer_pool_size
+                ),
+            ));
+        }
+        if self.dirty_page_write_interval.is_zero()
+            || self.dirty_page_write_batch == 0
+            || self.checkpoint_interval.is_zero()
+        {
+            return Err(Error::new(
+                InvalidArgument,
+                "Dirty page write and checkpoint intervals and batch size must be positive!"
+                    .to_string(),
+            ));
+        }
+        if !(self.bulk_load_fill_factor > 0.0 && self.bulk_load_fill_factor <= 1.0) {
+            return Err(Error::new(
+                InvalidArgument,
+                format!(
+                    "Bulk load fill factor must be in (0, 1], got {}!",
+                    self.bulk_load_fill_factor
+                ),
+            ));
+        }
+        if self.group_by_memory_budget == 0 || self.group_by_partition_count == 0 {
+            return Err(Error::new(
+                InvalidArgument,
+                "Group by memory budget and partition count must be positive!".to_string(),
+            ));
+        }
+        if self.sort_run_size == 0 || self.statistics_histogram_buckets == 0 {
+            return Err(Error::new(
+                InvalidArgument,
+                "Sort run size and statistics histogram buckets must be positive!".to_string(),
+            ));
+        }
+        if self.garbage_collection_interval.is_zero() {
+            return Err(Error::new(
+                InvalidArgument,
+                "Garbage collection interval must be positive!".to_string(),
+            ));
+        }
+        if self.replacement_policy == ReplacementPolicy::LruK(0) {
+            return Err(Error::new(
+                InvalidArgument,
+                "LRU-K replacement requires k to be positive!".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    // The chunk geometry of files created with these options.
+    pub(crate) fn table_config(&self) -> TableConfig {
+        let mut config = TableConfig::new();
+        config.chunk_size = self.page_size as u32;
+        config.chunk_overflow_size = BUFFER_OVERFLOW_BUFFER as u32;
+        config
+    }
+}
+
+// A chunk geometry large enough to read the first chunk of any file, i.e. before its page size
+// is known.
+pub(crate) fn max_table_config() -> TableConfig {
+    let mut config = TableConfig::new();
+    config.chunk_size = MAX_PAGE_SIZE as u32;
+    config.chunk_overflow_size = BUFFER_OVERFLOW_BUFFER as u32;
+    config
+}
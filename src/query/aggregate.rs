@@ -0,0 +1,229 @@
+use crate::database::*;
+use crate::error::{ErrorKind::*, *};
+use crate::filelike::Filelike;
+use crate::protos::generated::operations::aggregate_proto::aggregate_column_proto::AggregateFunction;
+use crate::protos::generated::operations::aggregate_proto::AggregateColumnProto;
+use crate::protos::generated::operations::*;
+use crate::query;
+use crate::query::filter;
+use crate::query::{reader::ResultsReader, writer::ResultsWriter};
+use crate::schema;
+use protobuf::MessageField;
+
+enum Accumulator {
+    Count(i64),
+    Sum(i64),
+    Min(Option<ValueProto>),
+    Max(Option<ValueProto>),
+    Avg { sum: i64, count: i64 },
+}
+
+// Computes a set of aggregates over a stream of rows.
+pub(crate) struct Aggregator {
+    aggregates: Vec<AggregateColumnProto>,
+    accumulators: Vec<Accumulator>,
+}
+
+impl Aggregator {
+    pub(crate) fn new(aggregates: &[AggregateColumnProto]) -> Self {
+        let accumulators = aggregates
+            .iter()
+            .map(
+                |aggregate| match aggregate.function.enum_value_or_default() {
+                    AggregateFunction::COUNT => Accumulator::Count(0),
+                    AggregateFunction::SUM => Accumulator::Sum(0),
+                    AggregateFunction::MIN => Accumulator::Min(None),
+                    AggregateFunction::MAX => Accumulator::Max(None),
+                    AggregateFunction::AVG => Accumulator::Avg { sum: 0, count: 0 },
+                    AggregateFunction::UNDEFINED => unreachable!(),
+                },
+            )
+            .collect();
+        Self {
+            aggregates: aggregates.to_vec(),
+            accumulators,
+        }
+    }
+
+    // Returns the columns that rows passed to update must contain.
+    pub(crate) fn required_columns(&self) -> Vec<String> {
+        self.aggregates
+            .iter()
+            .filter(|aggregate| !aggregate.name.is_empty())
+            .map(|aggregate| aggregate.name.clone())
+            .collect()
+    }
+
+    pub(crate) fn update(&mut self, row: &RowProto) {
+        for (aggregate, accumulator) in self.aggregates.iter().zip(self.accumulators.iter_mut()) {
+            if aggregate.name.is_empty() {
+                if let Accumulator::Count(count) = accumulator {
+                    *count += 1;
+                }
+                continue;
+            }
+            let value = schema::get_col(row, &aggregate.name)
+                .value
+                .as_ref()
+                .unwrap();
+            match accumulator {
+                Accumulator::Count(count) => *count += 1,
+                Accumulator::Sum(sum) => *sum += schema::get_col_value_as_i64(value),
+                Accumulator::Min(min) => {
+                    if min.as_ref().is_none_or(|min| {
+                        schema::get_col_value_as_i64(value) < schema::get_col_value_as_i64(min)
+                    }) {
+                        *min = Some(value.clone());
+                    }
+                }
+                Accumulator::Max(max) => {
+                    if max.as_ref().is_none_or(|max| {
+                        schema::get_col_value_as_i64(value) > schema::get_col_value_as_i64(max)
+                    }) {
+                        *max = Some(value.clone());
+                    }
+                }
+                Accumulator::Avg { sum, count } => {
+                    *sum += schema::get_col_value_as_i64(value);
+                    *count += 1;
+                }
+            }
+        }
+    }
+
+    // Updates all aggregates as if the given number of rows were seen.
+    // NOTE: Expects that no columns are required, i.e. all aggregates are COUNT(*).
+    pub(crate) fn update_many(&mut self, num_rows: usize) {
+        debug_assert!(self.required_columns().is_empty());
+        for accumulator in self.accumulators.iter_mut() {
+            if let Accumulator::Count(count) = accumulator {
+                *count += num_rows as i64;
+            }
+        }
+    }
+
+    // Approximates the memory (in bytes) the aggregator's state takes.
+    pub(crate) fn size(&self) -> usize {
+        let aggregates_size: usize = self
+            .aggregates
+            .iter()
+            .map(|aggregate| std::mem::size_of_val(aggregate) + aggregate.name.len())
+            .sum();
+        std::mem::size_of::<Self>()
+            + aggregates_size
+            + self.accumulators.len() * std::mem::size_of::<Accumulator>()
+    }
+
+    // Returns a row with one column per aggregate. Aggregates without a defined value (e.g. the
+    // MIN of no rows) are left unset.
+    pub(crate) fn finish(self) -> RowProto {
+        let mut row = RowProto::new();
+        for (aggregate, accumulator) in self.aggregates.iter().zip(self.accumulators) {
+            let mut value = ValueProto::new();
+            match accumulator {
+                Accumulator::Count(count) => value.set_long_value(count),
+                Accumulator::Sum(sum) => value.set_long_value(sum),
+                Accumulator::Min(Some(min)) => value = min,
+                Accumulator::Max(Some(max)) => value = max,
+                Accumulator::Avg { sum, count } if count > 0 => {
+                    value.set_double_value(sum as f64 / count as f64)
+                }
+                _ => {}
+            }
+            let mut column = ColumnProto::new();
+            column.name = aggregate_col_name(aggregate);
+            column.value = MessageField::some(value);
+            row.columns.push(column);
+        }
+        row
+    }
+}
+
+// e.g. COUNT(*), SUM(Value).
+pub(crate) fn aggregate_col_name(aggregate: &AggregateColumnProto) -> String {
+    let function = match aggregate.function.enum_value_or_default() {
+        AggregateFunction::COUNT => "COUNT",
+        AggregateFunction::SUM => "SUM",
+        AggregateFunction::MIN => "MIN",
+        AggregateFunction::MAX => "MAX",
+        AggregateFunction::AVG => "AVG",
+        AggregateFunction::UNDEFINED => unreachable!(),
+    };
+    let name = if aggregate.name.is_empty() {
+        "*"
+    } else {
+        aggregate.name.as_str()
+    };
+    format!("{}({})", function, name)
+}
+
+pub(crate) fn validate_aggregates<F: Filelike>(
+    db: &Database<F>,
+    aggregates: &[AggregateColumnProto],
+) -> Result<(), Error> {
+    for aggregate in aggregates {
+        let function = aggregate.function.enum_value().map_err(|e| {
+            Error::new(InvalidArgument, format!("Unknown aggregate function: {e}!"))
+        })?;
+        if function == AggregateFunction::UNDEFINED {
+            return Err(Error::new(
+                InvalidArgument,
+                "Aggregate function not specified!".to_string(),
+            ));
+        }
+        if aggregate.name.is_empty() {
+            if function != AggregateFunction::COUNT {
+                return Err(Error::new(
+                    InvalidArgument,
+                    format!("{:?} requires a column!", function),
+                ));
+            }
+        } else if !schema::is_col_in_table(&db.table.schema, &aggregate.name) {
+            return Err(Error::new(
+                InvalidArgument,
+                format!("Column not in table: {}!", aggregate.name),
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub(crate) async fn execute_aggregate<F: Filelike>(
+    db: &Database<F>,
+    aggregate: AggregateProto,
+) -> Result<F, Error> {
+    if aggregate.aggregates.is_empty() {
+        return Err(Error::new(
+            InvalidArgument,
+            "Aggregate requires at least one aggregate column!".to_string(),
+        ));
+    }
+    validate_aggregates(db, &aggregate.aggregates)?;
+
+    let mut aggregator = Aggregator::new(&aggregate.aggregates);
+    let required_columns = aggregator.required_columns();
+    let dep = aggregate.dep.unwrap();
+    match &dep.stage_type {
+        // COUNT(*) over a range can be answered by counting keys directly in the table / index,
+        // without materializing (and sorting) the filter results.
+        Some(query_proto::Stage_type::Filter(filter)) if required_columns.is_empty() => {
+            aggregator.update_many(filter::count_filter(db, filter).await?);
+        }
+        _ => {
+            let mut dep =
+                ResultsReader::new(query::execute_query(db, dep).await?, &db.table.config);
+            while let Some((key, dep_row)) = dep.next_key_row().await? {
+                if required_columns.is_empty() {
+                    aggregator.update_many(1);
+                    continue;
+                }
+                let row = query::resolve_row(db, key, dep_row, &required_columns).await?;
+                aggregator.update(&row);
+            }
+        }
+    }
+
+    let mut out = ResultsWriter::new(F::temp().await?, &db.table.config);
+    out.write_key_row(0, aggregator.finish()).await?;
+    out.finish().await
+}
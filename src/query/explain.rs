@@ -0,0 +1,365 @@
+use crate::database::*;
+use crate::error::*;
+use crate::filelike::Filelike;
+use crate::protos::generated::operations::*;
+use crate::query;
+use crate::query::aggregate;
+use crate::query::join;
+use crate::query::planner;
+use crate::query::reader::ResultsReader;
+use crate::query::StageBounds;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Stages executed while a query is analyzed are recorded by the profiler of the enclosing task.
+// NOTE: a task local is used so that stages need not be aware of profiling. This relies on stages
+// executing their dependencies on the same task.
+tokio::task_local! {
+    static PROFILER: Arc<Mutex<Profiler>>;
+}
+
+// Work done by the profiler itself (e.g. estimating rows), excluded from the stats of stages.
+#[derive(Clone, Copy, Default)]
+struct Overhead {
+    hits: u64,
+    misses: u64,
+    time: Duration,
+}
+
+#[derive(Default)]
+struct Profiler {
+    // The stages currently executing, innermost last, along with their dependencies so far.
+    stack: Vec<QueryPlanProto>,
+    root: Option<QueryPlanProto>,
+    overhead: Overhead,
+}
+
+impl Profiler {
+    fn add_overhead<F: Filelike>(
+        &mut self,
+        db: &Database<F>,
+        start: Instant,
+        hits: u64,
+        misses: u64,
+    ) {
+        let stats = db.table.buffer_pool.stats();
+        self.overhead.hits += stats.hits - hits;
+        self.overhead.misses += stats.misses - misses;
+        self.overhead.time += start.elapsed();
+    }
+}
+
+pub(crate) fn is_profiling() -> bool {
+    PROFILER.try_with(|_| ()).is_ok()
+}
+
+fn format_value(value: &ValueProto) -> String {
+    match value.value_type {
+        Some(value_proto::Value_type::IntValue(value)) => value.to_string(),
+        Some(value_proto::Value_type::UintValue(value)) => value.to_string(),
+        Some(value_proto::Value_type::LongValue(value)) => value.to_string(),
+        Some(value_proto::Value_type::DoubleValue(value)) => value.to_string(),
+        None => "NULL".to_string(),
+    }
+}
+
+fn format_filter(filter: &FilterProto) -> String {
+    match &filter.filter_type {
+        Some(filter_proto::Filter_type::Equals(equals)) => {
+            format!("{} = {}", equals.name, format_value(&equals.value))
+        }
+        Some(filter_proto::Filter_type::InRange(in_range)) => format!(
+            "{} in [{}, {}]",
+            in_range.name,
+            in_range
+                .lower_value
+                .as_ref()
+                .map_or("-inf".to_string(), format_value),
+            in_range
+                .upper_value
+                .as_ref()
+                .map_or("inf".to_string(), format_value)
+        ),
+        None => String::new(),
+    }
+}
+
+fn format_predicate(predicate: &PredicateProto) -> String {
+    let (predicates, separator) = match &predicate.predicate_type {
+        Some(predicate_proto::Predicate_type::And(and)) => (&and.predicates, " AND "),
+        Some(predicate_proto::Predicate_type::Or(or)) => (&or.predicates, " OR "),
+        Some(predicate_proto::Predicate_type::Filter(filter)) => return format_filter(filter),
+        None => return String::new(),
+    };
+    predicates
+        .iter()
+        .map(|predicate| match predicate.predicate_type {
+            Some(predicate_proto::Predicate_type::Filter(_)) => format_predicate(predicate),
+            _ => format!("({})", format_predicate(predicate)),
+        })
+        .collect::<Vec<String>>()
+        .join(separator)
+}
+
+fn new_plan(stage: &str, detail: String, deps: Vec<QueryPlanProto>) -> QueryPlanProto {
+    let mut plan = QueryPlanProto::new();
+    plan.stage = stage.to_string();
+    plan.detail = detail;
+    plan.deps = deps;
+    plan
+}
+
+// Describes the given stage and its dependencies, without executing them. Rows produced by filters
+// and scans are estimated from table statistics, other stages' estimates are derived from their
+// dependencies.
+pub(crate) async fn explain_stage<F: Filelike>(
+    db: &Database<F>,
+    query: &QueryProto,
+) -> Result<QueryPlanProto, Error> {
+    let plan = match &query.stage_type {
+        Some(query_proto::Stage_type::Intersect(op)) => {
+            let deps = vec![
+                Box::pin(explain_stage(db, &op.lhs)).await?,
+                Box::pin(explain_stage(db, &op.rhs)).await?,
+            ];
+            // NOTE: inputs are assumed to be independent.
+            let num_rows = std::cmp::max(db.table.statistics().row_count, 1);
+            let estimated_rows = deps[0].estimated_rows * deps[1].estimated_rows / num_rows;
+            let mut plan = new_plan("Intersect", String::new(), deps);
+            plan.estimated_rows = estimated_rows;
+            plan
+        }
+        Some(query_proto::Stage_type::Filter(op)) => {
+            let name = match &op.filter_type {
+                Some(filter_proto::Filter_type::Equals(equals)) => &equals.name,
+                Some(filter_proto::Filter_type::InRange(in_range)) => &in_range.name,
+                None => panic!(),
+            };
+            let table = db.find_table_keyed_on_column(name)?;
+            let mut plan = new_plan("Filter", format_filter(op), Vec::new());
+            plan.table = table.name.clone();
+            plan.estimated_rows = planner::estimate_filter_rows(&table, op).round() as u64;
+            plan
+        }
+        Some(query_proto::Stage_type::Select(op)) => {
+            let dep = Box::pin(explain_stage(db, &op.dep)).await?;
+            let detail = if op.columns.is_empty() {
+                "*".to_string()
+            } else {
+                op.columns.join(", ")
+            };
+            let mut plan = new_plan("Select", detail, Vec::new());
+            plan.estimated_rows = dep.estimated_rows;
+            plan.deps.push(dep);
+            plan
+        }
+        Some(query_proto::Stage_type::OrderBy(op)) => {
+            let dep = Box::pin(explain_stage(db, &op.dep)).await?;
+            let detail = op
+                .columns
+                .iter()
+                .map(|col| match col.descending {
+                    true => format!("{} DESC", col.name),
+                    false => col.name.clone(),
+                })
+                .collect::<Vec<String>>()
+                .join(", ");
+            let mut plan = new_plan("OrderBy", detail, Vec::new());
+            plan.estimated_rows = dep.estimated_rows;
+            plan.deps.push(dep);
+            plan
+        }
+        Some(query_proto::Stage_type::Limit(op)) => {
+            let dep = Box::pin(explain_stage(db, &op.dep)).await?;
+            let detail = format!("limit {} offset {}", op.limit, op.offset);
+            let mut plan = new_plan("Limit", detail, Vec::new());
+            plan.estimated_rows = std::cmp::min(
+                op.limit as u64,
+                dep.estimated_rows.saturating_sub(op.offset as u64),
+            );
+            plan.deps.push(dep);
+            plan
+        }
+        Some(query_proto::Stage_type::Aggregate(op)) => {
+            aggregate::validate_aggregates(db, &op.aggregates)?;
+            let dep = Box::pin(explain_stage(db, &op.dep)).await?;
+            let detail = op
+                .aggregates
+                .iter()
+                .map(aggregate::aggregate_col_name)
+                .collect::<Vec<String>>()
+                .join(", ");
+            let mut plan = new_plan("Aggregate", detail, vec![dep]);
+            plan.estimated_rows = 1;
+            plan
+        }
+        Some(query_proto::Stage_type::GroupBy(op)) => {
+            aggregate::validate_aggregates(db, &op.aggregates)?;
+            let dep = Box::pin(explain_stage(db, &op.dep)).await?;
+            let aggregates = op
+                .aggregates
+                .iter()
+                .map(aggregate::aggregate_col_name)
+                .collect::<Vec<String>>();
+            let detail = format!("{}: {}", op.columns.join(", "), aggregates.join(", "));
+            let mut plan = new_plan("GroupBy", detail, Vec::new());
+            // NOTE: an upper bound, every row may be in its own group.
+            plan.estimated_rows = dep.estimated_rows;
+            plan.deps.push(dep);
+            plan
+        }
+        Some(query_proto::Stage_type::Join(op)) => {
+            let lhs = Box::pin(explain_stage(db, &op.lhs)).await?;
+            let join_type = match op.join_type.enum_value_or_default() {
+                join_proto::JoinType::INNER => "inner",
+                join_proto::JoinType::LEFT_OUTER => "left outer",
+            };
+            let condition = format!("lhs.{} = rhs.{}", op.lhs_column, op.rhs_column);
+            match join::choose_index_nested_loop(db, op)? {
+                Some(index_nested_loop) => {
+                    let mut detail = format!("{} index nested loop on {}", join_type, condition);
+                    if let Some(predicate) = &index_nested_loop.rhs_predicate {
+                        detail += &format!(" where rhs {}", format_predicate(predicate));
+                    }
+                    let mut plan = new_plan("Join", detail, Vec::new());
+                    plan.table = index_nested_loop.inner.name.clone();
+                    plan.estimated_rows = lhs.estimated_rows;
+                    plan.deps.push(lhs);
+                    plan
+                }
+                _ => {
+                    let mut scan = QueryProto::new();
+                    scan.set_scan(ScanProto::new());
+                    let rhs = op.rhs.as_ref().unwrap_or(&scan);
+                    let rhs = Box::pin(explain_stage(db, rhs)).await?;
+                    let detail = format!("{} sort merge on {}", join_type, condition);
+                    let mut plan = new_plan("Join", detail, Vec::new());
+                    plan.estimated_rows = std::cmp::max(lhs.estimated_rows, rhs.estimated_rows);
+                    plan.deps = vec![lhs, rhs];
+                    plan
+                }
+            }
+        }
+        Some(query_proto::Stage_type::Union(op)) => {
+            let deps = vec![
+                Box::pin(explain_stage(db, &op.lhs)).await?,
+                Box::pin(explain_stage(db, &op.rhs)).await?,
+            ];
+            let estimated_rows = std::cmp::min(
+                deps.iter().map(|dep| dep.estimated_rows).sum(),
+                db.table.statistics().row_count,
+            );
+            let mut plan = new_plan("Union", String::new(), deps);
+            plan.estimated_rows = estimated_rows;
+            plan
+        }
+        Some(query_proto::Stage_type::Scan(op)) => {
+            let detail = op
+                .predicate
+                .as_ref()
+                .map(format_predicate)
+                .unwrap_or_default();
+            let mut plan = new_plan("Scan", detail, Vec::new());
+            let num_rows = match op.dep.as_ref() {
+                Some(dep) => {
+                    let dep = Box::pin(explain_stage(db, dep)).await?;
+                    let num_rows = dep.estimated_rows;
+                    plan.deps.push(dep);
+                    num_rows
+                }
+                None => {
+                    plan.table = db.table.name.clone();
+                    db.table.statistics().row_count
+                }
+            };
+            plan.estimated_rows = match op.predicate.is_some() {
+                true => (num_rows as f64 * planner::RESIDUAL_SELECTIVITY).ceil() as u64,
+                false => num_rows,
+            };
+            plan
+        }
+        Some(query_proto::Stage_type::Predicate(op)) => {
+            let physical_plan = planner::plan_predicate(db, op.clone())?;
+            let dep = Box::pin(explain_stage(db, &physical_plan)).await?;
+            let mut plan = new_plan("Predicate", format_predicate(op), Vec::new());
+            plan.estimated_rows = dep.estimated_rows;
+            plan.deps.push(dep);
+            plan
+        }
+        None => panic!(),
+    };
+    Ok(plan)
+}
+
+// Executes the given stage, recording its stats with the enclosing task's profiler.
+pub(crate) async fn execute_profiled_stage<F: Filelike>(
+    db: &Database<F>,
+    query: QueryProto,
+    bounds: StageBounds,
+) -> Result<F, Error> {
+    let profiler = PROFILER.with(|profiler| profiler.clone());
+    let buffer_pool = &db.table.buffer_pool;
+
+    let overhead_start = Instant::now();
+    let stats = buffer_pool.stats();
+    let mut plan = explain_stage(db, &query).await?;
+    // NOTE: dependencies are recorded as they execute instead.
+    plan.deps.clear();
+    let overhead = {
+        let mut profiler = profiler.lock().unwrap();
+        profiler.add_overhead(db, overhead_start, stats.hits, stats.misses);
+        profiler.stack.push(plan);
+        profiler.overhead
+    };
+
+    let start = Instant::now();
+    let start_stats = buffer_pool.stats();
+    let result = query::dispatch_stage(db, query, bounds).await;
+    let wall_time = start.elapsed();
+    let end_stats = buffer_pool.stats();
+    let mut plan = profiler.lock().unwrap().stack.pop().unwrap();
+    let output = result?;
+
+    let overhead_start = Instant::now();
+    let mut reader = ResultsReader::new(output, &db.table.config);
+    let mut num_rows = 0;
+    while reader.next_key_row().await?.is_some() {
+        num_rows += 1;
+    }
+    let output = reader.into_inner();
+
+    let mut profiler = profiler.lock().unwrap();
+    let hits = end_stats.hits - start_stats.hits - (profiler.overhead.hits - overhead.hits);
+    let misses =
+        end_stats.misses - start_stats.misses - (profiler.overhead.misses - overhead.misses);
+    let stats = plan.stats.mut_or_insert_default();
+    stats.rows = num_rows;
+    stats.pages_read = hits + misses;
+    stats.cache_hits = hits;
+    stats.cache_misses = misses;
+    stats.wall_time_micros =
+        (wall_time - (profiler.overhead.time - overhead.time)).as_micros() as u64;
+    match profiler.stack.last_mut() {
+        Some(parent) => parent.deps.push(plan),
+        None => profiler.root = Some(plan),
+    }
+    // NOTE: counting rows doesn't touch the buffer pool.
+    profiler.overhead.time += overhead_start.elapsed();
+    Ok(output)
+}
+
+// Executes the query, returning its plan annotated with the stats of each executed stage.
+pub(crate) async fn explain_analyze<F: Filelike>(
+    db: &Database<F>,
+    query: QueryProto,
+) -> Result<QueryPlanProto, Error> {
+    let profiler = Arc::new(Mutex::new(Profiler::default()));
+    PROFILER
+        .scope(
+            profiler.clone(),
+            query::execute_stage(db, query, StageBounds::default()),
+        )
+        .await?;
+    let root = profiler.lock().unwrap().root.take().unwrap();
+    Ok(root)
+}
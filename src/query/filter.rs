@@ -1,8 +1,11 @@
 use crate::database::Database;
-use crate::error::*;
+use crate::error::{ErrorKind::*, *};
 use crate::filelike::Filelike;
+use crate::protos::generated::chunk::*;
 use crate::protos::generated::operations::*;
+use crate::query::sort::ExternalSorter;
 use crate::query::writer::ResultsWriter;
+use crate::query::StageBounds;
 use crate::schema;
 use crate::table::Table;
 use std::sync::Arc;
@@ -10,6 +13,7 @@ use std::sync::Arc;
 async fn execute_filter_equals<F: Filelike>(
     db: &Database<F>,
     equals: filter_proto::FilterEqualsProto,
+    bounds: StageBounds,
 ) -> Result<F, Error> {
     let table: Arc<Table<F>> = db.find_table_keyed_on_column(&equals.name)?;
     log::trace!(
@@ -18,32 +22,121 @@ async fn execute_filter_equals<F: Filelike>(
         table.name,
     );
 
-    // TODO: return empty on doesn't exist instead of error.
     let key = schema::get_hashed_col_value(&equals.value);
-    let row = table.read_row(key).await?;
+    let mut out = ResultsWriter::new(F::temp().await?, &db.table.config);
+    let row = match table.read_row(key).await {
+        Ok(row) => row,
+        Err(e) if e.kind == NotFound => return out.finish().await,
+        Err(e) => return Err(e),
+    };
     let pk = schema::get_col(&row, &db.table.schema.key.name);
     let pk_hash = schema::get_hashed_col_value(&pk.value);
 
-    let mut out = ResultsWriter::new(F::create("TODO").await?);
-    out.write_key(pk_hash).await?;
+    // NOTE: the row read is forwarded so that later stages may avoid re-reading
+    // it, e.g. a select that only needs columns stored in this index.
+    if bounds.is_key_in_bounds(pk_hash) && !bounds.is_limit_reached(0) {
+        out.write_key_row(pk_hash, row).await?;
+    }
     out.finish().await
 }
 
-fn execute_filter_in_range<F: Filelike>(
-    _db: &Database<F>,
-    _in_range: filter_proto::FilterInRangeProto,
+async fn execute_filter_in_range<F: Filelike>(
+    db: &Database<F>,
+    in_range: filter_proto::FilterInRangeProto,
+    bounds: StageBounds,
 ) -> Result<F, Error> {
-    // TODO: current api returns the row only, for this we need to keep state.
-    todo!()
+    let table: Arc<Table<F>> = db.find_table_keyed_on_column(&in_range.name)?;
+    log::trace!(
+        "Filtering on range of column: {} in table: {}",
+        in_range.name,
+        table.name,
+    );
+
+    let mut lower = schema::get_hashed_col_value(&in_range.lower_value);
+    let mut upper = schema::get_hashed_col_value(&in_range.upper_value);
+    let mut out = ResultsWriter::new(F::temp().await?, &db.table.config);
+    if Arc::ptr_eq(&table, &db.table) {
+        if let Some(after_key) = bounds.after_key {
+            match after_key.checked_add(1) {
+                Some(after_key) => lower = std::cmp::max(lower, after_key),
+                None => (lower, upper) = (1, 0),
+            }
+        }
+        let mut cursor = table.read_range(lower, upper);
+        let mut num_results = 0;
+        'scan: while let Some((keys, rows)) = cursor.next_leaf().await? {
+            for (key, row) in keys.into_iter().zip(rows.iter()) {
+                if bounds.is_limit_reached(num_results) {
+                    break 'scan;
+                }
+                let row = schema::internal_row_to_row(row, &table.schema);
+                out.write_key_row(key, row).await?;
+                num_results += 1;
+            }
+        }
+    } else {
+        let mut cursor = table.read_range(lower, upper);
+        // secondary index rows are sorted by the index key, so they must be re-sorted by
+        // primary key before being handed to later stages.
+        let mut sorter =
+            ExternalSorter::<F>::new(Vec::new(), &db.table.config, db.table.options.sort_run_size);
+        while let Some((_, rows)) = cursor.next_leaf().await? {
+            for row in rows {
+                let row = schema::internal_row_to_row(&row, &table.schema);
+                let pk = schema::get_col(&row, &db.table.schema.key.name);
+                let pk_hash = schema::get_hashed_col_value(&pk.value);
+                if bounds.is_key_in_bounds(pk_hash) {
+                    sorter.push(pk_hash, row, InternalRowProto::new()).await?;
+                }
+            }
+        }
+        sorter.finish(&mut out, bounds.limit).await?;
+    }
+    out.finish().await
+}
+
+// Counts the number of results the given filter would produce, using keys alone.
+pub(crate) async fn count_filter<F: Filelike>(
+    db: &Database<F>,
+    filter: &FilterProto,
+) -> Result<usize, Error> {
+    match &filter.filter_type {
+        Some(filter_proto::Filter_type::Equals(equals)) => {
+            let table: Arc<Table<F>> = db.find_table_keyed_on_column(&equals.name)?;
+            let key = schema::get_hashed_col_value(&equals.value);
+            match table.read_row(key).await {
+                Ok(_) => Ok(1),
+                Err(e) if e.kind == NotFound => Ok(0),
+                Err(e) => Err(e),
+            }
+        }
+        Some(filter_proto::Filter_type::InRange(in_range)) => {
+            let table: Arc<Table<F>> = db.find_table_keyed_on_column(&in_range.name)?;
+            let lower = schema::get_hashed_col_value(&in_range.lower_value);
+            let upper = schema::get_hashed_col_value(&in_range.upper_value);
+            let mut cursor = table.read_range(lower, upper);
+            let mut count = 0;
+            while let Some((keys, _)) = cursor.next_leaf().await? {
+                count += keys.len();
+            }
+            Ok(count)
+        }
+        None => panic!(),
+    }
 }
 
 pub(crate) async fn execute_filter<F: Filelike>(
     db: &Database<F>,
     filter: FilterProto,
+    bounds: StageBounds,
 ) -> Result<F, Error> {
     match filter.filter_type {
-        Some(filter_proto::Filter_type::Equals(equals)) => execute_filter_equals(db, equals).await,
-        Some(filter_proto::Filter_type::InRange(in_range)) => execute_filter_in_range(db, in_range),
+        Some(filter_proto::Filter_type::Equals(equals)) => {
+            execute_filter_equals(db, equals, bounds).await
+        }
+        Some(filter_proto::Filter_type::InRange(in_range)) => {
+            execute_filter_in_range(db, in_range, bounds).await
+        }
         None => panic!(),
     }
 }
@@ -0,0 +1,363 @@
+use crate::database::*;
+use crate::error::{ErrorKind::*, *};
+use crate::filelike::Filelike;
+use crate::options::DatabaseOptions;
+use crate::protos::generated::config::*;
+use crate::protos::generated::operations::aggregate_proto::AggregateColumnProto;
+use crate::protos::generated::operations::*;
+use crate::query;
+use crate::query::aggregate::{self, Aggregator};
+use crate::query::StageBounds;
+use crate::query::{reader::ResultsReader, writer::ResultsWriter};
+use crate::schema;
+use crate::table::Table;
+use protobuf::Message;
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::Arc;
+
+// Writes a single group's result to out, keyed on the number of groups written so far.
+async fn write_group<F: Filelike>(
+    out: &mut ResultsWriter<F>,
+    num_results: &mut usize,
+    mut group_row: RowProto,
+    aggregator: Aggregator,
+) -> Result<(), Error> {
+    group_row.columns.extend(aggregator.finish().columns);
+    out.write_key_row(*num_results as u32, group_row).await?;
+    *num_results += 1;
+    Ok(())
+}
+
+// Approximates the memory (in bytes) a group held in a hash table takes.
+fn group_size(group_key: &[i64], group_row: &RowProto, aggregator: &Aggregator) -> usize {
+    std::mem::size_of::<(Vec<i64>, (RowProto, Aggregator))>()
+        + std::mem::size_of_val(group_key)
+        + group_row.compute_size() as usize
+        + aggregator.size()
+}
+
+// Hash aggregation over rows that may not fit in memory. Groups are aggregated in a hash table
+// until it takes up the memory budget, after which rows belonging to any other group are spilled
+// to one of partition_count partitions (by hash of their group). Once all rows are seen, each
+// partition is grouped separately, recursively.
+struct HashAggregator<F: Filelike> {
+    group_columns: Vec<String>,
+    aggregates: Vec<AggregateColumnProto>,
+    // NOTE: used to vary the partitioning hash function between recursive calls.
+    depth: u64,
+    groups: HashMap<Vec<i64>, (RowProto, Aggregator)>,
+    // The approximate memory taken by groups, see group_size.
+    groups_size: usize,
+    partitions: Vec<Option<ResultsWriter<F>>>,
+    options: Arc<DatabaseOptions>,
+    // The geometry of spilled partitions.
+    config: TableConfig,
+}
+
+impl<F: Filelike> HashAggregator<F> {
+    fn new(
+        group_columns: &[String],
+        aggregates: &[AggregateColumnProto],
+        depth: u64,
+        options: &Arc<DatabaseOptions>,
+        config: &TableConfig,
+    ) -> Self {
+        Self {
+            group_columns: group_columns.to_vec(),
+            aggregates: aggregates.to_vec(),
+            depth,
+            groups: HashMap::new(),
+            groups_size: 0,
+            partitions: (0..options.group_by_partition_count)
+                .map(|_| None)
+                .collect(),
+            options: options.clone(),
+            config: config.clone(),
+        }
+    }
+
+    // NOTE: Expects the row to contain all group columns, and columns required by the aggregates.
+    async fn push(&mut self, row: RowProto) -> Result<(), Error> {
+        let group_key = group_key(&row, &self.group_columns);
+        if let Some((_, aggregator)) = self.groups.get_mut(&group_key) {
+            aggregator.update(&row);
+            return Ok(());
+        }
+        let group_row = schema::project_row(&row, &self.group_columns).unwrap();
+        let aggregator = Aggregator::new(&self.aggregates);
+        let size = group_size(&group_key, &group_row, &aggregator);
+        // NOTE: the first group is always held, so that each partition makes progress.
+        if self.groups.is_empty() || self.groups_size + size <= self.options.group_by_memory_budget
+        {
+            self.groups_size += size;
+            let (_, aggregator) = self
+                .groups
+                .entry(group_key)
+                .or_insert((group_row, aggregator));
+            aggregator.update(&row);
+            return Ok(());
+        }
+
+        let mut hasher = DefaultHasher::new();
+        (self.depth, &group_key).hash(&mut hasher);
+        let num_partitions = self.partitions.len();
+        let partition = &mut self.partitions[hasher.finish() as usize % num_partitions];
+        if partition.is_none() {
+            log::trace!("Spilling group by partition at depth {}.", self.depth);
+            *partition = Some(ResultsWriter::new(F::temp().await?, &self.config));
+        }
+        partition.as_mut().unwrap().write_key_row(0, row).await
+    }
+
+    // Writes one result per group to out, stopping early if the limit is reached.
+    async fn finish(
+        self,
+        out: &mut ResultsWriter<F>,
+        num_results: &mut usize,
+        bounds: StageBounds,
+    ) -> Result<(), Error> {
+        for (_, (group_row, aggregator)) in self.groups {
+            if bounds.is_limit_reached(*num_results) {
+                return Ok(());
+            }
+            write_group(out, num_results, group_row, aggregator).await?;
+        }
+        for partition in self.partitions.into_iter().flatten() {
+            let mut partition = ResultsReader::new(partition.finish().await?, &self.config);
+            let mut aggregator = HashAggregator::<F>::new(
+                &self.group_columns,
+                &self.aggregates,
+                self.depth + 1,
+                &self.options,
+                &self.config,
+            );
+            while let Some((_, row)) = partition.next_key_row().await? {
+                aggregator.push(row.unwrap()).await?;
+            }
+            Box::pin(aggregator.finish(out, num_results, bounds)).await?;
+        }
+        Ok(())
+    }
+}
+
+// Aggregates rows which arrive in group order (i.e. all rows of each group in a row), writing out
+// each group once the next one starts.
+struct SortedAggregator<F: Filelike, K: PartialEq> {
+    aggregates: Vec<AggregateColumnProto>,
+    out: ResultsWriter<F>,
+    num_results: usize,
+    group: Option<(K, RowProto, Aggregator)>,
+}
+
+impl<F: Filelike, K: PartialEq> SortedAggregator<F, K> {
+    async fn new(aggregates: &[AggregateColumnProto], config: &TableConfig) -> Result<Self, Error> {
+        Ok(Self {
+            aggregates: aggregates.to_vec(),
+            out: ResultsWriter::new(F::temp().await?, config),
+            num_results: 0,
+            group: None,
+        })
+    }
+
+    // Aggregates the given row into the group with the given key, starting a new group (with the
+    // given group columns) unless it's the current one. Returns false, without aggregating the
+    // row, once the limit is reached.
+    async fn push(
+        &mut self,
+        key: K,
+        group_row: impl FnOnce() -> RowProto,
+        row: &RowProto,
+        bounds: StageBounds,
+    ) -> Result<bool, Error> {
+        if self
+            .group
+            .as_ref()
+            .is_none_or(|(group_key, _, _)| *group_key != key)
+        {
+            if let Some((_, group_row, aggregator)) = self.group.take() {
+                write_group(&mut self.out, &mut self.num_results, group_row, aggregator).await?;
+            }
+            if bounds.is_limit_reached(self.num_results) {
+                return Ok(false);
+            }
+            self.group = Some((key, group_row(), Aggregator::new(&self.aggregates)));
+        }
+        self.group.as_mut().unwrap().2.update(row);
+        Ok(true)
+    }
+
+    async fn finish(mut self) -> Result<F, Error> {
+        if let Some((_, group_row, aggregator)) = self.group.take() {
+            write_group(&mut self.out, &mut self.num_results, group_row, aggregator).await?;
+        }
+        self.out.finish().await
+    }
+}
+
+// If grouping on a single column that the dependency filters on, results can be grouped by
+// walking the table / index keyed on that column in order instead. Returns the table and the
+// range of keys to walk if so.
+fn find_sorted_group_range<F: Filelike>(
+    db: &Database<F>,
+    group_by: &GroupByProto,
+) -> Option<(Arc<Table<F>>, u32, u32)> {
+    let [group_column] = group_by.columns.as_slice() else {
+        return None;
+    };
+    // NOTE: selects only project the rows of their dependency.
+    let mut dep = &group_by.dep;
+    while let Some(query_proto::Stage_type::Select(select)) = &dep.stage_type {
+        dep = &select.dep;
+    }
+    let Some(query_proto::Stage_type::Filter(filter)) = &dep.stage_type else {
+        return None;
+    };
+    let (name, lower, upper) = match &filter.filter_type {
+        Some(filter_proto::Filter_type::Equals(equals)) => {
+            let key = schema::get_hashed_col_value(&equals.value);
+            (&equals.name, key, key)
+        }
+        Some(filter_proto::Filter_type::InRange(in_range)) => (
+            &in_range.name,
+            schema::get_hashed_col_value(&in_range.lower_value),
+            schema::get_hashed_col_value(&in_range.upper_value),
+        ),
+        None => return None,
+    };
+    if name != group_column {
+        return None;
+    }
+    let table = db.find_table_keyed_on_column(name).ok()?;
+    Some((table, lower, upper))
+}
+
+async fn execute_sorted_group_by<F: Filelike>(
+    db: &Database<F>,
+    group_by: &GroupByProto,
+    table: Arc<Table<F>>,
+    lower: u32,
+    upper: u32,
+    bounds: StageBounds,
+) -> Result<F, Error> {
+    log::trace!("Grouping in order of table: {}", table.name);
+    let required_columns = Aggregator::new(&group_by.aggregates).required_columns();
+    let mut aggregator = SortedAggregator::new(&group_by.aggregates, &db.table.config).await?;
+    let mut cursor = table.read_range(lower, upper);
+    'scan: while let Some((keys, rows)) = cursor.next_leaf().await? {
+        for (key, row) in keys.into_iter().zip(rows.iter()) {
+            let row = schema::internal_row_to_row(row, &table.schema);
+            let pk = schema::get_col(&row, &db.table.schema.key.name);
+            let pk_hash = schema::get_hashed_col_value(&pk.value);
+            let row = query::resolve_row(db, pk_hash, Some(row), &required_columns).await?;
+            // NOTE: the row holds the group column, whether read from the index or the table.
+            let group_row = || schema::project_row(&row, &group_by.columns).unwrap();
+            if !aggregator.push(key, group_row, &row, bounds).await? {
+                break 'scan;
+            }
+        }
+    }
+    aggregator.finish().await
+}
+
+// Returns true iff the results of the given query are sorted by the given group columns (in any
+// direction), so that all rows of each group are adjacent.
+fn is_sorted_by_group<F: Filelike>(
+    db: &Database<F>,
+    query: &QueryProto,
+    group_columns: &[String],
+) -> bool {
+    match &query.stage_type {
+        Some(query_proto::Stage_type::Select(op)) => is_sorted_by_group(db, &op.dep, group_columns),
+        Some(query_proto::Stage_type::Limit(op)) => is_sorted_by_group(db, &op.dep, group_columns),
+        Some(query_proto::Stage_type::OrderBy(op)) => {
+            op.columns.len() >= group_columns.len()
+                && op.columns[..group_columns.len()]
+                    .iter()
+                    .all(|column| group_columns.contains(&column.name))
+        }
+        _ => {
+            group_columns == [db.table.schema.key.name.clone()]
+                && query::is_sorted_by_primary_key(query)
+        }
+    }
+}
+
+// Returns the values of the given group columns of the row.
+fn group_key(row: &RowProto, group_columns: &[String]) -> Vec<i64> {
+    group_columns
+        .iter()
+        .map(|name| schema::get_col_value_as_i64(&schema::get_col(row, name).value))
+        .collect()
+}
+
+pub(crate) async fn execute_group_by<F: Filelike>(
+    db: &Database<F>,
+    group_by: GroupByProto,
+    bounds: StageBounds,
+) -> Result<F, Error> {
+    if group_by.columns.is_empty() {
+        return Err(Error::new(
+            InvalidArgument,
+            "Group by requires at least one column!".to_string(),
+        ));
+    }
+    for col_name in &group_by.columns {
+        if !schema::is_col_in_table(&db.table.schema, col_name) {
+            return Err(Error::new(
+                InvalidArgument,
+                format!("Column not in table: {}!", col_name),
+            ));
+        }
+    }
+    aggregate::validate_aggregates(db, &group_by.aggregates)?;
+
+    if let Some((table, lower, upper)) = find_sorted_group_range(db, &group_by) {
+        return execute_sorted_group_by(db, &group_by, table, lower, upper, bounds).await;
+    }
+
+    let mut required_columns = group_by.columns.clone();
+    for col_name in Aggregator::new(&group_by.aggregates).required_columns() {
+        if !required_columns.contains(&col_name) {
+            required_columns.push(col_name);
+        }
+    }
+    let is_sorted = is_sorted_by_group(db, &group_by.dep, &group_by.columns);
+    let mut dep = ResultsReader::new(
+        query::execute_query(db, group_by.dep.unwrap()).await?,
+        &db.table.config,
+    );
+
+    if is_sorted {
+        log::trace!("Grouping results already in group order.");
+        let mut aggregator = SortedAggregator::new(&group_by.aggregates, &db.table.config).await?;
+        while let Some((key, dep_row)) = dep.next_key_row().await? {
+            let row = query::resolve_row(db, key, dep_row, &required_columns).await?;
+            let group_row = || schema::project_row(&row, &group_by.columns).unwrap();
+            let group_key = group_key(&row, &group_by.columns);
+            if !aggregator.push(group_key, group_row, &row, bounds).await? {
+                break;
+            }
+        }
+        return aggregator.finish().await;
+    }
+
+    let mut aggregator = HashAggregator::<F>::new(
+        &group_by.columns,
+        &group_by.aggregates,
+        0,
+        &db.table.options,
+        &db.table.config,
+    );
+    while let Some((key, dep_row)) = dep.next_key_row().await? {
+        let row = query::resolve_row(db, key, dep_row, &required_columns).await?;
+        // NOTE: only required columns are kept, to limit the size of spilled partitions.
+        aggregator
+            .push(schema::project_row(&row, &required_columns).unwrap())
+            .await?;
+    }
+
+    let mut out = ResultsWriter::new(F::temp().await?, &db.table.config);
+    aggregator.finish(&mut out, &mut 0, bounds).await?;
+    out.finish().await
+}
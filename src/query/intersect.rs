@@ -3,46 +3,44 @@ use crate::error::*;
 use crate::filelike::Filelike;
 use crate::protos::generated::operations::*;
 use crate::query;
-use crate::query::{reader::ResultsReader, writer::ResultsWriter};
-use std::cmp::Ordering;
+use crate::query::merge::{MergeCursor, MergeKey, MergeMode};
+use crate::query::writer::ResultsWriter;
+use crate::query::StageBounds;
 
 pub(crate) async fn execute_intersect<F: Filelike>(
     db: &Database<F>,
     intersect: IntersectProto,
+    bounds: StageBounds,
 ) -> Result<F, Error> {
-    let mut out = ResultsWriter::new(F::create("TODO").await?);
+    let mut out = ResultsWriter::new(F::temp().await?, &db.table.config);
 
-    let mut lhs_it = ResultsReader::new(query::execute_query(db, intersect.lhs.unwrap()).await?);
-    let mut rhs_it = ResultsReader::new(query::execute_query(db, intersect.rhs.unwrap()).await?);
-    let mut lhs = lhs_it.next_key().await?;
-    let mut rhs = rhs_it.next_key().await?;
-    loop {
-        let ord = lhs.cmp(&rhs);
-        match ord {
-            Ordering::Less => {
-                let Ok(next_lhs) = lhs_it.next_key().await else {
-                    break;
-                };
-                lhs = next_lhs;
-            }
-            Ordering::Greater => {
-                let Ok(next_rhs) = rhs_it.next_key().await else {
-                    break;
-                };
-                rhs = next_rhs;
-            }
-            Ordering::Equal => {
-                out.write_key(lhs).await?;
-
-                let Ok(next_lhs) = lhs_it.next_key().await else {
-                    break;
-                };
-                lhs = next_lhs;
-                let Ok(next_rhs) = rhs_it.next_key().await else {
-                    break;
-                };
-                rhs = next_rhs;
-            }
+    // NOTE: the limit can't be forwarded, since it isn't known how many results of each
+    // dependency are required to produce enough matches.
+    let dep_bounds = StageBounds {
+        limit: None,
+        after_key: bounds.after_key,
+    };
+    let lhs = query::execute_stage(db, intersect.lhs.unwrap(), dep_bounds).await?;
+    let rhs = query::execute_stage(db, intersect.rhs.unwrap(), dep_bounds).await?;
+    let mut merge = MergeCursor::new(
+        lhs,
+        MergeKey::PrimaryKey,
+        rhs,
+        MergeKey::PrimaryKey,
+        MergeMode::Inner,
+        &db.table.config,
+    )
+    .await?;
+    let mut num_results = 0;
+    while let Some((lhs_group, _)) = merge.next_group().await? {
+        let key = lhs_group[0].0;
+        if !bounds.is_key_in_bounds(key) {
+            continue;
+        }
+        out.write_key(key).await?;
+        num_results += 1;
+        if bounds.is_limit_reached(num_results) {
+            break;
         }
     }
     out.finish().await
@@ -0,0 +1,246 @@
+use crate::database::*;
+use crate::error::{ErrorKind::*, *};
+use crate::filelike::Filelike;
+use crate::protos::generated::chunk::*;
+use crate::protos::generated::operations::*;
+use crate::query;
+use crate::query::merge::{MergeCursor, MergeKey, MergeMode};
+use crate::query::sort::ExternalSorter;
+use crate::query::StageBounds;
+use crate::query::{planner, scan};
+use crate::query::{reader::ResultsReader, writer::ResultsWriter};
+use crate::schema;
+use crate::table::Table;
+use std::sync::Arc;
+
+// NOTE: only a single table is currently supported, so both sides of a join are queries over
+// the same table (i.e. self-joins).
+
+fn joined_row(lhs: &RowProto, rhs: Option<&RowProto>) -> RowProto {
+    let mut row = RowProto::new();
+    for (prefix, side) in [("lhs", Some(lhs)), ("rhs", rhs)] {
+        for col in side.iter().flat_map(|side| side.columns.iter()) {
+            let mut col = col.clone();
+            col.name = format!("{}.{}", prefix, col.name);
+            row.columns.push(col);
+        }
+    }
+    row
+}
+
+// Executes the given query, and sorts its results by the given column. Rows missing the column
+// are read from the main table.
+async fn execute_sorted_on_column<F: Filelike>(
+    db: &Database<F>,
+    query: QueryProto,
+    col_name: &str,
+) -> Result<F, Error> {
+    let col_names = [col_name.to_string()];
+    let mut sorter = ExternalSorter::<F>::new(
+        vec![false],
+        &db.table.config,
+        db.table.options.sort_run_size,
+    );
+    let mut dep = ResultsReader::new(query::execute_query(db, query).await?, &db.table.config);
+    while let Some((key, dep_row)) = dep.next_key_row().await? {
+        let row = query::resolve_row(db, key, dep_row, &col_names).await?;
+        let mut sort_values = InternalRowProto::new();
+        sort_values
+            .col_values
+            .push(schema::get_col(&row, col_name).value.clone().unwrap());
+        sorter.push(key, row, sort_values).await?;
+    }
+    let mut out = ResultsWriter::new(F::temp().await?, &db.table.config);
+    sorter.finish(&mut out, None).await?;
+    out.finish().await
+}
+
+// An index nested loop join, looking up the rows matching each lhs result in the inner table /
+// index, keeping those which match the rhs predicate (if any).
+pub(crate) struct IndexNestedLoop<F: Filelike> {
+    pub(crate) inner: Arc<Table<F>>,
+    pub(crate) rhs_predicate: Option<PredicateProto>,
+}
+
+// Chooses an index nested loop join if the table or an index is keyed on the rhs column, and the
+// rows of the rhs (if set) can be told apart by a predicate, i.e. it's a filter or a predicate.
+// Returns None if the sides should be sorted and merged instead.
+pub(crate) fn choose_index_nested_loop<F: Filelike>(
+    db: &Database<F>,
+    join: &JoinProto,
+) -> Result<Option<IndexNestedLoop<F>>, Error> {
+    let Ok(inner) = db.find_table_keyed_on_column(&join.rhs_column) else {
+        return Ok(None);
+    };
+    let rhs_predicate = match join.rhs.as_ref().map(|rhs| &rhs.stage_type) {
+        None => None,
+        Some(Some(query_proto::Stage_type::Filter(filter))) => {
+            let mut predicate = PredicateProto::new();
+            predicate.set_filter(filter.clone());
+            Some(predicate)
+        }
+        Some(Some(query_proto::Stage_type::Predicate(predicate))) => {
+            planner::validate_predicate(db, predicate)?;
+            Some(planner::resolve_predicate(db, predicate.clone()))
+        }
+        Some(_) => return Ok(None),
+    };
+    Ok(Some(IndexNestedLoop {
+        inner,
+        rhs_predicate,
+    }))
+}
+
+// For each lhs result, finds matching rows through the table / index keyed on the rhs column.
+async fn execute_index_nested_loop_join<F: Filelike>(
+    db: &Database<F>,
+    join: JoinProto,
+    index_nested_loop: IndexNestedLoop<F>,
+    out: &mut ResultsWriter<F>,
+    bounds: StageBounds,
+) -> Result<(), Error> {
+    let IndexNestedLoop {
+        inner,
+        rhs_predicate,
+    } = index_nested_loop;
+    log::trace!("Index nested loop join on table: {}", inner.name);
+    let lhs_col_names = [join.lhs_column.clone()];
+    let mut num_results = 0;
+    let mut lhs = ResultsReader::new(
+        query::execute_query(db, join.lhs.unwrap()).await?,
+        &db.table.config,
+    );
+    while let Some((key, dep_row)) = lhs.next_key_row().await? {
+        let lhs_row = query::resolve_row(db, key, dep_row, &lhs_col_names).await?;
+        let value = schema::get_col(&lhs_row, &join.lhs_column)
+            .value
+            .as_ref()
+            .unwrap();
+        let hashed_value = schema::get_hashed_col_value(value);
+
+        let mut num_matches = 0;
+        let mut cursor = inner.read_range(hashed_value, hashed_value);
+        while let Some((_, rows)) = cursor.next_leaf().await? {
+            for row in rows {
+                if bounds.is_limit_reached(num_results) {
+                    return Ok(());
+                }
+                let row = schema::internal_row_to_row(&row, &inner.schema);
+                let rhs_row = if std::ptr::eq(&*inner, &*db.table) {
+                    row
+                } else {
+                    let pk = schema::get_col(&row, &db.table.schema.key.name);
+                    db.table
+                        .read_row(schema::get_hashed_col_value(&pk.value))
+                        .await?
+                };
+                // NOTE: values are compared by their hashes, as by the rhs filter.
+                if rhs_predicate
+                    .as_ref()
+                    .is_some_and(|predicate| !scan::matches_predicate(&rhs_row, predicate))
+                {
+                    continue;
+                }
+                out.write_key_row(key, joined_row(&lhs_row, Some(&rhs_row)))
+                    .await?;
+                num_results += 1;
+                num_matches += 1;
+            }
+        }
+        if num_matches == 0
+            && join.join_type.enum_value_or_default() == join_proto::JoinType::LEFT_OUTER
+        {
+            if bounds.is_limit_reached(num_results) {
+                return Ok(());
+            }
+            out.write_key_row(key, joined_row(&lhs_row, None)).await?;
+            num_results += 1;
+        }
+    }
+    Ok(())
+}
+
+// Sorts both sides on their join columns, then merges them.
+async fn execute_sort_merge_join<F: Filelike>(
+    db: &Database<F>,
+    join: JoinProto,
+    out: &mut ResultsWriter<F>,
+    bounds: StageBounds,
+) -> Result<(), Error> {
+    log::trace!("Sort merge join.");
+    let rhs = match join.rhs.into_option() {
+        Some(rhs) => rhs,
+        None => {
+            let mut scan = QueryProto::new();
+            scan.set_scan(ScanProto::new());
+            scan
+        }
+    };
+    let lhs = execute_sorted_on_column(db, join.lhs.unwrap(), &join.lhs_column).await?;
+    let rhs = execute_sorted_on_column(db, rhs, &join.rhs_column).await?;
+    let mode = match join.join_type.enum_value_or_default() {
+        join_proto::JoinType::INNER => MergeMode::Inner,
+        join_proto::JoinType::LEFT_OUTER => MergeMode::LeftOuter,
+    };
+    let mut merge = MergeCursor::new(
+        lhs,
+        MergeKey::Column(join.lhs_column),
+        rhs,
+        MergeKey::Column(join.rhs_column),
+        mode,
+        &db.table.config,
+    )
+    .await?;
+    let mut num_results = 0;
+    while let Some((lhs_group, rhs_group)) = merge.next_group().await? {
+        for (key, lhs_row) in &lhs_group {
+            let lhs_row = lhs_row.as_ref().unwrap();
+            let rhs_rows: Vec<Option<&RowProto>> = if rhs_group.is_empty() {
+                vec![None]
+            } else {
+                rhs_group.iter().map(|(_, row)| row.as_ref()).collect()
+            };
+            for rhs_row in rhs_rows {
+                if bounds.is_limit_reached(num_results) {
+                    return Ok(());
+                }
+                out.write_key_row(*key, joined_row(lhs_row, rhs_row))
+                    .await?;
+                num_results += 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(crate) async fn execute_join<F: Filelike>(
+    db: &Database<F>,
+    join: JoinProto,
+    bounds: StageBounds,
+) -> Result<F, Error> {
+    for col_name in [&join.lhs_column, &join.rhs_column] {
+        if !schema::is_col_in_table(&db.table.schema, col_name) {
+            return Err(Error::new(
+                InvalidArgument,
+                format!("Column not in table: {}!", col_name),
+            ));
+        }
+    }
+    join.join_type
+        .enum_value()
+        .map_err(|e| Error::new(InvalidArgument, format!("Unknown join type: {e}!")))?;
+
+    // NOTE: results aren't sorted by key, so only the limit is meaningful here.
+    let bounds = StageBounds {
+        limit: bounds.limit,
+        after_key: None,
+    };
+    let mut out = ResultsWriter::new(F::temp().await?, &db.table.config);
+    match choose_index_nested_loop(db, &join)? {
+        Some(index_nested_loop) => {
+            execute_index_nested_loop_join(db, join, index_nested_loop, &mut out, bounds).await?
+        }
+        None => execute_sort_merge_join(db, join, &mut out, bounds).await?,
+    }
+    out.finish().await
+}
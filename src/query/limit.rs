@@ -0,0 +1,76 @@
+use crate::database::*;
+use crate::error::{ErrorKind::*, *};
+use crate::filelike::Filelike;
+use crate::protos::generated::operations::*;
+use crate::query;
+use crate::query::StageBounds;
+use crate::query::{reader::ResultsReader, writer::ResultsWriter};
+
+// Returns the requested page of results, along with a token to retrieve the next page. The token
+// is only returned if the page is full, in which case the next page may still be empty.
+pub(crate) async fn execute_limit_page<F: Filelike>(
+    db: &Database<F>,
+    limit: LimitProto,
+) -> Result<(F, Option<ContinuationTokenProto>), Error> {
+    let after_key = limit
+        .continuation_token
+        .as_ref()
+        .map(|token| token.last_key);
+    if after_key.is_some() && !query::is_sorted_by_primary_key(&limit.dep) {
+        return Err(Error::new(
+            InvalidArgument,
+            "Continuation tokens require results sorted by primary key!".to_string(),
+        ));
+    }
+
+    let offset = limit.offset as usize;
+    let num_results = limit.limit as usize;
+    let dep_bounds = StageBounds {
+        limit: Some(offset + num_results),
+        after_key,
+    };
+    let mut out = ResultsWriter::new(F::temp().await?, &db.table.config);
+    let mut dep = ResultsReader::new(
+        query::execute_stage(db, limit.dep.unwrap(), dep_bounds).await?,
+        &db.table.config,
+    );
+    let mut num_skipped = 0;
+    let mut last_key = None;
+    let mut num_written = 0;
+    while num_written < num_results {
+        let Some((key, dep_row)) = dep.next_key_row().await? else {
+            break;
+        };
+        if !dep_bounds.is_key_in_bounds(key) {
+            continue;
+        }
+        if num_skipped < offset {
+            num_skipped += 1;
+            continue;
+        }
+        match dep_row {
+            Some(row) => out.write_key_row(key, row).await?,
+            None => out.write_key(key).await?,
+        }
+        last_key = Some(key);
+        num_written += 1;
+    }
+
+    let continuation_token = match last_key {
+        Some(last_key) if num_written == num_results => {
+            let mut token = ContinuationTokenProto::new();
+            token.last_key = last_key;
+            Some(token)
+        }
+        _ => None,
+    };
+    Ok((out.finish().await?, continuation_token))
+}
+
+pub(crate) async fn execute_limit<F: Filelike>(
+    db: &Database<F>,
+    limit: LimitProto,
+) -> Result<F, Error> {
+    let (out, _) = execute_limit_page(db, limit).await?;
+    Ok(out)
+}
@@ -0,0 +1,143 @@
+#[cfg(test)]
+#[path = "./merge_test.rs"]
+mod test;
+
+use crate::error::*;
+use crate::filelike::Filelike;
+use crate::protos::generated::config::*;
+use crate::protos::generated::operations::*;
+use crate::query::reader::ResultsReader;
+use crate::schema;
+
+// What the results of a stream are sorted by, and should be matched on.
+pub(crate) enum MergeKey {
+    PrimaryKey,
+    // NOTE: Expects all results to have a row containing the column.
+    Column(String),
+}
+
+struct MergeSide<F: Filelike> {
+    reader: ResultsReader<F>,
+    merge_key: MergeKey,
+    head: Option<(i64, u32, Option<RowProto>)>,
+}
+
+impl<F: Filelike> MergeSide<F> {
+    async fn new(file: F, merge_key: MergeKey, config: &TableConfig) -> Result<Self, Error> {
+        let mut side = Self {
+            reader: ResultsReader::new(file, config),
+            merge_key,
+            head: None,
+        };
+        side.advance().await?;
+        Ok(side)
+    }
+
+    async fn advance(&mut self) -> Result<(), Error> {
+        self.head = match self.reader.next_key_row().await? {
+            Some((key, row)) => {
+                let merge_value = match &self.merge_key {
+                    MergeKey::PrimaryKey => key as i64,
+                    MergeKey::Column(name) => schema::get_col_value_as_i64(
+                        &schema::get_col(row.as_ref().unwrap(), name).value,
+                    ),
+                };
+                Some((merge_value, key, row))
+            }
+            None => None,
+        };
+        Ok(())
+    }
+
+    // Consumes all results sharing the head's merge value.
+    async fn take_group(&mut self) -> Result<Vec<(u32, Option<RowProto>)>, Error> {
+        let mut group = Vec::new();
+        let Some((merge_value, _, _)) = self.head else {
+            return Ok(group);
+        };
+        while let Some((next_merge_value, key, row)) = self.head.take() {
+            if next_merge_value != merge_value {
+                self.head = Some((next_merge_value, key, row));
+                break;
+            }
+            group.push((key, row));
+            self.advance().await?;
+        }
+        Ok(group)
+    }
+}
+
+// Which groups a merge produces, by whether they have results on each side.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum MergeMode {
+    // Only groups with results on both sides.
+    Inner,
+    // Also lhs groups without any matching rhs results.
+    LeftOuter,
+    // All groups.
+    FullOuter,
+}
+
+// Walks two result streams sorted by their merge keys in lockstep, producing groups of results
+// with equal merge values (e.g. for intersections, unions, or sort-merge joins).
+pub(crate) struct MergeCursor<F: Filelike> {
+    lhs: MergeSide<F>,
+    rhs: MergeSide<F>,
+    mode: MergeMode,
+}
+
+impl<F: Filelike> MergeCursor<F> {
+    pub(crate) async fn new(
+        lhs: F,
+        lhs_merge_key: MergeKey,
+        rhs: F,
+        rhs_merge_key: MergeKey,
+        mode: MergeMode,
+        config: &TableConfig,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            lhs: MergeSide::new(lhs, lhs_merge_key, config).await?,
+            rhs: MergeSide::new(rhs, rhs_merge_key, config).await?,
+            mode,
+        })
+    }
+
+    // Returns the next group of lhs and rhs results sharing a merge value, or None when done.
+    // NOTE: returned groups are only ever empty for outer merges.
+    #[allow(clippy::type_complexity)]
+    pub(crate) async fn next_group(
+        &mut self,
+    ) -> Result<Option<(Vec<(u32, Option<RowProto>)>, Vec<(u32, Option<RowProto>)>)>, Error> {
+        let keep_lhs = self.mode != MergeMode::Inner;
+        let keep_rhs = self.mode == MergeMode::FullOuter;
+        loop {
+            let ord = match (&self.lhs.head, &self.rhs.head) {
+                (None, None) => return Ok(None),
+                (Some(_), None) if !keep_lhs => return Ok(None),
+                (None, Some(_)) if !keep_rhs => return Ok(None),
+                (Some(_), None) => std::cmp::Ordering::Less,
+                (None, Some(_)) => std::cmp::Ordering::Greater,
+                (Some((lhs_value, _, _)), Some((rhs_value, _, _))) => lhs_value.cmp(rhs_value),
+            };
+            match ord {
+                std::cmp::Ordering::Less => {
+                    let lhs_group = self.lhs.take_group().await?;
+                    if keep_lhs {
+                        return Ok(Some((lhs_group, Vec::new())));
+                    }
+                }
+                std::cmp::Ordering::Greater => {
+                    let rhs_group = self.rhs.take_group().await?;
+                    if keep_rhs {
+                        return Ok(Some((Vec::new(), rhs_group)));
+                    }
+                }
+                std::cmp::Ordering::Equal => {
+                    let lhs_group = self.lhs.take_group().await?;
+                    let rhs_group = self.rhs.take_group().await?;
+                    return Ok(Some((lhs_group, rhs_group)));
+                }
+            }
+        }
+    }
+}
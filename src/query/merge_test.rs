@@ -0,0 +1,57 @@
+use super::*;
+use crate::error::ErrorKind::*;
+use crate::options::DatabaseOptions;
+use crate::query::writer::ResultsWriter;
+use std::io::Cursor;
+
+async fn write_keys(keys: impl IntoIterator<Item = u32>) -> Result<Cursor<Vec<u8>>, Error> {
+    let config = DatabaseOptions::default().table_config();
+    let mut out = ResultsWriter::new(Cursor::new(Vec::new()), &config);
+    for key in keys {
+        out.write_key(key).await?;
+    }
+    out.finish().await
+}
+
+// Returns the number of groups the merge produces.
+async fn count_groups(lhs: Cursor<Vec<u8>>, rhs: Cursor<Vec<u8>>) -> Result<usize, Error> {
+    let config = DatabaseOptions::default().table_config();
+    let mut merge = MergeCursor::new(
+        lhs,
+        MergeKey::PrimaryKey,
+        rhs,
+        MergeKey::PrimaryKey,
+        MergeMode::FullOuter,
+        &config,
+    )
+    .await?;
+    let mut num_groups = 0;
+    while merge.next_group().await?.is_some() {
+        num_groups += 1;
+    }
+    Ok(num_groups)
+}
+
+#[tokio::test]
+async fn merge_ok() -> Result<(), Error> {
+    let lhs = write_keys((0..5000).map(|key| key * 2)).await?;
+    let rhs = write_keys((0..5000).map(|key| key * 3)).await?;
+    // keys divisible by 2 or 3.
+    assert_eq!(count_groups(lhs, rhs).await?, 5000 + 5000 - 1667);
+    Ok(())
+}
+
+#[tokio::test]
+async fn merge_corrupt_results_fails() -> Result<(), Error> {
+    let size = DatabaseOptions::default().page_size;
+    let lhs = write_keys(0..5000).await?;
+    let rhs = write_keys(0..10).await?;
+    assert!(lhs.get_ref().len() > size);
+
+    // the second chunk of lhs results can't be read, rather than ending them.
+    let mut bytes = lhs.into_inner();
+    bytes[size + 2..size + 10].fill(u8::MAX);
+    let result = count_groups(Cursor::new(bytes), rhs).await;
+    assert_eq!(result.unwrap_err().kind, DataLoss);
+    Ok(())
+}
@@ -2,31 +2,175 @@ use crate::database::*;
 use crate::error::*;
 use crate::filelike::Filelike;
 use crate::protos::generated::operations::*;
+use crate::schema;
 
+mod aggregate;
+mod explain;
 mod filter;
+mod group_by;
 mod intersect;
-mod reader;
+mod join;
+mod limit;
+mod merge;
+mod order_by;
+mod planner;
+pub(crate) mod reader;
+mod scan;
 mod select;
-mod writer;
+pub(crate) mod sort;
+mod union;
+pub(crate) mod writer;
 
 // Queries can be visualized as a tree of dependent operations (e.g. a tree) that must be completed
 // bottom-up. Currently, each stage creates and outputs its contents to a file, as we cannot assume
 // that the query results fit in memory.
 //
+// Unless otherwise specified (e.g. by an order by stage), stage outputs are sorted by primary key.
+// Stages such as intersect rely on this.
+//
 // TODO: switch to a polling-iterator style of query runner instead -- client doesn't have to be
 // aware of file format, etc.
 pub async fn execute_query<F: Filelike>(db: &Database<F>, query: QueryProto) -> Result<F, Error> {
+    execute_stage(db, query, StageBounds::default()).await
+}
+
+// Hints passed from a stage to its dependencies, describing which of their results will actually
+// be consumed: only the first `limit` results with keys greater than `after_key`. Stages may use
+// these to stop doing work early, but are not required to -- consumers must still enforce them.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct StageBounds {
+    pub(crate) limit: Option<usize>,
+    pub(crate) after_key: Option<u32>,
+}
+
+impl StageBounds {
+    pub(crate) fn is_key_in_bounds(&self, key: u32) -> bool {
+        self.after_key.is_none_or(|after_key| after_key < key)
+    }
+
+    pub(crate) fn is_limit_reached(&self, num_results: usize) -> bool {
+        self.limit.is_some_and(|limit| limit <= num_results)
+    }
+}
+
+pub(crate) async fn execute_stage<F: Filelike>(
+    db: &Database<F>,
+    query: QueryProto,
+    bounds: StageBounds,
+) -> Result<F, Error> {
+    if explain::is_profiling() {
+        return Box::pin(explain::execute_profiled_stage(db, query, bounds)).await;
+    }
+    dispatch_stage(db, query, bounds).await
+}
+
+async fn dispatch_stage<F: Filelike>(
+    db: &Database<F>,
+    query: QueryProto,
+    bounds: StageBounds,
+) -> Result<F, Error> {
     let output = match query.stage_type {
         Some(query_proto::Stage_type::Intersect(op)) => {
-            Box::pin(intersect::execute_intersect(db, op)).await?
+            Box::pin(intersect::execute_intersect(db, op, bounds)).await?
         }
         Some(query_proto::Stage_type::Filter(op)) => {
-            Box::pin(filter::execute_filter(db, op)).await?
+            Box::pin(filter::execute_filter(db, op, bounds)).await?
         }
         Some(query_proto::Stage_type::Select(op)) => {
-            Box::pin(select::execute_select(db, op)).await?
+            Box::pin(select::execute_select(db, op, bounds)).await?
+        }
+        Some(query_proto::Stage_type::OrderBy(op)) => {
+            Box::pin(order_by::execute_order_by(db, op, bounds)).await?
+        }
+        Some(query_proto::Stage_type::Limit(op)) => Box::pin(limit::execute_limit(db, op)).await?,
+        Some(query_proto::Stage_type::Aggregate(op)) => {
+            Box::pin(aggregate::execute_aggregate(db, op)).await?
+        }
+        Some(query_proto::Stage_type::GroupBy(op)) => {
+            Box::pin(group_by::execute_group_by(db, op, bounds)).await?
+        }
+        Some(query_proto::Stage_type::Join(op)) => {
+            Box::pin(join::execute_join(db, op, bounds)).await?
+        }
+        Some(query_proto::Stage_type::Union(op)) => {
+            Box::pin(union::execute_union(db, op, bounds)).await?
+        }
+        Some(query_proto::Stage_type::Scan(op)) => {
+            Box::pin(scan::execute_scan(db, op, bounds)).await?
+        }
+        Some(query_proto::Stage_type::Predicate(op)) => {
+            let plan = planner::plan_predicate(db, op)?;
+            Box::pin(execute_stage(db, plan, bounds)).await?
         }
         None => panic!(),
     };
     Ok(output)
 }
+
+pub(crate) async fn explain<F: Filelike>(
+    db: &Database<F>,
+    query: QueryProto,
+) -> Result<QueryPlanProto, Error> {
+    explain::explain_stage(db, &query).await
+}
+
+pub(crate) async fn explain_analyze<F: Filelike>(
+    db: &Database<F>,
+    query: QueryProto,
+) -> Result<QueryPlanProto, Error> {
+    explain::explain_analyze(db, query).await
+}
+
+pub(crate) fn plan_predicate<F: Filelike>(
+    db: &Database<F>,
+    predicate: PredicateProto,
+) -> Result<QueryProto, Error> {
+    planner::plan_predicate(db, predicate)
+}
+
+pub(crate) async fn execute_page<F: Filelike>(
+    db: &Database<F>,
+    op: LimitProto,
+) -> Result<(F, Option<ContinuationTokenProto>), Error> {
+    limit::execute_limit_page(db, op).await
+}
+
+// Returns true iff the results of the given query are sorted by primary key.
+pub(crate) fn is_sorted_by_primary_key(query: &QueryProto) -> bool {
+    match &query.stage_type {
+        Some(query_proto::Stage_type::Intersect(_)) => true,
+        Some(query_proto::Stage_type::Filter(_)) => true,
+        Some(query_proto::Stage_type::Select(op)) => is_sorted_by_primary_key(&op.dep),
+        Some(query_proto::Stage_type::OrderBy(_)) => false,
+        Some(query_proto::Stage_type::Limit(op)) => is_sorted_by_primary_key(&op.dep),
+        Some(query_proto::Stage_type::Aggregate(_)) => true,
+        Some(query_proto::Stage_type::GroupBy(_)) => false,
+        Some(query_proto::Stage_type::Join(_)) => false,
+        Some(query_proto::Stage_type::Union(_)) => true,
+        Some(query_proto::Stage_type::Scan(op)) => {
+            op.dep.as_ref().is_none_or(is_sorted_by_primary_key)
+        }
+        Some(query_proto::Stage_type::Predicate(_)) => true,
+        None => panic!(),
+    }
+}
+
+// Returns the row produced by a dependency if it holds all of the given columns, else reads the
+// full row with the given key from the main table.
+pub(crate) async fn resolve_row<F: Filelike>(
+    db: &Database<F>,
+    key: u32,
+    dep_row: Option<RowProto>,
+    col_names: &[String],
+) -> Result<RowProto, Error> {
+    match dep_row {
+        Some(row)
+            if col_names
+                .iter()
+                .all(|name| schema::find_col(&row, name).is_some()) =>
+        {
+            Ok(row)
+        }
+        _ => db.table.read_row(key).await,
+    }
+}
@@ -0,0 +1,79 @@
+use crate::database::*;
+use crate::error::{ErrorKind::*, *};
+use crate::filelike::Filelike;
+use crate::protos::generated::chunk::*;
+use crate::protos::generated::operations::*;
+use crate::query;
+use crate::query::sort::ExternalSorter;
+use crate::query::StageBounds;
+use crate::query::{reader::ResultsReader, writer::ResultsWriter};
+use crate::schema;
+
+fn sort_values_from_row(
+    row: &RowProto,
+    columns: &[order_by_proto::OrderByColumnProto],
+) -> Option<InternalRowProto> {
+    let mut sort_values = InternalRowProto::new();
+    for column in columns {
+        let col = schema::find_col(row, &column.name)?;
+        sort_values
+            .col_values
+            .push(col.value.clone().into_option()?);
+    }
+    Some(sort_values)
+}
+
+pub(crate) async fn execute_order_by<F: Filelike>(
+    db: &Database<F>,
+    order_by: OrderByProto,
+    bounds: StageBounds,
+) -> Result<F, Error> {
+    if order_by.columns.is_empty() {
+        return Err(Error::new(
+            InvalidArgument,
+            "Order by requires at least one column!".to_string(),
+        ));
+    }
+    for column in &order_by.columns {
+        if !schema::is_col_in_table(&db.table.schema, &column.name) {
+            return Err(Error::new(
+                InvalidArgument,
+                format!("Column not in table: {}!", column.name),
+            ));
+        }
+    }
+
+    let descending = order_by.columns.iter().map(|col| col.descending).collect();
+    let mut sorter =
+        ExternalSorter::<F>::new(descending, &db.table.config, db.table.options.sort_run_size);
+    let mut dep = ResultsReader::new(
+        query::execute_query(db, order_by.dep.unwrap()).await?,
+        &db.table.config,
+    );
+    while let Some((key, dep_row)) = dep.next_key_row().await? {
+        // NOTE: the main table is only read if the dependency did not produce the row,
+        // or the sort columns were projected out of it.
+        let row = match dep_row {
+            Some(row) => row,
+            None => db.table.read_row(key).await?,
+        };
+        let sort_values = match sort_values_from_row(&row, &order_by.columns) {
+            Some(sort_values) => sort_values,
+            None => sort_values_from_row(&db.table.read_row(key).await?, &order_by.columns)
+                .ok_or_else(|| {
+                    let col_names: Vec<String> = order_by
+                        .columns
+                        .iter()
+                        .map(|col| col.name.clone())
+                        .collect();
+                    schema::missing_col_error(key, &col_names)
+                })?,
+        };
+        sorter.push(key, row, sort_values).await?;
+    }
+
+    let mut out = ResultsWriter::new(F::temp().await?, &db.table.config);
+    // NOTE: results aren't sorted by key, so only the limit is meaningful here.
+    sorter.finish(&mut out, bounds.limit).await?;
+    out.finish().await
+}
@@ -0,0 +1,385 @@
+use crate::database::*;
+use crate::error::{ErrorKind::*, *};
+use crate::filelike::Filelike;
+use crate::protos::generated::operations::*;
+use crate::schema;
+use crate::statistics;
+use crate::table::Table;
+use protobuf::MessageField;
+use std::sync::Arc;
+
+// The estimated fraction of rows matched by each predicate which can't use an index.
+pub(crate) const RESIDUAL_SELECTIVITY: f64 = 0.5;
+
+// A physical plan producing the rows matching some predicate, sorted by primary key.
+struct AccessPath {
+    query: QueryProto,
+    selectivity: f64,
+}
+
+fn filter_query(filter: FilterProto) -> QueryProto {
+    let mut query = QueryProto::new();
+    query.set_filter(filter);
+    query
+}
+
+fn scan_query(dep: Option<QueryProto>, predicate: Option<PredicateProto>) -> QueryProto {
+    let mut scan = ScanProto::new();
+    scan.dep = dep.into();
+    scan.predicate = predicate.into();
+    let mut query = QueryProto::new();
+    query.set_scan(scan);
+    query
+}
+
+fn and_predicate(mut predicates: Vec<PredicateProto>) -> PredicateProto {
+    if predicates.len() == 1 {
+        return predicates.pop().unwrap();
+    }
+    let mut and = predicate_proto::AndProto::new();
+    and.predicates = predicates;
+    let mut predicate = PredicateProto::new();
+    predicate.set_and(and);
+    predicate
+}
+
+pub(crate) fn validate_predicate(
+    db: &Database<impl Filelike>,
+    predicate: &PredicateProto,
+) -> Result<(), Error> {
+    let name = match &predicate.predicate_type {
+        Some(predicate_proto::Predicate_type::And(and)) => {
+            return and
+                .predicates
+                .iter()
+                .try_for_each(|predicate| validate_predicate(db, predicate));
+        }
+        Some(predicate_proto::Predicate_type::Or(or)) => {
+            return or
+                .predicates
+                .iter()
+                .try_for_each(|predicate| validate_predicate(db, predicate));
+        }
+        Some(predicate_proto::Predicate_type::Filter(filter)) => match &filter.filter_type {
+            Some(filter_proto::Filter_type::Equals(equals)) => &equals.name,
+            Some(filter_proto::Filter_type::InRange(in_range)) => &in_range.name,
+            None => return Err(Error::new(InvalidArgument, "Empty filter!".to_string())),
+        },
+        None => return Err(Error::new(InvalidArgument, "Empty predicate!".to_string())),
+    };
+    if !schema::is_col_in_table(&db.table.schema, name) {
+        return Err(Error::new(
+            InvalidArgument,
+            format!("Column not in table: {}!", name),
+        ));
+    }
+    Ok(())
+}
+
+// Compiles a logical predicate into a physical query producing the matching rows, sorted by
+// primary key. Each filter is answered by an index where one exists: a point lookup for primary
+// key equality, and an index range otherwise. Conjunctions become intersections (most selective
+// input first, as estimated from table statistics) and disjunctions become unions. Predicates
+// which can't use an index are pushed down into a scan over the narrowest indexed input
+// available, or over the whole table as a last resort.
+pub(crate) fn plan_predicate<F: Filelike>(
+    db: &Database<F>,
+    predicate: PredicateProto,
+) -> Result<QueryProto, Error> {
+    validate_predicate(db, &predicate)?;
+    let query = match plan_access_path(db, &predicate) {
+        Some(path) => path.query,
+        None => scan_query(None, Some(resolve_predicate(db, predicate))),
+    };
+    log::trace!("Planned query: {:?}", query);
+    Ok(query)
+}
+
+// Returns None if the predicate can't be answered using indexes alone.
+fn plan_access_path<F: Filelike>(
+    db: &Database<F>,
+    predicate: &PredicateProto,
+) -> Option<AccessPath> {
+    match &predicate.predicate_type {
+        Some(predicate_proto::Predicate_type::And(and)) => plan_and(db, and),
+        Some(predicate_proto::Predicate_type::Or(or)) => plan_or(db, or),
+        Some(predicate_proto::Predicate_type::Filter(filter)) => plan_filter(db, filter),
+        None => panic!(),
+    }
+}
+
+// Estimates the number of rows matched by a filter over the table (or index) keyed on its column.
+pub(crate) fn estimate_filter_rows<F: Filelike>(table: &Table<F>, filter: &FilterProto) -> f64 {
+    let statistics = table.statistics();
+    match &filter.filter_type {
+        Some(filter_proto::Filter_type::Equals(equals)) => statistics::estimate_equal_rows(
+            &statistics,
+            schema::get_hashed_col_value(&equals.value),
+        ),
+        Some(filter_proto::Filter_type::InRange(in_range)) => statistics::estimate_range_rows(
+            &statistics,
+            schema::get_hashed_col_value(&in_range.lower_value),
+            schema::get_hashed_col_value(&in_range.upper_value),
+        ),
+        None => panic!(),
+    }
+}
+
+fn estimate_selectivity<F: Filelike>(
+    db: &Database<F>,
+    table: &Table<F>,
+    filter: &FilterProto,
+) -> f64 {
+    let num_rows = db.table.statistics().row_count;
+    if num_rows == 0 {
+        return 0.0;
+    }
+    f64::min(estimate_filter_rows(table, filter) / num_rows as f64, 1.0)
+}
+
+fn plan_filter<F: Filelike>(db: &Database<F>, filter: &FilterProto) -> Option<AccessPath> {
+    match &filter.filter_type {
+        Some(filter_proto::Filter_type::Equals(equals)) => {
+            let table = db.find_table_keyed_on_column(&equals.name).ok()?;
+            let selectivity = estimate_selectivity(db, &table, filter);
+            if Arc::ptr_eq(&table, &db.table) {
+                return Some(AccessPath {
+                    query: filter_query(filter.clone()),
+                    selectivity,
+                });
+            }
+            // NOTE: secondary index keys aren't unique, so all matches are found with a range.
+            let mut in_range = filter_proto::FilterInRangeProto::new();
+            in_range.name = equals.name.clone();
+            in_range.lower_value = equals.value.clone();
+            in_range.upper_value = equals.value.clone();
+            let mut range_filter = FilterProto::new();
+            range_filter.set_in_range(in_range);
+            Some(AccessPath {
+                query: filter_query(range_filter),
+                selectivity,
+            })
+        }
+        Some(filter_proto::Filter_type::InRange(in_range)) => {
+            let table = db.find_table_keyed_on_column(&in_range.name).ok()?;
+            let paths = hashed_range_filters(db, in_range)
+                .into_iter()
+                .map(|filter| AccessPath {
+                    selectivity: estimate_selectivity(db, &table, &filter),
+                    query: filter_query(filter),
+                });
+            union_paths(paths)
+        }
+        None => panic!(),
+    }
+}
+
+fn range_filter(name: &str, lower: u32, upper: u32) -> FilterProto {
+    let mut in_range = filter_proto::FilterInRangeProto::new();
+    in_range.name = name.to_string();
+    in_range
+        .lower_value
+        .mut_or_insert_default()
+        .set_uint_value(lower);
+    in_range
+        .upper_value
+        .mut_or_insert_default()
+        .set_uint_value(upper);
+    let mut filter = FilterProto::new();
+    filter.set_in_range(in_range);
+    filter
+}
+
+// Compiles a range of column values, as given in predicates, into the filters over ranges of
+// hashed values (as used by indexes and scans) matching it. Returns a single empty range if no
+// value matches.
+fn hashed_range_filters<F: Filelike>(
+    db: &Database<F>,
+    in_range: &filter_proto::FilterInRangeProto,
+) -> Vec<FilterProto> {
+    let column_type = schema::find_col_schema(&db.table.schema, &in_range.name)
+        .map(|col| col.column_type.enum_value_or_default())
+        .unwrap_or_default();
+    let ranges = schema::get_hashed_ranges(
+        column_type,
+        in_range.lower_value.as_ref(),
+        in_range.upper_value.as_ref(),
+    );
+    if ranges.is_empty() {
+        return vec![range_filter(&in_range.name, 1, 0)];
+    }
+    ranges
+        .into_iter()
+        .map(|(lower, upper)| range_filter(&in_range.name, lower, upper))
+        .collect()
+}
+
+// Compiles the ranges of column values in a predicate into ranges of hashed values, e.g. for
+// scans to evaluate.
+pub(crate) fn resolve_predicate<F: Filelike>(
+    db: &Database<F>,
+    predicate: PredicateProto,
+) -> PredicateProto {
+    let mut predicate = predicate;
+    match &mut predicate.predicate_type {
+        Some(predicate_proto::Predicate_type::And(and)) => {
+            and.predicates = std::mem::take(&mut and.predicates)
+                .into_iter()
+                .map(|predicate| resolve_predicate(db, predicate))
+                .collect();
+        }
+        Some(predicate_proto::Predicate_type::Or(or)) => {
+            or.predicates = std::mem::take(&mut or.predicates)
+                .into_iter()
+                .map(|predicate| resolve_predicate(db, predicate))
+                .collect();
+        }
+        Some(predicate_proto::Predicate_type::Filter(filter)) => {
+            let Some(filter_proto::Filter_type::InRange(in_range)) = &filter.filter_type else {
+                return predicate;
+            };
+            let mut predicates: Vec<PredicateProto> = hashed_range_filters(db, in_range)
+                .into_iter()
+                .map(|filter| {
+                    let mut predicate = PredicateProto::new();
+                    predicate.set_filter(filter);
+                    predicate
+                })
+                .collect();
+            if predicates.len() == 1 {
+                return predicates.pop().unwrap();
+            }
+            let mut or = predicate_proto::OrProto::new();
+            or.predicates = predicates;
+            predicate.set_or(or);
+        }
+        None => {}
+    }
+    predicate
+}
+
+fn as_in_range(predicate: &PredicateProto) -> Option<&filter_proto::FilterInRangeProto> {
+    match &predicate.predicate_type {
+        Some(predicate_proto::Predicate_type::Filter(filter)) => match &filter.filter_type {
+            Some(filter_proto::Filter_type::InRange(range)) => Some(range),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn as_in_range_mut(
+    predicate: &mut PredicateProto,
+) -> Option<&mut filter_proto::FilterInRangeProto> {
+    match &mut predicate.predicate_type {
+        Some(predicate_proto::Predicate_type::Filter(filter)) => match &mut filter.filter_type {
+            Some(filter_proto::Filter_type::InRange(range)) => Some(range),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Flattens nested conjunctions, and merges ranges over the same column into a single range.
+fn collect_conjuncts(and: &predicate_proto::AndProto, conjuncts: &mut Vec<PredicateProto>) {
+    for predicate in &and.predicates {
+        if let Some(predicate_proto::Predicate_type::And(and)) = &predicate.predicate_type {
+            collect_conjuncts(and, conjuncts);
+            continue;
+        }
+        let Some(new_range) = as_in_range(predicate) else {
+            conjuncts.push(predicate.clone());
+            continue;
+        };
+        let existing_range = conjuncts
+            .iter_mut()
+            .filter_map(as_in_range_mut)
+            .find(|range| range.name == new_range.name);
+        let Some(range) = existing_range else {
+            conjuncts.push(predicate.clone());
+            continue;
+        };
+        // NOTE: values are merged in their natural order, before being hashed.
+        if let Some(lower) = new_range.lower_value.as_ref() {
+            if range.lower_value.as_ref().is_none_or(|existing| {
+                schema::get_col_value_as_i64(existing) < schema::get_col_value_as_i64(lower)
+            }) {
+                range.lower_value = MessageField::some(lower.clone());
+            }
+        }
+        if let Some(upper) = new_range.upper_value.as_ref() {
+            if range.upper_value.as_ref().is_none_or(|existing| {
+                schema::get_col_value_as_i64(existing) > schema::get_col_value_as_i64(upper)
+            }) {
+                range.upper_value = MessageField::some(upper.clone());
+            }
+        }
+    }
+}
+
+fn plan_and<F: Filelike>(db: &Database<F>, and: &predicate_proto::AndProto) -> Option<AccessPath> {
+    let mut conjuncts = Vec::new();
+    collect_conjuncts(and, &mut conjuncts);
+
+    let mut paths = Vec::new();
+    let mut residuals = Vec::new();
+    for conjunct in conjuncts {
+        match plan_access_path(db, &conjunct) {
+            Some(path) => paths.push(path),
+            None => residuals.push(conjunct),
+        }
+    }
+    if paths.is_empty() {
+        return None;
+    }
+
+    paths.sort_by(|lhs, rhs| lhs.selectivity.total_cmp(&rhs.selectivity));
+    let mut paths = paths.into_iter();
+    let mut path = paths.next().unwrap();
+    for rhs in paths {
+        let mut intersect = IntersectProto::new();
+        intersect.lhs = MessageField::some(path.query);
+        intersect.rhs = MessageField::some(rhs.query);
+        path.query = QueryProto::new();
+        path.query.set_intersect(intersect);
+        path.selectivity *= rhs.selectivity;
+    }
+    if !residuals.is_empty() {
+        path.selectivity *= RESIDUAL_SELECTIVITY.powi(residuals.len() as i32);
+        let predicate = resolve_predicate(db, and_predicate(residuals));
+        path.query = scan_query(Some(path.query), Some(predicate));
+    }
+    Some(path)
+}
+
+fn plan_or<F: Filelike>(db: &Database<F>, or: &predicate_proto::OrProto) -> Option<AccessPath> {
+    let mut paths = Vec::new();
+    for predicate in &or.predicates {
+        match &predicate.predicate_type {
+            // NOTE: nested disjunctions are flattened.
+            Some(predicate_proto::Predicate_type::Or(or)) => paths.push(plan_or(db, or)?),
+            _ => paths.push(plan_access_path(db, predicate)?),
+        }
+    }
+
+    Some(union_paths(paths).unwrap_or_else(|| AccessPath {
+        // NOTE: an empty disjunction matches nothing.
+        query: filter_query(range_filter(&db.table.schema.key.name, 1, 0)),
+        selectivity: 0.0,
+    }))
+}
+
+// Returns None if there are no paths to union.
+fn union_paths(paths: impl IntoIterator<Item = AccessPath>) -> Option<AccessPath> {
+    let mut paths = paths.into_iter();
+    let mut path = paths.next()?;
+    for rhs in paths {
+        let mut union = UnionProto::new();
+        union.lhs = MessageField::some(path.query);
+        union.rhs = MessageField::some(rhs.query);
+        path.query = QueryProto::new();
+        path.query.set_union(union);
+        path.selectivity = f64::min(path.selectivity + rhs.selectivity, 1.0);
+    }
+    Some(path)
+}
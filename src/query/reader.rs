@@ -1,7 +1,9 @@
 use crate::buffer::Buffer;
-use crate::error::{ErrorKind::*, *};
+use crate::error::*;
 use crate::filelike::Filelike;
 use crate::protos::generated::chunk::*;
+use crate::protos::generated::config::*;
+use crate::protos::generated::operations::*;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
@@ -13,30 +15,67 @@ pub(crate) struct ResultsReader<F: Filelike> {
 }
 
 impl<F: Filelike> ResultsReader<F> {
-    pub(crate) fn new(file: F) -> Self {
+    pub(crate) fn new(file: F, config: &TableConfig) -> Self {
         let file = Arc::new(Mutex::new(file));
         Self {
             file: file.clone(),
-            current_buffer: Buffer::new_for_file(file, 0, InternalQueryResultsProto::new()),
+            current_buffer: Buffer::new_for_file(file, config, 0, InternalQueryResultsProto::new()),
             current_buffer_offset: std::u32::MAX,
             idx: std::usize::MAX,
         }
     }
 
-    // TODO: stages currently read until there is an error, assuming that the first error returned
-    // will be of type "the file is done". this assumption likely doesn't always hold.
-    pub(crate) async fn next_key(&mut self) -> Result<u32, Error> {
+    // Moves on to the next result, returning false once there are none left.
+    async fn advance(&mut self) -> Result<bool, Error> {
         self.idx = self.idx.wrapping_add(1);
         if self.idx >= self.current_buffer.get().keys.len() {
             self.idx = 0;
             self.current_buffer_offset = self.current_buffer_offset.wrapping_add(1);
-            self.current_buffer =
-                Buffer::read_from_file(self.file.clone(), self.current_buffer_offset).await?;
-            // NOTE: it seems like cursors don't OOB when reading outside written bounds.
-            if self.current_buffer.get().keys.len() == 0 {
-                return Err(Error::new(OutOfBounds, "".to_string()));
+            self.current_buffer = Buffer::read_from_file(
+                self.file.clone(),
+                &self.current_buffer.config,
+                self.current_buffer_offset,
+            )
+            .await?;
+            // NOTE: reading past the end of a file reads an empty chunk, rather than failing.
+            if self.current_buffer.get().keys.is_empty() {
+                return Ok(false);
             }
         }
-        Ok(self.current_buffer.get().keys[self.idx])
+        Ok(true)
+    }
+
+    // Returns the underlying file, e.g. to be read again from the start.
+    pub(crate) fn into_inner(self) -> F {
+        drop(self.current_buffer);
+        Arc::into_inner(self.file).unwrap().into_inner()
+    }
+
+    // Returns the next key along with its row, if the producing stage wrote one.
+    // Returns None once all results were read.
+    pub(crate) async fn next_key_row(&mut self) -> Result<Option<(u32, Option<RowProto>)>, Error> {
+        if !self.advance().await? {
+            return Ok(None);
+        }
+        let results = self.current_buffer.get();
+        Ok(Some((
+            results.keys[self.idx],
+            results.rows.get(self.idx).cloned(),
+        )))
+    }
+
+    // Returns the next entry of a sorted run, see ResultsWriter::write_sort_entry.
+    pub(crate) async fn next_sort_entry(
+        &mut self,
+    ) -> Result<Option<(u32, RowProto, InternalRowProto)>, Error> {
+        if !self.advance().await? {
+            return Ok(None);
+        }
+        let results = self.current_buffer.get();
+        Ok(Some((
+            results.keys[self.idx],
+            results.rows[self.idx].clone(),
+            results.sort_values[self.idx].clone(),
+        )))
     }
 }
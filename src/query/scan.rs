@@ -0,0 +1,147 @@
+use crate::database::*;
+use crate::error::*;
+use crate::filelike::Filelike;
+use crate::protos::generated::operations::*;
+use crate::query;
+use crate::query::reader::ResultsReader;
+use crate::query::writer::ResultsWriter;
+use crate::query::StageBounds;
+use crate::schema;
+
+// Returns true iff the row satisfies the predicate. Values are compared by their hashes, matching
+// the semantics of filters over indexes.
+pub(crate) fn matches_predicate(row: &RowProto, predicate: &PredicateProto) -> bool {
+    match &predicate.predicate_type {
+        Some(predicate_proto::Predicate_type::And(and)) => and
+            .predicates
+            .iter()
+            .all(|predicate| matches_predicate(row, predicate)),
+        Some(predicate_proto::Predicate_type::Or(or)) => or
+            .predicates
+            .iter()
+            .any(|predicate| matches_predicate(row, predicate)),
+        Some(predicate_proto::Predicate_type::Filter(filter)) => match &filter.filter_type {
+            Some(filter_proto::Filter_type::Equals(equals)) => schema::find_col(row, &equals.name)
+                .is_some_and(|col| {
+                    schema::get_hashed_col_value(&col.value)
+                        == schema::get_hashed_col_value(&equals.value)
+                }),
+            Some(filter_proto::Filter_type::InRange(in_range)) => {
+                schema::find_col(row, &in_range.name).is_some_and(|col| {
+                    let value = schema::get_hashed_col_value(&col.value);
+                    schema::get_hashed_col_value(&in_range.lower_value) <= value
+                        && value <= schema::get_hashed_col_value(&in_range.upper_value)
+                })
+            }
+            None => panic!(),
+        },
+        None => panic!(),
+    }
+}
+
+// Collects the names of all columns referenced by the predicate.
+pub(crate) fn predicate_columns(predicate: &PredicateProto, col_names: &mut Vec<String>) {
+    let name = match &predicate.predicate_type {
+        Some(predicate_proto::Predicate_type::And(and)) => {
+            for predicate in &and.predicates {
+                predicate_columns(predicate, col_names);
+            }
+            return;
+        }
+        Some(predicate_proto::Predicate_type::Or(or)) => {
+            for predicate in &or.predicates {
+                predicate_columns(predicate, col_names);
+            }
+            return;
+        }
+        Some(predicate_proto::Predicate_type::Filter(filter)) => match &filter.filter_type {
+            Some(filter_proto::Filter_type::Equals(equals)) => &equals.name,
+            Some(filter_proto::Filter_type::InRange(in_range)) => &in_range.name,
+            None => panic!(),
+        },
+        None => panic!(),
+    };
+    if !col_names.contains(name) {
+        col_names.push(name.clone());
+    }
+}
+
+// Checks the row against the (optional) predicate, writing it if it matches.
+async fn write_if_matches<F: Filelike>(
+    out: &mut ResultsWriter<F>,
+    key: u32,
+    row: RowProto,
+    predicate: Option<&PredicateProto>,
+) -> Result<bool, Error> {
+    if predicate.is_some_and(|predicate| !matches_predicate(&row, predicate)) {
+        return Ok(false);
+    }
+    out.write_key_row(key, row).await?;
+    Ok(true)
+}
+
+pub(crate) async fn execute_scan<F: Filelike>(
+    db: &Database<F>,
+    scan: ScanProto,
+    bounds: StageBounds,
+) -> Result<F, Error> {
+    let predicate = scan.predicate.into_option();
+    let mut out = ResultsWriter::new(F::temp().await?, &db.table.config);
+    let mut num_results = 0;
+
+    if let Some(dep) = scan.dep.into_option() {
+        log::trace!("Scanning dependency results");
+        let mut col_names = Vec::new();
+        if let Some(predicate) = &predicate {
+            predicate_columns(predicate, &mut col_names);
+        }
+        // NOTE: the limit can't be forwarded, as some of the dependency's results may not match.
+        let dep_bounds = StageBounds {
+            limit: if predicate.is_some() {
+                None
+            } else {
+                bounds.limit
+            },
+            after_key: bounds.after_key,
+        };
+        let mut dep = ResultsReader::new(
+            query::execute_stage(db, dep, dep_bounds).await?,
+            &db.table.config,
+        );
+        while let Some((key, dep_row)) = dep.next_key_row().await? {
+            if bounds.is_limit_reached(num_results) {
+                break;
+            }
+            if !bounds.is_key_in_bounds(key) {
+                continue;
+            }
+            let row = query::resolve_row(db, key, dep_row, &col_names).await?;
+            if write_if_matches(&mut out, key, row, predicate.as_ref()).await? {
+                num_results += 1;
+            }
+        }
+        return out.finish().await;
+    }
+
+    log::trace!("Scanning table: {}", db.table.name);
+    let lower = match bounds.after_key {
+        Some(after_key) => match after_key.checked_add(1) {
+            Some(lower) => lower,
+            None => return out.finish().await,
+        },
+        None => 0,
+    };
+    let mut cursor = db.table.read_range(lower, u32::MAX);
+    while let Some((keys, rows)) = cursor.next_leaf().await? {
+        for (key, row) in keys.into_iter().zip(rows.iter()) {
+            if bounds.is_limit_reached(num_results) {
+                return out.finish().await;
+            }
+            let row = schema::internal_row_to_row(row, &db.table.schema);
+            if write_if_matches(&mut out, key, row, predicate.as_ref()).await? {
+                num_results += 1;
+            }
+        }
+    }
+    out.finish().await
+}
@@ -1,22 +1,130 @@
+use crate::bp_tree;
+use crate::buffer_pool;
 use crate::database::*;
-use crate::error::*;
+use crate::error::{ErrorKind::*, *};
 use crate::filelike::Filelike;
 use crate::protos::generated::operations::*;
 use crate::query;
+use crate::query::StageBounds;
 use crate::query::{reader::ResultsReader, writer::ResultsWriter};
+use crate::schema;
 use crate::table::Table;
+use std::collections::VecDeque;
 use std::sync::Arc;
 
+// How many rows a select reads from the table at once, see Table::read_rows.
+const SELECT_BATCH_SIZE: usize = 64;
+
 pub(crate) async fn execute_select<F: Filelike>(
     db: &Database<F>,
     select: SelectProto,
+    bounds: StageBounds,
 ) -> Result<F, Error> {
-    let mut out = ResultsWriter::new(F::create("TODO").await?);
-    let mut dep = ResultsReader::new(query::execute_query(db, select.dep.unwrap()).await?);
+    for col_name in &select.columns {
+        if !schema::is_col_in_table(&db.table.schema, col_name) {
+            return Err(Error::new(
+                InvalidArgument,
+                format!("Column not in table: {}!", col_name),
+            ));
+        }
+    }
+
+    let mut out = ResultsWriter::new(F::temp().await?, &db.table.config);
+    // NOTE: select produces exactly one result per dependency result, so bounds can be forwarded.
+    let mut dep = ResultsReader::new(
+        query::execute_stage(db, select.dep.unwrap(), bounds).await?,
+        &db.table.config,
+    );
     let table: Arc<Table<F>> = db.table.clone();
-    while let Ok(key) = dep.next_key().await {
-        let row = table.read_row(key).await?;
-        out.write_key_row(key, row).await?;
+    let window = table.options.read_ahead_window;
+    // Keys in bounds read from the dependency, but not yet selected, with their covered rows.
+    let mut upcoming = VecDeque::new();
+    // How many of the upcoming keys (from the front) have been prefetched.
+    let mut num_prefetched: usize = 0;
+    let mut dep_done = false;
+    let mut num_results = 0;
+    loop {
+        // NOTE: every upcoming key is selected, so no more than the limit are read.
+        while !dep_done
+            && upcoming.len() < SELECT_BATCH_SIZE + window
+            && !bounds.is_limit_reached(num_results + upcoming.len())
+        {
+            let Some((key, dep_row)) = dep.next_key_row().await? else {
+                dep_done = true;
+                break;
+            };
+            if !bounds.is_key_in_bounds(key) {
+                continue;
+            }
+            // If the dependency already produced every requested column (e.g. it read
+            // from a secondary index), the main table does not need to be read at all.
+            let covered_row = match dep_row {
+                Some(dep_row) if !select.columns.is_empty() => {
+                    schema::project_row(&dep_row, &select.columns)
+                }
+                _ => None,
+            };
+            upcoming.push_back((key, covered_row));
+        }
+        if upcoming.is_empty() {
+            break;
+        }
+
+        let batch_size = std::cmp::min(upcoming.len(), SELECT_BATCH_SIZE);
+        let batch: Vec<_> = upcoming.drain(..batch_size).collect();
+        // The rows of the keys after the batch (up to window) are prefetched while the batch is
+        // read, so that they are cached by the time they are selected.
+        num_prefetched = num_prefetched.saturating_sub(batch_size);
+        if window > 0 && num_prefetched < upcoming.len() {
+            prefetch_rows(&table, uncovered_keys(upcoming.range(num_prefetched..)));
+            num_prefetched = upcoming.len();
+        }
+
+        let mut rows = table
+            .read_rows(&uncovered_keys(batch.iter()))
+            .await?
+            .into_iter();
+        for (key, covered_row) in batch {
+            let row = match covered_row {
+                Some(row) => row,
+                None => {
+                    let Some(row) = rows.next().unwrap() else {
+                        return Err(Error::new(
+                            NotFound,
+                            format!("Row with key {} not found!", key),
+                        ));
+                    };
+                    match select.columns.is_empty() {
+                        true => row,
+                        false => schema::project_row(&row, &select.columns)
+                            .ok_or_else(|| schema::missing_col_error(key, &select.columns))?,
+                    }
+                }
+            };
+            out.write_key_row(key, row).await?;
+            num_results += 1;
+        }
     }
     out.finish().await
 }
+
+// The keys of rows which must be read from the table, i.e. are not covered by the dependency.
+fn uncovered_keys<'a>(keys: impl Iterator<Item = &'a (u32, Option<RowProto>)>) -> Vec<u32> {
+    keys.filter(|(_, covered_row)| covered_row.is_none())
+        .map(|(key, _)| *key)
+        .collect()
+}
+
+// Reads the rows with the given keys into the buffer pool, in the background.
+fn prefetch_rows<F: Filelike>(table: &Arc<Table<F>>, mut keys: Vec<u32>) {
+    if keys.is_empty() {
+        return;
+    }
+    keys.sort_unstable();
+    keys.dedup();
+    let table = table.clone();
+    buffer_pool::spawn_prefetch(async move {
+        bp_tree::read_rows(&table, table.root_chunk_offset, &keys).await?;
+        Ok(())
+    });
+}
@@ -0,0 +1,139 @@
+use crate::error::*;
+use crate::filelike::Filelike;
+use crate::protos::generated::chunk::*;
+use crate::protos::generated::config::*;
+use crate::protos::generated::operations::*;
+use crate::query::{reader::ResultsReader, writer::ResultsWriter};
+use crate::schema;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+// Entries are ordered by their sort values, then by key.
+type SortKey = (Vec<i64>, u32);
+
+struct SortEntry {
+    sort_key: SortKey,
+    row: RowProto,
+    sort_values: InternalRowProto,
+}
+
+// External merge sort over query results, since they may not fit in memory.
+// Entries are buffered until run_size is reached, at which point they are
+// sorted and spilled to a temporary file (a sorted run). Once all entries are
+// pushed, the runs are k-way merged into the output.
+pub(crate) struct ExternalSorter<F: Filelike> {
+    descending: Vec<bool>,
+    entries: Vec<SortEntry>,
+    runs: Vec<F>,
+    // The geometry of spilled runs.
+    config: TableConfig,
+    run_size: usize,
+}
+
+impl<F: Filelike> ExternalSorter<F> {
+    // descending[i] denotes the direction to sort the ith sort value by.
+    pub(crate) fn new(descending: Vec<bool>, config: &TableConfig, run_size: usize) -> Self {
+        Self {
+            descending,
+            entries: Vec::new(),
+            runs: Vec::new(),
+            config: config.clone(),
+            run_size,
+        }
+    }
+
+    fn sort_key(&self, key: u32, sort_values: &InternalRowProto) -> SortKey {
+        let values = sort_values
+            .col_values
+            .iter()
+            .zip(self.descending.iter())
+            .map(|(value, descending)| {
+                let value = schema::get_col_value_as_i64(value);
+                if *descending {
+                    -value
+                } else {
+                    value
+                }
+            })
+            .collect();
+        (values, key)
+    }
+
+    pub(crate) async fn push(
+        &mut self,
+        key: u32,
+        row: RowProto,
+        sort_values: InternalRowProto,
+    ) -> Result<(), Error> {
+        self.entries.push(SortEntry {
+            sort_key: self.sort_key(key, &sort_values),
+            row,
+            sort_values,
+        });
+        if self.entries.len() >= self.run_size {
+            self.spill().await?;
+        }
+        Ok(())
+    }
+
+    // Sorts all buffered entries and writes them out as a new run.
+    async fn spill(&mut self) -> Result<(), Error> {
+        log::trace!("Spilling sorted run of {} entries.", self.entries.len());
+        self.entries
+            .sort_unstable_by(|lhs, rhs| lhs.sort_key.cmp(&rhs.sort_key));
+        let mut run = ResultsWriter::new(F::temp().await?, &self.config);
+        for entry in self.entries.drain(..) {
+            run.write_sort_entry(entry.sort_key.1, entry.row, entry.sort_values)
+                .await?;
+        }
+        self.runs.push(run.finish().await?);
+        Ok(())
+    }
+
+    // Writes all pushed entries to out in sorted order, or only the first `limit` if given.
+    pub(crate) async fn finish(
+        mut self,
+        out: &mut ResultsWriter<F>,
+        limit: Option<usize>,
+    ) -> Result<(), Error> {
+        let limit = limit.unwrap_or(usize::MAX);
+        if self.runs.is_empty() {
+            self.entries
+                .sort_unstable_by(|lhs, rhs| lhs.sort_key.cmp(&rhs.sort_key));
+            for entry in self.entries.into_iter().take(limit) {
+                out.write_key_row(entry.sort_key.1, entry.row).await?;
+            }
+            return Ok(());
+        }
+        if !self.entries.is_empty() {
+            self.spill().await?;
+        }
+
+        log::trace!("Merging {} sorted runs.", self.runs.len());
+        let mut readers: Vec<ResultsReader<F>> = std::mem::take(&mut self.runs)
+            .into_iter()
+            .map(|run| ResultsReader::new(run, &self.config))
+            .collect();
+        let mut heads: Vec<Option<RowProto>> = vec![None; readers.len()];
+        let mut heap = BinaryHeap::<Reverse<(SortKey, usize)>>::new();
+        for (i, reader) in readers.iter_mut().enumerate() {
+            if let Some((key, row, sort_values)) = reader.next_sort_entry().await? {
+                heads[i] = Some(row);
+                heap.push(Reverse((self.sort_key(key, &sort_values), i)));
+            }
+        }
+        let mut num_results = 0;
+        while let Some(Reverse(((_, key), i))) = heap.pop() {
+            if num_results >= limit {
+                break;
+            }
+            out.write_key_row(key, heads[i].take().unwrap()).await?;
+            num_results += 1;
+            if let Some((key, row, sort_values)) = readers[i].next_sort_entry().await? {
+                heads[i] = Some(row);
+                heap.push(Reverse((self.sort_key(key, &sort_values), i)));
+            }
+        }
+        Ok(())
+    }
+}
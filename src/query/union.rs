@@ -0,0 +1,42 @@
+use crate::database::*;
+use crate::error::*;
+use crate::filelike::Filelike;
+use crate::protos::generated::operations::*;
+use crate::query;
+use crate::query::merge::{MergeCursor, MergeKey, MergeMode};
+use crate::query::writer::ResultsWriter;
+use crate::query::StageBounds;
+
+pub(crate) async fn execute_union<F: Filelike>(
+    db: &Database<F>,
+    union: UnionProto,
+    bounds: StageBounds,
+) -> Result<F, Error> {
+    let mut out = ResultsWriter::new(F::temp().await?, &db.table.config);
+
+    // NOTE: each dependency produces at most limit results that will be consumed.
+    let lhs = query::execute_stage(db, union.lhs.unwrap(), bounds).await?;
+    let rhs = query::execute_stage(db, union.rhs.unwrap(), bounds).await?;
+    let mut merge = MergeCursor::new(
+        lhs,
+        MergeKey::PrimaryKey,
+        rhs,
+        MergeKey::PrimaryKey,
+        MergeMode::FullOuter,
+        &db.table.config,
+    )
+    .await?;
+    let mut num_results = 0;
+    while let Some((lhs_group, rhs_group)) = merge.next_group().await? {
+        let key = lhs_group.first().or(rhs_group.first()).unwrap().0;
+        if !bounds.is_key_in_bounds(key) {
+            continue;
+        }
+        out.write_key(key).await?;
+        num_results += 1;
+        if bounds.is_limit_reached(num_results) {
+            break;
+        }
+    }
+    out.finish().await
+}
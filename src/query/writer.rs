@@ -2,21 +2,35 @@ use crate::buffer::Buffer;
 use crate::error::*;
 use crate::filelike::Filelike;
 use crate::protos::generated::chunk::*;
+use crate::protos::generated::config::*;
 use crate::protos::generated::operations::*;
+use protobuf::rt::compute_raw_varint64_size;
 use protobuf::Message;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
+// The size of a key in the (packed) keys of a results chunk.
+fn key_size(key: u32) -> usize {
+    compute_raw_varint64_size(key as u64) as usize
+}
+
+// The size of a message in a repeated field, including its tag and length.
+fn entry_size(message: &impl Message) -> usize {
+    let size = message.compute_size();
+    1 + compute_raw_varint64_size(size) as usize + size as usize
+}
+
 pub(crate) struct ResultsWriter<F: Filelike> {
     current_buffer: Buffer<F, InternalQueryResultsProto>,
     current_buffer_offset: u32,
 }
 
 impl<F: Filelike> ResultsWriter<F> {
-    pub(crate) fn new(file: F) -> Self {
+    pub(crate) fn new(file: F, config: &TableConfig) -> Self {
         Self {
             current_buffer: Buffer::new_for_file(
                 Arc::new(Mutex::new(file)),
+                config,
                 0,
                 InternalQueryResultsProto::new(),
             ),
@@ -24,40 +38,47 @@ impl<F: Filelike> ResultsWriter<F> {
         }
     }
 
-    pub(crate) async fn write_key(&mut self, key: u32) -> Result<(), Error> {
-        if self
-            .current_buffer
-            .would_overflow(std::mem::size_of::<u32>())
-        {
+    // Commits the current buffer and moves on to the next one if it cannot fit
+    // the given number of additional bytes.
+    async fn reserve(&mut self, size: usize) -> Result<(), Error> {
+        if self.current_buffer.would_overflow(size) {
             let file = self.current_buffer.file.clone();
             self.current_buffer.write_to_file().await?;
             self.current_buffer_offset += 1;
             self.current_buffer = Buffer::new_for_file(
                 file,
+                &self.current_buffer.config,
                 self.current_buffer_offset,
                 InternalQueryResultsProto::new(),
             );
         }
+        Ok(())
+    }
+
+    pub(crate) async fn write_key(&mut self, key: u32) -> Result<(), Error> {
+        self.reserve(key_size(key)).await?;
         self.current_buffer.get_mut().keys.push(key);
         Ok(())
     }
 
     pub(crate) async fn write_key_row(&mut self, key: u32, row: RowProto) -> Result<(), Error> {
-        if self
-            .current_buffer
-            .would_overflow(row.compute_size() as usize + std::mem::size_of::<u32>())
-        {
-            let file = self.current_buffer.file.clone();
-            self.current_buffer.write_to_file().await?;
-            self.current_buffer_offset += 1;
-            self.current_buffer = Buffer::new_for_file(
-                file,
-                self.current_buffer_offset,
-                InternalQueryResultsProto::new(),
-            );
-        }
+        self.reserve(key_size(key) + entry_size(&row)).await?;
+        self.current_buffer.get_mut().keys.push(key);
+        self.current_buffer.get_mut().rows.push(row);
+        Ok(())
+    }
+
+    pub(crate) async fn write_sort_entry(
+        &mut self,
+        key: u32,
+        row: RowProto,
+        sort_values: InternalRowProto,
+    ) -> Result<(), Error> {
+        self.reserve(key_size(key) + entry_size(&row) + entry_size(&sort_values))
+            .await?;
         self.current_buffer.get_mut().keys.push(key);
         self.current_buffer.get_mut().rows.push(row);
+        self.current_buffer.get_mut().sort_values.push(sort_values);
         Ok(())
     }
 
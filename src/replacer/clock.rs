@@ -0,0 +1,76 @@
+use crate::replacer::{PageId, Replacer};
+use std::collections::HashMap;
+
+// Approximates LRU without reordering pages on every access: pages sit in a circular buffer of
+// frames, each with a reference bit set when accessed. To evict, a hand sweeps the frames,
+// clearing set bits, and evicts the first page whose bit was already clear.
+pub(crate) struct Clock {
+    // Each frame's page and reference bit, or None if free.
+    frames: Vec<Option<(PageId, bool)>>,
+    idxs: HashMap<PageId, usize>,
+    free_idxs: Vec<usize>,
+    hand: usize,
+}
+
+impl Clock {
+    pub(crate) fn new() -> Self {
+        Self {
+            frames: Vec::new(),
+            idxs: HashMap::new(),
+            free_idxs: Vec::new(),
+            hand: 0,
+        }
+    }
+}
+
+impl Replacer for Clock {
+    fn access(&mut self, page: PageId) {
+        if let Some(idx) = self.idxs.get(&page) {
+            self.frames[*idx].as_mut().unwrap().1 = true;
+        }
+    }
+
+    fn insert(&mut self, page: PageId) {
+        debug_assert!(!self.idxs.contains_key(&page));
+        // NOTE: the reference bit starts set, so that a page survives at least one sweep.
+        let idx = match self.free_idxs.pop() {
+            Some(idx) => {
+                self.frames[idx] = Some((page, true));
+                idx
+            }
+            None => {
+                self.frames.push(Some((page, true)));
+                self.frames.len() - 1
+            }
+        };
+        self.idxs.insert(page, idx);
+    }
+
+    fn evict(&mut self, is_evictable: &dyn Fn(&PageId) -> bool) -> Option<PageId> {
+        if self.idxs.is_empty() {
+            return None;
+        }
+        // NOTE: after 2 sweeps every reference bit is clear, so only unevictable pages remain.
+        for _ in 0..2 * self.frames.len() + 1 {
+            let idx = self.hand;
+            self.hand = (self.hand + 1) % self.frames.len();
+            match &mut self.frames[idx] {
+                Some((page, _)) if !is_evictable(page) => {}
+                Some((_, referenced)) if *referenced => *referenced = false,
+                Some((page, _)) => {
+                    let page = *page;
+                    self.frames[idx] = None;
+                    self.free_idxs.push(idx);
+                    self.idxs.remove(&page);
+                    return Some(page);
+                }
+                None => {}
+            }
+        }
+        None
+    }
+
+    fn reinstate(&mut self, page: PageId) {
+        self.insert(page);
+    }
+}
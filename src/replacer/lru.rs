@@ -0,0 +1,63 @@
+use crate::replacer::{PageId, Replacer};
+use std::collections::{BTreeMap, HashMap};
+
+// Evicts the least recently used page.
+pub(crate) struct Lru {
+    // Incremented on every access, to order them.
+    tick: u64,
+    // The tick of each page's most recent access, and vice versa.
+    ticks: HashMap<PageId, u64>,
+    pages: BTreeMap<u64, PageId>,
+}
+
+impl Lru {
+    pub(crate) fn new() -> Self {
+        Self {
+            tick: 0,
+            ticks: HashMap::new(),
+            pages: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.ticks.len()
+    }
+
+    pub(crate) fn contains(&self, page: &PageId) -> bool {
+        self.ticks.contains_key(page)
+    }
+}
+
+impl Replacer for Lru {
+    fn access(&mut self, page: PageId) {
+        if let Some(tick) = self.ticks.get_mut(&page) {
+            self.pages.remove(tick);
+            self.tick += 1;
+            *tick = self.tick;
+            self.pages.insert(self.tick, page);
+        }
+    }
+
+    fn insert(&mut self, page: PageId) {
+        debug_assert!(!self.contains(&page));
+        self.tick += 1;
+        self.ticks.insert(page, self.tick);
+        self.pages.insert(self.tick, page);
+    }
+
+    fn evict(&mut self, is_evictable: &dyn Fn(&PageId) -> bool) -> Option<PageId> {
+        let (tick, page) = self
+            .pages
+            .iter()
+            .find(|(_, page)| is_evictable(page))
+            .map(|(tick, page)| (*tick, *page))?;
+        self.pages.remove(&tick);
+        self.ticks.remove(&page);
+        Some(page)
+    }
+
+    // NOTE: the page was least recently used, so is used now.
+    fn reinstate(&mut self, page: PageId) {
+        self.insert(page);
+    }
+}
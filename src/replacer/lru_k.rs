@@ -0,0 +1,111 @@
+use crate::replacer::{PageId, Replacer};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+
+// O'Neil et al.'s LRU-K: https://www.cs.cmu.edu/~natassa/courses/15-721/papers/p297-o_neil.pdf
+//
+// Evicts the page whose K-th most recent access is furthest in the past. Pages accessed fewer
+// than K times are evicted first (least recently used first), so pages read once, e.g. by a
+// scan, don't displace pages read repeatedly. The histories of evicted pages are remembered
+// (for up to capacity pages), so that pages re-read soon after eviction aren't treated as new.
+pub(crate) struct LruK {
+    k: usize,
+    capacity: usize,
+    // Incremented on every access, to order them.
+    tick: u64,
+    // The (up to) K most recent access ticks of each tracked page, most recent last.
+    histories: HashMap<PageId, VecDeque<u64>>,
+    // Tracked pages, in eviction order, see priority.
+    pages: BTreeSet<(u64, u64, PageId)>,
+    // The histories of evicted pages along with when they were evicted, and the order they
+    // were evicted in.
+    // NOTE: the order may contain stale entries for pages evicted more than once.
+    retained: HashMap<PageId, (u64, VecDeque<u64>)>,
+    retained_order: VecDeque<(u64, PageId)>,
+}
+
+impl LruK {
+    pub(crate) fn new(k: usize, capacity: usize) -> Self {
+        debug_assert!(k > 0);
+        Self {
+            k,
+            capacity,
+            tick: 0,
+            histories: HashMap::new(),
+            pages: BTreeSet::new(),
+            retained: HashMap::new(),
+            retained_order: VecDeque::new(),
+        }
+    }
+
+    // Orders pages by their K-th most recent access (0 if there isn't one), then their most
+    // recent access.
+    fn priority(&self, history: &VecDeque<u64>) -> (u64, u64) {
+        let kth_tick = match history.len() >= self.k {
+            true => history[history.len() - self.k],
+            false => 0,
+        };
+        (kth_tick, *history.back().unwrap())
+    }
+
+    fn record_access(&mut self, page: PageId, mut history: VecDeque<u64>) {
+        self.tick += 1;
+        history.push_back(self.tick);
+        if history.len() > self.k {
+            history.pop_front();
+        }
+        let (kth_tick, tick) = self.priority(&history);
+        self.pages.insert((kth_tick, tick, page));
+        self.histories.insert(page, history);
+    }
+}
+
+impl Replacer for LruK {
+    fn access(&mut self, page: PageId) {
+        let Some(history) = self.histories.remove(&page) else {
+            return;
+        };
+        let (kth_tick, tick) = self.priority(&history);
+        self.pages.remove(&(kth_tick, tick, page));
+        self.record_access(page, history);
+    }
+
+    fn insert(&mut self, page: PageId) {
+        debug_assert!(!self.histories.contains_key(&page));
+        let history = match self.retained.remove(&page) {
+            Some((_, history)) => history,
+            None => VecDeque::with_capacity(self.k),
+        };
+        self.record_access(page, history);
+    }
+
+    fn evict(&mut self, is_evictable: &dyn Fn(&PageId) -> bool) -> Option<PageId> {
+        let entry = *self.pages.iter().find(|(_, _, page)| is_evictable(page))?;
+        self.pages.remove(&entry);
+        let (_, _, page) = entry;
+        let history = self.histories.remove(&page).unwrap();
+        self.retained.insert(page, (self.tick, history));
+        self.retained_order.push_back((self.tick, page));
+        while self.retained.len() > self.capacity {
+            let (evicted_tick, page) = self.retained_order.pop_front().unwrap();
+            if self
+                .retained
+                .get(&page)
+                .is_some_and(|(tick, _)| *tick == evicted_tick)
+            {
+                self.retained.remove(&page);
+            }
+        }
+        Some(page)
+    }
+
+    // NOTE: unlike insert, the page's history is restored without recording another access.
+    fn reinstate(&mut self, page: PageId) {
+        debug_assert!(!self.histories.contains_key(&page));
+        let Some((_, history)) = self.retained.remove(&page) else {
+            return self.insert(page);
+        };
+        let (kth_tick, tick) = self.priority(&history);
+        self.pages.insert((kth_tick, tick, page));
+        self.histories.insert(page, history);
+    }
+}
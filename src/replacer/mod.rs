@@ -0,0 +1,45 @@
+#[cfg(test)]
+#[path = "./replacer_test.rs"]
+mod test;
+
+use crate::options::ReplacementPolicy;
+
+mod clock;
+mod lru;
+mod lru_k;
+mod two_queue;
+
+// Identifies a cached page by its table id and offset.
+pub(crate) type PageId = (u32, u32);
+
+// Chooses which page to evict from a full cache (e.g. a buffer pool shard), given the accesses
+// made to the pages it holds. Implementations are only told about pages, so may also remember
+// pages no longer cached (e.g. to recognize pages which are re-read soon after eviction).
+pub(crate) trait Replacer: Send {
+    // Records a read of a page which is cached.
+    fn access(&mut self, page: PageId);
+
+    // Records a page newly added to the cache.
+    // NOTE: Expects the page to not already be tracked!
+    fn insert(&mut self, page: PageId);
+
+    // Chooses a page to evict out of those the predicate allows (e.g. those not in use), and
+    // stops tracking it. Returns None if there are none.
+    fn evict(&mut self, is_evictable: &dyn Fn(&PageId) -> bool) -> Option<PageId>;
+
+    // Undoes the eviction of a page which stayed cached after all (e.g. as it was used while
+    // being written back), tracking it as it was before rather than as a page newly added.
+    // NOTE: Expects the page to have been evicted, and not inserted since! Other pages may have
+    // been evicted meanwhile, so implementations may no longer know everything about it.
+    fn reinstate(&mut self, page: PageId);
+}
+
+// Creates a replacer for a cache holding up to capacity pages.
+pub(crate) fn new(policy: ReplacementPolicy, capacity: usize) -> Box<dyn Replacer> {
+    match policy {
+        ReplacementPolicy::Lru => Box::new(lru::Lru::new()),
+        ReplacementPolicy::Clock => Box::new(clock::Clock::new()),
+        ReplacementPolicy::LruK(k) => Box::new(lru_k::LruK::new(k, capacity)),
+        ReplacementPolicy::TwoQueue => Box::new(two_queue::TwoQueue::new(capacity)),
+    }
+}
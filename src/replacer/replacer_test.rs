@@ -0,0 +1,124 @@
+use crate::options::ReplacementPolicy;
+use crate::replacer::{self, PageId};
+
+fn page(offset: u32) -> PageId {
+    (0, offset)
+}
+
+#[test]
+fn lru_evicts_least_recently_used() {
+    let mut replacer = replacer::new(ReplacementPolicy::Lru, 3);
+    replacer.insert(page(1));
+    replacer.insert(page(2));
+    replacer.insert(page(3));
+    replacer.access(page(1));
+
+    assert_eq!(replacer.evict(&|_| true), Some(page(2)));
+    assert_eq!(replacer.evict(&|_| true), Some(page(3)));
+    assert_eq!(replacer.evict(&|_| true), Some(page(1)));
+    assert_eq!(replacer.evict(&|_| true), None);
+}
+
+#[test]
+fn clock_skips_referenced_pages() {
+    let mut replacer = replacer::new(ReplacementPolicy::Clock, 3);
+    replacer.insert(page(1));
+    replacer.insert(page(2));
+    replacer.insert(page(3));
+
+    // all pages start referenced, so the first sweep clears them.
+    assert_eq!(replacer.evict(&|_| true), Some(page(1)));
+    replacer.insert(page(4));
+    replacer.access(page(2));
+    assert_eq!(replacer.evict(&|_| true), Some(page(3)));
+    // the hand wraps around, past the newly inserted page.
+    assert_eq!(replacer.evict(&|_| true), Some(page(2)));
+    assert_eq!(replacer.evict(&|_| true), Some(page(4)));
+    assert_eq!(replacer.evict(&|_| true), None);
+}
+
+#[test]
+fn lru_k_evicts_pages_read_once_first() {
+    let mut replacer = replacer::new(ReplacementPolicy::LruK(2), 3);
+    replacer.insert(page(1));
+    replacer.access(page(1));
+    replacer.insert(page(2));
+    replacer.insert(page(3));
+
+    assert_eq!(replacer.evict(&|_| true), Some(page(2)));
+    assert_eq!(replacer.evict(&|_| true), Some(page(3)));
+    assert_eq!(replacer.evict(&|_| true), Some(page(1)));
+}
+
+#[test]
+fn lru_k_remembers_evicted_pages() {
+    let mut replacer = replacer::new(ReplacementPolicy::LruK(2), 3);
+    replacer.insert(page(1));
+    assert_eq!(replacer.evict(&|_| true), Some(page(1)));
+
+    // re-reading the page counts as its second access.
+    replacer.insert(page(1));
+    replacer.insert(page(2));
+    assert_eq!(replacer.evict(&|_| true), Some(page(2)));
+    assert_eq!(replacer.evict(&|_| true), Some(page(1)));
+}
+
+#[test]
+fn two_queue_promotes_pages_read_again() {
+    // A1in holds 1 page, A1out 2.
+    let mut replacer = replacer::new(ReplacementPolicy::TwoQueue, 4);
+    replacer.insert(page(1));
+    replacer.insert(page(2));
+    assert_eq!(replacer.evict(&|_| true), Some(page(1)));
+    // re-read while remembered by A1out, so cached in Am.
+    replacer.insert(page(1));
+
+    // a scan passes through A1in.
+    for offset in 3..10 {
+        replacer.insert(page(offset));
+        assert_eq!(replacer.evict(&|_| true), Some(page(offset - 1)));
+    }
+    assert_eq!(replacer.evict(&|_| true), Some(page(1)));
+    assert_eq!(replacer.evict(&|_| true), Some(page(9)));
+    assert_eq!(replacer.evict(&|_| true), None);
+}
+
+#[test]
+fn lru_k_reinstates_history() {
+    let mut replacer = replacer::new(ReplacementPolicy::LruK(2), 3);
+    replacer.insert(page(1));
+    replacer.insert(page(2));
+    assert_eq!(replacer.evict(&|_| true), Some(page(1)));
+
+    // unlike re-reading it, reinstating the page doesn't count as its second access.
+    replacer.reinstate(page(1));
+    replacer.insert(page(3));
+    replacer.access(page(3));
+    assert_eq!(replacer.evict(&|_| true), Some(page(1)));
+    assert_eq!(replacer.evict(&|_| true), Some(page(2)));
+    assert_eq!(replacer.evict(&|_| true), Some(page(3)));
+}
+
+#[test]
+fn two_queue_reinstates_without_promoting() {
+    // A1in holds 1 page, A1out 2.
+    let mut replacer = replacer::new(ReplacementPolicy::TwoQueue, 4);
+    replacer.insert(page(1));
+    replacer.insert(page(2));
+    assert_eq!(replacer.evict(&|_| true), Some(page(1)));
+    // back in A1in, so no longer remembered by A1out either.
+    replacer.reinstate(page(1));
+    assert_eq!(replacer.evict(&|_| true), Some(page(2)));
+    assert_eq!(replacer.evict(&|_| true), Some(page(1)));
+    replacer.insert(page(3));
+    assert_eq!(replacer.evict(&|_| true), Some(page(3)));
+
+    // pages evicted from Am return to it.
+    replacer.insert(page(1));
+    replacer.insert(page(4));
+    assert_eq!(replacer.evict(&|_| true), Some(page(1)));
+    replacer.reinstate(page(1));
+    assert_eq!(replacer.evict(&|_| true), Some(page(1)));
+    assert_eq!(replacer.evict(&|_| true), Some(page(4)));
+    assert_eq!(replacer.evict(&|_| true), None);
+}
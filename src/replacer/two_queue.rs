@@ -0,0 +1,77 @@
+use crate::replacer::lru::Lru;
+use crate::replacer::{PageId, Replacer};
+use std::collections::{HashSet, VecDeque};
+
+// Johnson & Shasha's 2Q: http://www.vldb.org/conf/1994/P439.PDF
+//
+// Newly cached pages enter a FIFO queue (A1in). Pages evicted from it are remembered by a ghost
+// queue (A1out), and only those read again meanwhile are cached in the main LRU queue (Am).
+// Pages read once, e.g. by a scan, pass through A1in without displacing pages read repeatedly.
+pub(crate) struct TwoQueue {
+    // NOTE: accesses aren't recorded, so it behaves as a FIFO queue.
+    a1in: Lru,
+    a1out: VecDeque<PageId>,
+    a1out_pages: HashSet<PageId>,
+    am: Lru,
+    // The size of A1in before evicting from it rather than Am, and the size of A1out.
+    // NOTE: the paper recommends 25% and 50% of the cache's capacity.
+    a1in_capacity: usize,
+    a1out_capacity: usize,
+}
+
+impl TwoQueue {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            a1in: Lru::new(),
+            a1out: VecDeque::new(),
+            a1out_pages: HashSet::new(),
+            am: Lru::new(),
+            a1in_capacity: std::cmp::max(capacity / 4, 1),
+            a1out_capacity: std::cmp::max(capacity / 2, 1),
+        }
+    }
+}
+
+impl Replacer for TwoQueue {
+    fn access(&mut self, page: PageId) {
+        self.am.access(page);
+    }
+
+    fn insert(&mut self, page: PageId) {
+        if self.a1out_pages.remove(&page) {
+            self.a1out.retain(|ghost| *ghost != page);
+            self.am.insert(page);
+        } else {
+            self.a1in.insert(page);
+        }
+    }
+
+    fn evict(&mut self, is_evictable: &dyn Fn(&PageId) -> bool) -> Option<PageId> {
+        if self.a1in.len() <= self.a1in_capacity {
+            if let Some(page) = self.am.evict(is_evictable) {
+                return Some(page);
+            }
+        }
+        let Some(page) = self.a1in.evict(is_evictable) else {
+            return self.am.evict(is_evictable);
+        };
+        self.a1out.push_back(page);
+        self.a1out_pages.insert(page);
+        if self.a1out.len() > self.a1out_capacity {
+            let ghost = self.a1out.pop_front().unwrap();
+            self.a1out_pages.remove(&ghost);
+        }
+        Some(page)
+    }
+
+    // NOTE: unlike insert, a page evicted from A1in (so now remembered by A1out) returns to A1in
+    // rather than being promoted to Am. Pages A1out forgot meanwhile are assumed to be from Am.
+    fn reinstate(&mut self, page: PageId) {
+        if self.a1out_pages.remove(&page) {
+            self.a1out.retain(|ghost| *ghost != page);
+            self.a1in.insert(page);
+        } else {
+            self.am.insert(page);
+        }
+    }
+}
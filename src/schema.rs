@@ -1,3 +1,4 @@
+use crate::error::{ErrorKind::*, *};
 use crate::protos::generated::chunk::*;
 use crate::protos::generated::config::*;
 use crate::protos::generated::operations::*;
@@ -13,6 +14,45 @@ pub(crate) fn get_col<'a>(row: &'a RowProto, col_name: &str) -> &'a ColumnProto
     todo!();
 }
 
+pub(crate) fn find_col<'a>(row: &'a RowProto, col_name: &str) -> Option<&'a ColumnProto> {
+    row.columns.iter().find(|col| col.name == col_name)
+}
+
+pub(crate) fn is_col_in_table(schema: &TableSchema, col_name: &str) -> bool {
+    find_col_schema(schema, col_name).is_some()
+}
+
+pub(crate) fn find_col_schema<'a>(
+    schema: &'a TableSchema,
+    col_name: &str,
+) -> Option<&'a ColumnSchema> {
+    iter::once(schema.key.get_or_default())
+        .chain(&schema.columns)
+        .find(|col| col.name == col_name)
+}
+
+// Builds a row holding only the requested columns, in the requested order.
+// Returns None if the source row is missing any of them.
+pub(crate) fn project_row(row: &RowProto, col_names: &[String]) -> Option<RowProto> {
+    let mut projected_row = RowProto::new();
+    for col_name in col_names {
+        projected_row.columns.push(find_col(row, col_name)?.clone());
+    }
+    Some(projected_row)
+}
+
+// The error for a row read from a table which is missing a column of its schema, e.g. as it was
+// written without it.
+pub(crate) fn missing_col_error(key: u32, col_names: &[String]) -> Error {
+    Error::new(
+        DataLoss,
+        format!(
+            "Row with key {} is missing some of the columns: {:?}!",
+            key, col_names
+        ),
+    )
+}
+
 pub(crate) fn get_hashed_key_from_row(row: &RowProto, schema: &TableSchema) -> u32 {
     let key_column = get_col(row, &schema.key.name);
     get_hashed_col_value(key_column.value.as_ref().unwrap())
@@ -22,10 +62,47 @@ pub(crate) fn get_hashed_col_value(value: &ValueProto) -> u32 {
     match value.value_type {
         Some(value_proto::Value_type::IntValue(i)) => i as u32,
         Some(value_proto::Value_type::UintValue(u)) => u,
+        Some(value_proto::Value_type::LongValue(l)) => l as u32,
+        Some(value_proto::Value_type::DoubleValue(d)) => d as u32,
         None => unreachable!(),
     }
 }
 
+// Interprets the given value as a signed integer, preserving the natural ordering
+// of values (unlike get_hashed_col_value).
+pub(crate) fn get_col_value_as_i64(value: &ValueProto) -> i64 {
+    match value.value_type {
+        Some(value_proto::Value_type::IntValue(i)) => i as i64,
+        Some(value_proto::Value_type::UintValue(u)) => u as i64,
+        Some(value_proto::Value_type::LongValue(l)) => l,
+        Some(value_proto::Value_type::DoubleValue(d)) => d as i64,
+        None => unreachable!(),
+    }
+}
+
+// Returns the ranges of hashed values holding the values from lower to upper (inclusive, and
+// unbounded if unset) of a column of the given type. Negative values of INTEGER columns hash above
+// all non-negative ones, so ranges spanning zero are split in two.
+pub(crate) fn get_hashed_ranges(
+    column_type: column_schema::ColumnType,
+    lower: Option<&ValueProto>,
+    upper: Option<&ValueProto>,
+) -> Vec<(u32, u32)> {
+    let (min, max) = match column_type {
+        column_schema::ColumnType::INTEGER => (i32::MIN as i64, i32::MAX as i64),
+        _ => (0, u32::MAX as i64),
+    };
+    let lower = lower.map_or(min, |value| get_col_value_as_i64(value).max(min));
+    let upper = upper.map_or(max, |value| get_col_value_as_i64(value).min(max));
+    if lower > upper {
+        Vec::new()
+    } else if lower < 0 && upper >= 0 {
+        vec![(0, upper as u32), (lower as u32, u32::MAX)]
+    } else {
+        vec![(lower as u32, upper as u32)]
+    }
+}
+
 pub(crate) fn internal_col_to_col(value: &ValueProto, column_schema: &ColumnSchema) -> ColumnProto {
     let mut column = ColumnProto::new();
     column.name = column_schema.name.clone();
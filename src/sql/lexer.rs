@@ -0,0 +1,103 @@
+use crate::error::{ErrorKind::*, *};
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Token {
+    // NOTE: keywords are lexed as identifiers, and interpreted by the parser (case insensitive).
+    Ident(String),
+    Int(i64),
+    Symbol(&'static str),
+    End,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Spanned {
+    pub(crate) token: Token,
+    pub(crate) line: usize,
+    pub(crate) column: usize,
+}
+
+// NOTE: longer symbols must come first.
+static SYMBOLS: [&str; 10] = ["<=", ">=", "(", ")", ",", "*", "=", "<", ">", ";"];
+
+pub(crate) fn syntax_error(line: usize, column: usize, msg: &str) -> Error {
+    Error::new(
+        InvalidArgument,
+        format!("Syntax error at line {}, column {}: {}", line, column, msg),
+    )
+}
+
+// Splits the given statement into tokens, tracking their (1-indexed) line and column.
+pub(crate) fn tokenize(sql: &str) -> Result<Vec<Spanned>, Error> {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    let mut line = 1;
+    let mut line_start = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i - line_start + 1;
+        if c == '\n' {
+            i += 1;
+            line += 1;
+            line_start = i;
+            continue;
+        }
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        // comments run to the end of the line.
+        if c == '-' && chars.get(i + 1) == Some(&'-') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            continue;
+        }
+
+        let start = i;
+        let token = if c.is_ascii_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            Token::Ident(chars[start..i].iter().collect())
+        } else if c.is_ascii_digit()
+            || (c == '-' && chars.get(i + 1).is_some_and(|c| c.is_ascii_digit()))
+        {
+            i += 1;
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            let literal: String = chars[start..i].iter().collect();
+            let value = literal
+                .parse::<i64>()
+                .map_err(|_| syntax_error(line, column, "integer literal out of range"))?;
+            Token::Int(value)
+        } else {
+            let Some(symbol) = SYMBOLS.iter().find(|symbol| {
+                symbol
+                    .chars()
+                    .enumerate()
+                    .all(|(j, symbol_c)| chars.get(i + j) == Some(&symbol_c))
+            }) else {
+                return Err(syntax_error(
+                    line,
+                    column,
+                    &format!("unexpected character '{}'", c),
+                ));
+            };
+            i += symbol.len();
+            Token::Symbol(symbol)
+        };
+        tokens.push(Spanned {
+            token,
+            line,
+            column,
+        });
+    }
+    tokens.push(Spanned {
+        token: Token::End,
+        line,
+        column: i - line_start + 1,
+    });
+    Ok(tokens)
+}
@@ -0,0 +1,58 @@
+#[cfg(test)]
+#[path = "./sql_test.rs"]
+mod test;
+
+use crate::error::*;
+use crate::protos::generated::config::*;
+use crate::protos::generated::operations::*;
+
+mod lexer;
+mod parser;
+
+// A single parsed SQL statement, compiled to the equivalent operation.
+#[derive(Debug, PartialEq)]
+pub enum Statement {
+    Query(QueryProto),
+    Explain(QueryProto),
+    ExplainAnalyze(QueryProto),
+    Insert(InsertProto),
+    Delete(DeleteProto),
+    Update(UpdateProto),
+    CreateTable(TableSchema),
+    CreateIndex(IndexSchema),
+    Analyze,
+}
+
+// The result of executing a statement, see Database::execute.
+#[derive(Debug)]
+pub enum Output<F> {
+    Rows(F),
+    Plan(QueryPlanProto),
+    Done,
+}
+
+// Parses a statement in a small subset of SQL. Keywords are case insensitive, identifiers are not.
+// Only integer literals are supported.
+//
+// SELECT * | col, ... FROM t
+//     [WHERE predicate]
+//     [ORDER BY col [ASC | DESC], ...]
+//     [LIMIT n [OFFSET m]]
+// EXPLAIN [ANALYZE] SELECT ...
+// INSERT INTO t (col, ...) VALUES (value, ...)
+// DELETE FROM t WHERE key = value
+// UPDATE t SET col = value, ... WHERE key = value
+// CREATE TABLE t (col INTEGER | UNSIGNED INTEGER [PRIMARY KEY], ...)
+// CREATE INDEX [name] ON t (col)
+// ANALYZE [t]
+//
+// Predicates combine col = value, col < | <= | > | >= value and col BETWEEN a AND b with AND, OR
+// and parentheses. They are compiled to a logical predicate stage, which the planner turns into
+// filters over indexes where possible. Ranges compare values in the order of the column's type,
+// e.g. negative values are below zero for INTEGER columns.
+//
+// Syntax errors are reported as InvalidArgument, with the line and column of the offending token.
+pub fn parse(sql: &str) -> Result<Statement, Error> {
+    let tokens = lexer::tokenize(sql)?;
+    parser::Parser::new(tokens).parse_statement()
+}
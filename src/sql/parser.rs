@@ -0,0 +1,456 @@
+use crate::error::*;
+use crate::protos::generated::config::*;
+use crate::protos::generated::operations::*;
+use crate::sql::lexer::{self, Spanned, Token};
+use crate::sql::Statement;
+use protobuf::MessageField;
+
+// Recursive descent parser over a tokenized statement. See sql::parse for the grammar.
+pub(crate) struct Parser {
+    tokens: Vec<Spanned>,
+    pos: usize,
+}
+
+impl Parser {
+    pub(crate) fn new(tokens: Vec<Spanned>) -> Self {
+        debug_assert_eq!(tokens.last().unwrap().token, Token::End);
+        Self { tokens, pos: 0 }
+    }
+
+    fn peek(&self) -> &Spanned {
+        &self.tokens[self.pos]
+    }
+
+    fn advance(&mut self) -> &Spanned {
+        let token = &self.tokens[self.pos];
+        if token.token != Token::End {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn error_at_current(&self, msg: &str) -> Error {
+        let token = self.peek();
+        lexer::syntax_error(token.line, token.column, msg)
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(&self.peek().token, Token::Ident(ident) if ident.eq_ignore_ascii_case(keyword))
+    }
+
+    fn is_symbol(&self, symbol: &str) -> bool {
+        matches!(&self.peek().token, Token::Symbol(s) if *s == symbol)
+    }
+
+    // Consumes the keyword if it is next, returning whether it was.
+    fn accept_keyword(&mut self, keyword: &str) -> bool {
+        let is_keyword = self.is_keyword(keyword);
+        if is_keyword {
+            self.advance();
+        }
+        is_keyword
+    }
+
+    fn accept_symbol(&mut self, symbol: &str) -> bool {
+        let is_symbol = self.is_symbol(symbol);
+        if is_symbol {
+            self.advance();
+        }
+        is_symbol
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), Error> {
+        if !self.accept_keyword(keyword) {
+            return Err(self.error_at_current(&format!("expected {}", keyword)));
+        }
+        Ok(())
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), Error> {
+        if !self.accept_symbol(symbol) {
+            return Err(self.error_at_current(&format!("expected '{}'", symbol)));
+        }
+        Ok(())
+    }
+
+    fn expect_ident(&mut self) -> Result<String, Error> {
+        match &self.peek().token {
+            Token::Ident(ident) => {
+                let ident = ident.clone();
+                self.advance();
+                Ok(ident)
+            }
+            _ => Err(self.error_at_current("expected identifier")),
+        }
+    }
+
+    fn expect_int(&mut self) -> Result<i64, Error> {
+        match self.peek().token {
+            Token::Int(value) => {
+                self.advance();
+                Ok(value)
+            }
+            _ => Err(self.error_at_current("expected integer")),
+        }
+    }
+
+    fn expect_u32(&mut self) -> Result<u32, Error> {
+        let token = self.peek().clone();
+        let value = self.expect_int()?;
+        u32::try_from(value)
+            .map_err(|_| lexer::syntax_error(token.line, token.column, "expected unsigned integer"))
+    }
+
+    fn expect_value(&mut self) -> Result<ValueProto, Error> {
+        let token = self.peek().clone();
+        let value = self.expect_int()?;
+        let mut value_proto = ValueProto::new();
+        if let Ok(value) = i32::try_from(value) {
+            value_proto.set_int_value(value);
+        } else if let Ok(value) = u32::try_from(value) {
+            value_proto.set_uint_value(value);
+        } else {
+            return Err(lexer::syntax_error(
+                token.line,
+                token.column,
+                "integer literal out of range",
+            ));
+        }
+        Ok(value_proto)
+    }
+
+    fn expect_ident_list(&mut self) -> Result<Vec<String>, Error> {
+        let mut idents = vec![self.expect_ident()?];
+        while self.accept_symbol(",") {
+            idents.push(self.expect_ident()?);
+        }
+        Ok(idents)
+    }
+
+    fn expect_column_value(&mut self) -> Result<ColumnProto, Error> {
+        let mut column = ColumnProto::new();
+        column.name = self.expect_ident()?;
+        self.expect_symbol("=")?;
+        column.value = MessageField::some(self.expect_value()?);
+        Ok(column)
+    }
+
+    pub(crate) fn parse_statement(&mut self) -> Result<Statement, Error> {
+        let statement = if self.accept_keyword("SELECT") {
+            Statement::Query(self.parse_select()?)
+        } else if self.accept_keyword("EXPLAIN") {
+            let analyze = self.accept_keyword("ANALYZE");
+            self.expect_keyword("SELECT")?;
+            match analyze {
+                true => Statement::ExplainAnalyze(self.parse_select()?),
+                false => Statement::Explain(self.parse_select()?),
+            }
+        } else if self.accept_keyword("INSERT") {
+            Statement::Insert(self.parse_insert()?)
+        } else if self.accept_keyword("DELETE") {
+            Statement::Delete(self.parse_delete()?)
+        } else if self.accept_keyword("UPDATE") {
+            Statement::Update(self.parse_update()?)
+        } else if self.accept_keyword("CREATE") {
+            if self.accept_keyword("TABLE") {
+                Statement::CreateTable(self.parse_create_table()?)
+            } else if self.accept_keyword("INDEX") {
+                Statement::CreateIndex(self.parse_create_index()?)
+            } else {
+                return Err(self.error_at_current("expected TABLE or INDEX"));
+            }
+        } else if self.accept_keyword("ANALYZE") {
+            if let Token::Ident(_) = self.peek().token {
+                self.expect_ident()?;
+            }
+            Statement::Analyze
+        } else {
+            return Err(self.error_at_current("expected statement"));
+        };
+        self.accept_symbol(";");
+        if self.peek().token != Token::End {
+            return Err(self.error_at_current("expected end of statement"));
+        }
+        Ok(statement)
+    }
+
+    fn parse_select(&mut self) -> Result<QueryProto, Error> {
+        let columns = if self.accept_symbol("*") {
+            Vec::new()
+        } else {
+            self.expect_ident_list()?
+        };
+        self.expect_keyword("FROM")?;
+        // NOTE: only a single table is supported, so the table name is not used.
+        self.expect_ident()?;
+
+        let mut query = QueryProto::new();
+        if self.accept_keyword("WHERE") {
+            query.set_predicate(self.parse_or()?);
+        } else {
+            query.set_scan(ScanProto::new());
+        }
+
+        let mut select = SelectProto::new();
+        select.dep = MessageField::some(query);
+        select.columns = columns;
+        query = QueryProto::new();
+        query.set_select(select);
+
+        if self.accept_keyword("ORDER") {
+            self.expect_keyword("BY")?;
+            let mut order_by = OrderByProto::new();
+            loop {
+                let mut column = order_by_proto::OrderByColumnProto::new();
+                column.name = self.expect_ident()?;
+                if self.accept_keyword("DESC") {
+                    column.descending = true;
+                } else {
+                    self.accept_keyword("ASC");
+                }
+                order_by.columns.push(column);
+                if !self.accept_symbol(",") {
+                    break;
+                }
+            }
+            order_by.dep = MessageField::some(query);
+            query = QueryProto::new();
+            query.set_order_by(order_by);
+        }
+
+        if self.accept_keyword("LIMIT") {
+            let mut limit = LimitProto::new();
+            limit.limit = self.expect_u32()?;
+            if self.accept_keyword("OFFSET") {
+                limit.offset = self.expect_u32()?;
+            }
+            limit.dep = MessageField::some(query);
+            query = QueryProto::new();
+            query.set_limit(limit);
+        }
+
+        Ok(query)
+    }
+
+    // OR binds less tightly than AND.
+    fn parse_or(&mut self) -> Result<PredicateProto, Error> {
+        let mut predicates = vec![self.parse_and()?];
+        while self.accept_keyword("OR") {
+            predicates.push(self.parse_and()?);
+        }
+        if predicates.len() == 1 {
+            return Ok(predicates.pop().unwrap());
+        }
+        let mut or = predicate_proto::OrProto::new();
+        or.predicates = predicates;
+        let mut predicate = PredicateProto::new();
+        predicate.set_or(or);
+        Ok(predicate)
+    }
+
+    fn parse_and(&mut self) -> Result<PredicateProto, Error> {
+        let mut predicates = vec![self.parse_predicate()?];
+        while self.accept_keyword("AND") {
+            predicates.push(self.parse_predicate()?);
+        }
+        if predicates.len() == 1 {
+            return Ok(predicates.pop().unwrap());
+        }
+        let mut and = predicate_proto::AndProto::new();
+        and.predicates = predicates;
+        let mut predicate = PredicateProto::new();
+        predicate.set_and(and);
+        Ok(predicate)
+    }
+
+    fn parse_predicate(&mut self) -> Result<PredicateProto, Error> {
+        if self.accept_symbol("(") {
+            let predicate = self.parse_or()?;
+            self.expect_symbol(")")?;
+            return Ok(predicate);
+        }
+
+        let name = self.expect_ident()?;
+        let mut filter = FilterProto::new();
+        if self.accept_symbol("=") {
+            let mut equals = filter_proto::FilterEqualsProto::new();
+            equals.name = name;
+            equals.value = MessageField::some(self.expect_value()?);
+            filter.set_equals(equals);
+        } else {
+            let (lower, upper) = if self.accept_keyword("BETWEEN") {
+                let lower = self.expect_int()?;
+                self.expect_keyword("AND")?;
+                (Some(lower), Some(self.expect_int()?))
+            } else if self.accept_symbol("<") {
+                (None, Some(self.expect_int()?.saturating_sub(1)))
+            } else if self.accept_symbol("<=") {
+                (None, Some(self.expect_int()?))
+            } else if self.accept_symbol(">") {
+                (Some(self.expect_int()?.saturating_add(1)), None)
+            } else if self.accept_symbol(">=") {
+                (Some(self.expect_int()?), None)
+            } else {
+                return Err(self.error_at_current("expected comparison"));
+            };
+            filter.set_in_range(in_range_filter(name, lower, upper));
+        }
+
+        let mut predicate = PredicateProto::new();
+        predicate.set_filter(filter);
+        Ok(predicate)
+    }
+
+    fn parse_insert(&mut self) -> Result<InsertProto, Error> {
+        self.expect_keyword("INTO")?;
+        self.expect_ident()?;
+        self.expect_symbol("(")?;
+        let names = self.expect_ident_list()?;
+        self.expect_symbol(")")?;
+        self.expect_keyword("VALUES")?;
+        let values_token = self.expect_symbol_spanned("(")?;
+        let mut values = vec![self.expect_value()?];
+        while self.accept_symbol(",") {
+            values.push(self.expect_value()?);
+        }
+        self.expect_symbol(")")?;
+        if names.len() != values.len() {
+            return Err(lexer::syntax_error(
+                values_token.line,
+                values_token.column,
+                &format!("expected {} values, got {}", names.len(), values.len()),
+            ));
+        }
+
+        let mut row = RowProto::new();
+        for (name, value) in names.into_iter().zip(values) {
+            let mut column = ColumnProto::new();
+            column.name = name;
+            column.value = MessageField::some(value);
+            row.columns.push(column);
+        }
+        let mut insert = InsertProto::new();
+        insert.row = MessageField::some(row);
+        Ok(insert)
+    }
+
+    fn expect_symbol_spanned(&mut self, symbol: &str) -> Result<Spanned, Error> {
+        let token = self.peek().clone();
+        self.expect_symbol(symbol)?;
+        Ok(token)
+    }
+
+    fn parse_delete(&mut self) -> Result<DeleteProto, Error> {
+        self.expect_keyword("FROM")?;
+        self.expect_ident()?;
+        self.expect_keyword("WHERE")?;
+        let mut delete = DeleteProto::new();
+        delete.key = MessageField::some(self.expect_column_value()?);
+        Ok(delete)
+    }
+
+    fn parse_update(&mut self) -> Result<UpdateProto, Error> {
+        self.expect_ident()?;
+        self.expect_keyword("SET")?;
+        let mut update = UpdateProto::new();
+        update.columns.push(self.expect_column_value()?);
+        while self.accept_symbol(",") {
+            update.columns.push(self.expect_column_value()?);
+        }
+        self.expect_keyword("WHERE")?;
+        update.key = MessageField::some(self.expect_column_value()?);
+        Ok(update)
+    }
+
+    fn parse_column_type(&mut self) -> Result<column_schema::ColumnType, Error> {
+        if self.accept_keyword("INTEGER") || self.accept_keyword("INT") {
+            return Ok(column_schema::ColumnType::INTEGER);
+        }
+        if self.accept_keyword("UNSIGNED") {
+            if !self.accept_keyword("INTEGER") {
+                self.accept_keyword("INT");
+            }
+            return Ok(column_schema::ColumnType::UNSIGNED_INTEGER);
+        }
+        Err(self.error_at_current("expected column type"))
+    }
+
+    fn parse_create_table(&mut self) -> Result<TableSchema, Error> {
+        self.expect_ident()?;
+        self.expect_symbol("(")?;
+        let mut columns = Vec::new();
+        let mut key_idx = None;
+        loop {
+            let mut column = ColumnSchema::new();
+            column.name = self.expect_ident()?;
+            column.column_type = self.parse_column_type()?.into();
+            let primary_token = self.peek().clone();
+            if self.accept_keyword("PRIMARY") {
+                self.expect_keyword("KEY")?;
+                if key_idx.is_some() {
+                    return Err(lexer::syntax_error(
+                        primary_token.line,
+                        primary_token.column,
+                        "multiple primary keys",
+                    ));
+                }
+                key_idx = Some(columns.len());
+            }
+            columns.push(column);
+            if !self.accept_symbol(",") {
+                break;
+            }
+        }
+        self.expect_symbol(")")?;
+
+        // NOTE: the first column is the key if none is specified.
+        let mut table = TableSchema::new();
+        table.key = MessageField::some(columns.remove(key_idx.unwrap_or(0)));
+        table.columns = columns;
+        Ok(table)
+    }
+
+    fn parse_create_index(&mut self) -> Result<IndexSchema, Error> {
+        // NOTE: index names are optional, and not used.
+        if !self.is_keyword("ON") {
+            self.expect_ident()?;
+        }
+        self.expect_keyword("ON")?;
+        self.expect_ident()?;
+        self.expect_symbol("(")?;
+        let mut key = ColumnSchema::new();
+        key.name = self.expect_ident()?;
+        self.expect_symbol(")")?;
+        let mut index = IndexSchema::new();
+        index.key = MessageField::some(key);
+        Ok(index)
+    }
+}
+
+// Builds a range filter over the (inclusive) bounds.
+// NOTE: keys are ordered as unsigned integers, so bounds are clamped to that range.
+// NOTE: bounds are left unset for open ranges, and compared as values of the column's type (e.g.
+// negative values are below zero for signed columns) once the column is known, when planning.
+fn in_range_filter(
+    name: String,
+    lower: Option<i64>,
+    upper: Option<i64>,
+) -> filter_proto::FilterInRangeProto {
+    let mut in_range = filter_proto::FilterInRangeProto::new();
+    in_range.name = name;
+    in_range.lower_value = lower.map(bound_value).into();
+    in_range.upper_value = upper.map(bound_value).into();
+    in_range
+}
+
+fn bound_value(value: i64) -> ValueProto {
+    let mut value_proto = ValueProto::new();
+    if let Ok(value) = i32::try_from(value) {
+        value_proto.set_int_value(value);
+    } else if let Ok(value) = u32::try_from(value) {
+        value_proto.set_uint_value(value);
+    } else {
+        value_proto.set_long_value(value);
+    }
+    value_proto
+}
@@ -0,0 +1,216 @@
+use crate::error::ErrorKind::*;
+use crate::protos::generated::config::*;
+use crate::protos::generated::operations::*;
+use crate::sql::{parse, Statement};
+use protobuf::text_format::parse_from_str;
+
+fn parse_query(sql: &str) -> QueryProto {
+    match parse(sql).unwrap() {
+        Statement::Query(query) => query,
+        statement => panic!("expected query, got {:?}", statement),
+    }
+}
+
+#[test]
+fn parse_select_success() {
+    let query = parse_query(
+        "select Value from t
+        WHERE Key >= 10 AND (Value = 1 OR Value BETWEEN 5 AND 7)
+        ORDER BY Value DESC, Key
+        LIMIT 10 OFFSET 20;",
+    );
+    let expected = parse_from_str::<QueryProto>(
+        "
+        limit {
+            limit: 10
+            offset: 20
+            dep {
+                order_by {
+                    columns { name: \"Value\" descending: true }
+                    columns { name: \"Key\" }
+                    dep {
+                        select {
+                            columns: \"Value\"
+                            dep {
+                                predicate {
+                                    and {
+                                        predicates {
+                                            filter {
+                                                in_range {
+                                                    name: \"Key\"
+                                                    lower_value { int_value: 10 }
+                                                }
+                                            }
+                                        }
+                                        predicates {
+                                            or {
+                                                predicates {
+                                                    filter {
+                                                        equals {
+                                                            name: \"Value\"
+                                                            value { int_value: 1 }
+                                                        }
+                                                    }
+                                                }
+                                                predicates {
+                                                    filter {
+                                                        in_range {
+                                                            name: \"Value\"
+                                                            lower_value { int_value: 5 }
+                                                            upper_value { int_value: 7 }
+                                                        }
+                                                    }
+                                                }
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        ",
+    )
+    .unwrap();
+    assert_eq!(expected, query);
+}
+
+#[test]
+fn parse_select_without_where_success() {
+    let query = parse_query("SELECT * FROM t");
+    let expected = parse_from_str::<QueryProto>("select { dep { scan {} } }").unwrap();
+    assert_eq!(expected, query);
+}
+
+#[test]
+fn parse_explain_success() {
+    let expected = parse_from_str::<QueryProto>("select { dep { scan {} } }").unwrap();
+    assert_eq!(
+        parse("EXPLAIN SELECT * FROM t").unwrap(),
+        Statement::Explain(expected.clone())
+    );
+    assert_eq!(
+        parse("explain analyze select * from t").unwrap(),
+        Statement::ExplainAnalyze(expected)
+    );
+}
+
+#[test]
+fn parse_select_strict_bounds_success() {
+    let query = parse_query("SELECT * FROM t WHERE Key < 0");
+    let expected = parse_from_str::<QueryProto>(
+        "
+        select {
+            dep {
+                predicate {
+                    filter {
+                        in_range {
+                            name: \"Key\"
+                            upper_value { int_value: -1 }
+                        }
+                    }
+                }
+            }
+        }
+        ",
+    )
+    .unwrap();
+    assert_eq!(expected, query);
+}
+
+#[test]
+fn parse_select_extreme_bounds_success() {
+    let in_range = |sql| {
+        parse_query(sql)
+            .select()
+            .dep
+            .predicate()
+            .filter()
+            .in_range()
+            .clone()
+    };
+
+    let range = in_range("SELECT * FROM t WHERE Key < -9223372036854775808");
+    assert!(range.lower_value.is_none());
+    assert_eq!(range.upper_value.long_value(), i64::MIN);
+    let range = in_range("SELECT * FROM t WHERE Key > 9223372036854775807");
+    assert_eq!(range.lower_value.long_value(), i64::MAX);
+    assert!(range.upper_value.is_none());
+    let range = in_range("SELECT * FROM t WHERE Key BETWEEN -5 AND 3000000000");
+    assert_eq!(range.lower_value.int_value(), -5);
+    assert_eq!(range.upper_value.uint_value(), 3000000000);
+}
+
+#[test]
+fn parse_modifications_success() {
+    let insert = parse("INSERT INTO t (Key, Value) VALUES (1, -2)").unwrap();
+    let expected = parse_from_str::<InsertProto>(
+        "
+        row {
+            columns { name: \"Key\" value { int_value: 1 } }
+            columns { name: \"Value\" value { int_value: -2 } }
+        }
+        ",
+    )
+    .unwrap();
+    assert_eq!(Statement::Insert(expected), insert);
+
+    let delete = parse("DELETE FROM t WHERE Key = 3000000000").unwrap();
+    let expected =
+        parse_from_str::<DeleteProto>("key { name: \"Key\" value { uint_value: 3000000000 } }")
+            .unwrap();
+    assert_eq!(Statement::Delete(expected), delete);
+
+    let update = parse("UPDATE t SET Value = 4 WHERE Key = 1").unwrap();
+    let expected = parse_from_str::<UpdateProto>(
+        "
+        key { name: \"Key\" value { int_value: 1 } }
+        columns { name: \"Value\" value { int_value: 4 } }
+        ",
+    )
+    .unwrap();
+    assert_eq!(Statement::Update(expected), update);
+}
+
+#[test]
+fn parse_schema_success() {
+    let table = parse(
+        "-- the primary key need not come first
+        CREATE TABLE t (Value INT, Key UNSIGNED INTEGER PRIMARY KEY)",
+    )
+    .unwrap();
+    let expected = parse_from_str::<TableSchema>(
+        "
+        key { name: \"Key\" column_type: UNSIGNED_INTEGER }
+        columns { name: \"Value\" column_type: INTEGER }
+        ",
+    )
+    .unwrap();
+    assert_eq!(Statement::CreateTable(expected), table);
+
+    assert_eq!(parse("ANALYZE t;").unwrap(), Statement::Analyze);
+    assert_eq!(parse("ANALYZE").unwrap(), Statement::Analyze);
+
+    let index = parse("CREATE INDEX value_idx ON t (Value)").unwrap();
+    let expected = parse_from_str::<IndexSchema>("key { name: \"Value\" }").unwrap();
+    assert_eq!(Statement::CreateIndex(expected), index);
+}
+
+#[test]
+fn parse_syntax_error() {
+    let cases = [
+        ("SELECT * FROM t WHERE", "line 1, column 22"),
+        ("SELECT *\nFROM t WHERE Key ! 1", "line 2, column 18"),
+        ("SELECT * FROM t LIMIT -1", "line 1, column 23"),
+        ("INSERT INTO t (Key, Value) VALUES (1)", "line 1, column 35"),
+        ("DELETE FROM t WHERE Key = 1 extra", "line 1, column 29"),
+        ("DROP TABLE t", "line 1, column 1"),
+    ];
+    for (sql, position) in cases {
+        let err = parse(sql).unwrap_err();
+        assert_eq!(err.kind, InvalidArgument);
+        assert!(err.msg.contains(position), "{}: {}", sql, err.msg);
+    }
+}
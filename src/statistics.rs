@@ -0,0 +1,142 @@
+use crate::error::*;
+use crate::filelike::Filelike;
+use crate::protos::generated::chunk::*;
+use crate::table::Table;
+
+// Updates the statistics maintained on every write for an inserted key.
+pub(crate) fn record_insert(statistics: &mut TableStatisticsProto, key: u32) {
+    if statistics.row_count == 0 {
+        statistics.min_key = key;
+        statistics.max_key = key;
+    } else {
+        statistics.min_key = std::cmp::min(statistics.min_key, key);
+        statistics.max_key = std::cmp::max(statistics.max_key, key);
+    }
+    statistics.row_count += 1;
+    statistics.modified_count += 1;
+}
+
+pub(crate) fn record_delete(statistics: &mut TableStatisticsProto) {
+    statistics.row_count = statistics.row_count.saturating_sub(1);
+    statistics.modified_count += 1;
+}
+
+// Finds the height of the table's B+ tree by walking down its leftmost path.
+async fn tree_height<F: Filelike>(table: &Table<F>) -> Result<u32, Error> {
+    let mut height = 1;
+    let mut offset = table.root_chunk_offset;
+    loop {
+        let node_buffer_lock = table.buffer_pool.read_from_table(table, offset).await?;
+        let node_buffer = table.buffer_pool.locks.read(&node_buffer_lock).await?;
+        match &node_buffer.get().node_type {
+            Some(node_proto::Node_type::Internal(internal)) => match internal.child_offsets.first()
+            {
+                Some(child_offset) => offset = *child_offset,
+                None => return Ok(height),
+            },
+            Some(node_proto::Node_type::Leaf(_)) => return Ok(height),
+            None => panic!(),
+        }
+        height += 1;
+    }
+}
+
+// Rebuilds all statistics of the table from its contents. Keys are read twice, once to count them
+// and once to pick the histogram bounds, so that they need not fit in memory.
+pub(crate) async fn analyze_table<F: Filelike>(
+    table: &Table<F>,
+) -> Result<TableStatisticsProto, Error> {
+    log::trace!("Analyzing table: {}", table.name);
+    let mut statistics = TableStatisticsProto::new();
+    let mut last_key = None;
+    let mut cursor = table.read_range(0, u32::MAX);
+    while let Some((keys, _)) = cursor.next_leaf().await? {
+        for key in keys {
+            if last_key != Some(key) {
+                statistics.distinct_count += 1;
+            }
+            if last_key.is_none() {
+                statistics.min_key = key;
+            }
+            statistics.max_key = key;
+            statistics.row_count += 1;
+            last_key = Some(key);
+        }
+    }
+
+    // the i-th bucket ends with the ((i + 1) * row_count / bucket_count)-th key.
+    let num_buckets = std::cmp::min(
+        table.options.statistics_histogram_buckets as u64,
+        statistics.row_count,
+    );
+    let mut idx = 0;
+    let mut cursor = table.read_range(0, u32::MAX);
+    'scan: while let Some((keys, _)) = cursor.next_leaf().await? {
+        for key in keys {
+            idx += 1;
+            let bucket = statistics.histogram_bounds.len() as u64;
+            if bucket >= num_buckets {
+                break 'scan;
+            }
+            if idx == (bucket + 1) * statistics.row_count / num_buckets {
+                statistics.histogram_bounds.push(key);
+            }
+        }
+    }
+    // NOTE: concurrent deletes may leave the last bucket without a bound.
+    if statistics.histogram_bounds.len() < num_buckets as usize {
+        statistics.histogram_bounds.push(statistics.max_key);
+    }
+
+    statistics.height = tree_height(table).await?;
+    Ok(statistics)
+}
+
+// The fraction of the bucket [bucket_lower, bucket_upper] covered by the range [lower, upper].
+fn covered_fraction(bucket_lower: u32, bucket_upper: u32, lower: u32, upper: u32) -> f64 {
+    let covered_lower = std::cmp::max(bucket_lower, lower) as f64;
+    let covered_upper = std::cmp::min(bucket_upper, upper) as f64;
+    if covered_lower > covered_upper {
+        return 0.0;
+    }
+    (covered_upper - covered_lower + 1.0) / (bucket_upper as f64 - bucket_lower as f64 + 1.0)
+}
+
+// Estimates the number of rows with keys in the inclusive range [lower, upper]. Keys are assumed to
+// be uniformly distributed within each histogram bucket, or within [min_key, max_key] if the table
+// hasn't been analyzed.
+pub(crate) fn estimate_range_rows(
+    statistics: &TableStatisticsProto,
+    lower: u32,
+    upper: u32,
+) -> f64 {
+    if statistics.row_count == 0 || lower > upper {
+        return 0.0;
+    }
+    let bounds = &statistics.histogram_bounds;
+    let fraction = if bounds.is_empty() {
+        covered_fraction(statistics.min_key, statistics.max_key, lower, upper)
+    } else {
+        let mut bucket_lower = statistics.min_key;
+        let mut fraction = 0.0;
+        for bound in bounds {
+            if bucket_lower <= *bound {
+                fraction += covered_fraction(bucket_lower, *bound, lower, upper);
+            }
+            bucket_lower = *bound;
+        }
+        fraction / bounds.len() as f64
+    };
+    fraction * statistics.row_count as f64
+}
+
+// Estimates the number of rows with the given key.
+pub(crate) fn estimate_equal_rows(statistics: &TableStatisticsProto, key: u32) -> f64 {
+    if statistics.row_count == 0 || key < statistics.min_key || statistics.max_key < key {
+        return 0.0;
+    }
+    if statistics.distinct_count > 0 {
+        return statistics.row_count as f64 / statistics.distinct_count as f64;
+    }
+    estimate_range_rows(statistics, key, key)
+}
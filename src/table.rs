@@ -5,16 +5,22 @@ mod test;
 use crate::bp_tree;
 use crate::buffer::Buffer;
 use crate::buffer_pool::BufferPool;
-use crate::error::*;
+use crate::error::{ErrorKind::*, *};
 use crate::filelike::Filelike;
+use crate::lock_manager::LockGuard;
+use crate::metrics::TableCounters;
+use crate::mvcc;
+use crate::options::{self, DatabaseOptions};
 use crate::protos::generated::chunk::*;
 use crate::protos::generated::config::*;
 use crate::protos::generated::operations::*;
 use crate::schema;
+use crate::statistics;
 use protobuf::MessageField;
+use std::collections::HashMap;
 use std::sync::atomic::{AtomicU32, Ordering};
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::sync::{Arc, Mutex as SyncMutex};
+use tokio::sync::{Mutex, RwLock, RwLockWriteGuard};
 
 // Table file format:
 // Chunk 0:          Metadata chunk
@@ -24,11 +30,26 @@ use tokio::sync::Mutex;
 pub(crate) struct Table<F: Filelike> {
     pub(crate) file: Arc<Mutex<F>>,
     pub(crate) buffer_pool: Arc<BufferPool<F>>,
+    pub(crate) options: Arc<DatabaseOptions>,
+    pub(crate) config: TableConfig,
     pub(crate) name: String,
     pub(crate) id: u32,
     pub(crate) schema: TableSchema,
     pub(crate) root_chunk_offset: u32,
     pub(crate) next_chunk_offset: AtomicU32,
+    pub(crate) statistics: SyncMutex<TableStatisticsProto>,
+    pub(crate) counters: Arc<TableCounters>,
+    // Held by transactions (in key order) while they check that keys can be inserted and insert
+    // them, so that concurrent inserts of the same key conflict, see Transaction::insert_rows.
+    // NOTE: striped by key, so some unrelated inserts also wait on each other.
+    insert_locks: Vec<RwLock<()>>,
+}
+
+// The number of insert locks of each table.
+const INSERT_LOCK_STRIPES: usize = 64;
+
+fn new_insert_locks() -> Vec<RwLock<()>> {
+    (0..INSERT_LOCK_STRIPES).map(|_| RwLock::new(())).collect()
 }
 
 impl<F: Filelike> Table<F> {
@@ -48,20 +69,30 @@ impl<F: Filelike> Table<F> {
         metadata.schema = MessageField::some(self.schema.clone());
         metadata.root_chunk_offset = self.root_chunk_offset;
         metadata.next_chunk_offset = self.next_chunk_offset.load(Ordering::Relaxed);
-        Buffer::new_for_file(self.file.clone(), 0, metadata)
+        metadata.statistics = MessageField::some(self.statistics());
+        metadata.config = MessageField::some(self.config.clone());
+        Buffer::new_for_file(self.file.clone(), &self.config, 0, metadata)
             .write_to_file()
             .await?;
+        self.counters
+            .bytes_written
+            .fetch_add(self.config.chunk_size as u64, Ordering::Relaxed);
         Ok(())
     }
 
     pub(crate) async fn create(
         file: F,
         buffer_pool: Arc<BufferPool<F>>,
+        options: Arc<DatabaseOptions>,
         name: String,
         id: u32,
         schema: TableSchema,
     ) -> Result<Self, Error> {
         let file = Arc::new(Mutex::new(file));
+        let config = options.table_config();
+        // NOTE: the tree starts out as a single (empty) root node.
+        let mut statistics = TableStatisticsProto::new();
+        statistics.height = 1;
         {
             let mut metadata = TableMetadataProto::new();
             metadata.name = name.clone();
@@ -69,7 +100,9 @@ impl<F: Filelike> Table<F> {
             metadata.schema = MessageField::some(schema.clone());
             metadata.root_chunk_offset = 1;
             metadata.next_chunk_offset = 2;
-            Buffer::new_for_file(file.clone(), 0, metadata.clone())
+            metadata.statistics = MessageField::some(statistics.clone());
+            metadata.config = MessageField::some(config.clone());
+            Buffer::new_for_file(file.clone(), &config, 0, metadata.clone())
                 .write_to_file()
                 .await?;
         }
@@ -77,34 +110,266 @@ impl<F: Filelike> Table<F> {
             let mut root_node = NodeProto::new();
             root_node.offset = 1;
             root_node.set_internal(InternalNodeProto::new());
-            Buffer::new_for_file(file.clone(), 1, root_node)
+            Buffer::<F, bp_tree::Node>::new_for_file(file.clone(), &config, 1, root_node.into())
                 .write_to_file()
                 .await?;
         }
         Ok(Self {
             file: file,
             buffer_pool: buffer_pool,
+            options,
+            config,
             name: name,
             id: id,
             schema: schema,
             root_chunk_offset: 1,
             next_chunk_offset: AtomicU32::new(2),
+            statistics: SyncMutex::new(statistics),
+            counters: Arc::new(TableCounters::default()),
+            insert_locks: new_insert_locks(),
         })
     }
 
+    // Opens an existing table, read with the page size it was created with (regardless of
+    // options).
+    pub(crate) async fn open(
+        file: F,
+        buffer_pool: Arc<BufferPool<F>>,
+        options: Arc<DatabaseOptions>,
+    ) -> Result<Self, Error> {
+        let file = Arc::new(Mutex::new(file));
+        // NOTE: the metadata chunk starts the file, so can be read before its size is known.
+        let metadata: Buffer<F, TableMetadataProto> =
+            Buffer::read_from_file(file.clone(), &options::max_table_config(), 0).await?;
+        let metadata = metadata.data;
+        if metadata.root_chunk_offset == 0 {
+            return Err(Error::new(
+                DataLoss,
+                "Table metadata is missing or corrupt!".to_string(),
+            ));
+        }
+        // NOTE: tables created before page sizes were configurable don't record theirs.
+        let config = match metadata.config.into_option() {
+            Some(config) => config,
+            None => DatabaseOptions::default().table_config(),
+        };
+        Ok(Self {
+            file,
+            buffer_pool,
+            options,
+            config,
+            name: metadata.name,
+            id: metadata.id,
+            schema: metadata.schema.unwrap_or_default(),
+            root_chunk_offset: metadata.root_chunk_offset,
+            next_chunk_offset: AtomicU32::new(metadata.next_chunk_offset),
+            statistics: SyncMutex::new(metadata.statistics.unwrap_or_default()),
+            counters: Arc::new(TableCounters::default()),
+            insert_locks: new_insert_locks(),
+        })
+    }
+
+    // NOTE: transactions insert rows in batches instead, see insert_batch.
+    #[cfg(test)]
     pub(crate) async fn insert(&self, key: u32, row: InternalRowProto) -> Result<(), Error> {
         log::trace!("Inserting row: {row}");
-        bp_tree::insert(self, key, row).await
+        self.insert_batch(&[(key, row)], &mut 0).await
     }
 
+    // Takes the insert locks of the given keys, through the lock manager.
+    pub(crate) async fn lock_for_insert(
+        &self,
+        keys: &[u32],
+    ) -> Result<Vec<LockGuard<'_, RwLockWriteGuard<'_, ()>>>, Error> {
+        let mut stripes: Vec<usize> = keys
+            .iter()
+            .map(|key| *key as usize % self.insert_locks.len())
+            .collect();
+        // NOTE: in order, so that transactions inserting several keys don't deadlock.
+        stripes.sort();
+        stripes.dedup();
+        let mut guards = Vec::with_capacity(stripes.len());
+        for stripe in stripes {
+            guards.push(
+                self.buffer_pool
+                    .locks
+                    .write(&self.insert_locks[stripe])
+                    .await?,
+            );
+        }
+        Ok(guards)
+    }
+
+    // Inserts the given rows (sorted by key), see bp_tree::insert_batch. num_inserted is set to
+    // the number of rows inserted, even on error. The metadata is committed once, for all rows.
+    pub(crate) async fn insert_batch(
+        &self,
+        rows: &[(u32, InternalRowProto)],
+        num_inserted: &mut usize,
+    ) -> Result<(), Error> {
+        log::trace!("Inserting {} rows.", rows.len());
+        let result = bp_tree::insert_batch(self, rows, num_inserted).await;
+        if *num_inserted == 0 {
+            return result;
+        }
+        {
+            let mut statistics = self.statistics.lock().unwrap();
+            for (key, _) in &rows[..*num_inserted] {
+                statistics::record_insert(&mut statistics, *key);
+            }
+        }
+        let committed = self.commit_metadata().await;
+        result.and(committed)
+    }
+
+    // Removes the first row with the given key, regardless of its versions.
+    // NOTE: transactions mark versions as deleted instead, see mark_deleted.
+    #[cfg(test)]
     pub(crate) async fn delete(&self, key: u32) -> Result<InternalRowProto, Error> {
         log::trace!("Deleting row with key {key}");
-        bp_tree::delete(self, key).await
+        let row = bp_tree::delete(self, key).await?;
+        statistics::record_delete(&mut self.statistics.lock().unwrap());
+        self.commit_metadata().await?;
+        Ok(row)
+    }
+
+    // Deletes the row with the given key and contents, see bp_tree::delete_matching.
+    // NOTE: statistics are only updated in memory here, and persisted along with the next
+    // metadata commit (e.g. at the next checkpoint), rather than writing metadata per row.
+    pub(crate) async fn delete_matching(
+        &self,
+        key: u32,
+        row: &InternalRowProto,
+    ) -> Result<(), Error> {
+        log::trace!("Deleting row with key {key}: {row}");
+        bp_tree::delete_matching(self, key, row).await?;
+        statistics::record_delete(&mut self.statistics.lock().unwrap());
+        Ok(())
     }
 
+    // Marks the given version of the row with the given key as deleted by the given transaction.
+    // Returns NotFound if the version doesn't exist (or was already marked).
+    pub(crate) async fn mark_deleted(
+        &self,
+        key: u32,
+        row: &InternalRowProto,
+        txn_id: u64,
+    ) -> Result<(), Error> {
+        log::trace!("Marking row with key {key} deleted by transaction {txn_id}: {row}");
+        let mut marked_row = row.clone();
+        marked_row.deleted_txn_id = txn_id;
+        bp_tree::replace_matching(self, key, row, &marked_row).await?;
+        statistics::record_delete(&mut self.statistics.lock().unwrap());
+        Ok(())
+    }
+
+    // Reverts mark_deleted, given the marked version.
+    pub(crate) async fn unmark_deleted(
+        &self,
+        key: u32,
+        marked_row: &InternalRowProto,
+    ) -> Result<(), Error> {
+        log::trace!("Unmarking deleted row with key {key}: {marked_row}");
+        let mut row = marked_row.clone();
+        row.deleted_txn_id = mvcc::NOT_DELETED;
+        bp_tree::replace_matching(self, key, marked_row, &row).await?;
+        statistics::record_insert(&mut self.statistics.lock().unwrap(), key);
+        Ok(())
+    }
+
+    // Removes a version of a row which is no longer visible, see mvcc::collect_garbage.
+    // NOTE: the version was already counted as deleted when marked.
+    pub(crate) async fn purge(&self, key: u32, row: &InternalRowProto) -> Result<(), Error> {
+        bp_tree::delete_matching(self, key, row).await
+    }
+
+    // Returns the row with the given key, visible to the snapshot of the enclosing task (if any).
     pub(crate) async fn read_row(&self, key: u32) -> Result<RowProto, Error> {
         log::trace!("Retrieving row with key: {key}");
-        let internal_row = bp_tree::read_row(self, self.root_chunk_offset, key).await?;
+        let internal_row = if mvcc::has_snapshot() {
+            let mut cursor = self.read_range(key, key);
+            match cursor.next_leaf().await? {
+                Some((_, mut rows)) => rows.swap_remove(0),
+                None => {
+                    return Err(Error::new(
+                        NotFound,
+                        format!("Row with key {} not found!", key),
+                    ))
+                }
+            }
+        } else {
+            bp_tree::read_row(self, self.root_chunk_offset, key).await?
+        };
         Ok(schema::internal_row_to_row(&internal_row, &self.schema))
     }
+
+    // Returns the rows with the given keys (in the same order), or None for keys without one,
+    // visible to the snapshot of the enclosing task (if any). The keys are read in a single
+    // traversal of the tree, see bp_tree::read_rows.
+    pub(crate) async fn read_rows(&self, keys: &[u32]) -> Result<Vec<Option<RowProto>>, Error> {
+        log::trace!("Retrieving rows with keys: {keys:?}");
+        let mut sorted_keys = keys.to_vec();
+        sorted_keys.sort_unstable();
+        sorted_keys.dedup();
+        let mut found = HashMap::new();
+        let has_snapshot = mvcc::has_snapshot();
+        for (key, row) in bp_tree::read_rows(self, self.root_chunk_offset, &sorted_keys).await? {
+            // NOTE: the first (visible) version is returned, as by read_row.
+            if !has_snapshot || mvcc::is_visible(&row) {
+                found.entry(key).or_insert(row);
+            }
+        }
+        Ok(keys
+            .iter()
+            .map(|key| {
+                found
+                    .get(key)
+                    .map(|row| schema::internal_row_to_row(row, &self.schema))
+            })
+            .collect())
+    }
+
+    // Returns all versions of the row with the given key, regardless of snapshot.
+    pub(crate) async fn read_versions(&self, key: u32) -> Result<Vec<InternalRowProto>, Error> {
+        let mut versions = Vec::new();
+        let mut cursor = self.read_range(key, key).all_versions();
+        while let Some((_, rows)) = cursor.next_leaf().await? {
+            versions.extend(rows);
+        }
+        Ok(versions)
+    }
+
+    // Returns all versions of the rows with the given (sorted, distinct) keys, regardless of
+    // snapshot, in key order.
+    pub(crate) async fn read_versions_of(
+        &self,
+        keys: &[u32],
+    ) -> Result<Vec<(u32, InternalRowProto)>, Error> {
+        bp_tree::read_rows(self, self.root_chunk_offset, keys).await
+    }
+
+    pub(crate) fn read_range(&self, lower: u32, upper: u32) -> bp_tree::RangeCursor<'_, F> {
+        log::trace!("Retrieving rows with keys in range: {lower}, {upper}");
+        bp_tree::RangeCursor::new(self, lower, upper)
+    }
+
+    // Reads every page of the table, so that those in an outdated format are rewritten once
+    // written back, see Encoding::from_bytes.
+    pub(crate) async fn migrate(&self) -> Result<(), Error> {
+        for offset in self.root_chunk_offset..self.next_chunk_offset.load(Ordering::Relaxed) {
+            self.buffer_pool.read_from_table(self, offset).await?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn statistics(&self) -> TableStatisticsProto {
+        self.statistics.lock().unwrap().clone()
+    }
+
+    // Rebuilds the table's statistics from its contents, and persists them.
+    pub(crate) async fn analyze(&self) -> Result<(), Error> {
+        let statistics = statistics::analyze_table(self).await?;
+        *self.statistics.lock().unwrap() = statistics;
+        self.commit_metadata().await
+    }
 }
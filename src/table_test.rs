@@ -1,18 +1,19 @@
+use crate::bp_tree::Node;
 use crate::buffer::Buffer;
 use crate::buffer_pool::BufferPool;
 use crate::error::{Error, ErrorKind::*};
+use crate::options::DatabaseOptions;
 use crate::protos::generated::chunk::*;
 use crate::protos::generated::config::*;
 use crate::protos::generated::operations::*;
 use crate::schema;
 use crate::table::Table;
-use crate::BUFFER_SIZE;
 use protobuf::text_format::parse_from_str;
 use std::io::Cursor;
 use std::sync::Arc;
 
 type MetadataBuffer = Buffer<Cursor<Vec<u8>>, TableMetadataProto>;
-type NodeBuffer = Buffer<Cursor<Vec<u8>>, NodeProto>;
+type NodeBuffer = Buffer<Cursor<Vec<u8>>, Node>;
 
 struct TestContext {
     table: Arc<Table<Cursor<Vec<u8>>>>,
@@ -33,7 +34,8 @@ async fn setup() -> TestContext {
         table: Arc::new(
             Table::create(
                 Cursor::<Vec<u8>>::new(Vec::new()),
-                Arc::new(BufferPool::new()),
+                Arc::new(BufferPool::new(&DatabaseOptions::default())),
+                Arc::new(DatabaseOptions::default()),
                 "TestTable".to_string(),
                 0,
                 schema,
@@ -63,14 +65,14 @@ async fn create_ok() -> Result<(), Error> {
 
     assert_eq!(
         table.file.lock().await.get_ref().len(),
-        (BUFFER_SIZE * 2) as usize
+        table.config.chunk_size as usize * 2
     );
 
-    let metadata = MetadataBuffer::read_from_file(table.file.clone(), 0).await?;
+    let metadata = MetadataBuffer::read_from_file(table.file.clone(), &table.config, 0).await?;
     assert_eq!(metadata.data.root_chunk_offset, 1);
     assert_eq!(metadata.data.next_chunk_offset, 2);
 
-    let node = NodeBuffer::read_from_file(table.file.clone(), 1).await?;
+    let node = NodeBuffer::read_from_file(table.file.clone(), &table.config, 1).await?;
     assert_eq!(node.data.offset, 1);
 
     Ok(())
@@ -90,17 +92,17 @@ async fn insert_single_ok() -> Result<(), Error> {
     table.buffer_pool.flush().await?;
     assert_eq!(
         table.file.lock().await.get_ref().len(),
-        (BUFFER_SIZE * 3) as usize
+        table.config.chunk_size as usize * 3
     );
 
-    let root = NodeBuffer::read_from_file(table.file.clone(), 1).await?;
+    let root = NodeBuffer::read_from_file(table.file.clone(), &table.config, 1).await?;
     assert_eq!(root.data.offset, 1);
     assert!(root.data.has_internal());
     assert_eq!(root.data.internal().keys.len(), 0);
     assert_eq!(root.data.internal().child_offsets.len(), 1);
     assert_eq!(root.data.internal().child_offsets[0], 2);
 
-    let child = NodeBuffer::read_from_file(table.file.clone(), 2).await?;
+    let child = NodeBuffer::read_from_file(table.file.clone(), &table.config, 2).await?;
     assert!(child.data.has_leaf());
     assert_eq!(child.data.offset, 2);
     assert_eq!(row, child.data.leaf().rows[0]);
@@ -134,15 +136,15 @@ async fn insert_sorted() -> Result<(), Error> {
     table.buffer_pool.flush().await?;
     assert_eq!(
         table.file.lock().await.get_ref().len(),
-        (BUFFER_SIZE * 3) as usize
+        table.config.chunk_size as usize * 3
     );
 
-    let root = NodeBuffer::read_from_file(table.file.clone(), 1).await?;
+    let root = NodeBuffer::read_from_file(table.file.clone(), &table.config, 1).await?;
     assert_eq!(root.data.offset, 1);
     assert!(root.data.has_internal());
     assert_eq!(root.data.internal().child_offsets.len(), 1);
 
-    let child = NodeBuffer::read_from_file(table.file.clone(), 2).await?;
+    let child = NodeBuffer::read_from_file(table.file.clone(), &table.config, 2).await?;
     assert_eq!(child.data.offset, 2);
     assert!(child.data.has_leaf());
     assert_eq!(child.data.leaf().rows.len(), 3);
@@ -171,15 +173,15 @@ async fn insert_with_split_ok() -> Result<(), Error> {
     table.buffer_pool.flush().await?;
     assert_eq!(
         table.file.lock().await.get_ref().len(),
-        (BUFFER_SIZE * 4) as usize
+        table.config.chunk_size as usize * 4
     );
 
-    let metadata = MetadataBuffer::read_from_file(table.file.clone(), 0).await?;
+    let metadata = MetadataBuffer::read_from_file(table.file.clone(), &table.config, 0).await?;
     assert_eq!(metadata.data.next_chunk_offset, 4);
     assert_eq!(metadata.data.root_chunk_offset, 1);
 
     for i in 1..2 {
-        let node = NodeBuffer::read_from_file(table.file.clone(), i).await?;
+        let node = NodeBuffer::read_from_file(table.file.clone(), &table.config, i).await?;
         validate_node_sorted(&node.get());
     }
 
@@ -234,6 +236,57 @@ async fn read_row_many_ok() -> Result<(), Error> {
     Ok(())
 }
 
+#[tokio::test]
+async fn read_range_ok() -> Result<(), Error> {
+    let ctx = setup().await;
+    let table = ctx.table;
+
+    for i in 0..500 {
+        let mut col = ValueProto::new();
+        col.set_int_value(i);
+        let mut row = InternalRowProto::new();
+        row.col_values.push(col);
+
+        table.insert(i as u32, row).await?;
+    }
+
+    let mut keys = Vec::new();
+    let mut cursor = table.read_range(100, 399);
+    while let Some((leaf_keys, leaf_rows)) = cursor.next_leaf().await? {
+        assert_eq!(leaf_keys.len(), leaf_rows.len());
+        keys.extend(leaf_keys);
+    }
+    assert_eq!(keys, (100..400).collect::<Vec<u32>>());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn read_range_duplicate_keys_ok() -> Result<(), Error> {
+    let ctx = setup().await;
+    let table = ctx.table;
+
+    // runs of duplicate keys span several leaves.
+    for i in 0..500 {
+        let mut col = ValueProto::new();
+        col.set_int_value(i);
+        let mut row = InternalRowProto::new();
+        row.col_values.push(col);
+
+        table.insert((i / 200) as u32, row).await?;
+    }
+
+    let mut keys = Vec::new();
+    let mut cursor = table.read_range(1, 2);
+    while let Some((leaf_keys, _)) = cursor.next_leaf().await? {
+        keys.extend(leaf_keys);
+    }
+    let expected_keys: Vec<u32> = (200..500).map(|i| i / 200).collect();
+    assert_eq!(keys, expected_keys);
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn async_read_write_success() -> Result<(), Error> {
     let ctx = setup().await;
@@ -292,3 +345,20 @@ async fn delete_ok() -> Result<(), Error> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn statistics_persisted_ok() -> Result<(), Error> {
+    let ctx = setup().await;
+    let table = ctx.table;
+    for i in 0..100 {
+        table.insert(i, InternalRowProto::new()).await?;
+    }
+    table.analyze().await?;
+
+    let metadata = MetadataBuffer::read_from_file(table.file.clone(), &table.config, 0).await?;
+    assert_eq!(*metadata.get().statistics, table.statistics());
+    assert_eq!(metadata.get().statistics.row_count, 100);
+    assert_eq!(metadata.get().statistics.distinct_count, 100);
+
+    Ok(())
+}
@@ -0,0 +1,477 @@
+use crate::database::Database;
+use crate::error::{ErrorKind::*, *};
+use crate::filelike::Filelike;
+use crate::mvcc::{self, Snapshot};
+use crate::protos::generated::chunk::*;
+use crate::protos::generated::operations::*;
+use crate::query;
+use crate::schema;
+use crate::table::Table;
+use protobuf::MessageField;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+// A group of operations which take effect together, or not at all. Changes are applied to tables
+// as each operation is made (so later operations observe them), and recorded so that they can be
+// undone on rollback. On commit, they're appended to the write-ahead log, making them durable.
+//
+// Each transaction reads from a snapshot taken when it began, and other transactions don't see
+// its changes until it commits, see mvcc.rs. Writes to a row which a concurrent transaction has
+// written fail with Aborted, as do operations which deadlock or time out waiting on locks (see
+// lock_manager.rs), after which the transaction should be rolled back.
+//
+// Every transaction should end with either commit or rollback. Transactions dropped otherwise
+// are rolled back in the background, see Drop.
+pub struct Transaction<'a, F: Filelike> {
+    db: &'a Database<F>,
+    snapshot: Arc<Snapshot>,
+    lock_timeout: Option<Duration>,
+    // All changes made so far, in order.
+    changes: Vec<(Arc<Table<F>>, RowChangeProto)>,
+    is_finished: bool,
+}
+
+// The rows of a single operation to apply to a table, see Transaction::apply.
+type TableRows<F> = (Arc<Table<F>>, Vec<(u32, InternalRowProto)>);
+
+fn write_conflict(key: u32) -> Error {
+    Error::new(
+        Aborted,
+        format!(
+            "Row with key {} was written by a concurrent transaction!",
+            key
+        ),
+    )
+}
+
+impl<'a, F: Filelike> Transaction<'a, F> {
+    pub(crate) fn new(db: &'a Database<F>, snapshot: Arc<Snapshot>) -> Self {
+        Self {
+            db,
+            snapshot,
+            lock_timeout: db.table.options.lock_wait_timeout,
+            changes: Vec::new(),
+            is_finished: false,
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.snapshot.txn_id
+    }
+
+    // Limits how long later operations wait on any single lock, or None to wait indefinitely.
+    pub fn set_lock_timeout(&mut self, lock_timeout: Option<Duration>) {
+        self.lock_timeout = lock_timeout;
+    }
+
+    fn row_change(
+        table: &Table<F>,
+        key: u32,
+        row: InternalRowProto,
+        is_delete: bool,
+    ) -> RowChangeProto {
+        let mut change = RowChangeProto::new();
+        change.table_id = table.id;
+        change.key = key;
+        change.row = MessageField::some(row);
+        change.is_delete = is_delete;
+        change
+    }
+
+    // Inserts new versions of the given rows, created by this transaction, or marks the given
+    // versions as deleted by it. Each table's rows are applied concurrently with the others',
+    // and all rows applied are recorded, even if others fail (see undo_failed).
+    // NOTE: tables are applied to in joined futures rather than spawned tasks, so that they keep
+    // the enclosing task's snapshot and lock scope (see LockManager::join_all).
+    async fn apply(
+        &mut self,
+        mut batches: Vec<TableRows<F>>,
+        is_delete: bool,
+    ) -> Result<(), Error> {
+        let txn_id = self.id();
+        if !is_delete {
+            for (_, rows) in &mut batches {
+                rows.sort_by_key(|(key, _)| *key);
+                for (_, row) in rows.iter_mut() {
+                    row.created_txn_id = txn_id;
+                    row.deleted_txn_id = mvcc::NOT_DELETED;
+                }
+            }
+        }
+        let mut num_applied = vec![0; batches.len()];
+        let results =
+            self.db
+                .locks()
+                .join_all(batches.iter().zip(&mut num_applied).map(
+                    |((table, rows), num_applied)| {
+                        Self::apply_to(table, rows, is_delete, txn_id, num_applied)
+                    },
+                ))
+                .await;
+        for ((table, rows), num_applied) in batches.into_iter().zip(num_applied) {
+            for (key, row) in rows.into_iter().take(num_applied) {
+                let change = Self::row_change(&table, key, row, is_delete);
+                self.changes.push((table.clone(), change));
+            }
+        }
+        results.into_iter().collect()
+    }
+
+    // Applies the given rows (sorted by key, for inserts) to a single table, counting those
+    // applied in num_applied.
+    async fn apply_to(
+        table: &Table<F>,
+        rows: &[(u32, InternalRowProto)],
+        is_delete: bool,
+        txn_id: u64,
+        num_applied: &mut usize,
+    ) -> Result<(), Error> {
+        if !is_delete {
+            return table.insert_batch(rows, num_applied).await;
+        }
+        for (key, row) in rows {
+            table
+                .mark_deleted(*key, row, txn_id)
+                .await
+                .map_err(|e| match e.kind {
+                    NotFound => write_conflict(*key),
+                    _ => e,
+                })?;
+            *num_applied += 1;
+        }
+        Ok(())
+    }
+
+    // If the given result (of an operation) is an error, undoes the changes recorded since
+    // num_changes, so that the operation takes effect entirely or not at all, e.g. an insert into
+    // the table isn't kept if an insert into one of its secondary indexes failed.
+    async fn undo_failed(
+        &mut self,
+        num_changes: usize,
+        result: Result<(), Error>,
+    ) -> Result<(), Error> {
+        while result.is_err() && self.changes.len() > num_changes {
+            let (table, change) = self.changes.last().unwrap();
+            // NOTE: changes which can't be undone now are left to rollback.
+            if let Err(e) = self.undo(table, change).await {
+                log::error!("Unable to undo change of failed operation: {e}");
+                break;
+            }
+            self.changes.pop();
+        }
+        result
+    }
+
+    // Reverts the given change, made by this transaction.
+    async fn undo(&self, table: &Table<F>, change: &RowChangeProto) -> Result<(), Error> {
+        undo(table, change, self.id()).await
+    }
+
+    pub async fn insert(&mut self, op: InsertProto) -> Result<(), Error> {
+        let db = self.db;
+        let num_changes = self.changes.len();
+        let result = db
+            .locks()
+            .scope(self.lock_timeout, self.insert_row(op))
+            .await;
+        self.undo_failed(num_changes, result).await
+    }
+
+    // Inserts the given rows together, which is faster than inserting them one at a time: rows
+    // belonging to the same leaf are inserted at once, the table and its secondary indexes are
+    // inserted into concurrently, and their metadata is committed once for the batch.
+    pub async fn insert_batch(&mut self, ops: Vec<InsertProto>) -> Result<(), Error> {
+        let db = self.db;
+        let num_changes = self.changes.len();
+        let result = db
+            .locks()
+            .scope(self.lock_timeout, self.insert_rows(ops))
+            .await;
+        self.undo_failed(num_changes, result).await
+    }
+
+    pub async fn delete(&mut self, op: DeleteProto) -> Result<(), Error> {
+        let db = self.db;
+        let num_changes = self.changes.len();
+        let result = db
+            .locks()
+            .scope(self.lock_timeout, self.delete_row(op))
+            .await;
+        self.undo_failed(num_changes, result).await
+    }
+
+    // Replaces the given columns of the row with the given key.
+    // NOTE: implemented as a delete followed by an insert, so that secondary indexes are kept
+    // up to date.
+    pub async fn update(&mut self, op: UpdateProto) -> Result<(), Error> {
+        let db = self.db;
+        let num_changes = self.changes.len();
+        let result = db
+            .locks()
+            .scope(self.lock_timeout, self.update_row(op))
+            .await;
+        self.undo_failed(num_changes, result).await
+    }
+
+    pub async fn read_row(&self, op: ReadRowProto) -> Result<RowProto, Error> {
+        let hashed_key = schema::get_hashed_col_value(&op.key.value);
+        self.read_scope(self.db.table.read_row(hashed_key)).await
+    }
+
+    // Returns the rows with the given keys, in the same order, or None for keys without one.
+    pub async fn read_rows(&self, op: ReadRowsProto) -> Result<Vec<Option<RowProto>>, Error> {
+        let hashed_keys: Vec<u32> = op
+            .keys
+            .iter()
+            .map(|key| schema::get_hashed_col_value(&key.value))
+            .collect();
+        self.read_scope(self.db.table.read_rows(&hashed_keys)).await
+    }
+
+    pub async fn query(&self, op: QueryProto) -> Result<F, Error> {
+        self.read_scope(query::execute_query::<F>(self.db, op))
+            .await
+    }
+
+    // Runs the given future with reads made from this transaction's snapshot.
+    async fn read_scope<T>(&self, future: impl Future<Output = T>) -> T {
+        mvcc::with_snapshot(
+            self.snapshot.clone(),
+            self.db.locks().scope(self.lock_timeout, future),
+        )
+        .await
+    }
+
+    async fn insert_row(&mut self, op: InsertProto) -> Result<(), Error> {
+        self.insert_rows(vec![op]).await
+    }
+
+    // Fails unless a row with the given key, of which the given version exists, can be
+    // inserted, i.e. unless the version was deleted before this transaction began.
+    fn check_insertable(&self, key: u32, version: &InternalRowProto) -> Result<(), Error> {
+        if !self.snapshot.is_committed(version.created_txn_id)
+            || (mvcc::is_deleted(version) && !self.snapshot.is_committed(version.deleted_txn_id))
+        {
+            return Err(write_conflict(key));
+        }
+        if !mvcc::is_deleted(version) {
+            return Err(Error::new(
+                AlreadyExists,
+                format!("Row with key {} already exists!", key),
+            ));
+        }
+        Ok(())
+    }
+
+    async fn insert_rows(&mut self, ops: Vec<InsertProto>) -> Result<(), Error> {
+        let table = self.db.table.clone();
+        let mut rows: Vec<(u32, RowProto)> = ops
+            .into_iter()
+            .map(|op| {
+                let row = op.row.unwrap_or_default();
+                (schema::get_hashed_key_from_row(&row, &table.schema), row)
+            })
+            .collect();
+        rows.sort_by_key(|(key, _)| *key);
+        if let Some(pair) = rows.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+            return Err(Error::new(
+                AlreadyExists,
+                format!("Row with key {} inserted twice!", pair[0].0),
+            ));
+        }
+        let keys: Vec<u32> = rows.iter().map(|(key, _)| *key).collect();
+        // NOTE: held until the rows are inserted, after which concurrent inserts of the same
+        // keys find their versions, see check_insertable.
+        let _insert_locks = table.lock_for_insert(&keys).await?;
+        for (key, version) in table.read_versions_of(&keys).await? {
+            self.check_insertable(key, &version)?;
+        }
+
+        let mut batches = vec![(table.clone(), Vec::with_capacity(rows.len()))];
+        for secondary_index in &self.db.secondary_indexes {
+            batches.push((secondary_index.clone(), Vec::with_capacity(rows.len())));
+        }
+        for (key, row) in &rows {
+            batches[0].1.push((*key, schema::row_to_internal_row(row)));
+            for (secondary_index, index_rows) in &mut batches[1..] {
+                let index_row =
+                    schema::table_row_to_index_row(row, &secondary_index.schema, &table.schema);
+                let index_key =
+                    schema::get_hashed_key_from_row(&index_row, &secondary_index.schema);
+                index_rows.push((index_key, schema::row_to_internal_row(&index_row)));
+            }
+        }
+        self.apply(batches, false).await
+    }
+
+    async fn delete_row(&mut self, op: DeleteProto) -> Result<(), Error> {
+        let table = self.db.table.clone();
+        let hashed_key = schema::get_hashed_col_value(&op.key.value);
+        let Some(version) = table
+            .read_versions(hashed_key)
+            .await?
+            .into_iter()
+            .find(|version| self.snapshot.is_visible(version))
+        else {
+            return Err(Error::new(
+                NotFound,
+                format!("Row with key {} not found!", hashed_key),
+            ));
+        };
+        // NOTE: visible versions can only have been deleted by concurrent transactions.
+        if mvcc::is_deleted(&version) {
+            return Err(write_conflict(hashed_key));
+        }
+        let row = schema::internal_row_to_row(&version, &table.schema);
+
+        let mut batches = Vec::new();
+        for secondary_index in &self.db.secondary_indexes {
+            let index_row =
+                schema::table_row_to_index_row(&row, &secondary_index.schema, &table.schema);
+            let index_key = schema::get_hashed_key_from_row(&index_row, &secondary_index.schema);
+            // NOTE: index entries are versioned along with the rows they refer to.
+            let mut index_version = schema::row_to_internal_row(&index_row);
+            index_version.created_txn_id = version.created_txn_id;
+            index_version.deleted_txn_id = version.deleted_txn_id;
+            batches.push((secondary_index.clone(), vec![(index_key, index_version)]));
+        }
+        batches.insert(0, (table, vec![(hashed_key, version)]));
+        self.apply(batches, true).await
+    }
+
+    async fn update_row(&mut self, op: UpdateProto) -> Result<(), Error> {
+        for col in &op.columns {
+            if self.db.table.is_table_keyed_on_column(&col.name) {
+                return Err(Error::new(
+                    InvalidArgument,
+                    format!("Cannot update key column: {}!", col.name),
+                ));
+            }
+            if !schema::is_col_in_table(&self.db.table.schema, &col.name) {
+                return Err(Error::new(
+                    InvalidArgument,
+                    format!("Column not in table: {}!", col.name),
+                ));
+            }
+        }
+
+        let hashed_key = schema::get_hashed_col_value(&op.key.value);
+        let mut row = self.read_scope(self.db.table.read_row(hashed_key)).await?;
+        for col in op.columns {
+            match row
+                .columns
+                .iter_mut()
+                .find(|row_col| row_col.name == col.name)
+            {
+                Some(row_col) => row_col.value = col.value,
+                None => row.columns.push(col),
+            }
+        }
+
+        let mut delete_op = DeleteProto::new();
+        delete_op.key = op.key;
+        self.delete_row(delete_op).await?;
+        let mut insert_op = InsertProto::new();
+        insert_op.row = MessageField::some(row);
+        self.insert_row(insert_op).await
+    }
+
+    // Makes all changes durable, and visible to later snapshots. If the changes can't be logged,
+    // they're rolled back instead.
+    pub async fn commit(mut self) -> Result<(), Error> {
+        log::trace!("Committing transaction: {}", self.id());
+        if !self.changes.is_empty() {
+            let changes = self
+                .changes
+                .iter()
+                .map(|(_, change)| change.clone())
+                .collect();
+            if let Err(e) = self.db.wal.commit(self.id(), changes).await {
+                self.rollback().await?;
+                return Err(e);
+            }
+        }
+        self.finish();
+        Ok(())
+    }
+
+    // Undoes all changes, in reverse order.
+    pub async fn rollback(mut self) -> Result<(), Error> {
+        log::trace!("Rolling back transaction: {}", self.id());
+        while let Some((table, change)) = self.changes.pop() {
+            self.undo(&table, &change).await?;
+        }
+        self.finish();
+        Ok(())
+    }
+
+    fn finish(&mut self) {
+        self.is_finished = true;
+        self.db.transactions.finish(self.id());
+    }
+
+    // Commits if the given result (of an operation in this transaction) is ok, else rolls back
+    // and returns its error.
+    pub(crate) async fn commit_if_ok<T>(self, result: Result<T, Error>) -> Result<T, Error> {
+        match result {
+            Ok(value) => {
+                self.commit().await?;
+                Ok(value)
+            }
+            Err(e) => {
+                self.rollback().await?;
+                Err(e)
+            }
+        }
+    }
+}
+
+// Reverts the given change, made by the given transaction.
+// NOTE: not a method, so that it can be spawned without borrowing the transaction, see Drop.
+async fn undo<F: Filelike>(
+    table: &Table<F>,
+    change: &RowChangeProto,
+    txn_id: u64,
+) -> Result<(), Error> {
+    let row = change.row.as_ref().unwrap();
+    if change.is_delete {
+        let mut marked_row = row.clone();
+        marked_row.deleted_txn_id = txn_id;
+        table.unmark_deleted(change.key, &marked_row).await
+    } else {
+        table.delete_matching(change.key, row).await
+    }
+}
+
+impl<F: Filelike> Drop for Transaction<'_, F> {
+    // Rolls back the changes of transactions which weren't committed or rolled back, e.g. after
+    // an early return, in a spawned task. Until its changes are undone, the transaction remains
+    // in progress so that they're never visible.
+    fn drop(&mut self) {
+        if self.is_finished {
+            return;
+        }
+        if self.changes.is_empty() {
+            self.finish();
+            return;
+        }
+        let txn_id = self.id();
+        log::error!("Transaction {txn_id} dropped without commit or rollback, rolling back!");
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            log::error!("Unable to roll back transaction {txn_id}: no runtime to roll back on!");
+            return;
+        };
+        let mut changes = std::mem::take(&mut self.changes);
+        let transactions = self.db.transactions.clone();
+        runtime.spawn(async move {
+            while let Some((table, change)) = changes.pop() {
+                if let Err(e) = undo(&table, &change, txn_id).await {
+                    log::error!("Unable to roll back transaction {txn_id}: {e}");
+                    return;
+                }
+            }
+            transactions.finish(txn_id);
+        });
+    }
+}
@@ -0,0 +1,174 @@
+#[cfg(test)]
+#[path = "./wal_test.rs"]
+mod test;
+
+use crate::buffer::Buffer;
+use crate::error::*;
+use crate::filelike::Filelike;
+use crate::protos::generated::chunk::*;
+use crate::protos::generated::config::*;
+use protobuf::Message;
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+// Write-ahead log file format:
+// Chunks 0 - end: LogRecordProto chunks, in commit order.
+//
+// The changes of each transaction are appended (and flushed) when it commits, before it is
+// reported as committed. Changes are applied to tables before commit, so the log only needs to
+// be replayed if table pages were lost, i.e. after a crash.
+//
+// Checkpoints record (after writing all dirty pages to disk) the offset of the first record
+// whose changes may not have been written to tables, so only records from there need replaying.
+// They also record the next transaction id, so that ids aren't reused after reopening.
+// TODO: truncate the log before the last checkpoint, keeping the ids of committed transactions
+// (see mvcc::undo_uncommitted).
+// The transactions recorded in an existing log, see Wal::open.
+pub(crate) struct LoggedTransactions {
+    // The lowest transaction id not yet used, as of the last checkpoint or commit.
+    pub(crate) next_txn_id: u64,
+    // The ids of all committed transactions (with changes).
+    pub(crate) committed: HashSet<u64>,
+}
+
+pub(crate) struct Wal<F: Filelike> {
+    file: Arc<Mutex<F>>,
+    config: TableConfig,
+    // NOTE: held for the duration of each append, so that records aren't interleaved.
+    state: Mutex<WalState>,
+}
+
+struct WalState {
+    next_offset: u32,
+    // The redo offset of the last checkpoint, or 0 if none.
+    redo_offset: u32,
+}
+
+impl<F: Filelike> Wal<F> {
+    pub(crate) fn new(file: F, config: &TableConfig) -> Self {
+        Self {
+            file: Arc::new(Mutex::new(file)),
+            config: config.clone(),
+            state: Mutex::new(WalState {
+                next_offset: 0,
+                redo_offset: 0,
+            }),
+        }
+    }
+
+    // Opens an existing log, so that later commits are appended to it. Also returns what was
+    // recorded of the transactions which used the database before, see LoggedTransactions.
+    pub(crate) async fn open(
+        file: F,
+        config: &TableConfig,
+    ) -> Result<(Self, LoggedTransactions), Error> {
+        let mut wal = Self::new(file, config);
+        let mut logged = LoggedTransactions {
+            next_txn_id: 1,
+            committed: HashSet::new(),
+        };
+        let state = wal.state.get_mut();
+        loop {
+            // NOTE: chunks past the end of the file read as empty, i.e. without a transaction.
+            let buffer: Buffer<F, LogRecordProto> =
+                Buffer::read_from_file(wal.file.clone(), &wal.config, state.next_offset).await?;
+            let record = buffer.data;
+            if let Some(redo_offset) = record.redo_offset {
+                state.redo_offset = redo_offset;
+            } else if record.txn_id == 0 {
+                break;
+            }
+            logged.next_txn_id = logged
+                .next_txn_id
+                .max(record.next_txn_id)
+                .max(record.txn_id + 1);
+            if record.commit {
+                logged.committed.insert(record.txn_id);
+            }
+            state.next_offset += 1;
+        }
+        Ok((wal, logged))
+    }
+
+    // Appends the changes of the given transaction, marking it as committed.
+    // NOTE: the end of the log only advances once the whole transaction is written, so that the
+    // records of a failed commit are overwritten by the next one, see also read_committed.
+    pub(crate) async fn commit(
+        &self,
+        txn_id: u64,
+        changes: Vec<RowChangeProto>,
+    ) -> Result<(), Error> {
+        let mut state = self.state.lock().await;
+        let mut offset = state.next_offset;
+        let mut record = LogRecordProto::new();
+        record.txn_id = txn_id;
+        for change in changes {
+            let buffer = Buffer::new_for_file(self.file.clone(), &self.config, offset, record);
+            if buffer.would_overflow(change.compute_size() as usize + std::mem::size_of::<u32>()) {
+                buffer.write_to_file().await?;
+                offset += 1;
+                record = LogRecordProto::new();
+                record.txn_id = txn_id;
+            } else {
+                record = buffer.data;
+            }
+            record.changes.push(change);
+        }
+        record.commit = true;
+        Buffer::new_for_file(self.file.clone(), &self.config, offset, record)
+            .write_to_file()
+            .await?;
+        state.next_offset = offset + 1;
+        Ok(())
+    }
+
+    // The offset the next record will be appended at.
+    pub(crate) async fn end_offset(&self) -> u32 {
+        self.state.lock().await.next_offset
+    }
+
+    // Appends a checkpoint record, marking the changes of all records before redo_offset as
+    // written to tables, and ids below next_txn_id as used.
+    pub(crate) async fn checkpoint(&self, redo_offset: u32, next_txn_id: u64) -> Result<(), Error> {
+        let mut state = self.state.lock().await;
+        let mut record = LogRecordProto::new();
+        record.redo_offset = Some(redo_offset);
+        record.next_txn_id = next_txn_id;
+        Buffer::new_for_file(self.file.clone(), &self.config, state.next_offset, record)
+            .write_to_file()
+            .await?;
+        state.next_offset += 1;
+        state.redo_offset = redo_offset;
+        Ok(())
+    }
+
+    // Returns the changes of all transactions committed since the last checkpoint, in commit
+    // order, see Database::replay. Records of transactions which weren't committed (e.g. left
+    // by a failed commit, before the log was reopened) are skipped.
+    pub(crate) async fn read_committed(&self) -> Result<Vec<(u64, Vec<RowChangeProto>)>, Error> {
+        let state = self.state.lock().await;
+        let mut txns = Vec::new();
+        // The changes of the transaction whose records are being read, if any.
+        let mut run: Option<(u64, Vec<RowChangeProto>)> = None;
+        for offset in state.redo_offset..state.next_offset {
+            let buffer: Buffer<F, LogRecordProto> =
+                Buffer::read_from_file(self.file.clone(), &self.config, offset).await?;
+            let record = buffer.data;
+            if record.redo_offset.is_some() {
+                run = None;
+                continue;
+            }
+            let (txn_id, changes) = match run.as_mut() {
+                Some(run) if run.0 == record.txn_id => run,
+                _ => run.insert((record.txn_id, Vec::new())),
+            };
+            changes.extend(record.changes);
+            if record.commit {
+                txns.push((*txn_id, std::mem::take(changes)));
+                run = None;
+            }
+        }
+        Ok(txns)
+    }
+}
@@ -0,0 +1,81 @@
+use crate::error::Error;
+use crate::faulty_file::{Faults, FaultyFile};
+use crate::protos::generated::chunk::*;
+use crate::protos::generated::config::*;
+use crate::protos::generated::operations::*;
+use crate::wal::Wal;
+use std::io::Cursor;
+use std::sync::Arc;
+
+fn config() -> TableConfig {
+    let mut config = TableConfig::new();
+    config.chunk_size = 256;
+    config.chunk_overflow_size = 5;
+    config
+}
+
+// Creates the given number of changes, each inserting a row (padded, so that few fit in a chunk).
+fn changes(txn_id: u64, num_changes: u32) -> Vec<RowChangeProto> {
+    (0..num_changes)
+        .map(|key| {
+            let mut value = ValueProto::new();
+            value.set_int_value(key as i32);
+            let mut change = RowChangeProto::new();
+            change.key = key;
+            let row = change.row.mut_or_insert_default();
+            row.col_values = vec![value; 10];
+            row.created_txn_id = txn_id;
+            change
+        })
+        .collect()
+}
+
+// Reopens the log from its written bytes.
+async fn reopen(wal: &Wal<FaultyFile>, faults: &Arc<Faults>) -> Result<Wal<FaultyFile>, Error> {
+    let bytes = wal.file.lock().await.inner.get_ref().clone();
+    let file = FaultyFile {
+        inner: Cursor::new(bytes),
+        faults: faults.clone(),
+    };
+    Ok(Wal::open(file, &config()).await?.0)
+}
+
+#[tokio::test]
+async fn failed_commit_not_replayed() -> Result<(), Error> {
+    let faults = Arc::new(Faults::default());
+    let file = FaultyFile {
+        faults: faults.clone(),
+        ..Default::default()
+    };
+    let wal = Wal::new(file, &config());
+    wal.commit(1, changes(1, 1)).await?;
+
+    // the first chunks of the transaction are written, then a later one fails.
+    *faults.fail_writes_after.lock().unwrap() = Some(2);
+    assert!(wal.commit(2, changes(2, 20)).await.is_err());
+    *faults.fail_writes_after.lock().unwrap() = None;
+    wal.commit(3, changes(3, 1)).await?;
+    let committed = wal.read_committed().await?;
+    assert_eq!(committed.len(), 2);
+    assert_eq!(committed[1], (3, changes(3, 1)));
+
+    // chunks of the failed commit remain past the end of the log, until overwritten.
+    let wal = reopen(&wal, &faults).await?;
+    wal.commit(4, changes(4, 2)).await?;
+    let committed = wal.read_committed().await?;
+    let txn_ids: Vec<u64> = committed.iter().map(|(txn_id, _)| *txn_id).collect();
+    assert_eq!(txn_ids, vec![1, 3, 4]);
+    assert_eq!(committed[2].1, changes(4, 2));
+
+    let (_, logged) = Wal::open(
+        FaultyFile {
+            inner: Cursor::new(wal.file.lock().await.inner.get_ref().clone()),
+            faults,
+        },
+        &config(),
+    )
+    .await?;
+    assert!(!logged.committed.contains(&2));
+
+    Ok(())
+}